@@ -0,0 +1,30 @@
+/* C API of the exhash editing engine, built into the cdylib with `--features ffi`.
+ * Every returned string is owned by the caller and must be released with
+ * exhash_free_result. */
+#ifndef EXHASH_H
+#define EXHASH_H
+
+#include <stddef.h>
+
+#ifdef __cplusplus
+extern "C" {
+#endif
+
+/* Apply ncommands command strings to text. Returns a JSON object: on success
+ * {"ok":true,"lines":[...],"hashes":[...],"modified":[...],"deleted":[...],"text":"..."},
+ * otherwise {"ok":false,"error":{"message":"...","kind":"stale_hash","command":1,...}},
+ * with the lineno and the expected and actual hashes of a stale address. */
+char *exhash_edit(const char *text, const char *const *commands, size_t ncommands);
+
+/* The lnhash view of text, one "lineno|hash|  content\n" line per line, or NULL if text
+ * is NULL or not UTF-8. */
+char *exhash_lnhashview(const char *text);
+
+/* Release a string returned by the functions above. NULL is ignored. */
+void exhash_free_result(char *result);
+
+#ifdef __cplusplus
+}
+#endif
+
+#endif
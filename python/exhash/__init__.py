@@ -1,18 +1,70 @@
+import asyncio, os
+from collections.abc import Callable
+
 from .exhash import line_hash as _line_hash, lnhash as _lnhash, lnhashview as _lnhashview, exhash as _exhash
+from .exhash import file_fingerprint as _file_fingerprint, line_hash_u64 as _line_hash_u64
+from .exhash import exhash_script as _exhash_script, edit_file as _edit_file, exhash_many as _exhash_many
+from .exhash import verify_only as _verify_only
+from .exhash import StaleHashError, ParseError, AddressRangeError, EditSession as _EditSession
+from .exhash import Command, Subcommand, LnHash, EditOptions
+from .exhash import parse_lnhash as _parse_lnhash, format_lnhash as _format_lnhash, lnhashes as _lnhashes
+
+# Text arguments may also be bytes, decoded from the ``encoding`` argument (a WHATWG label such as
+# ``"latin1"``, or ``"auto"``; UTF-8 by default), or a path to a file to read the same way.
+Text = str | bytes | os.PathLike
+
+def line_hash(line:str, width:int=4, seed:str|None=None, nfc:bool=False, ignore_case:bool=False) -> str:
+    'Return a ``width``-char (4, 6 or 8) lowercase hex hash for a single line of text, optionally mixing in ``seed``.'
+    return _line_hash(line, width=width, seed=seed, nfc=nfc, ignore_case=ignore_case)
+
+
+def line_hash_u64(line:str, seed:str|None=None, nfc:bool=False, ignore_case:bool=False) -> int:
+    'Return the full 64-bit hash of ``line``; ``line_hash`` is its low bits. Useful as a dedup/index key.'
+    return _line_hash_u64(line, seed=seed, nfc=nfc, ignore_case=ignore_case)
+
+
+def lnhash(lineno:int, line:str, width:int=4, compact:bool=False, seed:str|None=None, nfc:bool=False,
+           ignore_case:bool=False, versioned:bool=False, chained:bool=False, prev:str|None=None) -> str:
+    'Return an lnhash address ``lineno|hash|`` (or compact ``base36:hash``) for ``line`` at 1-based ``lineno``.'
+    return _lnhash(lineno, line, prev, width=width, compact=compact, seed=seed, nfc=nfc, ignore_case=ignore_case,
+                   versioned=versioned, chained=chained)
+
 
-def line_hash(line:str) -> str:
-    'Return a 4-char lowercase hex hash for a single line of text.'
-    return _line_hash(line)
+def parse_lnhash(addr:str) -> LnHash:
+    'Parse ``lineno|hash|`` (or compact ``base36:hash``) into an ``LnHash`` with ``lineno``, ``hash`` (int), ``width``, ``formatted``.'
+    return _parse_lnhash(addr)
 
 
-def lnhash(lineno:int, line:str) -> str:
-    'Return an lnhash address ``lineno|hash|`` for ``line`` at 1-based ``lineno``.'
-    return _lnhash(lineno, line)
+def format_lnhash(lineno:int, hash:int, width:int=4, compact:bool=False) -> str:
+    'Format an untagged address from its parts: ``format_lnhash(12, 0xa3f2)`` is ``"12|a3f2|"``.'
+    return _format_lnhash(lineno, hash, width, compact)
 
 
-def lnhashview(text:str) -> list[str]:
-    'Return lines formatted as ``lineno|hash|  content`` for each line in ``text``.'
-    return _lnhashview(text)
+def lnhashes(text:Text, width:int=4, seed:str|None=None, nfc:bool=False, ignore_case:bool=False, versioned:bool=False,
+             chained:bool=False, encoding:str|None=None) -> list[LnHash]:
+    'Return the ``LnHash`` of every line in ``text``, as ``lnhashview`` addresses them.'
+    return _lnhashes(text, width=width, seed=seed, nfc=nfc, ignore_case=ignore_case, versioned=versioned,
+                     chained=chained, encoding=encoding)
+
+
+def file_fingerprint(text:Text, encoding:str|None=None) -> str:
+    'Return a 16-char hex fingerprint of the whole of ``text``, for an ``@file`` header.'
+    return _file_fingerprint(text, encoding)
+
+
+def lnhashview(text:Text, width:int=4, compact:bool=False, seed:str|None=None, nfc:bool=False,
+               ignore_case:bool=False, versioned:bool=False, chained:bool=False, encoding:str|None=None,
+               start:int|None=None, end:int|None=None, pattern:str|None=None, context:int=0,
+               rows:bool=False) -> list[str]|list[dict]:
+    """Return lines formatted as ``lineno|hash|  content`` for each line in ``text`` (a str, bytes or file path).
+
+    As with the ``lnhashview`` CLI, ``start`` and ``end`` (1-based, inclusive) limit the view to a range, and
+    ``pattern`` (a regex) to the lines in it that match, with ``context`` lines around each. With ``rows``, each
+    line is a dict instead: ``lineno``, ``hash`` (its address), ``text``, and ``context`` (True for a line shown
+    only as context around a match).
+    """
+    return _lnhashview(text, start, end, pattern, context, rows, width=width, compact=compact, seed=seed, nfc=nfc,
+                       ignore_case=ignore_case, versioned=versioned, chained=chained, encoding=encoding)
 
 
 def exhash_result(results:list[dict]) -> str:
@@ -28,7 +80,9 @@ def exhash_result(results:list[dict]) -> str:
     return '\n'.join(out)
 
 
-def exhash(text:str, cmds:list[str]) -> dict:
+def exhash(text:Text, cmds:list[str], width:int=4, seed:str|None=None, nfc:bool=False, ignore_case:bool=False,
+           versioned:bool=False, chained:bool=False, encoding:str|None=None, options:EditOptions|None=None,
+           on_command:Callable[[dict], object]|None=None) -> dict:
     """Verified line-addressed editor. Apply commands to `text`, return a result dict.
 
     Commands use lnhash addresses: ``lineno|hash|cmd`` where hash is a 4-char
@@ -43,7 +97,8 @@ def exhash(text:str, cmds:list[str]) -> dict:
       Special:  ``0|0000|`` targets before line 1 (only with a or i)
 
     Commands:
-      s/pat/rep/[flags]  Substitute (regex). Flags: g=all, i=case-insensitive
+      s/pat/rep/[flags]  Substitute (regex). Flags: g=all, i=case-insensitive,
+                         @FROM-TO=only display columns FROM to TO
       d                  Delete line(s)
       a                  Append text after line
       i                  Insert text before line
@@ -59,16 +114,61 @@ def exhash(text:str, cmds:list[str]) -> dict:
       g!/pat/cmd         Inverted global (also v/pat/cmd)
 
     For a/i/c, remaining lines in the command string are the text block
-    (no '.' terminator needed, unlike the CLI).
+    (no '.' terminator needed, unlike the CLI). Short text can instead be
+    inline: ``a\\text``, where ``\\n`` starts a new line.
+
+    ``text`` may also be ``bytes`` or a path to a file (which is read, not
+    written; see ``edit_file``), decoded from ``encoding`` (a WHATWG label or
+    ``"auto"``; UTF-8 by default).
+
+    Returns a dict (an ``EditResult``, which also has ``unified_diff()`` and
+    ``to_patch()`` methods) with:
+      lines        list of output lines
+      hashes       lnhash for each output line
+      full_hashes  full 64-bit hash (int) for each output line
+      modified     1-based line numbers of modified/added lines
+      deleted      1-based line numbers of removed lines (in original)
 
-    Returns a dict with:
-      lines     list of output lines
-      hashes    lnhash for each output line
-      modified  1-based line numbers of modified/added lines
-      deleted   1-based line numbers of removed lines (in original)
+    `cmds` is a required iterable of command strings or ``Command`` objects. For
+    `a`/`i`/`c`, include the text block in the same command string after a
+    newline. A first command
+    of ``@file <fingerprint>`` (see ``file_fingerprint``) checks the whole text
+    before any lnhash is verified.
 
-    `cmds` is a required iterable of command strings. For `a`/`i`/`c`, include
-    the text block in the same command string after a newline.
+    The hashing keywords (``width``, ``seed``, ``nfc``, ``ignore_case``,
+    ``versioned``, ``chained``) are shared with ``lnhash`` and ``lnhashview``.
+    ``width`` sets the hex digits in returned hashes; input addresses are
+    verified at whatever width they were written with.
+
+    Failures raise a ``ValueError`` subclass where one fits: ``StaleHashError``
+    when an address or ``@file`` fingerprint no longer matches (fetch addresses
+    again and retry; it has ``lineno``, ``expected`` and ``actual``, with
+    ``lineno`` None for a fingerprint), ``ParseError`` for a malformed command,
+    and ``AddressRangeError`` for an address past the end of the text.
+    With ``seed``, hashes are computed in that seed's namespace: addresses must
+    come from ``lnhash``/``lnhashview`` with the same seed (they are tagged
+    ``lineno|sNNNN:hash|``), and returned hashes are seeded too. With ``nfc``,
+    returned hashes are computed after Unicode NFC normalization; addresses
+    tagged ``nfc:`` are verified that way regardless. ``ignore_case`` likewise
+    lowercases lines before hashing (tagged ``lc:``). With ``versioned``,
+    returned hashes name their algorithm (``lineno|v2:hash|``). With ``chained``,
+    returned hashes also cover the previous line (tagged ``prev:``).
+
+    ``options`` is an ``EditOptions`` holding the rest of the CLI's edit settings
+    (``indent_width``, ``fuzz``, ``bottom_up``, ``join``, ``strict_substitute``,
+    ``compact`` hashes and the ``max_file_size``/``max_commands``/``max_block_size``
+    limits) along with
+    the hashing ones, which are then not given as keywords.
+    ``EditOptions.from_config()`` reads them from the same config files as the
+    CLI, so both edit alike.
+
+    ``on_command``, for progress reporting or audit logs, is called once the
+    edit succeeds with a dict for each command in the order they ran:
+    ``command`` (its index, not counting an ``@file`` header), ``text`` (its
+    script form), ``changed`` (``(lineno, text)`` of the lines it changed or
+    added, as they stood right after it), ``deleted`` (the original line
+    numbers it removed), ``line_count`` and ``stats`` (counts of lines
+    ``added``, ``changed`` and ``deleted`` and of ``substitutions``).
 
     Examples::
 
@@ -80,5 +180,168 @@ def exhash(text:str, cmds:list[str]) -> dict:
       "\\n".join(res["lines"])           # "baz\\nbar"
       res = exhash(text, [f"{addr}a\\nnew line 1\\nnew line 2"])
     """
-    r = _exhash(text, *cmds)
-    return dict(lines=r.lines, hashes=r.hashes, modified=r.modified, deleted=r.deleted)
+    r = _exhash(text, *map(str, cmds), width=width, seed=seed, nfc=nfc, ignore_case=ignore_case, versioned=versioned,
+                chained=chained, encoding=encoding, options=options, on_command=on_command)
+    return _result_dict(r)
+
+
+def exhash_script(text:Text, script:str, width:int=4, seed:str|None=None, nfc:bool=False, ignore_case:bool=False,
+                  versioned:bool=False, chained:bool=False, encoding:str|None=None,
+                  options:EditOptions|None=None, on_command:Callable[[dict], object]|None=None) -> dict:
+    """Like ``exhash``, but with the commands as one ex-style script, as ``exhash --script`` reads them.
+
+    Commands are one per line. The text block of an ``a``/``i``/``c`` follows it on the next
+    lines and ends with a line holding only ``.``. The script may start with an
+    ``@file <fingerprint>`` line. Keywords and the returned dict are as for ``exhash``::
+
+      script = f"{lnhash(2, 'bar')}d\\n{lnhash(1, 'foo')}a\\nnew line\\n.\\n"
+      exhash_script("foo\\nbar\\n", script)["lines"]   # ["foo", "new line"]
+    """
+    r = _exhash_script(text, script, width=width, seed=seed, nfc=nfc, ignore_case=ignore_case, versioned=versioned,
+                       chained=chained, encoding=encoding, options=options, on_command=on_command)
+    return _result_dict(r)
+
+
+def verify_only(text:Text, cmds:list[str], suggest:bool=False, window:int=20, width:int=4, seed:str|None=None,
+                nfc:bool=False, ignore_case:bool=False, versioned:bool=False, chained:bool=False,
+                encoding:str|None=None, options:EditOptions|None=None) -> dict:
+    """Check ``cmds`` against ``text`` as ``exhash`` would apply them, without producing a result.
+
+    Unlike ``exhash``, this does not stop at the first failure: a failed command is skipped and
+    checking goes on, so one call lists every stale or out-of-range address. Returns a dict with:
+      ok           True if ``exhash(text, cmds)`` would succeed
+      errors       one dict per failure: ``message``, ``kind`` (``"stale_hash"``, ``"out_of_range"``, ...),
+                   ``command`` (its index) and ``text``, and for a stale hash ``lineno``, ``expected``
+                   and ``actual``
+      suggestions  with ``suggest``, for each stale address the line it names now (``current``) and
+                   the lines within ``window`` lines whose text its hash still matches (``matches``)
+
+    A command that cannot be parsed raises ``ParseError``. Keywords are as for ``exhash``.
+    """
+    return _verify_only(text, *map(str, cmds), suggest=suggest, window=window, width=width, seed=seed, nfc=nfc,
+                        ignore_case=ignore_case, versioned=versioned, chained=chained, encoding=encoding,
+                        options=options)
+
+
+def exhash_many(items:list[tuple[Text, list[str]]], width:int=4, seed:str|None=None, nfc:bool=False,
+                ignore_case:bool=False, versioned:bool=False, chained:bool=False,
+                return_exceptions:bool=False, encoding:str|None=None,
+                options:EditOptions|None=None) -> list[dict]:
+    """Apply ``exhash(text, cmds)`` to each ``(text, cmds)`` pair, in parallel Rust threads.
+
+    The edits run without the GIL, so other Python threads keep going meanwhile. Results come back in
+    the order of ``items``. The first failure is raised, unless ``return_exceptions`` is set: then
+    each failed edit's exception takes its place in the list, as with ``asyncio.gather``::
+
+      exhash_many([(text1, cmds1), (text2, cmds2)])   # [result1, result2]
+    """
+    items = [(text, [str(c) for c in cmds]) for text, cmds in items]
+    rs = _exhash_many(items, width=width, seed=seed, nfc=nfc, ignore_case=ignore_case, versioned=versioned,
+                      chained=chained, return_exceptions=return_exceptions, encoding=encoding, options=options)
+    return [r if isinstance(r, Exception) else _result_dict(r) for r in rs]
+
+
+def edit_file(path:str|os.PathLike, cmds:list[str], dry_run:bool=False, backup:str|None=None, encoding:str|None=None,
+              width:int=4, seed:str|None=None, nfc:bool=False, ignore_case:bool=False, versioned:bool=False,
+              chained:bool=False, options:EditOptions|None=None,
+              on_command:Callable[[dict], object]|None=None) -> dict:
+    """Apply ``cmds`` to the file at ``path`` and write it back atomically, as the ``exhash`` CLI does.
+
+    The new text goes to a temp file beside ``path`` that is renamed over it, keeping the file's
+    permissions and line endings. With ``dry_run`` nothing is written; with ``backup`` the original
+    is first copied to ``path + backup``. ``encoding`` is a WHATWG label (``"latin1"``,
+    ``"shift_jis"``) or ``"auto"``; without one the file must be UTF-8. The file is only written
+    if every command succeeds, and not at all if it is over ``options.max_file_size``. Commands,
+    keywords and the returned dict are as for ``exhash``::
+
+      edit_file("notes.txt", [f"{lnhash(1, 'foo')}s/foo/baz/"], backup=".bak")
+    """
+    r = _edit_file(os.fspath(path), *map(str, cmds), dry_run=dry_run, backup=backup, encoding=encoding, width=width,
+                   seed=seed, nfc=nfc, ignore_case=ignore_case, versioned=versioned, chained=chained,
+                   options=options, on_command=on_command)
+    return _result_dict(r)
+
+
+async def aedit_file(path:str|os.PathLike, cmds:list[str], **kwargs) -> dict:
+    """An awaitable ``edit_file``: the read, edit and write run on a worker thread, leaving the event loop free.
+
+    Arguments, errors and the returned dict are as for ``edit_file``. An ``on_command`` callback is
+    called on the worker thread::
+
+      res = await aedit_file("notes.txt", [f"{lnhash(1, 'foo')}s/foo/baz/"])
+    """
+    return await asyncio.to_thread(edit_file, path, list(cmds), **kwargs)
+
+
+class EditSession:
+    """A text buffer to view and edit incrementally, with undo.
+
+    Each ``apply`` verifies its commands against the buffer as it is now, so addresses from
+    the latest ``view()`` (or from the ``hashes`` of the previous result) stay valid between
+    calls. Hashing keywords are as for ``lnhashview``, and ``options`` as for ``exhash``::
+
+      s = EditSession("foo\\nbar\\n")
+      s.view()                           # ["1|a1b2|  foo", "2|c3d4|  bar"]
+      s.apply([f"{lnhash(1, 'foo')}s/foo/baz/"])
+      s.text                             # "baz\\nbar\\n"
+      s.undo()                           # True; s.text is "foo\\nbar\\n" again
+    """
+    def __init__(self, text:Text, width:int=4, compact:bool=False, seed:str|None=None, nfc:bool=False,
+                 ignore_case:bool=False, versioned:bool=False, chained:bool=False, encoding:str|None=None,
+                 options:EditOptions|None=None):
+        self._s = _EditSession(text, width=width, compact=compact, seed=seed, nfc=nfc, ignore_case=ignore_case,
+                               versioned=versioned, chained=chained, encoding=encoding, options=options)
+
+    @property
+    def text(self) -> str:
+        'The current text, with the line endings it was given.'
+        return self._s.text
+
+    def view(self, start:int|None=None, end:int|None=None) -> list[str]:
+        'Return the lnhash view of lines ``start`` to ``end`` (1-based, inclusive; default all).'
+        return self._s.view(start, end)
+
+    def apply(self, cmds:list[str], on_command:Callable[[dict], object]|None=None) -> dict:
+        'Apply ``cmds`` as ``exhash`` does and keep the result; a failed batch changes nothing.'
+        return _result_dict(self._s.apply(*map(str, cmds), on_command=on_command))
+
+    def undo(self) -> bool:
+        'Revert the last successful ``apply``. Returns False if there is nothing to undo.'
+        return self._s.undo()
+
+
+class EditResult(dict):
+    'The result dict of an edit, with the result text and methods to show the edit as a diff.'
+    def __init__(self, r):
+        super().__init__(lines=r.lines, hashes=r.hashes, full_hashes=r.full_hashes, modified=r.modified,
+                         deleted=r.deleted)
+        self._r = r
+
+    @property
+    def text(self) -> str:
+        'The result text: the lines joined with the line endings of the input.'
+        return self._r.text
+
+    def to_dict(self) -> dict:
+        'Return the fields and ``text`` as a plain dict, e.g. for ``json.dumps``.'
+        return self._r.to_dict()
+
+    def rows(self) -> list[tuple[int, str, str]]:
+        'Return the result lines as ``(lineno, hash, text)`` tuples.'
+        return list(self._r)
+
+    def unified_diff(self, original:str|None=None, context:int=3, from_label:str='a', to_label:str='b') -> str:
+        'Return a unified diff from ``original`` (by default, the text that was edited) to the result; empty if unchanged.'
+        return self._r.unified_diff(original, context, from_label, to_label)
+
+    def to_patch(self, path:str|None=None) -> str:
+        'Return the edit as a patch for ``patch -p1`` or ``git apply``, naming ``path`` (by default the file edited).'
+        return self._r.to_patch(path)
+
+    def _repr_html_(self) -> str:
+        'Show the edit in a notebook as a table of the changed lines, colored, with their lnhashes.'
+        return self._r._repr_html_()
+
+
+def _result_dict(r) -> EditResult:
+    return EditResult(r)
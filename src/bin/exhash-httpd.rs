@@ -0,0 +1,285 @@
+use std::env;
+use std::fs;
+use std::net::SocketAddr;
+use std::path::PathBuf;
+use std::process;
+use std::sync::{Arc, Mutex};
+
+use axum::body::Bytes;
+use axum::extract::{DefaultBodyLimit, State};
+use axum::http::StatusCode;
+use axum::routing::post;
+use axum::{Json, Router};
+use exhash::{
+    edit_file, error_json, parse_commands_with_header, parse_size, result_json, str_param,
+    usize_param, verify_commands, view_json, Command, Config, EditError, EditOptions, ErrorKind,
+    FileOptions, ParamError,
+};
+use serde_json::{json, Map, Value};
+
+/// Files larger than this are refused unless `max-file-size` is configured.
+const DEFAULT_MAX_FILE_SIZE: u64 = 10 << 20;
+
+fn usage() {
+    eprintln!(
+        "Usage: exhash-httpd [--listen ADDR] [--allow DIR]... [--max-body SIZE]\n\
+         \x20                  [--max-file-size SIZE] [--no-config]\n\n\
+         Serves exhash over HTTP: POST a JSON object to /view, /verify or /edit.\n\n\
+         \x20 /view    {{path, start?, end?}}    lines as {{lineno, hash, text}}, and the file's\n\
+         \x20                                  fingerprint for an @file header\n\
+         \x20 /verify  {{path, commands}}        check commands without applying them\n\
+         \x20 /edit    {{path, commands, dry_run?}}\n\
+         \x20                                  apply commands and write the file atomically\n\n\
+         `commands` is a list of command strings as the Python exhash() takes them. A\n\
+         failed request answers {{\"ok\": false, \"error\": {{message, kind, ...}}}} with\n\
+         status 400 (bad request or parse error), 403 (path not allowed), 404 (no such\n\
+         file), 409 (stale hash), 413 (too large) or 422 (address out of range).\n\n\
+         Options:\n\
+         \x20 --listen ADDR        address to listen on (default 127.0.0.1:8080)\n\
+         \x20 --allow DIR          serve only files under DIR (repeatable; default: the\n\
+         \x20                      current directory). Symlinks are resolved first.\n\
+         \x20 --max-body SIZE      largest request body (default 1M)\n\
+         \x20 --max-file-size SIZE largest file to view or edit (default: max-file-size\n\
+         \x20                      from the config files, or 10M)\n\
+         \x20 --no-config          ignore the config files\n\
+         SIZE may end in K, M or G. The server is unauthenticated: listen on a private\n\
+         address only."
+    );
+}
+
+/// A failed request: its HTTP status and the `error` object of the response.
+struct HttpError(StatusCode, Value);
+
+impl HttpError {
+    fn new(status: StatusCode, message: impl Into<String>) -> Self {
+        Self(status, json!({ "message": message.into(), "kind": ErrorKind::Other.name() }))
+    }
+
+    fn bad_request(message: impl Into<String>) -> Self {
+        Self::new(StatusCode::BAD_REQUEST, message)
+    }
+}
+
+impl From<EditError> for HttpError {
+    fn from(e: EditError) -> Self {
+        let status = match e.kind() {
+            ErrorKind::Parse => StatusCode::BAD_REQUEST,
+            ErrorKind::StaleHash | ErrorKind::ExpectationFailed => StatusCode::CONFLICT,
+            ErrorKind::OutOfRange => StatusCode::UNPROCESSABLE_ENTITY,
+            ErrorKind::Other | ErrorKind::Cancelled => StatusCode::INTERNAL_SERVER_ERROR,
+        };
+        Self(status, error_json(&e))
+    }
+}
+
+impl From<ParamError> for HttpError {
+    fn from(e: ParamError) -> Self {
+        Self::bad_request(e.0)
+    }
+}
+
+struct Server {
+    opts: EditOptions,
+    /// Canonical directories whose files may be served.
+    allow: Vec<PathBuf>,
+    max_file_size: u64,
+    /// Held while a file is edited, so that concurrent edits cannot interleave between
+    /// verifying a file and writing it.
+    writing: Mutex<()>,
+}
+
+type Handler = fn(&Server, &Map<String, Value>) -> Result<Value, HttpError>;
+
+impl Server {
+    /// The allowed file named by the `path` param, if it is within the size limit.
+    fn path(&self, params: &Map<String, Value>) -> Result<PathBuf, HttpError> {
+        let path = str_param(params, "path")?;
+        let full = fs::canonicalize(path)
+            .map_err(|e| HttpError::new(StatusCode::NOT_FOUND, format!("{path}: {e}")))?;
+        if !self.allow.iter().any(|dir| full.starts_with(dir)) {
+            let msg = format!("{path}: outside the allowed directories");
+            return Err(HttpError::new(StatusCode::FORBIDDEN, msg));
+        }
+        let meta = fs::metadata(&full);
+        let size = meta.map_err(|e| HttpError::bad_request(format!("{path}: {e}")))?;
+        if size.len() > self.max_file_size {
+            let limit = self.max_file_size;
+            let msg = format!("{path}: {} bytes is over the {limit} byte limit", size.len());
+            return Err(HttpError::new(StatusCode::PAYLOAD_TOO_LARGE, msg));
+        }
+        Ok(full)
+    }
+
+    fn read(&self, params: &Map<String, Value>) -> Result<String, HttpError> {
+        let path = self.path(params)?;
+        fs::read_to_string(&path).map_err(|e| {
+            HttpError::bad_request(format!("{}: {e}", str_param(params, "path").unwrap_or("")))
+        })
+    }
+
+    fn view(&self, params: &Map<String, Value>) -> Result<Value, HttpError> {
+        let text = self.read(params)?;
+        let (start, end) = (usize_param(params, "start")?, usize_param(params, "end")?);
+        let mut view = view_json(&text, &self.opts.hash_spec, self.opts.addr_style, start, end);
+        view["ok"] = Value::Bool(true);
+        Ok(view)
+    }
+
+    fn verify(&self, params: &Map<String, Value>) -> Result<Value, HttpError> {
+        let text = self.read(params)?;
+        let (commands, opts) = self.commands(params)?;
+        let errors = verify_commands(&text, &commands, &opts);
+        let errors: Vec<Value> = errors.iter().map(error_json).collect();
+        Ok(json!({ "ok": errors.is_empty(), "errors": errors }))
+    }
+
+    fn edit(&self, params: &Map<String, Value>) -> Result<Value, HttpError> {
+        let path = self.path(params)?;
+        let (commands, opts) = self.commands(params)?;
+        let file_opts = FileOptions {
+            dry_run: params.get("dry_run").and_then(Value::as_bool).unwrap_or(false),
+            ..Default::default()
+        };
+        let _writing = self.writing.lock().unwrap_or_else(|e| e.into_inner());
+        let (res, _) = edit_file(&path, &commands, &opts, &file_opts)?;
+        let mut result = result_json(&res);
+        result["ok"] = Value::Bool(true);
+        Ok(result)
+    }
+
+    /// The `commands` param, parsed, and the edit options with any `@file` fingerprint.
+    fn commands(
+        &self,
+        params: &Map<String, Value>,
+    ) -> Result<(Vec<Command>, EditOptions), HttpError> {
+        let bad = || HttpError::bad_request("commands must be a list of strings");
+        let mut cmd_refs = Vec::new();
+        for c in params.get("commands").and_then(Value::as_array).ok_or_else(bad)? {
+            cmd_refs.push(c.as_str().ok_or_else(bad)?);
+        }
+        let (commands, fingerprint) = parse_commands_with_header(&cmd_refs)?;
+        Ok((commands, EditOptions { fingerprint, ..self.opts.clone() }))
+    }
+}
+
+/// Run `handler` on a request body, answering its result or error as JSON.
+fn respond(server: &Server, body: &[u8], handler: Handler) -> (StatusCode, Json<Value>) {
+    let result = match serde_json::from_slice::<Value>(body) {
+        Ok(Value::Object(params)) => handler(server, &params),
+        Ok(_) => Err(HttpError::bad_request("the body must be a JSON object")),
+        Err(e) => Err(HttpError::bad_request(format!("invalid JSON: {e}"))),
+    };
+    match result {
+        Ok(value) => (StatusCode::OK, Json(value)),
+        Err(HttpError(status, error)) => (status, Json(json!({ "ok": false, "error": error }))),
+    }
+}
+
+fn route(handler: Handler) -> axum::routing::MethodRouter<Arc<Server>> {
+    post(move |State(server): State<Arc<Server>>, body: Bytes| async move {
+        respond(&server, &body, handler)
+    })
+}
+
+fn size_arg(args: &[String], i: usize) -> u64 {
+    args.get(i + 1).and_then(|s| parse_size(s)).unwrap_or_else(|| {
+        eprintln!("error: {} expects a size in bytes, e.g. 4096 or 10M", args[i]);
+        process::exit(2);
+    })
+}
+
+fn main() {
+    let args: Vec<String> = env::args().skip(1).collect();
+    let mut listen: SocketAddr = ([127, 0, 0, 1], 8080).into();
+    let mut allow: Vec<String> = Vec::new();
+    let mut max_body: u64 = 1 << 20;
+    let mut max_file_size: Option<u64> = None;
+    let mut no_config = false;
+    let mut i = 0;
+    while i < args.len() {
+        match args[i].as_str() {
+            "-h" | "--help" => {
+                usage();
+                return;
+            }
+            "--listen" => {
+                listen = args.get(i + 1).and_then(|a| a.parse().ok()).unwrap_or_else(|| {
+                    eprintln!("error: --listen expects an address such as 127.0.0.1:8080");
+                    process::exit(2);
+                });
+                i += 2;
+            }
+            "--allow" => {
+                let Some(dir) = args.get(i + 1) else {
+                    eprintln!("error: --allow requires a directory");
+                    process::exit(2);
+                };
+                allow.push(dir.clone());
+                i += 2;
+            }
+            "--max-body" => {
+                max_body = size_arg(&args, i);
+                i += 2;
+            }
+            "--max-file-size" => {
+                max_file_size = Some(size_arg(&args, i));
+                i += 2;
+            }
+            "--no-config" => {
+                no_config = true;
+                i += 1;
+            }
+            a => {
+                eprintln!("error: unknown argument {a:?}");
+                usage();
+                process::exit(2);
+            }
+        }
+    }
+    let config = if no_config {
+        Config::default()
+    } else {
+        Config::load().unwrap_or_else(|e| {
+            eprintln!("error: {e}");
+            process::exit(2);
+        })
+    };
+    if allow.is_empty() {
+        allow.push(".".to_string());
+    }
+    let allow = allow
+        .iter()
+        .map(|dir| {
+            fs::canonicalize(dir).unwrap_or_else(|e| {
+                eprintln!("error: --allow {dir}: {e}");
+                process::exit(2);
+            })
+        })
+        .collect();
+    let server = Server {
+        opts: config.edit_options(),
+        allow,
+        max_file_size: max_file_size.or(config.max_file_size).unwrap_or(DEFAULT_MAX_FILE_SIZE),
+        writing: Mutex::new(()),
+    };
+    let app = Router::new()
+        .route("/view", route(Server::view))
+        .route("/verify", route(Server::verify))
+        .route("/edit", route(Server::edit))
+        .layer(DefaultBodyLimit::max(usize::try_from(max_body).unwrap_or(usize::MAX)))
+        .with_state(Arc::new(server));
+
+    let runtime = tokio::runtime::Runtime::new().unwrap_or_else(|e| {
+        eprintln!("error: {e}");
+        process::exit(1);
+    });
+    let served = runtime.block_on(async {
+        let listener = tokio::net::TcpListener::bind(listen).await?;
+        eprintln!("exhash-httpd listening on http://{}", listener.local_addr()?);
+        axum::serve(listener, app).await
+    });
+    if let Err(e) = served {
+        eprintln!("error: {listen}: {e}");
+        process::exit(1);
+    }
+}
@@ -0,0 +1,328 @@
+use std::env;
+use std::fs;
+use std::io::{self, BufRead, Write};
+use std::path::Path;
+use std::process;
+
+use exhash::{
+    edit_file, parse_commands_with_header, str_param, suggest_addresses, usize_param,
+    verify_commands, Command, Config, EditError, EditOptions, ErrorKind, FileOptions, ParamError,
+};
+use serde_json::{json, Map, Value};
+
+/// How far either side of a stale address to look for the line it meant.
+const SUGGEST_WINDOW: usize = 20;
+
+/// Protocol versions this server speaks, newest first.
+const PROTOCOL_VERSIONS: [&str; 3] = ["2025-06-18", "2025-03-26", "2024-11-05"];
+
+const INSTRUCTIONS: &str = "\
+Edit files by line address. lnhash_view shows each line as `lineno|hash|  content`; \
+exhash_edit takes ed-style commands addressed by those `lineno|hash|` prefixes and refuses \
+the whole edit if any addressed line has changed since it was viewed. After an edit, use \
+the new addresses it returns (or view again) for the next one.";
+
+fn usage() {
+    eprintln!(
+        "Usage: exhash-mcp [--no-config]\n\n\
+         A Model Context Protocol server on stdin/stdout giving LLM clients the tools\n\
+         lnhash_view, exhash_edit and exhash_verify. Hash and edit options come from the\n\
+         config files, as for exhash. Register it with a client as a stdio server, e.g.\n\n\
+         \x20 {{\"mcpServers\": {{\"exhash\": {{\"command\": \"exhash-mcp\"}}}}}}"
+    );
+}
+
+/// The tools, as `tools/list` describes them.
+fn tools() -> Value {
+    let path = json!({ "type": "string", "description": "Path of the file" });
+    let commands = json!({
+        "type": "array",
+        "items": { "type": "string" },
+        "description": "Commands, applied in order, each addressed by `lineno|hash|` from \
+            lnhash_view: `s/old/new/[g]`, `d`, `a`, `i` or `c` with the new lines after the \
+            command line, `>`/`<` to indent, `m`/`t` to move or copy, `j` to join, and ranges \
+            `addr1,addr2cmd`. An optional first `@file <fingerprint>` checks the whole file."
+    });
+    json!([
+        {
+            "name": "lnhash_view",
+            "description": "Show a file's lines as `lineno|hash|  content`, the addresses \
+                exhash_edit takes.",
+            "inputSchema": {
+                "type": "object",
+                "properties": {
+                    "path": path,
+                    "start_line": { "type": "integer", "minimum": 1,
+                                    "description": "First line to show (1-based)" },
+                    "end_line": { "type": "integer", "minimum": 1,
+                                  "description": "Last line to show" },
+                },
+                "required": ["path"],
+            },
+        },
+        {
+            "name": "exhash_edit",
+            "description": "Apply ed-style commands to a file, verifying every address \
+                first. Nothing is written unless all of them apply. Returns the changed \
+                lines with their new addresses.",
+            "inputSchema": {
+                "type": "object",
+                "properties": {
+                    "path": path,
+                    "commands": commands,
+                    "dry_run": { "type": "boolean",
+                                 "description": "Report the result without writing" },
+                },
+                "required": ["path", "commands"],
+            },
+        },
+        {
+            "name": "exhash_verify",
+            "description": "Check commands against a file without applying them, listing \
+                every stale address, out-of-range line or other problem.",
+            "inputSchema": {
+                "type": "object",
+                "properties": { "path": path, "commands": commands },
+                "required": ["path", "commands"],
+            },
+        },
+    ])
+}
+
+/// A tool's failure, reported to the model as an error result rather than a protocol
+/// error.
+struct ToolError(String);
+
+impl From<EditError> for ToolError {
+    fn from(e: EditError) -> Self {
+        Self(format!("{} error: {e}", e.kind().name()))
+    }
+}
+
+impl From<ParamError> for ToolError {
+    fn from(e: ParamError) -> Self {
+        Self(e.0)
+    }
+}
+
+struct Server {
+    opts: EditOptions,
+}
+
+impl Server {
+    fn call_tool(&self, name: &str, args: &Map<String, Value>) -> Result<String, ToolError> {
+        match name {
+            "lnhash_view" => self.view(args),
+            "exhash_edit" => self.edit(args),
+            "exhash_verify" => self.verify(args),
+            _ => unreachable!("unknown tools are refused before the call"),
+        }
+    }
+
+    fn view(&self, args: &Map<String, Value>) -> Result<String, ToolError> {
+        let path = str_param(args, "path")?;
+        let text = read(path)?;
+        let lines: Vec<&str> = text.lines().collect();
+        if lines.is_empty() {
+            return Ok(format!("{path} is empty; add lines with `0|0000|a` (no hash to check)"));
+        }
+        let start = usize_param(args, "start_line")?.unwrap_or(1).max(1);
+        let end = usize_param(args, "end_line")?.unwrap_or(lines.len()).min(lines.len());
+        if start > end {
+            return Err(ToolError(format!("no lines {start}-{end}: {path} has {}", lines.len())));
+        }
+        let addrs = self.opts.hash_spec.lnhashes(lines.iter().copied());
+        let style = self.opts.addr_style;
+        let view: Vec<String> = (start..=end)
+            .map(|n| format!("{}  {}", addrs[n - 1].format_with(style), lines[n - 1]))
+            .collect();
+        Ok(view.join("\n"))
+    }
+
+    fn edit(&self, args: &Map<String, Value>) -> Result<String, ToolError> {
+        let path = str_param(args, "path")?;
+        let (commands, opts) = self.commands(args)?;
+        let file_opts = FileOptions {
+            dry_run: args.get("dry_run").and_then(Value::as_bool).unwrap_or(false),
+            ..Default::default()
+        };
+        let res = match edit_file(Path::new(path), &commands, &opts, &file_opts) {
+            Ok((res, _)) => res,
+            Err(e) => return Err(ToolError(self.describe(path, &e, &commands, &opts))),
+        };
+        let mut out = vec![format!(
+            "{} {path}: {} line(s) modified, {} deleted.",
+            if file_opts.dry_run { "Dry run, not written:" } else { "Edited" },
+            res.modified.len(),
+            res.deleted.len()
+        )];
+        if !res.modified.is_empty() {
+            out.push("Changed lines now:".to_string());
+        }
+        for &n in &res.modified {
+            out.push(format!("{}  {}", res.hashes[n - 1], res.lines[n - 1]));
+        }
+        Ok(out.join("\n"))
+    }
+
+    fn verify(&self, args: &Map<String, Value>) -> Result<String, ToolError> {
+        let path = str_param(args, "path")?;
+        let text = read(path)?;
+        let (commands, opts) = self.commands(args)?;
+        let errors = verify_commands(&text, &commands, &opts);
+        if errors.is_empty() {
+            return Ok(format!("OK: all {} command(s) apply to {path}.", commands.len()));
+        }
+        let problems: Vec<String> =
+            errors.iter().map(|e| self.describe(path, e, &commands, &opts)).collect();
+        Err(ToolError(problems.join("\n")))
+    }
+
+    /// The `commands` argument, parsed, and the edit options with any `@file` fingerprint.
+    fn commands(
+        &self,
+        args: &Map<String, Value>,
+    ) -> Result<(Vec<Command>, EditOptions), ToolError> {
+        let bad = || ToolError("commands must be a list of strings".to_string());
+        let mut cmd_refs = Vec::new();
+        for c in args.get("commands").and_then(Value::as_array).ok_or_else(bad)? {
+            cmd_refs.push(c.as_str().ok_or_else(bad)?);
+        }
+        let (commands, fingerprint) = parse_commands_with_header(&cmd_refs)?;
+        Ok((commands, EditOptions { fingerprint, ..self.opts.clone() }))
+    }
+
+    /// `err` with the failing command and, for a stale address, the lines it may have
+    /// meant, so that the model can retry without viewing the whole file again.
+    fn describe(
+        &self,
+        path: &str,
+        err: &EditError,
+        commands: &[Command],
+        opts: &EditOptions,
+    ) -> String {
+        let kind = err.kind().name();
+        let mut out = match err.command().and_then(|k| Some((k, commands.get(k)?))) {
+            Some((k, cmd)) => format!("{kind} error in command {} ({cmd}): {err}", k + 1),
+            None => format!("{kind} error: {err}"),
+        };
+        if err.kind() != ErrorKind::StaleHash {
+            return out;
+        }
+        let Ok(text) = fs::read_to_string(path) else { return out };
+        let k = err.command();
+        let hints = suggest_addresses(&text, commands, opts, SUGGEST_WINDOW);
+        for h in hints.iter().filter(|h| Some(h.command) == k) {
+            if let Some((addr, line)) = &h.current {
+                out.push_str(&format!("\n  line {} is now: {}  {line}", h.addr.lineno, addr));
+            }
+            for (addr, line) in &h.matches {
+                out.push_str(&format!("\n  the old hash matches: {addr}  {line}"));
+            }
+        }
+        out
+    }
+}
+
+fn read(path: &str) -> Result<String, ToolError> {
+    fs::read_to_string(path).map_err(|e| ToolError(format!("{path}: {e}")))
+}
+
+/// The result of a request, or its JSON-RPC error code and message.
+fn handle(
+    server: &Server,
+    method: &str,
+    params: &Map<String, Value>,
+) -> Result<Value, (i64, String)> {
+    match method {
+        "initialize" => {
+            let asked = params.get("protocolVersion").and_then(Value::as_str);
+            let version =
+                asked.filter(|v| PROTOCOL_VERSIONS.contains(v)).unwrap_or(PROTOCOL_VERSIONS[0]);
+            Ok(json!({
+                "protocolVersion": version,
+                "capabilities": { "tools": {} },
+                "serverInfo": { "name": "exhash", "version": env!("CARGO_PKG_VERSION") },
+                "instructions": INSTRUCTIONS,
+            }))
+        }
+        "ping" => Ok(json!({})),
+        "tools/list" => Ok(json!({ "tools": tools() })),
+        "tools/call" => {
+            let name = params.get("name").and_then(Value::as_str).unwrap_or_default();
+            if !["lnhash_view", "exhash_edit", "exhash_verify"].contains(&name) {
+                return Err((-32602, format!("unknown tool {name:?}")));
+            }
+            let empty = Map::new();
+            let args = params.get("arguments").and_then(Value::as_object).unwrap_or(&empty);
+            let (text, is_error) = match server.call_tool(name, args) {
+                Ok(text) => (text, false),
+                Err(ToolError(text)) => (text, true),
+            };
+            Ok(json!({ "content": [{ "type": "text", "text": text }], "isError": is_error }))
+        }
+        _ => Err((-32601, format!("unknown method {method:?}"))),
+    }
+}
+
+/// The response to one message, or none for a notification.
+fn respond(server: &Server, line: &str) -> Option<Value> {
+    let msg: Value = match serde_json::from_str(line) {
+        Ok(v) => v,
+        Err(e) => {
+            let error = json!({ "code": -32700, "message": format!("invalid JSON: {e}") });
+            return Some(json!({ "jsonrpc": "2.0", "id": null, "error": error }));
+        }
+    };
+    let id = msg.get("id").cloned()?;
+    let Some(method) = msg.get("method").and_then(Value::as_str) else {
+        // A response to a request we never sent.
+        return None;
+    };
+    let empty = Map::new();
+    let params = msg.get("params").and_then(Value::as_object).unwrap_or(&empty);
+    Some(match handle(server, method, params) {
+        Ok(result) => json!({ "jsonrpc": "2.0", "id": id, "result": result }),
+        Err((code, message)) => {
+            let error = json!({ "code": code, "message": message });
+            json!({ "jsonrpc": "2.0", "id": id, "error": error })
+        }
+    })
+}
+
+fn main() {
+    let args: Vec<String> = env::args().skip(1).collect();
+    if args.iter().any(|a| a == "-h" || a == "--help") {
+        usage();
+        return;
+    }
+    if let Some(a) = args.iter().find(|a| *a != "--no-config") {
+        eprintln!("error: unknown argument {a:?}");
+        usage();
+        process::exit(2);
+    }
+    let config = if args.is_empty() {
+        Config::load().unwrap_or_else(|e| {
+            eprintln!("error: {e}");
+            process::exit(2);
+        })
+    } else {
+        Config::default()
+    };
+    let opts = config.edit_options();
+    let server = Server { opts };
+
+    let stdout = io::stdout();
+    for line in io::stdin().lock().lines() {
+        let Ok(line) = line else { break };
+        if line.trim().is_empty() {
+            continue;
+        }
+        if let Some(response) = respond(&server, &line) {
+            let mut out = stdout.lock();
+            if writeln!(out, "{response}").and_then(|()| out.flush()).is_err() {
+                break;
+            }
+        }
+    }
+}
@@ -0,0 +1,265 @@
+use std::collections::HashMap;
+use std::env;
+use std::fs;
+use std::io::{self, BufRead, Write};
+use std::path::PathBuf;
+use std::process;
+use std::time::SystemTime;
+
+use exhash::{
+    edit_file, edit_text_with, error_json, parse_commands_with_header, result_json, str_param,
+    usize_param, verify_commands, view_json, Command, Config, EditError, EditOptions, ErrorKind,
+    FileOptions, ParamError,
+};
+use serde_json::{json, Map, Value};
+
+fn usage() {
+    eprintln!(
+        "Usage: exhash-server [--no-config]\n\n\
+         Serves JSON-RPC 2.0 on stdin/stdout, one message per line, so that a client can make\n\
+         many verified edits through one process. Hash and edit options come from the config\n\
+         files, as for exhash.\n\n\
+         Methods (params; `path` or `text` names the text to work on):\n\
+         \x20 view      {{path|text, start?, end?}}  lines as {{lineno, hash, text}}, and the\n\
+         \x20                                      text's fingerprint for an @file header\n\
+         \x20 verify    {{path|text, commands}}      check commands without applying them\n\
+         \x20 edit      {{path|text, commands}}      apply commands in memory\n\
+         \x20 editFile  {{path, commands, dryRun?, backup?}}\n\
+         \x20                                      apply commands and write the file back\n\
+         \x20 close     {{path}}                     forget a file's cached text\n\
+         \x20 shutdown                             stop the server\n\n\
+         `commands` is a list of command strings as the Python exhash() takes them: a/i/c\n\
+         text on the lines after the command, and an optional first `@file <fingerprint>`.\n\
+         A file's text is cached between calls and read again once it changes on disk.\n\
+         A failed edit is an error whose data has the error's kind (stale_hash, parse,\n\
+         out_of_range, ...), the failing command's index and, for a stale hash, lineno,\n\
+         expected and actual."
+    );
+}
+
+/// A JSON-RPC error response.
+struct RpcError {
+    code: i64,
+    message: String,
+    data: Option<Value>,
+}
+
+impl RpcError {
+    fn new(code: i64, message: impl Into<String>) -> Self {
+        Self { code, message: message.into(), data: None }
+    }
+
+    fn invalid_params(message: impl Into<String>) -> Self {
+        Self::new(-32602, message)
+    }
+
+    fn to_json(&self) -> Value {
+        let mut err = json!({ "code": self.code, "message": self.message });
+        if let Some(data) = &self.data {
+            err["data"] = data.clone();
+        }
+        err
+    }
+}
+
+/// An edit error, with a code per kind and the details in `data`.
+impl From<EditError> for RpcError {
+    fn from(e: EditError) -> Self {
+        let code = match e.kind() {
+            ErrorKind::Other => -32000,
+            ErrorKind::Parse => -32001,
+            ErrorKind::StaleHash => -32002,
+            ErrorKind::OutOfRange => -32003,
+            ErrorKind::ExpectationFailed => -32004,
+            ErrorKind::Cancelled => -32005,
+        };
+        Self { code, message: e.message().to_string(), data: Some(error_json(&e)) }
+    }
+}
+
+impl From<ParamError> for RpcError {
+    fn from(e: ParamError) -> Self {
+        Self::invalid_params(e.0)
+    }
+}
+
+/// A file's text as last read or written, with the modification time and size it had then.
+struct Session {
+    text: String,
+    stamp: Option<(SystemTime, u64)>,
+}
+
+fn stamp(path: &PathBuf) -> Option<(SystemTime, u64)> {
+    let meta = fs::metadata(path).ok()?;
+    Some((meta.modified().ok()?, meta.len()))
+}
+
+struct Server {
+    opts: EditOptions,
+    sessions: HashMap<PathBuf, Session>,
+}
+
+impl Server {
+    fn handle(&mut self, method: &str, params: &Map<String, Value>) -> Result<Value, RpcError> {
+        match method {
+            "view" => self.view(params),
+            "verify" => self.verify(params),
+            "edit" => self.edit(params),
+            "editFile" => self.edit_file(params),
+            "close" => {
+                let path = PathBuf::from(str_param(params, "path")?);
+                Ok(Value::Bool(self.sessions.remove(&path).is_some()))
+            }
+            "shutdown" => Ok(Value::Null),
+            _ => Err(RpcError::new(-32601, format!("unknown method {method:?}"))),
+        }
+    }
+
+    /// The text named by the `text` or `path` param, reading the file unless its cached
+    /// text is still current.
+    fn text(&mut self, params: &Map<String, Value>) -> Result<String, RpcError> {
+        if params.contains_key("text") {
+            return Ok(str_param(params, "text")?.to_string());
+        }
+        let path = PathBuf::from(str_param(params, "path")?);
+        let current = stamp(&path);
+        if let Some(s) = self.sessions.get(&path) {
+            if s.stamp.is_some() && s.stamp == current {
+                return Ok(s.text.clone());
+            }
+        }
+        let text = fs::read_to_string(&path).map_err(|e| {
+            let mut err = RpcError::new(-32000, format!("{}: {e}", path.display()));
+            err.data = Some(json!({ "kind": ErrorKind::Other.name() }));
+            err
+        })?;
+        self.sessions.insert(path, Session { text: text.clone(), stamp: current });
+        Ok(text)
+    }
+
+    fn view(&mut self, params: &Map<String, Value>) -> Result<Value, RpcError> {
+        let text = self.text(params)?;
+        let (start, end) = (usize_param(params, "start")?, usize_param(params, "end")?);
+        Ok(view_json(&text, &self.opts.hash_spec, self.opts.addr_style, start, end))
+    }
+
+    fn verify(&mut self, params: &Map<String, Value>) -> Result<Value, RpcError> {
+        let text = self.text(params)?;
+        let (commands, opts) = self.commands(params)?;
+        let errors = verify_commands(&text, &commands, &opts);
+        let errors: Vec<Value> = errors.iter().map(error_json).collect();
+        Ok(json!({ "ok": errors.is_empty(), "errors": errors }))
+    }
+
+    fn edit(&mut self, params: &Map<String, Value>) -> Result<Value, RpcError> {
+        let text = self.text(params)?;
+        let (commands, opts) = self.commands(params)?;
+        Ok(result_json(&edit_text_with(&text, &commands, &opts)?))
+    }
+
+    fn edit_file(&mut self, params: &Map<String, Value>) -> Result<Value, RpcError> {
+        let path = PathBuf::from(str_param(params, "path")?);
+        let (commands, opts) = self.commands(params)?;
+        let file_opts = FileOptions {
+            dry_run: params.get("dryRun").and_then(Value::as_bool).unwrap_or(false),
+            backup: params.get("backup").and_then(Value::as_str).map(str::to_string),
+            encoding: None,
+        };
+        let (res, _) = edit_file(&path, &commands, &opts, &file_opts)?;
+        if !file_opts.dry_run {
+            let session = Session { text: res.text(), stamp: stamp(&path) };
+            self.sessions.insert(path, session);
+        }
+        Ok(result_json(&res))
+    }
+
+    /// The `commands` param, parsed, and the edit options with any `@file` fingerprint.
+    fn commands(
+        &self,
+        params: &Map<String, Value>,
+    ) -> Result<(Vec<Command>, EditOptions), RpcError> {
+        let cmds = params
+            .get("commands")
+            .and_then(Value::as_array)
+            .ok_or_else(|| RpcError::invalid_params("commands must be a list of strings"))?;
+        let mut cmd_refs = Vec::with_capacity(cmds.len());
+        for c in cmds {
+            let c = c.as_str();
+            cmd_refs.push(c.ok_or_else(|| RpcError::invalid_params("commands must be strings"))?);
+        }
+        let (commands, fingerprint) = parse_commands_with_header(&cmd_refs)?;
+        Ok((commands, EditOptions { fingerprint, ..self.opts.clone() }))
+    }
+}
+
+/// Handle one message, returning the response (none for a notification) and whether the
+/// server should stop.
+fn respond(server: &mut Server, line: &str) -> (Option<Value>, bool) {
+    let msg: Value = match serde_json::from_str(line) {
+        Ok(v) => v,
+        Err(e) => {
+            let err = RpcError::new(-32700, format!("invalid JSON: {e}"));
+            return (Some(json!({ "jsonrpc": "2.0", "id": null, "error": err.to_json() })), false);
+        }
+    };
+    let id = msg.get("id").cloned();
+    let Some(method) = msg.get("method").and_then(Value::as_str) else {
+        let err = RpcError::new(-32600, "a request needs a method");
+        let id = id.unwrap_or(Value::Null);
+        return (Some(json!({ "jsonrpc": "2.0", "id": id, "error": err.to_json() })), false);
+    };
+    let empty = Map::new();
+    let result = match msg.get("params") {
+        None | Some(Value::Null) => server.handle(method, &empty),
+        Some(Value::Object(params)) => server.handle(method, params),
+        Some(_) => Err(RpcError::invalid_params("params must be an object")),
+    };
+    let stop = method == "shutdown";
+    let Some(id) = id else { return (None, stop) };
+    let response = match result {
+        Ok(result) => json!({ "jsonrpc": "2.0", "id": id, "result": result }),
+        Err(err) => json!({ "jsonrpc": "2.0", "id": id, "error": err.to_json() }),
+    };
+    (Some(response), stop)
+}
+
+fn main() {
+    let args: Vec<String> = env::args().skip(1).collect();
+    if args.iter().any(|a| a == "-h" || a == "--help") {
+        usage();
+        return;
+    }
+    if let Some(a) = args.iter().find(|a| *a != "--no-config") {
+        eprintln!("error: unknown argument {a:?}");
+        usage();
+        process::exit(2);
+    }
+    let config = if args.is_empty() {
+        Config::load().unwrap_or_else(|e| {
+            eprintln!("error: {e}");
+            process::exit(2);
+        })
+    } else {
+        Config::default()
+    };
+    let opts = config.edit_options();
+    let mut server = Server { opts, sessions: HashMap::new() };
+
+    let stdout = io::stdout();
+    for line in io::stdin().lock().lines() {
+        let Ok(line) = line else { break };
+        if line.trim().is_empty() {
+            continue;
+        }
+        let (response, stop) = respond(&mut server, &line);
+        if let Some(response) = response {
+            let mut out = stdout.lock();
+            if writeln!(out, "{response}").and_then(|()| out.flush()).is_err() {
+                break;
+            }
+        }
+        if stop {
+            break;
+        }
+    }
+}
@@ -1,20 +1,44 @@
+use std::collections::BTreeSet;
 use std::env;
 use std::fs;
-use std::io::{self, Read};
+use std::io::{self, BufRead, IsTerminal, Read};
 use std::path::{Path, PathBuf};
 use std::process;
 
-use exhash::{edit_text, parse_commands_from_args};
+use exhash::{
+    bottom_up_order, check_hash_width, check_limits, convert_script, create_beside, diff_commands,
+    edit_text_with, file_fingerprint, parse_commands_from_args, parse_commands_with_blocks,
+    parse_file_header, parse_file_scripts, parse_patch, parse_script, parse_size, quickfix_lines,
+    split_path, suggest_addresses, unified_diff, verify_commands, write_all_atomic, write_atomic,
+    AddrStyle, AddressHint, Command, Config, EditError, EditOptions, EditResult, EditStats,
+    ErrorKind, FileScript, HashAlgorithm, JoinPolicy, LnHash, LnHashSpec, Script, ScriptDialect,
+};
 
 fn usage() {
     eprintln!("\
-Usage: exhash [-h] [--dry-run] [--stdin] <file|-> [commands...]
+Usage: exhash [-h] [--dry-run] [--verify-only] [--stdin] [--json] [--diff]
+              [--bottom-up] [--join POLICY] [--hash-width N] [--compact]
+              [--seed STR] [--nfc] [--ignore-case] [--versioned] [--chained]
+              [--backup SUFFIX] [--context N] [--text-block TEXT]... [-q|-v]
+              [--encoding LABEL] [--strip-bom] [--fuzz N] [--keep-going] [--print-all] [--summary]
+              [--color=auto|always|never] [--require-clean [--force]] [--git-add]
+              [--max-file-size SIZE] [--max-commands N] [--max-block-size SIZE]
+              [--expect ADDR]... [--save-undo] [--suggest] [--indent-width N]
+              [--strict-substitute] [--quickfix] [--lang LANG] [--no-config]
+              <file|-> [commands...]
+       exhash [options] -f <script|-> <file|->
+       exhash [options] --patch <diff|-> [file]
+       exhash [options] --repl <file>
+       exhash [options] --edit <file>
+       exhash undo [options] <file>
+       exhash convert [options] [--ed] <script|-> <file>
+       exhash convert [options] --patch <diff|-> [file]
 
 Verified line-addressed file editor using lnhash addresses.
 
 ADDRESSING
   Commands use lnhash addresses: lineno|hash| where hash is a 4-char
-  hex content hash. Use lnhashview to get addresses:
+  hex content hash (6 or 8 chars for wider hashes). Use lnhashview to get addresses:
     lnhashview file.txt          show all lines with addresses
     lnhashview file.txt 10 20    show lines 10-20
   With multiple commands, hashes are checked immediately before each command runs.
@@ -22,9 +46,22 @@ ADDRESSING
   Single:   12|a3f2|cmd
   Range:    12|a3f2|,15|b1c3|cmd
   Special:  0|0000| targets before line 1 (only with a or i)
+  Compact:  c:a3f2 is the same address as 12|a3f2| (base-36 line number)
+
+  A first command of '@file <fingerprint>' (from lnhashview --fingerprint)
+  checks the whole file before any lnhash is verified.
+
+  Symbol:   @fn:parse_global cmd
+  In a Rust or Python file, a command argument may instead name a definition:
+  @fn: (function or method), @class: (class, struct, enum or trait), @impl:
+  or @mod:, qualified as @fn:Parser.parse if the name is not unique. It
+  stands for the lnhash range of the definition's lines, from its first
+  decorator, attribute or doc comment; a space ends the name. Needs the
+  treesitter feature.
 
 COMMANDS
-  s/pat/rep/[flags]  Substitute (regex). Flags: g=all, i=case-insensitive
+  s/pat/rep/[flags]  Substitute (regex). Flags: g=all, i=case-insensitive,
+                     @FROM-TO=only display columns FROM to TO (either optional)
   d                  Delete line(s)
   a                  Append text after line (reads text block)
   i                  Insert text before line (reads text block)
@@ -32,7 +69,7 @@ COMMANDS
   j                  Join with next line; with range, joins all lines in range
   m dest             Move line(s) after dest address
   t dest             Copy line(s) after dest address
-  >[n]               Indent n levels (default 1, 4 spaces each)
+  >[n]               Indent n levels (default 1, --indent-width spaces each)
   <[n]               Dedent n levels (default 1)
   sort               Sort lines alphabetically
   p                  Print (include lines in output without changing them)
@@ -43,16 +80,222 @@ COMMANDS
 TEXT BLOCKS (a/i/c)
   Text is read from stdin, terminated by a line containing just '.'
   Use '..' to insert a literal '.' line.
+  With -f, text blocks follow their command inside the script instead.
+  With --text-block TEXT (repeatable), each a/i/c command takes the next
+  TEXT in order, as is: real newlines separate lines, no '.' terminator.
+  Inline, 'a\\TEXT' (likewise i\\, c\\) carries its own text and reads no
+  block: '\\n' starts a new line and '\\\\' is a backslash, e.g.
+  '12|a3f2|a\\first\\nsecond'.
 
 OPTIONS
   --dry-run  Don't write; show what would change on stdout
+  --repl     Edit the file interactively: show its lnhash view, then read
+             commands one at a time, applying each to the in-memory text and
+             printing the changed lines. :w writes, :q quits, :help lists more.
+             Not with --dry-run.
+  --edit     Open the file's lnhash view in $VISUAL or $EDITOR (default vi).
+             Edit the text after the addresses, delete lines, or add new
+             lines (with or without an address). On exit the edited view is
+             diffed against the original to derive commands, which are then
+             verified against the file and applied like any others.
+  --verify-only
+             Don't apply anything; check every command's lnhashes and print
+             each stale address (one line per failing command). Exits 0 if
+             all verify, otherwise with the first failure's status.
+  -f, --script FILE
+             Read commands from an ex-style script FILE ('-' for stdin), one
+             per line, with text blocks inline and an optional leading
+             '@file <fingerprint>' line. No command arguments are allowed.
+  --patch DIFF
+             Apply a unified diff (git diff or diff -u output; '-' for stdin)
+             to the files it names, or with a file argument, its one file's
+             changes to that file. Each hunk must match the file exactly where
+             its header says, or nothing is written (status 3); the changes
+             are then made as verified a/c/d commands, and every file written
+             atomically, all or none.
   --stdin    Read input from stdin (file arg must be '-');
              outputs full file in lnhash format.
-             Text blocks (a/i/c) not supported in this mode.
+             Text blocks (a/i/c) must be inline (a\\TEXT) or come from
+             --text-block or a --script file in this mode.
+  --text-block TEXT
+             Text for the next a/i/c command, instead of reading stdin
+  --save-undo
+             After writing, save a script that reverses the edit under
+             .exhash/undo/ beside the file. 'exhash undo FILE' applies the
+             latest one (verifying its addresses like any script) and removes
+             it; repeat to step further back. To edit a file named undo, write
+             './undo' or put '--' before it.
+  --backup SUFFIX
+             Before writing, copy the original file to <file>SUFFIX
+             (e.g. --backup .bak). Not done with --dry-run.
+  --encoding LABEL
+             Read and write the file in LABEL (latin1, windows-1252,
+             shift-jis, ...) instead of UTF-8. 'auto' reads UTF-8 if the file
+             is valid UTF-8 and windows-1252 otherwise, and writes back the same.
+             Output listings are UTF-8 either way.
+  --strip-bom
+             Write the file without the UTF-8 byte order mark it starts with.
+             By default a BOM is kept. It is never part of line 1's hash.
+  --lang LANG
+             Resolve symbol addresses as rust or python, rather than by the
+             file's extension (.rs, .py, .pyi); needed with --stdin.
+  -q, --quiet
+             Print nothing on success (except a --summary); the exit status
+             says it all
+  -v, --verbose
+             Also describe each command on stderr as it is applied: the lines
+             it changed (as they stood right after it) and how many it deleted
+  --json     Print the result as one JSON object on stdout (see OUTPUT)
+  --print-all
+             Print every line of the edited file with its address, as
+             --stdin mode does, not just the changed ones; saves a
+             follow-up lnhashview
+  --summary  After the usual output, print one line of change statistics:
+             lines added, changed and deleted (comparing the result with the
+             input) and substitutions made. With -v, a line per command comes
+             first; with -q, only the summary is printed; with --json, it is
+             a summary field.
+  --context N
+             Also print up to N unchanged lines around each group of changed
+             lines, with their addresses (see OUTPUT)
+  --diff     Print a unified diff of the change instead of the changed
+             lines (with --json, add it as a \"diff\" field)
+  --quickfix Print a path:lineno: message line for each modified line and
+             each deleted one (at the line now in its place) instead of the
+             changed lines, for vim -q, :cfile or an editor's errorformat
+  --fuzz N   When an address is stale, look up to N lines above and below
+             for the line it names and use that instead, noting each move on
+             stderr. The nearest match wins; a tie is still an error.
+  --suggest  When an address is stale, follow the error with hints: the
+             current address and text of the line it names, and any line
+             within 20 lines that it still matches, as addresses ready to
+             retry with (with --json, a suggestions field)
+  --keep-going
+             Apply every command that can be applied and skip the rest,
+             listing each skipped command and why on stderr (or in the JSON
+             skipped field). The result is still written; exits with 5 if
+             anything was skipped.
+  --strict-substitute
+             Fail an s command whose pattern matches nothing in its range
+             (under g/v, on none of the lines selected) instead of leaving
+             the lines as they are
+  --color=WHEN
+             Color hashes, and with --diff added and removed lines: auto
+             (default: when stdout is a terminal and $NO_COLOR is unset),
+             always or never. JSON output is never colored.
+  --require-clean
+             Refuse to write a file that git reports as modified, staged or
+             untracked (or that is outside a git repository), so automated
+             edits never mix with uncommitted work. --force writes anyway.
+  --git-add  Stage the file with git add after writing it
+  --max-file-size SIZE
+             Refuse input (the file, or stdin with --stdin) larger than SIZE
+             bytes; SIZE may end in K, M or G (powers of 1024)
+  --max-commands N
+             Refuse scripts of more than N commands
+  --max-block-size SIZE
+             Refuse an a/i/c text block larger than SIZE bytes (counting a
+             newline per line)
+  --expect ADDR
+             After applying the commands, check that ADDR (lineno|hash|, as
+             lnhashview would print it for the result) matches the edited
+             text, or with '@file <fingerprint>' the whole result. Nothing is
+             written on a mismatch. Repeatable.
+  --bottom-up
+             Apply commands highest-address first, so every address refers
+             to the original file. Fails if commands touch overlapping lines.
+  --join smart|always|never
+             How j combines lines: smart (default) adds a space unless
+             whitespace is already at the seam; always/never add one or none
+  --join-sep STR
+             Join lines with a custom separator
+  --hash-width N
+             Hex digits in printed hashes: 4 (default), 6 or 8
+  --compact  Print addresses in compact form (<base36 lineno>:<hash>)
+  --seed STR Hash with a per-project seed (default: $EXHASH_SEED). Addresses
+             are tagged with the seed id, e.g. 12|s1a2b:a3f2|, and only
+             verify under the same seed.
+  --nfc      Hash printed addresses after Unicode NFC normalization, so
+             composed and decomposed text hash alike. Input addresses tagged
+             nfc: (e.g. 12|nfc:a3f2|) are always verified this way.
+  --ignore-case
+             Hash printed addresses case-insensitively, so case-only edits
+             keep them valid. Such addresses are tagged lc: (12|lc:a3f2|) and
+             always verified case-insensitively; untagged ones never are.
+  --versioned
+             Tag printed hashes with their algorithm version (12|v2:a3f2|).
+             Tagged input addresses are verified with the algorithm they name.
+  --chained  Fold 16 bits of the previous line's hash into printed hashes
+             (12|prev:a3f2|), so repeated lines such as '}}' get distinct
+             addresses. Editing a line also invalidates the next line's
+             chained address.
+  --indent-width N
+             Spaces per level for > and < (default 4); < also removes a tab
+  --no-config
+             Ignore the config files (see CONFIG)
   -h, --help Show this help
+  --         End the options: the next argument is the file, even one named
+             undo or convert
+
+CONVERTING SED AND ED SCRIPTS
+  exhash convert SCRIPT FILE prints an exhash script making the change the
+  sed script SCRIPT ('-' for stdin; with --ed, an ed script) would make to
+  FILE, addressed with FILE's current hashes and headed by its @file
+  fingerprint. Apply it with exhash -f, which refuses once FILE changes.
+  sed's s, d, a, i and c (line, $ and regex addresses, ranges, !) and ed's
+  a, i, c, d, s, m, t, j and g/v with d or s are translated; anything else
+  (hold space, branches, backreferences, ...) is an error (status 2).
+  exhash convert --patch DIFF [FILE] prints the script --patch would run
+  instead of applying it: an @path section per file the diff names, or
+  with FILE, that file's script alone. Each section is headed by its
+  file's @file fingerprint, so exhash -f replays it only on that version.
+  As with undo, edit a file named convert as './convert' or after '--'.
+
+CONFIG
+  Defaults for the options above are read from ~/.config/exhash/config.toml
+  ($XDG_CONFIG_HOME/exhash/config.toml if set) and then from the nearest
+  .exhash.toml in the current directory or a parent, which wins. Each line
+  is key = value, the key being an option's name without dashes:
+    hash-width = 6
+    ignore-case = true
+    backup = \".orig\"
+    max-file-size = \"10M\"
+  The keys are hash-width, seed, nfc, ignore-case, versioned, chained,
+  compact, indent-width, strip-bom, color, backup, fuzz, max-file-size,
+  max-commands and max-block-size. Flags override config files, and $EXHASH_SEED
+  overrides a configured seed.
 
 OUTPUT
   Modified/added lines are printed as: hash  content
+  Then each deleted line is printed as -hash  content, with the address it
+  had before the edit, so a d is never silent.
+  With --context N, every printed line starts with a marker column: '>' for
+  a changed line, a space for a context line, '-' for a deleted one. Groups
+  of lines that are not adjacent are separated by a line containing just '--'.
+
+  With --json, one JSON object is printed instead, with fields ok, dry_run,
+  modified and deleted (line numbers), deleted_lines (lineno, hash and text
+  of each deleted line before the edit), lines (lineno, hash and text of each
+  line that would otherwise be printed, plus context: true on --context
+  lines), commands (addr and status of each command) and, with --fuzz,
+  relocated (command index, addr and the lineno used instead of each
+  re-anchored address) if any were. With --keep-going, skipped commands
+  have status skipped and their errors are listed in skipped, and ok is
+  false if there are any. With --summary, summary holds added, changed,
+  deleted and substitutions, plus with -v the same per command under
+  commands. On failure (same
+  exit status) it has ok=false, error (message, kind, and the failing
+  command's index) and commands, whose status is ok (ran before the
+  failure), failed or skipped. Nothing is written when any command fails.
+
+EXIT STATUS
+  0  success
+  1  I/O error: the file, script or stdin could not be read or written
+  2  usage or parse error, or a command that cannot apply
+  3  stale lnhash (or seed / @file fingerprint mismatch): refetch and retry
+  4  address or destination past the end of the file
+  5  with --keep-going: some commands were skipped, the rest applied
+  6  the result did not match an --expect; nothing was written
 
 EXAMPLES
   lnhashview file.txt
@@ -63,62 +306,1061 @@ EXAMPLES
   exhash file.txt '2|aa|,3|bb|m5|cc|'
   exhash file.txt '1|ab|,10|ef|g/TODO/d'
   exhash --dry-run file.txt '3|1234|s/old/new/'
+  exhash -f edits.ex file.txt
   cat file.txt | exhash --stdin - '1|abcd|s/foo/bar/'
 ");
 }
 
 fn is_binary(bytes: &[u8]) -> bool {
-    bytes.iter().any(|&b| b == 0)
+    bytes.contains(&0)
+}
+
+/// Resource limits from `--max-file-size`, `--max-commands` and `--max-block-size`, for
+/// callers that need to bound exhash's memory and time.
+#[derive(Default)]
+struct Limits {
+    file_size: Option<u64>,
+    commands: Option<usize>,
+    block_size: Option<u64>,
+}
+
+impl Limits {
+    /// Fail unless `file` is within `--max-file-size`. Checked before reading it.
+    fn check_file(&self, file: &str, json: bool) {
+        let Some(max) = self.file_size else { return };
+        match fs::metadata(file) {
+            Ok(m) if m.len() > max => {
+                fail(json, &format!("{file} is {} bytes, over --max-file-size {max}", m.len()), 2)
+            }
+            // A missing file is reported when it is read.
+            _ => {}
+        }
+    }
+
+    /// Read stdin, failing if it exceeds `--max-file-size`.
+    fn read_stdin(&self, json: bool) -> Vec<u8> {
+        let mut bytes = Vec::new();
+        let max = self.file_size.unwrap_or(u64::MAX);
+        if let Err(e) = io::stdin().take(max.saturating_add(1)).read_to_end(&mut bytes) {
+            fail(json, &format!("failed to read stdin: {e}"), 1);
+        }
+        if bytes.len() as u64 > max {
+            fail(json, &format!("stdin is over --max-file-size {max}"), 2);
+        }
+        bytes
+    }
+
+    /// Fail if there are too many `commands` or one of their text blocks is too large.
+    fn check_commands(&self, commands: &[Command], json: bool) {
+        if let Err(e) = check_limits(commands, self.commands, self.block_size) {
+            fail(json, &e.to_string(), 2);
+        }
+    }
+}
+
+/// Run `git` with `args` in the directory holding `file`, returning its stdout, or its
+/// stderr as the error.
+fn git(file: &Path, args: &[&str]) -> Result<String, String> {
+    let dir = file
+        .parent()
+        .filter(|d| !d.as_os_str().is_empty())
+        .unwrap_or(Path::new("."));
+    let out = process::Command::new("git")
+        .arg("-C")
+        .arg(dir)
+        .args(args)
+        .output()
+        .map_err(|e| format!("cannot run git: {e}"))?;
+    if !out.status.success() {
+        return Err(String::from_utf8_lossy(&out.stderr).trim().to_string());
+    }
+    Ok(String::from_utf8_lossy(&out.stdout).into_owned())
+}
+
+/// For `--require-clean`: fail unless `file` is in a git repository and has no
+/// uncommitted changes, staged or not (an untracked file is not clean either).
+fn check_clean(file: &Path) -> Result<(), String> {
+    let name = file.file_name().and_then(|n| n.to_str()).unwrap_or_default();
+    git(file, &["rev-parse", "--show-toplevel"])
+        .map_err(|e| format!("cannot check git status: {e}"))?;
+    let status = git(file, &["status", "--porcelain", "--", name])?;
+    match status.lines().next() {
+        None => Ok(()),
+        Some(line) if line.starts_with("??") => Err("the file is not tracked by git".to_string()),
+        Some(_) => Err("the file has uncommitted changes".to_string()),
+    }
+}
+
+/// For `--git-add`: stage `file`.
+fn stage(file: &Path) -> Result<(), String> {
+    let name = file.file_name().and_then(|n| n.to_str()).unwrap_or_default();
+    git(file, &["add", "--", name]).map(drop)
+}
+
+/// Report a fatal error and exit: on stderr, or with `--json` as a JSON object on stdout.
+fn fail(json: bool, msg: &str, code: i32) -> ! {
+    if json {
+        println!("{{\"ok\":false,\"error\":{{\"message\":{}}}}}", json_str(msg));
+    } else {
+        eprintln!("error: {msg}");
+    }
+    process::exit(code);
+}
+
+/// How many lines either side of a stale address `--suggest` looks for the line it meant.
+const SUGGEST_WINDOW: usize = 20;
+
+/// Report a failed edit and exit. With `--json`, each command is listed as `ok` (applied
+/// before the failure; nothing is written), `failed`, or `skipped`.
+///
+/// With `--suggest`, `input` is the text the commands were applied to, and a stale address
+/// is followed by corrected addresses to retry with. In a multi-file edit, `file` is the
+/// file that failed, named before the message (and in the JSON object).
+fn fail_edit(
+    json: bool,
+    err: &EditError,
+    commands: &[Command],
+    opts: &EditOptions,
+    input: Option<&str>,
+    file: Option<&str>,
+) -> ! {
+    let hints = match input {
+        Some(text) if err.kind() == ErrorKind::StaleHash => {
+            suggest_addresses(text, commands, opts, SUGGEST_WINDOW)
+        }
+        _ => Vec::new(),
+    };
+    if !json {
+        let prefix = file.map_or_else(String::new, |f| format!("{f}: "));
+        eprintln!("error: {prefix}{}", describe_error(err, commands));
+        for line in hint_lines(&hints, opts.addr_style) {
+            eprintln!("{line}");
+        }
+        process::exit(exit_code(err));
+    }
+    let order = if opts.bottom_up {
+        bottom_up_order(commands).unwrap_or_default()
+    } else {
+        (0..commands.len()).collect()
+    };
+    // A failed expectation is only checked once every command has run.
+    let ran = err.kind() == ErrorKind::ExpectationFailed;
+    let mut status = vec![if ran { "ok" } else { "skipped" }; commands.len()];
+    if let Some(k) = err.command() {
+        for &i in order.iter().take_while(|&&i| i != k) {
+            status[i] = "ok";
+        }
+        status[k] = "failed";
+    }
+    let suggestions = match input {
+        Some(_) => format!(",\"suggestions\":{}", json_hints(&hints, opts.addr_style)),
+        None => String::new(),
+    };
+    let file = file.map_or_else(String::new, |f| format!("\"file\":{},", json_str(f)));
+    println!(
+        "{{{file}\"ok\":false,\"error\":{},\"commands\":{}{suggestions}}}",
+        json_error(err, commands),
+        json_commands(commands, &status, opts.addr_style)
+    );
+    process::exit(exit_code(err));
+}
+
+/// `--suggest` hints as text: for each stale address, the line it names as that line is
+/// now, and the nearby lines it still matches, both as `address  content` ready to paste.
+fn hint_lines(hints: &[AddressHint], style: AddrStyle) -> Vec<String> {
+    let mut out = Vec::new();
+    for h in hints {
+        let stale = h.addr.format_with(style);
+        if let Some((addr, text)) = &h.current {
+            out.push(format!("hint: {stale} is stale; line {} is now:", h.addr.lineno));
+            out.push(format!("  {}  {text}", addr.format_with(style)));
+        }
+        if h.matches.is_empty() {
+            out.push(format!(
+                "hint: no line within {SUGGEST_WINDOW} lines matches {stale}; refetch with \
+                 lnhashview"
+            ));
+        } else {
+            out.push(format!("hint: {stale} matches nearby:"));
+            for (addr, text) in &h.matches {
+                out.push(format!("  {}  {text}", addr.format_with(style)));
+            }
+        }
+    }
+    out
+}
+
+/// `--suggest` hints as a JSON list: the command index and stale addr of each, the
+/// `current` line there (or null) and the nearby `matches`, each line as lineno, hash and
+/// text.
+fn json_hints(hints: &[AddressHint], style: AddrStyle) -> String {
+    let line = |(addr, text): &(LnHash, String)| {
+        format!(
+            "{{\"lineno\":{},\"hash\":{},\"text\":{}}}",
+            addr.lineno,
+            json_str(&addr.format_with(style)),
+            json_str(text)
+        )
+    };
+    json_list(hints, |h| {
+        format!(
+            "{{\"command\":{},\"addr\":{},\"current\":{},\"matches\":{}}}",
+            h.command,
+            json_str(&h.addr.format_with(style)),
+            h.current.as_ref().map_or_else(|| "null".to_string(), line),
+            json_list(&h.matches, line)
+        )
+    })
+}
+
+/// `err`'s message, prefixed with the failing command's 1-based number and text (as in
+/// `command 3 (12|a3f2|s/foo/bar/): ...`) if it belongs to one.
+fn describe_error(err: &EditError, commands: &[Command]) -> String {
+    match err.command().and_then(|k| Some((k, commands.get(k)?))) {
+        Some((k, cmd)) => format!("command {} ({cmd}): {err}", k + 1),
+        None => err.to_string(),
+    }
+}
+
+/// `cmd_args` with each symbol address (`@fn:name`) replaced by its definition's lnhashes.
+#[cfg(feature = "treesitter")]
+fn resolve_symbols(
+    cmd_args: &[String],
+    file: &str,
+    text: &str,
+    lang: Option<&str>,
+    opts: &EditOptions,
+    json: bool,
+) -> Vec<String> {
+    use exhash::{is_symbol_address, resolve_symbol_address, SymbolLanguage};
+
+    if !cmd_args.iter().any(|a| is_symbol_address(a.trim_start())) {
+        return cmd_args.to_vec();
+    }
+    let language = match lang {
+        Some(name) => SymbolLanguage::from_name(name),
+        None => SymbolLanguage::from_path(file),
+    };
+    let Some(language) = language else {
+        fail(json, &format!("{file}: symbol addresses need --lang rust or python"), 2)
+    };
+    let spec = &opts.hash_spec;
+    (cmd_args.iter())
+        .map(|arg| {
+            resolve_symbol_address(arg, text, language, spec)
+                .unwrap_or_else(|e| fail(json, &e.to_string(), exit_code(&e)))
+        })
+        .collect()
+}
+
+#[cfg(not(feature = "treesitter"))]
+fn resolve_symbols(
+    cmd_args: &[String],
+    _: &str,
+    _: &str,
+    _: Option<&str>,
+    _: &EditOptions,
+    json: bool,
+) -> Vec<String> {
+    // Without the feature, `@fn:name` would fail to parse as an lnhash; say why instead.
+    let symbol = |a: &&String| {
+        a.starts_with('@') && a.split_whitespace().next().is_some_and(|w| w.contains(':'))
+    };
+    if let Some(arg) = cmd_args.iter().find(symbol) {
+        let msg = format!("{arg}: symbol addresses need the `treesitter` feature");
+        fail(json, &msg, 2);
+    }
+    cmd_args.to_vec()
+}
+
+/// The exit status for a failed edit; see EXIT STATUS in the usage text.
+fn exit_code(err: &EditError) -> i32 {
+    match err.kind() {
+        ErrorKind::StaleHash => 3,
+        ErrorKind::OutOfRange => 4,
+        ErrorKind::ExpectationFailed => 6,
+        ErrorKind::Other | ErrorKind::Cancelled | ErrorKind::Parse => 2,
+    }
+}
+
+/// An error as a JSON object. `command` is the failing command's 0-based index, and
+/// `text` the command itself.
+fn json_error(err: &EditError, commands: &[Command]) -> String {
+    let kind = err.kind().name();
+    let command = err.command().map_or_else(|| "null".to_string(), |k| k.to_string());
+    let text = err
+        .command()
+        .and_then(|k| commands.get(k))
+        .map_or_else(String::new, |c| format!(",\"text\":{}", json_str(&c.to_string())));
+    format!(
+        "{{\"message\":{},\"kind\":\"{kind}\",\"command\":{command}{text}}}",
+        json_str(err.message())
+    )
+}
+
+/// `linenos` plus up to `context` lines either side of each, in order, as
+/// `(lineno, changed)` for a file of `len` lines.
+fn with_context(linenos: &[usize], context: usize, len: usize) -> Vec<(usize, bool)> {
+    let changed: BTreeSet<usize> = linenos.iter().copied().collect();
+    let mut shown = BTreeSet::new();
+    for &n in linenos {
+        shown.extend(n.saturating_sub(context).max(1)..=(n + context).min(len));
+    }
+    shown.into_iter().map(|n| (n, changed.contains(&n))).collect()
+}
+
+/// Read `file` as text, in its `--encoding` if one was given. Returns the text and the
+/// encoding to write it back in (`None` for plain UTF-8).
+fn read_text(file: &str, json: bool, encoding: Option<&str>) -> (String, Option<&'static str>) {
+    let bytes = match fs::read(file) {
+        Ok(b) => b,
+        Err(e) => fail(json, &format!("failed to read {file}: {e}"), 1),
+    };
+
+    if is_binary(&bytes) {
+        fail(json, "binary file rejected (NUL byte found)", 1);
+    }
+    decode(bytes, encoding, json)
+}
+
+fn decode(bytes: Vec<u8>, encoding: Option<&str>, json: bool) -> (String, Option<&'static str>) {
+    #[cfg(feature = "encoding")]
+    if let Some(label) = encoding {
+        return match exhash::decode_text(&bytes, label) {
+            Ok((text, name)) => (text, Some(name)),
+            Err(e) => fail(json, e.message(), 1),
+        };
+    }
+    #[cfg(not(feature = "encoding"))]
+    let _ = encoding;
+    match String::from_utf8(bytes) {
+        Ok(s) => (s, None),
+        Err(_) => fail(json, "non-UTF8 file rejected (see --encoding)", 1),
+    }
 }
 
-fn write_atomic(path: &Path, content: &str) -> io::Result<()> {
-    let dir = path.parent().unwrap_or_else(|| Path::new("."));
-    let file_name = path
+/// The bytes to write for `text`: UTF-8, or encoded as the file was read.
+fn encode(text: &str, encoding: Option<&str>) -> Result<Vec<u8>, EditError> {
+    #[cfg(feature = "encoding")]
+    if let Some(name) = encoding {
+        return exhash::encode_text(text, name);
+    }
+    #[cfg(not(feature = "encoding"))]
+    let _ = encoding;
+    Ok(text.as_bytes().to_vec())
+}
+
+const REPL_HELP: &str = "\
+Enter commands as on the command line, e.g. 12|a3f2|s/foo/bar/; a/i/c read a
+text block ended by a line containing just '.'. Changed lines are printed with
+their new addresses.
+  :view [start [end]]  show the lnhash view (1-based, inclusive)
+  :w                   write the file
+  :wq, :x              write and quit
+  :q                   quit (refused with unsaved changes)
+  :q!                  quit, discarding unsaved changes
+  :help                show this help";
+
+/// `--repl`: edit `file` interactively, keeping the session in memory until `:w`.
+///
+/// Commands come from stdin one at a time and are verified against the session text. The
+/// prompt goes to stderr so stdout only carries views and changed lines.
+fn run_repl(
+    file: &str,
+    (mut text, encoding): (String, Option<&str>),
+    opts: &EditOptions,
+    backup: Option<&str>,
+    git_add: bool,
+) {
+    let view = |text: &str, start: usize, end: usize| {
+        let lines = opts.hash_spec.view_lines(text, opts.addr_style);
+        for line in lines.iter().take(end).skip(start.saturating_sub(1)) {
+            println!("{line}");
+        }
+    };
+    view(&text, 1, usize::MAX);
+
+    let mut stdin = io::stdin().lock();
+    let mut dirty = false;
+    let mut backed_up = false;
+    loop {
+        eprint!("exhash> ");
+        let mut line = String::new();
+        match stdin.read_line(&mut line) {
+            Ok(0) => {
+                eprintln!();
+                if dirty {
+                    eprintln!("error: unsaved changes to {file} discarded");
+                    process::exit(1);
+                }
+                return;
+            }
+            Ok(_) => {}
+            Err(e) => fail(false, &format!("failed to read stdin: {e}"), 1),
+        }
+        let line = line.trim_end_matches(['\n', '\r']);
+        let mut words = line.split_whitespace();
+        let (write, quit) = match words.next() {
+            None => continue,
+            Some(":w") => (true, false),
+            Some(":wq" | ":x") => (true, true),
+            Some(":q") if dirty => {
+                eprintln!("error: unsaved changes (use :w to write or :q! to discard)");
+                continue;
+            }
+            Some(":q" | ":q!") => return,
+            Some(":view") => {
+                let mut num = |default| words.next().map_or(Ok(default), str::parse);
+                match (num(1), num(usize::MAX)) {
+                    (Ok(start), Ok(end)) => view(&text, start, end),
+                    _ => eprintln!("error: :view takes line numbers"),
+                }
+                continue;
+            }
+            Some(":help") => {
+                println!("{REPL_HELP}");
+                continue;
+            }
+            Some(s) if s.starts_with(':') => {
+                eprintln!("error: unknown command {s} (try :help)");
+                continue;
+            }
+            Some(_) => (false, false),
+        };
+
+        if write {
+            let bytes = match encode(&text, encoding) {
+                Ok(b) => b,
+                Err(e) => {
+                    eprintln!("error: {e}");
+                    continue;
+                }
+            };
+            if let (Some(suffix), false) = (backup, backed_up) {
+                let bak = format!("{file}{suffix}");
+                if let Err(e) = fs::copy(file, &bak) {
+                    eprintln!("error: failed to write backup {bak}: {e}");
+                    continue;
+                }
+                backed_up = true;
+            }
+            if let Err(e) = write_atomic(Path::new(file), &bytes) {
+                eprintln!("error: failed to write {file}: {e}");
+                continue;
+            }
+            dirty = false;
+            if git_add {
+                if let Err(e) = stage(Path::new(file)) {
+                    eprintln!("error: wrote {file} but could not stage it: {e}");
+                    continue;
+                }
+            }
+            if quit {
+                return;
+            }
+            continue;
+        }
+
+        let result = parse_commands_from_args(&[line.to_string()], &mut stdin)
+            .and_then(|cmds| edit_text_with(&text, &cmds, opts));
+        match result {
+            Ok(result) => {
+                for &n in &result.modified {
+                    println!("{}  {}", result.hashes[n - 1], result.lines[n - 1]);
+                }
+                for r in removed_lines(&text, &result, opts) {
+                    println!("-{}  {}", r.addr, r.text);
+                }
+                let new_text = result.text();
+                dirty |= new_text != text;
+                text = new_text;
+            }
+            Err(e) => eprintln!("error: {e}"),
+        }
+    }
+}
+
+/// Where `--save-undo` keeps the undo scripts for `file`: `.exhash/undo/` beside it.
+fn undo_dir(file: &Path) -> PathBuf {
+    split_path(file).0.join(".exhash").join("undo")
+}
+
+/// The undo scripts saved for `file`, oldest first, with their sequence numbers. Each is
+/// named `<file name>.<n>.ex`.
+fn undo_scripts(file: &Path) -> Vec<(u64, PathBuf)> {
+    let (_, name) = split_path(file);
+    let Ok(entries) = fs::read_dir(undo_dir(file)) else {
+        return Vec::new();
+    };
+    let mut out: Vec<(u64, PathBuf)> = entries
+        .filter_map(|e| {
+            let e = e.ok()?;
+            let entry_name = e.file_name().into_string().ok()?;
+            let n = entry_name.strip_prefix(&name)?.strip_prefix('.')?.strip_suffix(".ex")?;
+            Some((n.parse().ok()?, e.path()))
+        })
+        .collect();
+    out.sort();
+    out
+}
+
+/// `--save-undo`: `file` was just rewritten from `old` to `new`; save the script that
+/// takes it back, addressed against `new`, for `exhash undo`.
+fn write_undo(file: &Path, old: &str, new: &str, opts: &EditOptions) -> io::Result<()> {
+    let commands = diff_commands(new, old, &opts.hash_spec);
+    if commands.is_empty() {
+        return Ok(());
+    }
+    let dir = undo_dir(file);
+    fs::create_dir_all(&dir)?;
+    let n = undo_scripts(file).last().map_or(1, |(n, _)| n + 1);
+    let script: String = commands.iter().map(|c| format!("{c}\n")).collect();
+    fs::write(dir.join(format!("{}.{n}.ex", split_path(file).1)), script)
+}
+
+/// `--edit`: let the user edit `text`'s lnhash view in their editor, and turn the
+/// difference into commands addressed against `text`.
+///
+/// Addresses left on lines of the edited view (including one copied onto an added line)
+/// are dropped, and the remaining text is compared with `text` by [`diff_commands`].
+fn commands_from_editor(file: &str, text: &str, opts: &EditOptions, json: bool) -> Vec<Command> {
+    let view = opts.hash_spec.view_lines(text, opts.addr_style);
+    let file_name = Path::new(file)
         .file_name()
         .map(|s| s.to_string_lossy().to_string())
         .unwrap_or_else(|| "file".to_string());
+    let edited = (|| {
+        let (tmp_path, mut tmp) = create_beside(&env::temp_dir(), &file_name, "edit")?;
+        let read_back = (|| {
+            use std::io::Write;
+            tmp.write_all(view.iter().map(|l| format!("{l}\n")).collect::<String>().as_bytes())?;
+            drop(tmp);
+            run_editor(&tmp_path)?;
+            fs::read_to_string(&tmp_path)
+        })();
+        let _ = fs::remove_file(&tmp_path);
+        read_back
+    })();
+    let edited = match edited {
+        Ok(s) => s,
+        Err(e) => fail(json, &format!("--edit: {e}"), 1),
+    };
 
-    let perms = fs::metadata(path).map(|m| m.permissions()).ok();
+    // The addresses only guide the user: drop them and compare the text itself.
+    let edited: String = edited
+        .lines()
+        .map(|line| match line.split_once("  ") {
+            Some((a, rest)) if opts.hash_spec.parse_lnhash(a).is_ok() => format!("{rest}\n"),
+            _ => format!("{line}\n"),
+        })
+        .collect();
+    let commands = diff_commands(text, &edited, &opts.hash_spec);
+    if commands.is_empty() {
+        eprintln!("note: no changes made in the editor; {file} left as is");
+        process::exit(0);
+    }
+    if opts.trace {
+        for c in &commands {
+            eprintln!("--edit: {c}");
+        }
+    }
+    commands
+}
 
-    let pid = process::id();
-    let mut attempt: u64 = 0;
-    let tmp_path: PathBuf;
-    loop {
-        let candidate = dir.join(format!(".{file_name}.exhash.tmp.{pid}.{attempt}"));
-        match fs::OpenOptions::new()
-            .write(true)
-            .create_new(true)
-            .open(&candidate)
-        {
-            Ok(mut f) => {
-                use std::io::Write;
-                f.write_all(content.as_bytes())?;
-                f.sync_all()?;
-                if let Some(p) = perms.clone() {
-                    let _ = fs::set_permissions(&candidate, p);
-                }
-                tmp_path = candidate;
-                break;
+/// Run `$VISUAL`, `$EDITOR` or `vi` on `path` and wait for it. The variable may carry
+/// arguments (`code --wait`).
+fn run_editor(path: &Path) -> io::Result<()> {
+    let editor = ["VISUAL", "EDITOR"]
+        .iter()
+        .find_map(|v| env::var(v).ok().filter(|s| !s.trim().is_empty()))
+        .unwrap_or_else(|| "vi".to_string());
+    let mut words = editor.split_whitespace();
+    let program = words.next().unwrap_or("vi");
+    let status = process::Command::new(program).args(words).arg(path).status()?;
+    if status.success() {
+        Ok(())
+    } else {
+        Err(io::Error::other(format!("editor {editor:?} exited with {status}")))
+    }
+}
+
+/// For `--verbose`: describe on stderr what each command did, in the order applied.
+fn echo_steps(result: &EditResult, commands: &[Command]) {
+    for step in &result.steps {
+        eprintln!(
+            "command {} ({}): {} line(s) changed, {} deleted, {} line(s) now",
+            step.command + 1,
+            commands[step.command].addr1,
+            step.changed.len(),
+            step.deleted.len(),
+            step.line_count
+        );
+        for (n, text) in &step.changed {
+            eprintln!("  {n}: {text}");
+        }
+    }
+}
+
+/// For `--fuzz`: note on stderr each stale address that was found on a nearby line. With
+/// `--json` they are in the result object instead.
+fn note_relocations(json: bool, result: &EditResult, commands: &[Command]) {
+    if json {
+        return;
+    }
+    for r in &result.relocated {
+        eprintln!(
+            "note: command {} ({}): {} is stale, used line {} instead",
+            r.command + 1,
+            commands[r.command].addr1,
+            r.addr,
+            r.lineno
+        );
+    }
+}
+
+/// For `--keep-going`: if any command was skipped, say which and why on stderr (with
+/// `--json`, the result object already lists them) and exit with status 5.
+fn exit_if_skipped(json: bool, result: &EditResult, commands: &[Command]) {
+    if result.skipped.is_empty() {
+        return;
+    }
+    if !json {
+        for e in &result.skipped {
+            eprintln!("skipped: {}", describe_error(e, commands));
+        }
+    }
+    process::exit(5);
+}
+
+/// For `--verify-only`: check every command against `text`, list the failures, and exit
+/// 0 if there were none or with the first failure's exit code otherwise.
+fn report_verification(
+    json: bool,
+    text: &str,
+    commands: &[Command],
+    opts: &EditOptions,
+    suggest: bool,
+) -> ! {
+    let errors = verify_commands(text, commands, opts);
+    let hints = if suggest {
+        suggest_addresses(text, commands, opts, SUGGEST_WINDOW)
+    } else {
+        Vec::new()
+    };
+    if json {
+        let mut status = vec!["ok"; commands.len()];
+        for k in errors.iter().filter_map(|e| e.command()) {
+            status[k] = "failed";
+        }
+        let errs = json_list(&errors, |e| json_error(e, commands));
+        let suggestions = if suggest {
+            format!(",\"suggestions\":{}", json_hints(&hints, opts.addr_style))
+        } else {
+            String::new()
+        };
+        println!(
+            "{{\"ok\":{},\"errors\":{errs},\"commands\":{}{suggestions}}}",
+            errors.is_empty(),
+            json_commands(commands, &status, opts.addr_style)
+        );
+    } else {
+        for e in &errors {
+            println!("{}", describe_error(e, commands));
+        }
+        for line in hint_lines(&hints, opts.addr_style) {
+            println!("{line}");
+        }
+    }
+    process::exit(errors.first().map_or(0, exit_code));
+}
+
+/// How [`report`] prints a successful edit.
+struct Output<'a> {
+    json: bool,
+    /// `--context N`.
+    context: Option<usize>,
+    /// With `--diff`, the diff to print in place of the lines.
+    diff: Option<&'a str>,
+    /// With `--quickfix`, the [`quickfix_lines`] to print in place of the lines.
+    quickfix: Option<&'a [String]>,
+    dry_run: bool,
+    /// `--summary`: add the change statistics to the JSON object. (Without `--json` they
+    /// are printed by [`print_summary`].)
+    summary: bool,
+    /// `--color`: color hashes and diff lines with ANSI escapes.
+    color: bool,
+    /// In a multi-file edit, the file this result is for (named in the JSON object).
+    file: Option<&'a str>,
+    /// The input lines the edit deleted, listed after the changed lines.
+    removed: &'a [Removed<'a>],
+    /// How command and relocated addresses are written in the JSON object (`--compact`).
+    style: AddrStyle,
+}
+
+/// A line of the input that an edit deleted, with the address it had there.
+struct Removed<'a> {
+    lineno: usize,
+    addr: String,
+    text: &'a str,
+}
+
+/// The lines of `input` listed in `result.deleted`, addressed as `opts` prints addresses.
+fn removed_lines<'a>(input: &'a str, result: &EditResult, opts: &EditOptions) -> Vec<Removed<'a>> {
+    if result.deleted.is_empty() {
+        return Vec::new();
+    }
+    let lines: Vec<&str> = input.lines().collect();
+    let addrs = opts.hash_spec.lnhashes(lines.iter().copied());
+    result
+        .deleted
+        .iter()
+        .filter(|&&n| n >= 1 && n <= lines.len())
+        .map(|&n| Removed {
+            lineno: n,
+            addr: addrs[n - 1].format_with(opts.addr_style),
+            text: lines[n - 1],
+        })
+        .collect()
+}
+
+const HASH_COLOR: &str = "\x1b[33m";
+const ADDED_COLOR: &str = "\x1b[32m";
+const REMOVED_COLOR: &str = "\x1b[31m";
+const HUNK_COLOR: &str = "\x1b[36m";
+const HEADER_COLOR: &str = "\x1b[1m";
+
+/// `text` wrapped in the ANSI `style`.
+fn paint(style: &str, text: &str) -> String {
+    format!("{style}{text}\x1b[0m")
+}
+
+/// Color a unified diff the way `git diff` does: file headers bold, hunk headers cyan,
+/// removed lines red and added lines green.
+fn paint_diff(diff: &str) -> String {
+    let mut out = String::with_capacity(diff.len());
+    let mut in_hunk = false;
+    for line in diff.lines() {
+        in_hunk |= line.starts_with("@@");
+        // Only the lines before the first hunk are file headers; a removed "-- x" is not.
+        let style = if !in_hunk {
+            HEADER_COLOR
+        } else if line.starts_with("@@") {
+            HUNK_COLOR
+        } else if line.starts_with('-') {
+            REMOVED_COLOR
+        } else if line.starts_with('+') {
+            ADDED_COLOR
+        } else {
+            ""
+        };
+        if style.is_empty() {
+            out.push_str(line);
+        } else {
+            out.push_str(&paint(style, line));
+        }
+        out.push('\n');
+    }
+    out
+}
+
+/// Print the lines numbered `linenos` as `hash  content`, then each deleted line as
+/// `-hash  content` with its address in the input (or the diff in their place, with
+/// `--diff`), or with `--json` the whole result as one JSON object.
+///
+/// With `--context N`, up to N unchanged lines around each group are printed too: changed
+/// lines are marked `>`, context lines are indented to match, deleted lines are marked `-`,
+/// and groups are separated by `--`.
+fn report(out: &Output, result: &EditResult, linenos: &[usize], commands: &[Command]) {
+    let Output { json, context, diff, quickfix, dry_run, summary, color, file, removed, style } =
+        *out;
+    let shown = match context {
+        Some(n) => with_context(linenos, n, result.lines.len()),
+        None => linenos.iter().map(|&n| (n, true)).collect(),
+    };
+    let printed = shown.iter().filter_map(|&(n, changed)| {
+        Some((n, changed, result.hashes.get(n - 1)?, result.lines.get(n - 1)?))
+    });
+    if !json {
+        if let Some(lines) = quickfix {
+            for line in lines {
+                println!("{line}");
             }
-            Err(e) if e.kind() == io::ErrorKind::AlreadyExists => {
-                attempt += 1;
+            return;
+        }
+        if let Some(diff) = diff {
+            if color {
+                print!("{}", paint_diff(diff));
+            } else {
+                print!("{diff}");
+            }
+            return;
+        }
+        let mut prev = None;
+        for (n, changed, h, line) in printed {
+            let painted;
+            let h = if color {
+                painted = paint(HASH_COLOR, h);
+                &painted
+            } else {
+                h
+            };
+            if context.is_none() {
+                println!("{h}  {line}");
                 continue;
             }
-            Err(e) => return Err(e),
+            if prev.is_some_and(|p| p + 1 != n) {
+                println!("--");
+            }
+            prev = Some(n);
+            println!("{} {h}  {line}", if changed { '>' } else { ' ' });
+        }
+        if context.is_some() && prev.is_some() && !removed.is_empty() {
+            println!("--");
+        }
+        for r in removed {
+            let line = match context {
+                Some(_) => format!("- {}  {}", r.addr, r.text),
+                None => format!("-{}  {}", r.addr, r.text),
+            };
+            if color {
+                println!("{}", paint(REMOVED_COLOR, &line));
+            } else {
+                println!("{line}");
+            }
+        }
+        return;
+    }
+    let diff = diff.map_or_else(String::new, |d| format!(",\"diff\":{}", json_str(d)));
+    let relocated = if result.relocated.is_empty() {
+        String::new()
+    } else {
+        let list = json_list(&result.relocated, |r| {
+            format!(
+                "{{\"command\":{},\"addr\":{},\"lineno\":{}}}",
+                r.command,
+                json_str(&r.addr.format_with(style)),
+                r.lineno
+            )
+        });
+        format!(",\"relocated\":{list}")
+    };
+    let mut status = vec!["ok"; commands.len()];
+    for k in result.skipped.iter().filter_map(|e| e.command()) {
+        status[k] = "skipped";
+    }
+    let skipped = if result.skipped.is_empty() {
+        String::new()
+    } else {
+        format!(",\"skipped\":{}", json_list(&result.skipped, |e| json_error(e, commands)))
+    };
+    let summary = if summary {
+        let per_command = if result.steps.is_empty() {
+            String::new()
+        } else {
+            let steps = json_list(&result.steps, |s| {
+                format!("{{\"command\":{},{}}}", s.command, json_stats(&s.stats))
+            });
+            format!(",\"commands\":{steps}")
+        };
+        format!(",\"summary\":{{{}{per_command}}}", json_stats(&result.stats))
+    } else {
+        String::new()
+    };
+    let lines = json_list(printed, |(n, changed, h, line)| {
+        let context = if changed { "" } else { ",\"context\":true" };
+        format!(
+            "{{\"lineno\":{n},\"hash\":{},\"text\":{}{context}}}",
+            json_str(h),
+            json_str(line)
+        )
+    });
+    let deleted_lines = json_list(removed, |r| {
+        format!(
+            "{{\"lineno\":{},\"hash\":{},\"text\":{}}}",
+            r.lineno,
+            json_str(&r.addr),
+            json_str(r.text)
+        )
+    });
+    let file = file.map_or_else(String::new, |f| format!("\"file\":{},", json_str(f)));
+    println!(
+        "{{{file}\"ok\":{},\"dry_run\":{dry_run},\"modified\":{},\"deleted\":{},\
+         \"deleted_lines\":{deleted_lines},\"lines\":{lines},\"commands\":{}\
+         {relocated}{skipped}{summary}{diff}}}",
+        result.skipped.is_empty(),
+        json_list(&result.modified, |n| n.to_string()),
+        json_list(&result.deleted, |n| n.to_string()),
+        json_commands(commands, &status, style)
+    );
+}
+
+/// The fields of a JSON stats object, without the braces.
+fn json_stats(s: &EditStats) -> String {
+    format!(
+        "\"added\":{},\"changed\":{},\"deleted\":{},\"substitutions\":{}",
+        s.added, s.changed, s.deleted, s.substitutions
+    )
+}
+
+/// For `--summary`: one line of change statistics on stdout, preceded with `--verbose` by
+/// one per command.
+fn print_summary(result: &EditResult, commands: &[Command]) {
+    let describe = |s: &EditStats| {
+        format!(
+            "{} added, {} changed, {} deleted, {} substitution(s)",
+            s.added, s.changed, s.deleted, s.substitutions
+        )
+    };
+    for step in &result.steps {
+        let k = step.command;
+        println!("command {} ({}): {}", k + 1, commands[k].addr1, describe(&step.stats));
+    }
+    println!("summary: {}", describe(&result.stats));
+}
+
+fn json_commands(commands: &[Command], status: &[&str], style: AddrStyle) -> String {
+    json_list(commands.iter().zip(status), |(c, s)| {
+        let addr = json_str(&c.addr1.format_with(style));
+        format!("{{\"addr\":{addr},\"status\":\"{s}\"}}")
+    })
+}
+
+fn json_list<T>(items: impl IntoIterator<Item = T>, f: impl Fn(T) -> String) -> String {
+    let items: Vec<String> = items.into_iter().map(f).collect();
+    format!("[{}]", items.join(","))
+}
+
+/// Quote `s` as a JSON string.
+fn json_str(s: &str) -> String {
+    let mut out = String::with_capacity(s.len() + 2);
+    out.push('"');
+    for c in s.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            '\r' => out.push_str("\\r"),
+            '\t' => out.push_str("\\t"),
+            c if (c as u32) < 0x20 => out.push_str(&format!("\\u{:04x}", c as u32)),
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+    out
+}
+
+/// Read and parse the `--script` file, or stdin for `-`, exiting on failure.
+fn read_script(path: &str, json: bool) -> Script {
+    match parse_script(&read_script_text(path, json)) {
+        Ok(s) => s,
+        Err(e) => fail(json, e.message(), 2),
+    }
+}
+
+fn read_script_text(path: &str, json: bool) -> String {
+    let read = if path == "-" {
+        let mut s = String::new();
+        io::stdin().read_to_string(&mut s).map(|_| s)
+    } else {
+        fs::read_to_string(path)
+    };
+    match read {
+        Ok(t) => t,
+        Err(e) => fail(json, &format!("failed to read script {path}: {e}"), 1),
+    }
+}
+
+/// The scripts applying the `--patch` diff at `path`: each file's changes as commands
+/// against the file as it is now, or with `target`, the diff's one file's changes against
+/// that file instead.
+fn patch_scripts(
+    path: &str,
+    target: Option<String>,
+    spec: &LnHashSpec,
+    json: bool,
+    encoding: Option<&str>,
+) -> Vec<FileScript> {
+    let patches = match parse_patch(&read_script_text(path, json)) {
+        Ok(p) => p,
+        Err(e) => fail(json, &format!("{path}: {e}"), 2),
+    };
+    if patches.is_empty() {
+        fail(json, &format!("{path}: no changes found in the patch"), 2);
+    }
+    if target.is_some() && patches.len() > 1 {
+        let msg = format!("{path}: the patch changes {} files; give no file", patches.len());
+        fail(json, &msg, 2);
+    }
+    let mut scripts = Vec::with_capacity(patches.len());
+    for patch in patches {
+        let (Some(old), Some(_)) = (&patch.old_path, &patch.new_path) else {
+            fail(json, &format!("{path}: creating or deleting files is not supported"), 2)
+        };
+        let file = target.clone().unwrap_or_else(|| old.clone());
+        let (text, _) = read_text(&file, json, encoding);
+        match patch.script(&text, spec) {
+            Ok(script) => scripts.push(FileScript { path: file, script }),
+            Err(e) => fail(json, &format!("{file}: {e}"), exit_code(&e)),
         }
     }
+    scripts
+}
 
-    fs::rename(&tmp_path, path)?;
-    Ok(())
+/// The defaults from the config files, or none with `--no-config`.
+fn load_config(args: &[String]) -> Config {
+    if args.iter().any(|a| a == "--no-config") {
+        return Config::default();
+    }
+    Config::load().unwrap_or_else(|e| {
+        eprintln!("error: {e}");
+        process::exit(2);
+    })
 }
 
 fn main() {
     let args: Vec<String> = env::args().collect();
+    let config = load_config(&args);
 
     let mut dry_run = false;
     let mut stdin_mode = false;
+    let mut script_path: Option<String> = None;
+    let mut patch_path: Option<String> = None;
+    let mut json = false;
+    let mut show_diff = false;
+    let mut quickfix = false;
+    let mut backup: Option<String> = config.backup.clone();
+    let mut verify_only = false;
+    let mut repl = false;
+    let mut edit = false;
+    let mut save_undo = false;
+    let mut suggest = false;
+    let mut context: Option<usize> = None;
+    let mut text_blocks: Vec<String> = Vec::new();
+    let mut quiet = false;
+    let mut print_all = false;
+    let mut summary = false;
+    let mut color_when: Option<String> = config.color.clone();
+    let mut require_clean = false;
+    let mut force = false;
+    let mut git_add = false;
+    let mut limits = Limits {
+        file_size: config.max_file_size,
+        commands: config.max_commands,
+        block_size: config.max_block_size,
+    };
+    let mut expects: Vec<String> = Vec::new();
+    let mut encoding: Option<String> = None;
+    let mut lang: Option<String> = None;
+    let mut opts = config.edit_options();
 
-    let mut idx = 1;
+    // A file called `undo` or `convert` is edited as `./undo`, or after `--`.
+    let subcommand = |name| args.get(1).is_some_and(|a| a == name);
+    // `exhash undo FILE` applies the last undo script saved for FILE by --save-undo.
+    let undo = subcommand("undo");
+    // `exhash convert SCRIPT FILE` translates a sed or ed script into an exhash script.
+    let convert = subcommand("convert");
+    let mut ed_script = false;
+    let mut idx = if undo || convert { 2 } else { 1 };
     while idx < args.len() {
         match args[idx].as_str() {
             "--dry-run" => {
@@ -129,10 +1371,289 @@ fn main() {
                 stdin_mode = true;
                 idx += 1;
             }
+            "--json" => {
+                json = true;
+                idx += 1;
+            }
+            "--repl" => {
+                repl = true;
+                idx += 1;
+            }
+            "--edit" => {
+                edit = true;
+                idx += 1;
+            }
+            "--save-undo" => {
+                save_undo = true;
+                idx += 1;
+            }
+            "--suggest" => {
+                suggest = true;
+                idx += 1;
+            }
+            "--verify-only" => {
+                verify_only = true;
+                idx += 1;
+            }
+            "-q" | "--quiet" => {
+                quiet = true;
+                idx += 1;
+            }
+            "-v" | "--verbose" => {
+                opts.trace = true;
+                idx += 1;
+            }
+            "--text-block" => {
+                let Some(block) = args.get(idx + 1) else {
+                    eprintln!("error: --text-block requires the text");
+                    process::exit(2);
+                };
+                text_blocks.push(block.clone());
+                idx += 2;
+            }
+            "--context" => {
+                context = match args.get(idx + 1).map(|s| s.parse::<usize>()) {
+                    Some(Ok(n)) => Some(n),
+                    _ => {
+                        eprintln!("error: --context expects a number of lines");
+                        process::exit(2);
+                    }
+                };
+                idx += 2;
+            }
+            "--fuzz" => {
+                opts.fuzz = match args.get(idx + 1).map(|s| s.parse::<usize>()) {
+                    Some(Ok(n)) => n,
+                    _ => {
+                        eprintln!("error: --fuzz expects a number of lines");
+                        process::exit(2);
+                    }
+                };
+                idx += 2;
+            }
+            "--indent-width" => {
+                opts.indent_width = match args.get(idx + 1).map(|s| s.parse::<usize>()) {
+                    Some(Ok(n)) if n > 0 => n,
+                    _ => {
+                        eprintln!("error: --indent-width expects a number of spaces (at least 1)");
+                        process::exit(2);
+                    }
+                };
+                idx += 2;
+            }
+            "--strip-bom" => {
+                opts.strip_bom = true;
+                idx += 1;
+            }
+            "--no-config" => idx += 1,
+            "--color" => {
+                color_when = args.get(idx + 1).cloned();
+                idx += 2;
+            }
+            s if s.starts_with("--color=") => {
+                color_when = Some(s["--color=".len()..].to_string());
+                idx += 1;
+            }
+            "--require-clean" => {
+                require_clean = true;
+                idx += 1;
+            }
+            "--force" => {
+                force = true;
+                idx += 1;
+            }
+            "--git-add" => {
+                git_add = true;
+                idx += 1;
+            }
+            "--max-file-size" | "--max-block-size" => {
+                let Some(size) = args.get(idx + 1).and_then(|s| parse_size(s)) else {
+                    eprintln!("error: {} expects a size in bytes, e.g. 4096 or 10M", args[idx]);
+                    process::exit(2);
+                };
+                if args[idx] == "--max-file-size" {
+                    limits.file_size = Some(size);
+                } else {
+                    limits.block_size = Some(size);
+                }
+                idx += 2;
+            }
+            "--max-commands" => {
+                limits.commands = match args.get(idx + 1).map(|s| s.parse::<usize>()) {
+                    Some(Ok(n)) => Some(n),
+                    _ => {
+                        eprintln!("error: --max-commands expects a number");
+                        process::exit(2);
+                    }
+                };
+                idx += 2;
+            }
+            "--expect" => {
+                let Some(expect) = args.get(idx + 1) else {
+                    eprintln!("error: --expect requires an address or '@file <fingerprint>'");
+                    process::exit(2);
+                };
+                expects.push(expect.clone());
+                idx += 2;
+            }
+            "--summary" => {
+                summary = true;
+                idx += 1;
+            }
+            "--print-all" => {
+                print_all = true;
+                idx += 1;
+            }
+            "--keep-going" => {
+                opts.keep_going = true;
+                idx += 1;
+            }
+            "--strict-substitute" => {
+                opts.strict_substitute = true;
+                idx += 1;
+            }
+            "--diff" => {
+                show_diff = true;
+                idx += 1;
+            }
+            "--quickfix" => {
+                quickfix = true;
+                idx += 1;
+            }
+            "--ed" if convert => {
+                ed_script = true;
+                idx += 1;
+            }
+            "--backup" => {
+                let Some(suffix) = args.get(idx + 1).filter(|s| !s.is_empty()) else {
+                    eprintln!("error: --backup requires a non-empty suffix");
+                    process::exit(2);
+                };
+                backup = Some(suffix.clone());
+                idx += 2;
+            }
+            "--encoding" => {
+                let Some(label) = args.get(idx + 1) else {
+                    eprintln!("error: --encoding requires a label");
+                    process::exit(2);
+                };
+                #[cfg(feature = "encoding")]
+                let checked = exhash::check_encoding(label).map_err(|e| e.to_string());
+                #[cfg(not(feature = "encoding"))]
+                let checked: Result<(), _> =
+                    Err("this exhash was built without the `encoding` feature".to_string());
+                if let Err(e) = checked {
+                    eprintln!("error: --encoding: {e}");
+                    process::exit(2);
+                }
+                encoding = Some(label.clone());
+                idx += 2;
+            }
+            "--lang" => {
+                let Some(name) = args.get(idx + 1) else {
+                    eprintln!("error: --lang requires a language (rust or python)");
+                    process::exit(2);
+                };
+                #[cfg(feature = "treesitter")]
+                let checked = exhash::SymbolLanguage::from_name(name)
+                    .map(|_| ())
+                    .ok_or_else(|| format!("unknown language {name:?} (rust or python)"));
+                #[cfg(not(feature = "treesitter"))]
+                let checked: Result<(), _> =
+                    Err("this exhash was built without the `treesitter` feature".to_string());
+                if let Err(e) = checked {
+                    eprintln!("error: --lang: {e}");
+                    process::exit(2);
+                }
+                lang = Some(name.clone());
+                idx += 2;
+            }
+            "-f" | "--script" => {
+                let Some(path) = args.get(idx + 1) else {
+                    eprintln!("error: --script requires a file ('-' for stdin)");
+                    process::exit(2);
+                };
+                script_path = Some(path.clone());
+                idx += 2;
+            }
+            "--patch" => {
+                let Some(path) = args.get(idx + 1) else {
+                    eprintln!("error: --patch requires a diff file ('-' for stdin)");
+                    process::exit(2);
+                };
+                patch_path = Some(path.clone());
+                idx += 2;
+            }
+            "--bottom-up" => {
+                opts.bottom_up = true;
+                idx += 1;
+            }
+            "--join" => {
+                opts.join = match args.get(idx + 1).map(|s| s.as_str()) {
+                    Some("smart") => JoinPolicy::Smart,
+                    Some("always") => JoinPolicy::Always,
+                    Some("never") => JoinPolicy::Never,
+                    _ => {
+                        eprintln!("error: --join expects smart, always or never");
+                        process::exit(2);
+                    }
+                };
+                idx += 2;
+            }
+            "--hash-width" => {
+                opts.hash_spec.width = match args.get(idx + 1).map(|s| s.parse::<usize>()) {
+                    Some(Ok(w)) if check_hash_width(w).is_ok() => w,
+                    _ => {
+                        eprintln!("error: --hash-width must be 4, 6 or 8");
+                        process::exit(2);
+                    }
+                };
+                idx += 2;
+            }
+            "--seed" => {
+                let Some(seed) = args.get(idx + 1) else {
+                    eprintln!("error: --seed requires a value");
+                    process::exit(2);
+                };
+                opts.hash_spec.seed = Some(seed.clone()).filter(|s| !s.is_empty());
+                idx += 2;
+            }
+            "--nfc" => {
+                opts.hash_spec.normalization.nfc = true;
+                idx += 1;
+            }
+            "--ignore-case" => {
+                opts.hash_spec.normalization.lowercase = true;
+                idx += 1;
+            }
+            "--chained" => {
+                opts.hash_spec.algorithm = HashAlgorithm::Chained;
+                idx += 1;
+            }
+            "--versioned" => {
+                opts.hash_spec.versioned = true;
+                idx += 1;
+            }
+            "--compact" => {
+                opts.addr_style = AddrStyle::Compact;
+                idx += 1;
+            }
+            "--join-sep" => {
+                let Some(sep) = args.get(idx + 1) else {
+                    eprintln!("error: --join-sep requires a separator");
+                    process::exit(2);
+                };
+                opts.join = JoinPolicy::Separator(sep.clone());
+                idx += 2;
+            }
             "--help" | "-h" => {
                 usage();
                 return;
             }
+            "--" => {
+                idx += 1;
+                break;
+            }
             s if s.starts_with('-') && s.len() > 1 => {
                 eprintln!("error: unknown flag {s}");
                 usage();
@@ -142,113 +1663,467 @@ fn main() {
         }
     }
 
-    if idx >= args.len() {
+    // Only a multi-file script (`-f SCRIPT` alone) or a patch names its own files.
+    if idx >= args.len() && script_path.is_none() && patch_path.is_none() {
         usage();
         process::exit(2);
     }
 
-    let file = args[idx].clone();
+    if convert {
+        // A patch becomes a script per file it names, or with a file, that file's script.
+        if let Some(path) = &patch_path {
+            let target = match &args[idx..] {
+                [] => None,
+                [file] => Some(file.clone()),
+                _ => {
+                    eprintln!("error: exhash convert --patch takes at most a file");
+                    process::exit(2);
+                }
+            };
+            let one_file = target.is_some();
+            for f in patch_scripts(path, target, &opts.hash_spec, json, encoding.as_deref()) {
+                if one_file {
+                    print!("{}", f.script);
+                } else {
+                    print!("{f}");
+                }
+            }
+            return;
+        }
+        let [script_path, target] = &args[idx..] else {
+            eprintln!("error: exhash convert takes a sed or ed script ('-' for stdin) and a file");
+            process::exit(2);
+        };
+        limits.check_file(target, json);
+        let (text, _) = read_text(target, json, encoding.as_deref());
+        let script = read_script_text(script_path, json);
+        let dialect = if ed_script { ScriptDialect::Ed } else { ScriptDialect::Sed };
+        match convert_script(dialect, &script, &text, &opts.hash_spec) {
+            Ok(commands) => {
+                print!("{}", Script { fingerprint: Some(file_fingerprint(&text)), commands });
+            }
+            Err(e) => fail(json, &format!("{script_path}: {e}"), exit_code(&e)),
+        }
+        return;
+    }
+
+    let mut file = args.get(idx).cloned();
     idx += 1;
 
-    let cmd_args: Vec<String> = args[idx..].iter().cloned().collect();
+    if quiet && opts.trace {
+        eprintln!("error: --quiet and --verbose cannot be combined");
+        process::exit(2);
+    }
+    // Parsed once all flags are in, so that --seed applies whatever the order.
+    for expect in &expects {
+        let parsed = match parse_file_header(expect) {
+            Some(fp) => fp.map(|fp| opts.expect_fingerprint = Some(fp)),
+            None => opts.hash_spec.parse_lnhash(expect).map(|a| opts.expect.push(a)),
+        };
+        if let Err(e) = parsed {
+            eprintln!("error: --expect {expect}: {e}");
+            process::exit(2);
+        }
+    }
+
+    let color = match color_when.as_deref() {
+        None | Some("auto") => io::stdout().is_terminal() && env::var_os("NO_COLOR").is_none(),
+        Some("always") => true,
+        Some("never") => false,
+        Some(_) => {
+            eprintln!("error: --color must be auto, always or never");
+            process::exit(2);
+        }
+    };
+    if print_all && (show_diff || context.is_some()) {
+        eprintln!("error: --print-all already shows every line; drop --diff and --context");
+        process::exit(2);
+    }
+    if quickfix && (json || show_diff || print_all || context.is_some() || stdin_mode) {
+        eprintln!(
+            "error: --quickfix cannot be combined with --json, --diff, --print-all, --context \
+             or --stdin"
+        );
+        process::exit(2);
+    }
+
+    // A patch becomes a script per file, applied as a multi-file script is.
+    let patch_files = patch_path.map(|path| {
+        if script_path.is_some() || idx < args.len() {
+            eprintln!("error: --patch takes at most a file, and no commands or --script");
+            process::exit(2);
+        }
+        patch_scripts(&path, file.take(), &opts.hash_spec, json, encoding.as_deref())
+    });
+
+    let Some(file) = file else {
+        // `-f SCRIPT` alone: the script's @path sections name the files, and they are
+        // written all together or not at all.
+        let unsupported = [
+            (stdin_mode, "--stdin"),
+            (repl, "--repl"),
+            (edit, "--edit"),
+            (verify_only, "--verify-only"),
+            (!text_blocks.is_empty(), "--text-block"),
+            (!expects.is_empty(), "--expect"),
+            (opts.keep_going, "--keep-going"),
+        ];
+        if let Some((_, flag)) = unsupported.iter().find(|(set, _)| *set) {
+            let with = if patch_files.is_some() { "--patch" } else { "a multi-file script" };
+            eprintln!("error: {flag} cannot be used with {with}");
+            process::exit(2);
+        }
+        let files = match patch_files {
+            Some(files) => files,
+            None => {
+                let path = script_path.unwrap_or_default();
+                match parse_file_scripts(&read_script_text(&path, json)) {
+                    Ok(f) => f,
+                    Err(e) => fail(json, &format!("{path}: {e} (or give the file to edit)"), 2),
+                }
+            }
+        };
+
+        // Every file is read, edited and encoded before anything is written.
+        let mut edits = Vec::with_capacity(files.len());
+        let mut writes = Vec::with_capacity(files.len());
+        for f in files {
+            limits.check_file(&f.path, json);
+            limits.check_commands(&f.script.commands, json);
+            let (text, file_encoding) = read_text(&f.path, json, encoding.as_deref());
+            let fingerprint = f.script.fingerprint.clone();
+            let file_opts = EditOptions { fingerprint, ..opts.clone() };
+            let result = match edit_text_with(&text, &f.script.commands, &file_opts) {
+                Ok(r) => r,
+                Err(e) => {
+                    let input = suggest.then_some(text.as_str());
+                    fail_edit(json, &e, &f.script.commands, &file_opts, input, Some(&f.path))
+                }
+            };
+            let new_text = result.text();
+            match encode(&new_text, file_encoding) {
+                Ok(b) => writes.push((PathBuf::from(&f.path), b)),
+                Err(e) => fail(json, &format!("{}: {e}", f.path), 2),
+            }
+            edits.push((f, text, new_text, result));
+        }
+
+        if !dry_run {
+            for (f, ..) in &edits {
+                if require_clean && !force {
+                    if let Err(e) = check_clean(Path::new(&f.path)) {
+                        let msg = format!("refusing to edit {}: {e}", f.path);
+                        fail(json, &format!("{msg} (use --force to override)"), 2);
+                    }
+                }
+            }
+            if let Some(suffix) = &backup {
+                for (f, ..) in &edits {
+                    let bak = format!("{}{suffix}", f.path);
+                    if let Err(e) = fs::copy(&f.path, &bak) {
+                        fail(json, &format!("failed to write backup {bak}: {e}"), 1);
+                    }
+                }
+            }
+            if let Err(e) = write_all_atomic(&writes) {
+                fail(json, &format!("failed to write files: {e}"), 1);
+            }
+            if git_add {
+                for (f, ..) in &edits {
+                    if let Err(e) = stage(Path::new(&f.path)) {
+                        fail(json, &format!("wrote {} but could not stage it: {e}", f.path), 1);
+                    }
+                }
+            }
+            if save_undo {
+                for (f, text, new_text, _) in &edits {
+                    if let Err(e) = write_undo(Path::new(&f.path), text, new_text, &opts) {
+                        eprintln!("warning: could not save an undo script for {}: {e}", f.path);
+                    }
+                }
+            }
+        }
+
+        for (f, text, new_text, result) in &edits {
+            let commands = &f.script.commands;
+            let diff = show_diff.then(|| unified_diff(text, new_text, &f.path, &f.path, 3));
+            if opts.trace {
+                echo_steps(result, commands);
+            }
+            let all: Vec<usize>;
+            let linenos = if print_all {
+                all = (1..=result.lines.len()).collect();
+                &all
+            } else {
+                &result.modified
+            };
+            if !quiet {
+                if !json && diff.is_none() && !quickfix {
+                    println!("==> {} <==", f.path);
+                }
+                note_relocations(json, result, commands);
+                let diff = diff.as_deref();
+                let qf = quickfix.then(|| quickfix_lines(&f.path, text, result));
+                let quickfix = qf.as_deref();
+                let file = Some(f.path.as_str());
+                let removed = &removed_lines(text, result, &opts);
+                let out = Output {
+                    json, context, diff, quickfix, dry_run, summary, color, file, removed,
+                    style: opts.addr_style,
+                };
+                report(&out, result, linenos, commands);
+            }
+            if summary && !json {
+                print_summary(result, commands);
+            }
+        }
+        return;
+    };
+
+    let mut cmd_args: Vec<String> = args[idx..].to_vec();
+
+    if repl {
+        if stdin_mode {
+            eprintln!("error: --repl edits a file and cannot be combined with --stdin");
+            process::exit(2);
+        }
+        if script_path.is_some() || !cmd_args.is_empty() || !text_blocks.is_empty() {
+            eprintln!("error: --repl reads commands from stdin; it takes no commands or --script");
+            process::exit(2);
+        }
+        if dry_run {
+            eprintln!("error: --repl writes only on :w; it cannot be combined with --dry-run");
+            process::exit(2);
+        }
+        if require_clean && !force {
+            if let Err(e) = check_clean(Path::new(&file)) {
+                fail(false, &format!("refusing to edit {file}: {e} (use --force to override)"), 2);
+            }
+        }
+        limits.check_file(&file, false);
+        let read = read_text(&file, false, encoding.as_deref());
+        run_repl(&file, read, &opts, backup.as_deref(), git_add);
+        return;
+    }
+
+    if undo
+        && (stdin_mode || repl || edit || script_path.is_some() || !cmd_args.is_empty()
+            || !text_blocks.is_empty())
+    {
+        eprintln!("error: exhash undo takes a file and no commands, --script or other mode");
+        process::exit(2);
+    }
+    if edit {
+        if stdin_mode || repl {
+            eprintln!("error: --edit edits a file and cannot be combined with --stdin or --repl");
+            process::exit(2);
+        }
+        if script_path.is_some() || !cmd_args.is_empty() || !text_blocks.is_empty() {
+            eprintln!("error: --edit takes commands from the editor, not arguments or --script");
+            process::exit(2);
+        }
+    }
+
+    let mut script = None;
+    if let Some(path) = &script_path {
+        if !cmd_args.is_empty() {
+            eprintln!("error: --script takes no command arguments");
+            process::exit(2);
+        }
+        if !text_blocks.is_empty() {
+            eprintln!("error: --text-block is for command arguments; put blocks in the script");
+            process::exit(2);
+        }
+        if stdin_mode && path == "-" {
+            eprintln!("error: --script - cannot be combined with --stdin");
+            process::exit(2);
+        }
+        let parsed = read_script(path, json);
+        opts.fingerprint = parsed.fingerprint.clone();
+        script = Some(parsed);
+    }
+
+    // An optional leading `@file <fingerprint>` pins the whole file.
+    if let Some(header) = cmd_args.first().and_then(|a| parse_file_header(a)) {
+        match header {
+            Ok(fp) => opts.fingerprint = Some(fp),
+            Err(e) => fail(json, e.message(), 2),
+        }
+        cmd_args.remove(0);
+    }
 
     if stdin_mode {
         if file != "-" {
             eprintln!("error: with --stdin, file must be '-' (got '{file}')");
             process::exit(2);
         }
-
-        let mut input = String::new();
-        if let Err(e) = io::stdin().read_to_string(&mut input) {
-            eprintln!("error: failed to read stdin: {e}");
-            process::exit(1);
+        if require_clean || git_add {
+            eprintln!("error: --require-clean and --git-add need a file, not --stdin");
+            process::exit(2);
         }
 
-        // In --stdin mode, stdin is consumed by the input. We therefore parse
-        // commands with an empty text stream; a/i/c will fail with a clear error.
-        let mut empty = io::Cursor::new("");
-        let commands = match parse_commands_from_args(&cmd_args, &mut empty) {
+        let bytes = limits.read_stdin(json);
+        let (input, _) = decode(bytes, encoding.as_deref(), json);
+        let cmd_args = resolve_symbols(&cmd_args, "-", &input, lang.as_deref(), &opts, json);
+
+        // In --stdin mode, stdin is consumed by the input, so a/i/c text must be inline
+        // (`a\text`) or come from --text-block or a --script file.
+        let commands = match script.map_or_else(
+            || parse_commands_with_blocks(&cmd_args, &text_blocks),
+            |s| Ok(s.commands),
+        ) {
             Ok(c) => c,
+            Err(e) if json => fail(true, e.message(), 2),
             Err(e) => {
                 eprintln!("error: {e}");
-                eprintln!("note: commands requiring text blocks (a/i/c) are not supported with --stdin");
+                eprintln!(
+                    "note: with --stdin, pass a/i/c text inline (a\\text), with --text-block \
+                     or --script"
+                );
                 process::exit(2);
             }
         };
 
-        let result = match edit_text(&input, &commands) {
+        limits.check_commands(&commands, json);
+
+        if verify_only {
+            report_verification(json, &input, &commands, &opts, suggest);
+        }
+
+        let result = match edit_text_with(&input, &commands, &opts) {
             Ok(r) => r,
-            Err(e) => {
-                eprintln!("error: {e}");
-                process::exit(2);
-            }
+            Err(e) => fail_edit(json, &e, &commands, &opts, suggest.then_some(&input), None),
         };
 
-        for (h, line) in result.hashes.iter().zip(result.lines.iter()) {
-            println!("{h}  {line}");
+        let all: Vec<usize> = (1..=result.lines.len()).collect();
+        let diff = show_diff.then(|| unified_diff(&input, &result.text(), "-", "-", 3));
+        if opts.trace {
+            echo_steps(&result, &commands);
         }
-
+        if !quiet {
+            note_relocations(json, &result, &commands);
+            let diff = diff.as_deref();
+            let removed = &removed_lines(&input, &result, &opts);
+            let (context, quickfix, file) = (None, None, None);
+            let out = Output {
+                json, context, diff, quickfix, dry_run, summary, color, file, removed,
+                style: opts.addr_style,
+            };
+            report(&out, &result, &all, &commands);
+        }
+        if summary && !json {
+            print_summary(&result, &commands);
+        }
+        exit_if_skipped(json, &result, &commands);
         return;
     }
 
     // File mode.
-    let bytes = match fs::read(&file) {
-        Ok(b) => b,
-        Err(e) => {
-            eprintln!("error: failed to read {file}: {e}");
-            process::exit(1);
-        }
-    };
+    limits.check_file(&file, json);
+    let (mut text, mut file_encoding) = read_text(&file, json, encoding.as_deref());
 
-    if is_binary(&bytes) {
-        eprintln!("error: binary file rejected (NUL byte found)");
-        process::exit(1);
-    }
-
-    let text = match String::from_utf8(bytes) {
-        Ok(s) => s,
-        Err(_) => {
-            eprintln!("error: non-UTF8 file rejected");
-            process::exit(1);
+    let undo_script = undo.then(|| match undo_scripts(Path::new(&file)).pop() {
+        Some((_, path)) => path,
+        None => fail(json, &format!("no undo script saved for {file} (see --save-undo)"), 1),
+    });
+    let commands = if let Some(path) = &undo_script {
+        read_script(&path.to_string_lossy(), json).commands
+    } else if edit {
+        let commands = commands_from_editor(&file, &text, &opts, json);
+        // The file may have changed while the editor was open: the derived commands are
+        // verified against what is there now, not against the view.
+        (text, file_encoding) = read_text(&file, json, encoding.as_deref());
+        commands
+    } else {
+        let cmd_args = resolve_symbols(&cmd_args, &file, &text, lang.as_deref(), &opts, json);
+        match script.map_or_else(
+            || {
+                if text_blocks.is_empty() {
+                    parse_commands_from_args(&cmd_args, &mut io::stdin().lock())
+                } else {
+                    parse_commands_with_blocks(&cmd_args, &text_blocks)
+                }
+            },
+            |s| Ok(s.commands),
+        ) {
+            Ok(c) => c,
+            Err(e) => fail(json, e.message(), 2),
         }
     };
+    limits.check_commands(&commands, json);
 
-    let mut stdin = io::stdin().lock();
-    let commands = match parse_commands_from_args(&cmd_args, &mut stdin) {
-        Ok(c) => c,
-        Err(e) => {
-            eprintln!("error: {e}");
-            process::exit(2);
-        }
-    };
+    if verify_only {
+        report_verification(json, &text, &commands, &opts, suggest);
+    }
 
-    let result = match edit_text(&text, &commands) {
+    let result = match edit_text_with(&text, &commands, &opts) {
         Ok(r) => r,
-        Err(e) => {
-            eprintln!("error: {e}");
-            process::exit(2);
-        }
+        Err(e) => fail_edit(json, &e, &commands, &opts, suggest.then_some(&text), None),
     };
 
-    let new_text = if result.lines.is_empty() {
-        String::new()
-    } else {
-        let mut s = result.lines.join("\n");
-        s.push('\n');
-        s
+    let new_text = result.text();
+    let new_bytes = match encode(&new_text, file_encoding) {
+        Ok(b) => b,
+        Err(e) => fail(json, e.message(), 2),
     };
 
     if !dry_run {
-        if let Err(e) = write_atomic(Path::new(&file), &new_text) {
-            eprintln!("error: failed to write {file}: {e}");
-            process::exit(1);
+        if require_clean && !force {
+            if let Err(e) = check_clean(Path::new(&file)) {
+                let msg = format!("refusing to edit {file}: {e} (use --force to override)");
+                fail(json, &msg, 2);
+            }
+        }
+        if let Some(suffix) = &backup {
+            let bak = format!("{file}{suffix}");
+            if let Err(e) = fs::copy(&file, &bak) {
+                fail(json, &format!("failed to write backup {bak}: {e}"), 1);
+            }
+        }
+        if let Err(e) = write_atomic(Path::new(&file), &new_bytes) {
+            fail(json, &format!("failed to write {file}: {e}"), 1);
+        }
+        if git_add {
+            if let Err(e) = stage(Path::new(&file)) {
+                fail(json, &format!("wrote {file} but could not stage it: {e}"), 1);
+            }
+        }
+        if let Some(path) = &undo_script {
+            if let Err(e) = fs::remove_file(path) {
+                eprintln!("warning: undid {file} but could not remove {}: {e}", path.display());
+            }
+        } else if save_undo {
+            if let Err(e) = write_undo(Path::new(&file), &text, &new_text, &opts) {
+                eprintln!("warning: could not save an undo script for {file}: {e}");
+            }
         }
     }
 
-    for lineno in &result.modified {
-        let i = lineno - 1;
-        if let (Some(h), Some(line)) = (result.hashes.get(i), result.lines.get(i)) {
-            println!("{h}  {line}");
-        }
+    let diff = show_diff.then(|| unified_diff(&text, &new_text, &file, &file, 3));
+    if opts.trace {
+        echo_steps(&result, &commands);
+    }
+    let all: Vec<usize>;
+    let linenos = if print_all {
+        all = (1..=result.lines.len()).collect();
+        &all
+    } else {
+        &result.modified
+    };
+    if !quiet {
+        note_relocations(json, &result, &commands);
+        let diff = diff.as_deref();
+        let qf = quickfix.then(|| quickfix_lines(&file, &text, &result));
+        let quickfix = qf.as_deref();
+        let (file, removed) = (None, &removed_lines(&text, &result, &opts));
+        let out = Output {
+            json, context, diff, quickfix, dry_run, summary, color, file, removed,
+            style: opts.addr_style,
+        };
+        report(&out, &result, linenos, &commands);
+    }
+    if summary && !json {
+        print_summary(&result, &commands);
     }
+    exit_if_skipped(json, &result, &commands);
 }
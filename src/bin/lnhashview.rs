@@ -1,114 +1,1379 @@
+use std::collections::{BTreeSet, HashMap};
 use std::env;
 use std::fs;
+use std::io::{self, IsTerminal, Read, Write};
+use std::path::Path;
 use std::process;
+use std::thread;
+use std::time::Duration;
 
-use exhash::format_lnhash;
+use exhash::{
+    changed_ranges, check_hash_width, file_fingerprint, hash_collisions_with, html_diff,
+    html_view_lines, parse_commands_from_strs, AddrStyle, Config, HashAlgorithm, LineGroup,
+    LnHash, LnHashSpec,
+};
+use regex::Regex;
+use unicode_segmentation::UnicodeSegmentation;
+use unicode_width::UnicodeWidthStr;
 
 fn usage() {
     eprintln!(
-        "Usage: lnhashview <file> [start_line [end_line]]\n\n\
+        "Usage: lnhashview [--hash-width N] [--compact] [--seed STR] [--nfc] [--ignore-case]\n\
+         \x20                 [--versioned] [--chained] [--collisions] [--stats] [--fingerprint]\n\
+         \x20                 [--encoding LABEL] [--no-config] [--grep PATTERN [-C N]]\n\
+         \x20                 [--format TEMPLATE] [--color=auto|always|never]\n\
+         \x20                 [--around LINE [-C N]]... [-A|-T|-E|-v]\n\
+         \x20                 [--max-lines N] [--plain[=nohash]] [--hashes-only] [--skip-blank]\n\
+         \x20                 [--lengths] [--blame] [--watch]\n\
+         \x20                 [--emit-cmd CMD] [--html]\n\
+         \x20                 <file|-> [start_line [end_line]]...\n\
+         \x20      lnhashview [options] --diff <old> <new>\n\
+         \x20      lnhashview [options] --glob PATTERN [--grep PATTERN [-C N]]\n\n\
          Prints lines as: <lineno>|<hash>|  <content>\n\
-         start_line/end_line are 1-based inclusive."
+         start_line/end_line are 1-based inclusive; negative ones count from the end (-1 is the\n\
+         last line), and a negative start_line alone shows the last lines, as tail does.\n\
+         Several ranges (10 20 55 60) are printed in turn, separated by --.\n\
+         With '-', or no file while stdin is not a terminal, the text is read from stdin.\n\
+         --hash-width N  hex digits per hash: 4 (default), 6 or 8\n\
+         --compact       print addresses in compact form: <base36 lineno>:<hash>\n\
+         --seed STR      mix STR into every hash (default: $EXHASH_SEED)\n\
+         --nfc           hash lines after Unicode NFC normalization (tagged nfc:)\n\
+         --ignore-case   hash lines lowercased, ignoring case-only edits (tagged lc:)\n\
+         --versioned     tag each hash with its algorithm version, e.g. 12|v2:a3f2|\n\
+         --chained       fold the previous line into each hash, telling repeated lines apart\n\
+         --collisions    list groups of distinct lines sharing a hash, and duplicate lines\n\
+         --stats         summarize the file instead: its lines, blank lines, longest line, and\n\
+         \x20               the groups of duplicate lines and of lines sharing a hash\n\
+         --watch         show the view again, on a cleared screen, whenever the file (or the\n\
+         \x20               --diff files) changes; stop with Ctrl-C\n\
+         --emit-cmd CMD  print the exhash command CMD (s/old/new/, d, >1, ...) addressed to\n\
+         \x20               each line in view, or each --grep match, instead of the lines\n\
+         --fingerprint   print an '@file <fingerprint>' header line before the view\n\
+         --html          print the view as an HTML page, a table with --grep matches\n\
+         \x20               highlighted; with --diff, the changes between the files with -C N\n\
+         \x20               (default 3) lines of context, added and removed lines colored\n\
+         --encoding L    read the file as L (latin1, shift-jis, auto, ...), as exhash does\n\
+         --diff          compare two files line by line by hash: removed lines (- old address),\n\
+         \x20               added ones (+ new address) and moved ones (~ old -> new), then a\n\
+         \x20               count of each and of renumbered lines; exits 1 if they differ\n\
+         --glob PATTERN  view every file matching PATTERN (e.g. 'src/**/*.rs'; repeatable)\n\
+         \x20               instead of one file, as <file>:<address>  <content>, skipping files\n\
+         \x20               that .gitignore excludes and hidden, binary or non-UTF-8 ones\n\
+         --no-config     ignore ~/.config/exhash/config.toml and .exhash.toml\n\
+         --grep PATTERN  print only lines matching the regex PATTERN (within the range, if\n\
+         \x20               given); exits 1 if none match\n\
+         -C, --context N also print N lines around each match; groups are separated by --\n\
+         --around LINE   print the lines around LINE (3 either side, or -C N) instead of a\n\
+         \x20               range; LINE is a line number (negative counts from the end) or an\n\
+         \x20               address such as 12|a3f2|, found at the nearest line it matches\n\
+         --format T      print each line as the template T instead of '{{addr}}  {{text}}',\n\
+         \x20               filling in {{lineno}}, {{hash}} (with tags), {{addr}}, {{text}},\n\
+         \x20               {{file}} and --lengths' {{chars}}, {{bytes}}, {{width}} and\n\
+         \x20               {{indent}}; \\t, \\n and \\\\ are escapes, and {{{{ and }}}} are\n\
+         \x20               literal braces\n\
+         --plain         print lines as <file>:<lineno>:<hash>:<content> for editors and\n\
+         \x20               tools that read grep -n output, with - for : on --grep context\n\
+         \x20               lines as grep does; --plain=nohash leaves out the hash\n\
+         --hashes-only   print only each line's address, a cheap check of which lines are\n\
+         \x20               where when the content is already known\n\
+         --lengths       add a column of each line's length in characters (as displayed, so\n\
+         \x20               é is one however it is encoded), in bytes and in terminal columns\n\
+         \x20               (wide characters take two, tabs stop every 8), and its indentation\n\
+         \x20               in whitespace characters, e.g. 12c 14b 12w 4i\n\
+         --blame         add a column of the commit that last changed each line and its\n\
+         \x20               author, from git blame\n\
+         --skip-blank    leave out blank (and whitespace-only) lines; the others keep their\n\
+         \x20               numbers\n\
+         --max-lines N   print at most N lines, then a notice of how many more there are\n\
+         \x20               (0: no limit)\n\
+         -T, --show-tabs print tabs as ^I\n\
+         -E, --show-ends print $ at the end of each line, so that trailing spaces show\n\
+         -v, --show-nonprinting\n\
+         \x20               print control characters as ^M, ^?, M-^E and so on, and invisible\n\
+         \x20               Unicode characters such as no-break or zero-width spaces as <U+00A0>\n\
+         -A, --show-all  -T, -E and -v together, as cat -A; hashes are still of the lines as\n\
+         \x20               they are\n\
+         --color=WHEN    color line numbers, hashes and --grep matches: auto (default: when\n\
+         \x20               stdout is a terminal and $NO_COLOR is unset), always or never\n\n\
+         Defaults for the hash options, --compact and --color are read from those files, as for\n\
+         exhash, and so is max-lines, a --max-lines for when stdout is not a terminal."
     );
 }
 
-fn main() {
-    let args: Vec<String> = env::args().collect();
-    if args.len() < 2 {
-        usage();
-        process::exit(2);
+#[cfg(feature = "encoding")]
+fn decode(bytes: &[u8], label: &str) -> String {
+    match exhash::decode_text(bytes, label) {
+        Ok((text, _)) => text,
+        Err(e) => {
+            eprintln!("error: {e}");
+            process::exit(1);
+        }
     }
+}
 
-    let file = &args[1];
-    let start_opt = args.get(2).map(|s| s.parse::<usize>());
-    let end_opt = args.get(3).map(|s| s.parse::<usize>());
+#[cfg(not(feature = "encoding"))]
+fn decode(_: &[u8], _: &str) -> String {
+    eprintln!("error: --encoding: this lnhashview was built without the `encoding` feature");
+    process::exit(2);
+}
 
-    if args.len() > 4 {
-        usage();
+/// The files matching any of the `--glob` patterns, sorted, leaving out what `.gitignore`
+/// (and `.ignore`) files exclude and hidden files, as ripgrep does.
+#[cfg(feature = "glob")]
+fn glob_files(patterns: &[String]) -> Vec<String> {
+    let patterns: Vec<&str> = patterns.iter().map(|p| p.as_str()).collect();
+    exhash::discover_files(&patterns).unwrap_or_else(|e| {
+        eprintln!("error: {e}");
         process::exit(2);
+    })
+}
+
+#[cfg(not(feature = "glob"))]
+fn glob_files(_: &[String]) -> Vec<String> {
+    eprintln!("error: --glob: this lnhashview was built without the `glob` feature");
+    process::exit(2);
+}
+
+/// A file found by `--glob` as text, or `None` (with a warning if it could not be read) for
+/// one to pass over: binary, or not UTF-8 without `--encoding`.
+fn read_scanned(path: &str, encoding: Option<&str>) -> Option<String> {
+    let bytes = fs::read(path)
+        .map_err(|e| eprintln!("warning: failed to read {path}: {e}"))
+        .ok()?;
+    if bytes.contains(&0) {
+        return None;
     }
+    match encoding {
+        Some(label) => Some(decode(&bytes, label)),
+        None => String::from_utf8(bytes).ok(),
+    }
+}
 
-    let start = match start_opt {
-        None => None,
-        Some(Ok(v)) => Some(v),
-        Some(Err(_)) => {
-            eprintln!("error: start_line must be an integer");
-            process::exit(2);
-        }
+const LINENO_COLOR: &str = "\x1b[32m";
+const HASH_COLOR: &str = "\x1b[33m";
+const MATCH_COLOR: &str = "\x1b[1;31m";
+const SEPARATOR_COLOR: &str = "\x1b[36m";
+const ADDED_COLOR: &str = "\x1b[32m";
+const REMOVED_COLOR: &str = "\x1b[31m";
+const FILE_COLOR: &str = "\x1b[35m";
+const BLAME_COLOR: &str = "\x1b[2m";
+
+/// A standalone HTML page titled `title` around `body`, for --html.
+fn html_page(title: &str, body: &str) -> String {
+    let title = title.replace('&', "&amp;").replace('<', "&lt;").replace('>', "&gt;");
+    format!(
+        "<!DOCTYPE html>\n<html>\n<head>\n<meta charset=\"utf-8\">\n<title>{title}</title>\n\
+         </head>\n<body>\n{body}</body>\n</html>\n"
+    )
+}
+
+/// `text` wrapped in the ANSI `style` if `color` is on.
+fn paint(color: bool, style: &str, text: &str) -> String {
+    if color {
+        format!("{style}{text}\x1b[0m")
+    } else {
+        text.to_string()
+    }
+}
+
+/// `addr` in `style`, with its line number and hash colored apart from the delimiters.
+fn paint_addr(color: bool, addr: &LnHash, style: AddrStyle) -> String {
+    let text = addr.format_with(style);
+    let (sep, end) = match style {
+        AddrStyle::Standard => ('|', "|"),
+        AddrStyle::Compact => (':', ""),
     };
+    match text.strip_suffix(end).and_then(|t| t.split_once(sep)) {
+        Some((lineno, hash)) if color => format!(
+            "{}{sep}{}{end}",
+            paint(true, LINENO_COLOR, lineno),
+            paint(true, HASH_COLOR, hash)
+        ),
+        _ => text,
+    }
+}
 
-    let end = match end_opt {
-        None => None,
-        Some(Ok(v)) => Some(v),
-        Some(Err(_)) => {
-            eprintln!("error: end_line must be an integer");
-            process::exit(2);
+/// Which characters `--show-tabs`, `--show-ends` and `--show-nonprinting` make visible.
+#[derive(Debug, Clone, Copy, Default)]
+struct Show {
+    tabs: bool,
+    ends: bool,
+    nonprinting: bool,
+}
+
+impl Show {
+    /// `text` as `cat -A` would print it: tabs as `^I`, control characters in caret
+    /// notation (`^M`, `^?`, `M-^[`), and the invisible Unicode characters that cat shows as
+    /// bytes, such as no-break and zero-width spaces, as `<U+00A0>`. The line end is left to
+    /// [`Show::end`].
+    fn text(self, text: &str) -> String {
+        let mut out = String::with_capacity(text.len());
+        for c in text.chars() {
+            let code = c as u32;
+            match c {
+                '\t' if self.tabs => out.push_str("^I"),
+                '\t' => out.push(c),
+                _ if !self.nonprinting => out.push(c),
+                '\x7f' => out.push_str("^?"),
+                '\0'..='\x1f' => out.extend(['^', char::from(code as u8 + 64)]),
+                '\u{80}'..='\u{9f}' => out.extend(['M', '-', '^', char::from(code as u8 - 64)]),
+                '\u{ad}' | '\u{200b}'..='\u{200f}' | '\u{2060}' | '\u{feff}' => {
+                    out.push_str(&format!("<U+{code:04X}>"))
+                }
+                c if c.is_whitespace() && c != ' ' => out.push_str(&format!("<U+{code:04X}>")),
+                c => out.push(c),
+            }
+        }
+        out
+    }
+
+    /// The `$` that `--show-ends` prints after each line, so trailing spaces show.
+    fn end(self) -> &'static str {
+        if self.ends {
+            "$"
+        } else {
+            ""
         }
+    }
+
+    /// `line` ready to print: [`Show::text`] and then [`Show::end`].
+    fn line(self, line: &str) -> String {
+        self.text(line) + self.end()
+    }
+}
+
+/// `line` with each `--grep` match colored, shown as `show` says.
+fn paint_matches(color: bool, line: &str, grep: Option<&Regex>, show: Show) -> String {
+    let Some(re) = grep.filter(|_| color) else {
+        return show.line(line);
     };
+    let mut out = String::new();
+    let mut last = 0;
+    for m in re.find_iter(line).filter(|m| !m.is_empty()) {
+        out.push_str(&show.text(&line[last..m.start()]));
+        out.push_str(&paint(true, MATCH_COLOR, &show.text(m.as_str())));
+        last = m.end();
+    }
+    out.push_str(&show.line(&line[last..]));
+    out
+}
 
-    let bytes = match fs::read(file) {
+/// `--plain` output, which editors and other tools that read `grep -n` can parse.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Plain {
+    /// `path:lineno:hash:content`
+    WithHash,
+    /// `path:lineno:content`, exactly as `grep -n` prints it
+    NoHash,
+}
+
+/// How each line is printed: `addr  text`, a `--format` template, `--plain`, or just `addr`
+/// for `--hashes-only`.
+struct LineFormat<'a> {
+    style: AddrStyle,
+    color: bool,
+    hashes_only: bool,
+    /// `--lengths`: a `12c 14b 4i` column after the address.
+    lengths: bool,
+    /// `--blame`: a commit and author column after the address, by line.
+    blame: Option<&'a [String]>,
+    pieces: Option<&'a [Piece]>,
+    plain: Option<Plain>,
+    grep: Option<&'a Regex>,
+    show: Show,
+}
+
+impl LineFormat<'_> {
+    /// The line `text` of `file`, whose address is `addr`. With `--glob` (`prefix`), the file name
+    /// comes first, as `file:addr  text`.
+    fn render(&self, file: &str, prefix: bool, addr: &LnHash, text: &str) -> String {
+        let color = self.color;
+        let painted = paint_matches(color, text, self.grep, self.show);
+        if let Some(plain) = self.plain {
+            // Like grep, `:` follows a match and `-` a line of context around it.
+            let sep = match self.grep {
+                Some(re) if !re.is_match(text) => "-",
+                _ => ":",
+            };
+            let sep = paint(color, SEPARATOR_COLOR, sep);
+            let mut out = paint(color, FILE_COLOR, file) + &sep;
+            out += &paint(color, LINENO_COLOR, &addr.lineno.to_string());
+            out += &sep;
+            if plain == Plain::WithHash {
+                out += &paint(color, HASH_COLOR, &addr.hash_field());
+                out += &sep;
+            }
+            return out + &painted;
+        }
+        let Some(pieces) = self.pieces else {
+            let mut out = paint_addr(color, addr, self.style);
+            if prefix {
+                out = format!("{}:{out}", paint(color, FILE_COLOR, file));
+            }
+            if self.lengths {
+                let LineLengths { chars, bytes, width, indent } = line_lengths(text);
+                out += &format!("  {chars}c {bytes}b {width}w {indent}i");
+            }
+            if let Some(column) = self.blame.and_then(|b| b.get(addr.lineno - 1)) {
+                out += "  ";
+                out += &paint(color, BLAME_COLOR, column);
+            }
+            if !self.hashes_only {
+                out += "  ";
+                out += &painted;
+            }
+            return out;
+        };
+        let mut out = String::new();
+        for piece in pieces {
+            match piece {
+                Piece::Literal(s) => out.push_str(s),
+                Piece::Lineno => {
+                    out.push_str(&paint(color, LINENO_COLOR, &addr.lineno.to_string()))
+                }
+                Piece::Hash => out.push_str(&paint(color, HASH_COLOR, &addr.hash_field())),
+                Piece::Addr => out.push_str(&paint_addr(color, addr, self.style)),
+                Piece::Text => out.push_str(&painted),
+                Piece::File => out.push_str(&paint(color, FILE_COLOR, file)),
+                Piece::Chars => out.push_str(&line_lengths(text).chars.to_string()),
+                Piece::Bytes => out.push_str(&line_lengths(text).bytes.to_string()),
+                Piece::Width => out.push_str(&line_lengths(text).width.to_string()),
+                Piece::Indent => out.push_str(&line_lengths(text).indent.to_string()),
+            }
+        }
+        out
+    }
+}
+
+/// The lines from `start_line` to `end_line` that match `re`, with `context` lines around
+/// each, as runs of consecutive line numbers.
+fn matching_runs(
+    lines: &[&str],
+    (start_line, end_line): (usize, usize),
+    re: &Regex,
+    context: usize,
+) -> Vec<Vec<usize>> {
+    let mut shown = BTreeSet::new();
+    for n in (start_line..=end_line).filter(|&n| re.is_match(lines[n - 1])) {
+        let first = n.saturating_sub(context).max(start_line);
+        shown.extend(first..=(n + context).min(end_line));
+    }
+    let mut runs: Vec<Vec<usize>> = Vec::new();
+    let mut run: Vec<usize> = Vec::new();
+    for n in shown {
+        if run.last().is_some_and(|&p| p + 1 != n) {
+            runs.push(std::mem::take(&mut run));
+        }
+        run.push(n);
+    }
+    if !run.is_empty() {
+        runs.push(run);
+    }
+    runs
+}
+
+/// The `--max-lines` cap: lines are printed while it lasts, and the rest only counted.
+struct Budget {
+    max: Option<usize>,
+    shown: usize,
+    hidden: usize,
+    first_hidden: Option<usize>,
+}
+
+impl Budget {
+    fn new(max: Option<usize>) -> Self {
+        Budget { max, shown: 0, hidden: 0, first_hidden: None }
+    }
+
+    /// Whether the cap has been reached.
+    fn is_spent(&self) -> bool {
+        self.max.is_some_and(|max| self.shown >= max)
+    }
+
+    /// Whether line `n` fits; if not, it is counted among the hidden ones.
+    fn take(&mut self, n: usize) -> bool {
+        if self.is_spent() {
+            self.hidden += 1;
+            self.first_hidden.get_or_insert(n);
+            return false;
+        }
+        self.shown += 1;
+        true
+    }
+
+    /// The start of the truncation notice: `… 12 more lines`.
+    fn more(&self) -> String {
+        let s = if self.hidden == 1 { "" } else { "s" };
+        format!("… {} more line{s}", self.hidden)
+    }
+}
+
+/// `--blame`: the commit that last changed each line of `file` and its author, from `git
+/// blame`, as columns such as `3f2a1b9c alice` padded to the longest author's name. Lines not
+/// committed yet get git's zero commit and `Not Committed Yet`.
+fn blame(file: &str) -> Result<Vec<String>, String> {
+    let path = Path::new(file);
+    let dir = path.parent().filter(|d| !d.as_os_str().is_empty()).unwrap_or(Path::new("."));
+    let name = path.file_name().ok_or_else(|| format!("{file} is not a file"))?;
+    let out = process::Command::new("git")
+        .arg("-C")
+        .arg(dir)
+        .args(["blame", "--porcelain", "--"])
+        .arg(name)
+        .output()
+        .map_err(|e| format!("cannot run git: {e}"))?;
+    if !out.status.success() {
+        return Err(String::from_utf8_lossy(&out.stderr).trim().to_string());
+    }
+    let porcelain = String::from_utf8_lossy(&out.stdout);
+
+    // Each line is a header `<commit> <old lineno> <final lineno> ...`, then the commit's
+    // details the first time it appears (`author <name>` among them), then a tab and the text.
+    let mut authors: HashMap<&str, &str> = HashMap::new();
+    let mut commits: Vec<(usize, &str)> = Vec::new();
+    let mut current = "";
+    for line in porcelain.lines() {
+        if line.starts_with('\t') {
+            continue;
+        }
+        if let Some(author) = line.strip_prefix("author ") {
+            authors.insert(current, author);
+            continue;
+        }
+        let mut fields = line.split(' ');
+        let commit = fields.next().unwrap_or_default();
+        if commit.len() == 40 && commit.bytes().all(|b| b.is_ascii_hexdigit()) {
+            current = commit;
+            let lineno = fields.nth(1).and_then(|n| n.parse().ok()).unwrap_or(0);
+            commits.push((lineno, commit));
+        }
+    }
+    commits.sort_unstable();
+    let width = authors.values().map(|a| a.chars().count()).max().unwrap_or(0);
+    Ok(commits
+        .iter()
+        .map(|(_, commit)| {
+            let author = authors.get(commit).copied().unwrap_or_default();
+            format!("{} {author:<width$}", &commit[..8])
+        })
+        .collect())
+}
+
+/// `--stats`: how many lines `text` has and how many are blank, its longest line, and how
+/// many lines share their content or their hash with another, which is what decides whether
+/// addresses are unambiguous.
+fn print_stats(lines: &[&str], text: &str, spec: &LnHashSpec) {
+    let blank = lines.iter().filter(|l| l.trim().is_empty()).count();
+    let lengths: Vec<usize> = lines.iter().map(|l| l.graphemes(true).count()).collect();
+    let longest = lengths.iter().max().map(|&max| (max, lengths.iter().position(|&n| n == max)));
+    let report = hash_collisions_with(text, spec);
+    let covered = |groups: &[LineGroup]| groups.iter().map(|g| g.linenos.len()).sum::<usize>();
+
+    println!("lines         {}", lines.len());
+    println!("blank lines   {blank}");
+    match longest {
+        Some((len, Some(i))) => println!("longest line  {len} characters (line {})", i + 1),
+        _ => println!("longest line  -"),
+    }
+    println!(
+        "duplicates    {} group(s), {} lines",
+        report.duplicates.len(),
+        covered(&report.duplicates)
+    );
+    println!(
+        "collisions    {} group(s), {} lines ({}-digit hashes)",
+        report.collisions.len(),
+        covered(&report.collisions),
+        spec.width
+    );
+}
+
+/// How often `--watch` checks the files for changes.
+const WATCH_INTERVAL: Duration = Duration::from_millis(300);
+
+/// `--watch`: clear the screen and run this lnhashview again with the `view` arguments each
+/// time one of `files` changes, until interrupted. Each run is a separate process, so an
+/// error (such as a range now past the end of the file) shows until the next change.
+fn watch_view(files: &[String], view: &[&String]) -> ! {
+    let exe = env::current_exe().unwrap_or_else(|e| {
+        eprintln!("error: --watch: cannot find this program: {e}");
+        process::exit(1);
+    });
+    // A file that cannot be read counts as empty, so that one replaced by a rename (as
+    // exhash writes files) is picked up once it reappears.
+    let snapshot = || files.iter().map(|f| fs::read(f).unwrap_or_default()).collect::<Vec<_>>();
+    let mut seen = snapshot();
+    loop {
+        print!("\x1b[H\x1b[2J");
+        let _ = io::stdout().flush();
+        if let Err(e) = process::Command::new(&exe).args(view).status() {
+            eprintln!("error: --watch: {e}");
+            process::exit(1);
+        }
+        loop {
+            thread::sleep(WATCH_INTERVAL);
+            let now = snapshot();
+            if now != seen {
+                seen = now;
+                break;
+            }
+        }
+    }
+}
+
+/// Read `file` (or stdin for `-`) as text, in `encoding` if given, exiting on failure.
+fn read_text(file: &str, encoding: Option<&str>) -> String {
+    let read = if file == "-" {
+        let mut buf = Vec::new();
+        io::stdin().read_to_end(&mut buf).map(|_| buf)
+    } else {
+        fs::read(file)
+    };
+    let bytes = match read {
         Ok(b) => b,
         Err(e) => {
-            eprintln!("error: failed to read {file}: {e}");
+            let name = if file == "-" { "stdin" } else { file };
+            eprintln!("error: failed to read {name}: {e}");
             process::exit(1);
         }
     };
 
-    if bytes.iter().any(|&b| b == 0) {
+    if bytes.contains(&0) {
         eprintln!("error: binary file rejected (NUL byte found)");
         process::exit(1);
     }
 
-    let text = match String::from_utf8(bytes) {
-        Ok(s) => s,
-        Err(_) => {
-            eprintln!("error: non-UTF8 file rejected");
-            process::exit(1);
+    match encoding {
+        Some(label) => decode(&bytes, label),
+        None => match String::from_utf8(bytes) {
+            Ok(s) => s,
+            Err(_) => {
+                eprintln!("error: non-UTF8 file rejected (see --encoding)");
+                process::exit(1);
+            }
+        },
+    }
+}
+
+/// `--diff`: print how `new` differs from `old`, lined up by hash, and return whether it
+/// does. Removed lines are printed as `- addr  text` with their address in `old`, added
+/// ones as `+ addr  text` with their address in `new`, and a line removed in one place and
+/// added in another as `~ old-addr -> new-addr  text`. A summary line says how many
+/// unchanged lines were renumbered, since their addresses went stale too.
+fn print_hash_diff(
+    (old_name, old): (&str, &str),
+    (new_name, new): (&str, &str),
+    spec: &LnHashSpec,
+    style: AddrStyle,
+    color: bool,
+    show: Show,
+) -> bool {
+    let old_lines: Vec<&str> = old.lines().collect();
+    let new_lines: Vec<&str> = new.lines().collect();
+    let old_hashes = spec.hash_lines(old_lines.iter().copied());
+    let new_hashes = spec.hash_lines(new_lines.iter().copied());
+    // Lines are compared by their full hashes, so the spec's normalization applies.
+    let keys = |hashes: &[(LnHash, u64)]| -> Vec<String> {
+        hashes.iter().map(|(_, full)| format!("{full:016x}")).collect()
+    };
+    let (old_keys, new_keys) = (keys(&old_hashes), keys(&new_hashes));
+    let old_refs: Vec<&str> = old_keys.iter().map(String::as_str).collect();
+    let new_refs: Vec<&str> = new_keys.iter().map(String::as_str).collect();
+    let ranges = changed_ranges(&old_refs, &new_refs);
+
+    // A removed line whose hash turns up among the added ones was moved.
+    let mut added: Vec<usize> = ranges.iter().flat_map(|(_, b)| b.clone()).collect();
+    let mut moved_to = vec![None; old_lines.len()];
+    for i in ranges.iter().flat_map(|(a, _)| a.clone()) {
+        if let Some(k) = added.iter().position(|&j| new_keys[j] == old_keys[i]) {
+            moved_to[i] = Some(added.remove(k));
         }
+    }
+    let moved_from: BTreeSet<usize> = moved_to.iter().flatten().copied().collect();
+
+    let addr = |hashes: &[(LnHash, u64)], i: usize| paint_addr(color, &hashes[i].0, style);
+    let (mut removed, mut moved) = (0, 0);
+    if !ranges.is_empty() {
+        println!("--- {old_name}\n+++ {new_name}");
+    }
+    for (a, b) in &ranges {
+        for i in a.clone() {
+            match moved_to[i] {
+                Some(j) => {
+                    moved += 1;
+                    let (from, to) = (addr(&old_hashes, i), addr(&new_hashes, j));
+                    let line = format!("~ {from} -> {to}  {}", show.line(old_lines[i]));
+                    println!("{}", paint(color, SEPARATOR_COLOR, &line));
+                }
+                None => {
+                    removed += 1;
+                    let line = format!("- {}  {}", addr(&old_hashes, i), show.line(old_lines[i]));
+                    println!("{}", paint(color, REMOVED_COLOR, &line));
+                }
+            }
+        }
+        for j in b.clone().filter(|j| !moved_from.contains(j)) {
+            let line = format!("+ {}  {}", addr(&new_hashes, j), show.line(new_lines[j]));
+            println!("{}", paint(color, ADDED_COLOR, &line));
+        }
+    }
+    let changed: usize = ranges.iter().map(|(_, b)| b.len()).sum();
+    let unchanged = new_lines.len() - changed;
+    // Unchanged lines pair up in order between the changed runs; a run of them that starts
+    // at a different line in each file was renumbered.
+    let mut renumbered = 0;
+    let (mut i, mut j) = (0, 0);
+    let end = (old_lines.len()..old_lines.len(), new_lines.len()..new_lines.len());
+    for (a, b) in ranges.iter().chain([&end]) {
+        if i != j {
+            renumbered += a.start - i;
+        }
+        (i, j) = (a.end, b.end);
+    }
+    println!(
+        "{removed} removed, {} added, {moved} moved, {unchanged} unchanged \
+         ({renumbered} renumbered)",
+        changed - moved
+    );
+    !ranges.is_empty()
+}
+
+/// The line `--around` names: a line number as given, or for an address, the nearest line
+/// it verifies against (in case the lines have moved since it was printed).
+fn locate(target: &str, lines: &[&str], spec: &LnHashSpec) -> Result<isize, String> {
+    if let Ok(n) = target.parse::<isize>() {
+        return Ok(n);
+    }
+    let addr = spec.parse_lnhash(target).map_err(|e| e.to_string())?;
+    let verifies = |lineno: usize| {
+        let prev = lineno.checked_sub(2).map(|i| lines[i]);
+        spec.verify(&LnHash { lineno, ..addr }, prev, lines[lineno - 1]).is_ok()
     };
+    let mut found = (1..=lines.len()).filter(|&n| verifies(n)).collect::<Vec<_>>();
+    found.sort_by_key(|&n| n.abs_diff(addr.lineno));
+    match found[..] {
+        [] => Err("no line matches it".to_string()),
+        [a, b, ..] if a.abs_diff(addr.lineno) == b.abs_diff(addr.lineno) => Err(format!(
+            "lines {} and {} match it equally well",
+            a.min(b),
+            a.max(b)
+        )),
+        [n, ..] => Ok(n as isize),
+    }
+}
 
-    let lines: Vec<&str> = text.lines().collect();
+/// A piece of a `--format` template.
+enum Piece {
+    Literal(String),
+    Lineno,
+    Hash,
+    Addr,
+    Text,
+    File,
+    Chars,
+    Bytes,
+    Width,
+    Indent,
+}
 
-    if lines.is_empty() {
-        return;
+/// What `--lengths` reports about a line.
+struct LineLengths {
+    /// User-perceived characters (grapheme clusters): `é` is one whether it is written as
+    /// one code point or as `e` and a combining accent.
+    chars: usize,
+    /// UTF-8 bytes.
+    bytes: usize,
+    /// Terminal columns: wide (CJK) characters take two and combining marks none, and a
+    /// tab moves to the next multiple of 8.
+    width: usize,
+    /// Leading whitespace characters.
+    indent: usize,
+}
+
+fn line_lengths(line: &str) -> LineLengths {
+    let graphemes = line.graphemes(true);
+    let width = graphemes.clone().fold(0, |col, g| match g {
+        "\t" => (col / 8 + 1) * 8,
+        g => col + g.width(),
+    });
+    LineLengths {
+        chars: graphemes.count(),
+        bytes: line.len(),
+        width,
+        indent: line.graphemes(true).take_while(|g| g.chars().all(char::is_whitespace)).count(),
+    }
+}
+
+/// Parse a `--format` template into its pieces.
+fn parse_format(template: &str) -> Result<Vec<Piece>, String> {
+    let mut pieces = Vec::new();
+    let mut lit = String::new();
+    let mut chars = template.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' => match chars.next() {
+                Some('t') => lit.push('\t'),
+                Some('n') => lit.push('\n'),
+                Some('\\') => lit.push('\\'),
+                Some(c) => return Err(format!("unknown escape \\{c}")),
+                None => return Err("trailing backslash".to_string()),
+            },
+            '{' if chars.as_str().starts_with('{') => {
+                chars.next();
+                lit.push('{');
+            }
+            '}' if chars.as_str().starts_with('}') => {
+                chars.next();
+                lit.push('}');
+            }
+            '{' => {
+                let rest = chars.as_str();
+                let Some(end) = rest.find('}') else {
+                    return Err("unclosed {".to_string());
+                };
+                let piece = match &rest[..end] {
+                    "lineno" => Piece::Lineno,
+                    "hash" => Piece::Hash,
+                    "addr" => Piece::Addr,
+                    "text" => Piece::Text,
+                    "file" => Piece::File,
+                    "chars" => Piece::Chars,
+                    "bytes" => Piece::Bytes,
+                    "width" => Piece::Width,
+                    "indent" => Piece::Indent,
+                    name => return Err(format!("unknown field {{{name}}}")),
+                };
+                pieces.push(Piece::Literal(std::mem::take(&mut lit)));
+                pieces.push(piece);
+                chars = rest[end + 1..].chars();
+            }
+            '}' => return Err("unmatched } (write }} for a brace)".to_string()),
+            c => lit.push(c),
+        }
+    }
+    pieces.push(Piece::Literal(lit));
+    Ok(pieces)
+}
+
+/// The defaults from the config files, or none with `--no-config`.
+fn load_config(args: &[String]) -> Config {
+    if args.iter().any(|a| a == "--no-config") {
+        return Config::default();
+    }
+    Config::load().unwrap_or_else(|e| {
+        eprintln!("error: {e}");
+        process::exit(2);
+    })
+}
+
+fn main() {
+    let args: Vec<String> = env::args().collect();
+    let config = load_config(&args);
+
+    let mut style = match config.compact {
+        Some(true) => AddrStyle::Compact,
+        _ => AddrStyle::Standard,
+    };
+    let mut spec = config.hash_spec();
+    let mut collisions = false;
+    let mut fingerprint = false;
+    let mut encoding: Option<String> = None;
+    let mut grep: Option<Regex> = None;
+    let mut context: Option<usize> = None;
+    let mut format: Option<Vec<Piece>> = None;
+    let mut color_when: Option<String> = config.color.clone();
+    let mut around: Vec<String> = Vec::new();
+    let mut diff = false;
+    let mut globs: Vec<String> = Vec::new();
+    let mut show = Show::default();
+    let mut max_lines: Option<usize> = None;
+    let mut plain: Option<Plain> = None;
+    let mut hashes_only = false;
+    let mut skip_blank = false;
+    let mut lengths = false;
+    let mut blame_lines = false;
+    let mut stats = false;
+    let mut watch = false;
+    let mut emit_cmd: Option<String> = None;
+    let mut html = false;
+    let mut positional: Vec<String> = Vec::new();
+    let mut idx = 1;
+    while idx < args.len() {
+        match args[idx].as_str() {
+            "--hash-width" => {
+                spec.width = match args.get(idx + 1).map(|s| s.parse::<usize>()) {
+                    Some(Ok(w)) if check_hash_width(w).is_ok() => w,
+                    _ => {
+                        eprintln!("error: --hash-width must be 4, 6 or 8");
+                        process::exit(2);
+                    }
+                };
+                idx += 2;
+            }
+            "--compact" => {
+                style = AddrStyle::Compact;
+                idx += 1;
+            }
+            "--seed" => {
+                let Some(seed) = args.get(idx + 1) else {
+                    eprintln!("error: --seed requires a value");
+                    process::exit(2);
+                };
+                spec.seed = Some(seed.clone()).filter(|s| !s.is_empty());
+                idx += 2;
+            }
+            "--nfc" => {
+                spec.normalization.nfc = true;
+                idx += 1;
+            }
+            "--ignore-case" => {
+                spec.normalization.lowercase = true;
+                idx += 1;
+            }
+            "--chained" => {
+                spec.algorithm = HashAlgorithm::Chained;
+                idx += 1;
+            }
+            "--versioned" => {
+                spec.versioned = true;
+                idx += 1;
+            }
+            "--collisions" => {
+                collisions = true;
+                idx += 1;
+            }
+            "--fingerprint" => {
+                fingerprint = true;
+                idx += 1;
+            }
+            "--encoding" => {
+                let Some(label) = args.get(idx + 1) else {
+                    eprintln!("error: --encoding requires a label");
+                    process::exit(2);
+                };
+                encoding = Some(label.clone());
+                idx += 2;
+            }
+            "--grep" => {
+                let Some(pattern) = args.get(idx + 1) else {
+                    eprintln!("error: --grep requires a pattern");
+                    process::exit(2);
+                };
+                grep = match Regex::new(pattern) {
+                    Ok(re) => Some(re),
+                    Err(e) => {
+                        eprintln!("error: --grep: invalid pattern: {e}");
+                        process::exit(2);
+                    }
+                };
+                idx += 2;
+            }
+            "-C" | "--context" => {
+                context = match args.get(idx + 1).map(|s| s.parse::<usize>()) {
+                    Some(Ok(n)) => Some(n),
+                    _ => {
+                        eprintln!("error: {} expects a number of lines", args[idx]);
+                        process::exit(2);
+                    }
+                };
+                idx += 2;
+            }
+            "--format" => {
+                let Some(template) = args.get(idx + 1) else {
+                    eprintln!("error: --format requires a template");
+                    process::exit(2);
+                };
+                format = match parse_format(template) {
+                    Ok(pieces) => Some(pieces),
+                    Err(e) => {
+                        eprintln!("error: --format: {e}");
+                        process::exit(2);
+                    }
+                };
+                idx += 2;
+            }
+            "--color" => {
+                color_when = args.get(idx + 1).cloned();
+                idx += 2;
+            }
+            s if s.starts_with("--color=") => {
+                color_when = Some(s["--color=".len()..].to_string());
+                idx += 1;
+            }
+            "--around" => {
+                let Some(line) = args.get(idx + 1) else {
+                    eprintln!("error: --around requires a line number or address");
+                    process::exit(2);
+                };
+                around.push(line.clone());
+                idx += 2;
+            }
+            "--diff" => {
+                diff = true;
+                idx += 1;
+            }
+            "--glob" => {
+                let Some(pattern) = args.get(idx + 1) else {
+                    eprintln!("error: --glob requires a pattern");
+                    process::exit(2);
+                };
+                globs.push(pattern.clone());
+                idx += 2;
+            }
+            "-T" | "--show-tabs" => {
+                show.tabs = true;
+                idx += 1;
+            }
+            "-E" | "--show-ends" => {
+                show.ends = true;
+                idx += 1;
+            }
+            "-v" | "--show-nonprinting" => {
+                show.nonprinting = true;
+                idx += 1;
+            }
+            "-A" | "--show-all" => {
+                show = Show { tabs: true, ends: true, nonprinting: true };
+                idx += 1;
+            }
+            "--max-lines" => {
+                max_lines = match args.get(idx + 1).map(|s| s.parse::<usize>()) {
+                    Some(Ok(n)) => Some(n),
+                    _ => {
+                        eprintln!("error: --max-lines expects a number of lines");
+                        process::exit(2);
+                    }
+                };
+                idx += 2;
+            }
+            "--plain" => {
+                plain = Some(Plain::WithHash);
+                idx += 1;
+            }
+            "--plain=nohash" => {
+                plain = Some(Plain::NoHash);
+                idx += 1;
+            }
+            "--hashes-only" => {
+                hashes_only = true;
+                idx += 1;
+            }
+            "--emit-cmd" => {
+                let Some(cmd) = args.get(idx + 1) else {
+                    eprintln!("error: --emit-cmd requires a command, such as 's/old/new/' or d");
+                    process::exit(2);
+                };
+                // Any address will do to check that the rest is a valid command.
+                if let Err(e) = parse_commands_from_strs(&[&format!("1|0000|{cmd}")]) {
+                    eprintln!("error: --emit-cmd: {e}");
+                    process::exit(2);
+                }
+                emit_cmd = Some(cmd.clone());
+                idx += 2;
+            }
+            "--watch" => {
+                watch = true;
+                idx += 1;
+            }
+            "--html" => {
+                html = true;
+                idx += 1;
+            }
+            "--stats" => {
+                stats = true;
+                idx += 1;
+            }
+            "--blame" => {
+                blame_lines = true;
+                idx += 1;
+            }
+            "--lengths" => {
+                lengths = true;
+                idx += 1;
+            }
+            "--skip-blank" => {
+                skip_blank = true;
+                idx += 1;
+            }
+            "--no-config" => idx += 1,
+            "--help" | "-h" => {
+                usage();
+                return;
+            }
+            // A negative line number, not a flag.
+            s if s.len() > 1 && s[1..].bytes().all(|b| b.is_ascii_digit()) => {
+                positional.push(s.to_string());
+                idx += 1;
+            }
+            s if s.starts_with('-') && s.len() > 1 => {
+                eprintln!("error: unknown flag {s}");
+                usage();
+                process::exit(2);
+            }
+            s => {
+                positional.push(s.to_string());
+                idx += 1;
+            }
+        }
     }
 
-    let (start_line, end_line) = match (start, end) {
-        (None, None) => (1, lines.len()),
-        (Some(s), None) => (s, s),
-        (Some(s), Some(e)) => (s, e),
-        (None, Some(_)) => {
-            eprintln!("error: end_line requires start_line");
+    let color = match color_when.as_deref() {
+        None | Some("auto") => io::stdout().is_terminal() && env::var_os("NO_COLOR").is_none(),
+        Some("always") => true,
+        Some("never") => false,
+        Some(_) => {
+            eprintln!("error: --color must be auto, always or never");
             process::exit(2);
         }
     };
+    // A configured cap is for callers reading through a pipe; a terminal has a pager.
+    let max_lines = max_lines
+        .or(config.max_lines.filter(|_| !io::stdout().is_terminal()))
+        .filter(|&n| n > 0);
+    let layouts = [
+        (format.is_some(), "--format"),
+        (plain.is_some(), "--plain"),
+        (emit_cmd.is_some(), "--emit-cmd"),
+        (html, "--html"),
+    ];
+    let mut chosen = layouts.iter().filter(|(set, _)| *set).map(|(_, flag)| flag);
+    if let (Some(first), Some(second)) = (chosen.next(), chosen.next()) {
+        eprintln!("error: {first} cannot be combined with {second}");
+        process::exit(2);
+    }
+    let columns =
+        [("--hashes-only", hashes_only), ("--lengths", lengths), ("--blame", blame_lines)];
+    for (flag, set) in columns {
+        if let Some((_, other)) = layouts.iter().find(|(other, _)| set && *other) {
+            eprintln!("error: {flag} cannot be combined with {other}");
+            process::exit(2);
+        }
+    }
+    if context.is_some() && grep.is_none() && around.is_empty() && !(diff && html) {
+        eprintln!("error: -C/--context only applies to --grep, --around and --diff --html");
+        process::exit(2);
+    }
+    // An HTML page holds one view of the lines; the reports and listings stay text.
+    let reports = [
+        (collisions, "--collisions"),
+        (stats, "--stats"),
+        (fingerprint, "--fingerprint"),
+        (!globs.is_empty(), "--glob"),
+        (watch, "--watch"),
+    ];
+    if let Some((_, flag)) = reports.iter().find(|(set, _)| html && *set) {
+        eprintln!("error: --html cannot be combined with {flag}");
+        process::exit(2);
+    }
+    if !around.is_empty() && grep.is_some() {
+        eprintln!("error: --around cannot be combined with --grep");
+        process::exit(2);
+    }
+    let mut line_format = LineFormat {
+        style,
+        color,
+        hashes_only,
+        lengths,
+        blame: None,
+        pieces: format.as_deref(),
+        plain,
+        grep: grep.as_ref(),
+        show,
+    };
+    // With --skip-blank, blank lines are left out of the output but still numbered.
+    let shown = |line: &str| !(skip_blank && line.trim().is_empty());
 
-    if start_line == 0 {
-        eprintln!("error: start_line is 1-based (must be >= 1)");
+    if !globs.is_empty() {
+        if !positional.is_empty() {
+            eprintln!("error: --glob takes the place of the file and line range");
+            process::exit(2);
+        }
+        let conflicting = [
+            (collisions, "--collisions"),
+            (fingerprint, "--fingerprint"),
+            (diff, "--diff"),
+            (!around.is_empty(), "--around"),
+            (blame_lines, "--blame"),
+            (stats, "--stats"),
+            (watch, "--watch"),
+            (emit_cmd.is_some(), "--emit-cmd"),
+        ];
+        if let Some((_, flag)) = conflicting.iter().find(|(set, _)| *set) {
+            eprintln!("error: --glob cannot be combined with {flag}");
+            process::exit(2);
+        }
+        let mut printed = false;
+        let mut budget = Budget::new(max_lines);
+        for path in glob_files(&globs) {
+            let Some(text) = read_scanned(&path, encoding.as_deref()) else {
+                continue;
+            };
+            let lines: Vec<&str> = text.lines().collect();
+            let addrs = spec.lnhashes(lines.iter().copied());
+            let runs = match &grep {
+                _ if lines.is_empty() => Vec::new(),
+                Some(re) => matching_runs(&lines, (1, lines.len()), re, context.unwrap_or(0)),
+                None => vec![(1..=lines.len()).collect()],
+            };
+            for run in runs {
+                if printed && !budget.is_spent() {
+                    println!("{}", paint(color, SEPARATOR_COLOR, "--"));
+                }
+                printed = true;
+                for n in run.into_iter().filter(|&n| shown(lines[n - 1]) && budget.take(n)) {
+                    println!("{}", line_format.render(&path, true, &addrs[n - 1], lines[n - 1]));
+                }
+            }
+        }
+        if budget.hidden > 0 {
+            println!("{} (narrow --glob or --grep to view them)", budget.more());
+        }
+        process::exit(i32::from(!printed));
+    }
+
+    if positional.is_empty() && !io::stdin().is_terminal() {
+        positional.push("-".to_string());
+    }
+    if positional.is_empty() {
+        usage();
         process::exit(2);
     }
 
-    if end_line < start_line {
-        eprintln!("error: end_line must be >= start_line");
+    if watch {
+        let files = &positional[..positional.len().min(if diff { 2 } else { 1 })];
+        if files.iter().any(|f| f == "-") {
+            eprintln!("error: --watch needs a file to watch, not stdin");
+            process::exit(2);
+        }
+        let view: Vec<&String> = args[1..].iter().filter(|a| *a != "--watch").collect();
+        watch_view(files, &view);
+    }
+
+    if diff {
+        if positional.len() != 2 {
+            eprintln!("error: --diff takes two files, old and new");
+            process::exit(2);
+        }
+        let conflicting = [
+            (collisions, "--collisions"),
+            (fingerprint, "--fingerprint"),
+            (grep.is_some(), "--grep"),
+            (!around.is_empty(), "--around"),
+            (format.is_some(), "--format"),
+            (plain.is_some(), "--plain"),
+            (blame_lines, "--blame"),
+            (stats, "--stats"),
+            (emit_cmd.is_some(), "--emit-cmd"),
+        ];
+        if let Some((_, flag)) = conflicting.iter().find(|(set, _)| *set) {
+            eprintln!("error: --diff cannot be combined with {flag}");
+            process::exit(2);
+        }
+        let old = read_text(&positional[0], encoding.as_deref());
+        let new = read_text(&positional[1], encoding.as_deref());
+        if html {
+            let table = html_diff(&old, &new, &spec, style, context.unwrap_or(3));
+            let title = format!("{} → {}", positional[0], positional[1]);
+            print!("{}", html_page(&title, &table));
+            process::exit(i32::from(!table.is_empty()));
+        }
+        let (old, new) = ((positional[0].as_str(), &old[..]), (positional[1].as_str(), &new[..]));
+        process::exit(i32::from(print_hash_diff(old, new, &spec, style, color, show)));
+    }
+
+    let file = &positional[0];
+    let bounds: Vec<isize> = positional[1..]
+        .iter()
+        .map(|s| {
+            s.parse().unwrap_or_else(|_| {
+                eprintln!("error: line numbers must be integers (got {s:?})");
+                process::exit(2);
+            })
+        })
+        .collect();
+
+    let text = read_text(file, encoding.as_deref());
+
+    let lines: Vec<&str> = text.lines().collect();
+    let addrs = spec.lnhashes(lines.iter().copied());
+
+    let blame_columns = if blame_lines && file == "-" {
+        eprintln!("error: --blame needs a file in a git repository, not stdin");
         process::exit(2);
+    } else if blame_lines {
+        blame(file).unwrap_or_else(|e| {
+            eprintln!("error: --blame: {e}");
+            process::exit(1);
+        })
+    } else {
+        Vec::new()
+    };
+    if blame_lines {
+        line_format.blame = Some(&blame_columns);
+    }
+
+    if fingerprint {
+        println!("@file {}", file_fingerprint(&text));
     }
 
-    if end_line > lines.len() {
-        eprintln!(
-            "error: end_line {end_line} is beyond EOF (file has {} line(s))",
-            lines.len()
+    if stats {
+        let selects = grep.is_some() || !around.is_empty() || !bounds.is_empty();
+        if selects || collisions || emit_cmd.is_some() {
+            eprintln!("error: --stats covers the whole file and takes no range, --around, --grep,");
+            eprintln!("--collisions or --emit-cmd");
+            process::exit(2);
+        }
+        print_stats(&lines, &text, &spec);
+        return;
+    }
+
+    if collisions {
+        let flags = [
+            (grep.is_some(), "--grep"),
+            (plain.is_some(), "--plain"),
+            (blame_lines, "--blame"),
+            (emit_cmd.is_some(), "--emit-cmd"),
+        ];
+        if let Some(flag) = flags.into_iter().find_map(|(set, flag)| set.then_some(flag)) {
+            eprintln!("error: --collisions cannot be combined with {flag}");
+            process::exit(2);
+        }
+        if !bounds.is_empty() {
+            eprintln!("error: --collisions scans the whole file and takes no range");
+            process::exit(2);
+        }
+        let report = hash_collisions_with(&text, &spec);
+        let print_group = |label: &str, g: &LineGroup| {
+            println!("{label} {:0w$x}:", g.hash, w = spec.width);
+            for &n in &g.linenos {
+                let line = show.line(lines[n - 1]);
+                println!("  {}  {line}", paint_addr(color, &addrs[n - 1], style));
+            }
+        };
+        for g in &report.collisions {
+            print_group("collision", g);
+        }
+        for g in &report.duplicates {
+            print_group("duplicate", g);
+        }
+        println!(
+            "{} collision group(s), {} duplicate group(s)",
+            report.collisions.len(),
+            report.duplicates.len()
         );
-        process::exit(2);
+        return;
     }
 
-    for (idx, line) in lines
-        .iter()
-        .enumerate()
-        .skip(start_line - 1)
-        .take(end_line - start_line + 1)
-    {
-        let lineno = idx + 1;
-        let lnh = format_lnhash(lineno, line);
-        println!("{lnh}  {line}");
+    if lines.is_empty() {
+        // Nothing to show, so nothing matches a --grep either.
+        process::exit(i32::from(grep.is_some()));
+    }
+
+    // -1 is the last line; counting back past the first line stops there.
+    let from_end = |n: isize| match usize::try_from(n) {
+        Ok(n) => n,
+        Err(_) => lines.len().saturating_sub(n.unsigned_abs() - 1).max(1),
+    };
+    // The numbers pair up as start/end; one left over is a single line (or a tail).
+    let ranges: Vec<(usize, usize)> = if !around.is_empty() {
+        if !bounds.is_empty() {
+            eprintln!("error: --around takes the place of start_line/end_line");
+            process::exit(2);
+        }
+        let context = context.unwrap_or(3);
+        around
+            .iter()
+            .map(|target| {
+                let n = locate(target, &lines, &spec).unwrap_or_else(|e| {
+                    eprintln!("error: --around {target}: {e}");
+                    process::exit(2);
+                });
+                let n = from_end(n);
+                if n == 0 || n > lines.len() {
+                    let len = lines.len();
+                    eprintln!("error: --around: line {n} is beyond EOF (file has {len} line(s))");
+                    process::exit(2);
+                }
+                (n.saturating_sub(context).max(1), (n + context).min(lines.len()))
+            })
+            .collect()
+    } else if bounds.is_empty() {
+        vec![(1, lines.len())]
+    } else {
+        bounds
+            .chunks(2)
+            .map(|pair| match *pair {
+                [s] if s < 0 => (from_end(s), lines.len()),
+                [s] => (from_end(s), from_end(s)),
+                [s, e] => (from_end(s), from_end(e)),
+                _ => unreachable!("chunks(2) yields one or two numbers"),
+            })
+            .collect()
+    };
+
+    for &(start_line, end_line) in &ranges {
+        if start_line == 0 {
+            eprintln!("error: start_line is 1-based (must be >= 1)");
+            process::exit(2);
+        }
+
+        if end_line < start_line {
+            eprintln!("error: end_line must be >= start_line");
+            process::exit(2);
+        }
+
+        if end_line > lines.len() {
+            eprintln!(
+                "error: end_line {end_line} is beyond EOF (file has {} line(s))",
+                lines.len()
+            );
+            process::exit(2);
+        }
+    }
+
+    // Runs of consecutive lines to print, separated by `--`: each range, or with --grep
+    // each group of matches and their context within a range.
+    let mut groups: Vec<Vec<usize>> = Vec::new();
+    for &range in &ranges {
+        match &grep {
+            Some(re) => groups.extend(matching_runs(&lines, range, re, context.unwrap_or(0))),
+            None => groups.push((range.0..=range.1).collect()),
+        }
+    }
+    if groups.is_empty() {
+        // Only --grep can find nothing to print.
+        process::exit(1);
+    }
+    if let Some(cmd) = &emit_cmd {
+        // Context lines are there to read; the commands are for the lines that matched.
+        let is_target = |n: usize| grep.as_ref().is_none_or(|re| re.is_match(lines[n - 1]));
+        for &n in groups.iter().flatten().filter(|&&n| is_target(n)) {
+            println!("{}{cmd}", addrs[n - 1].format_with(style));
+        }
+        return;
+    }
+    if html {
+        // The page shows every line chosen, as a page is scrolled rather than paged.
+        let linenos: Vec<usize> =
+            groups.iter().flatten().copied().filter(|&n| shown(lines[n - 1])).collect();
+        let matched: Vec<usize> = match &grep {
+            Some(re) => linenos.iter().copied().filter(|&n| re.is_match(lines[n - 1])).collect(),
+            None => Vec::new(),
+        };
+        let table = html_view_lines(&text, &spec, style, &linenos, &matched);
+        print!("{}", html_page(file, &table));
+        return;
+    }
+    let mut budget = Budget::new(max_lines);
+    for (i, group) in groups.iter().enumerate() {
+        if i > 0 && !budget.is_spent() {
+            println!("{}", paint(color, SEPARATOR_COLOR, "--"));
+        }
+        for &n in group.iter().filter(|&&n| shown(lines[n - 1]) && budget.take(n)) {
+            println!("{}", line_format.render(file, false, &addrs[n - 1], lines[n - 1]));
+        }
+    }
+    if let Some(next) = budget.first_hidden {
+        println!("{} (use start_line/end_line to view, from line {next})", budget.more());
     }
 }
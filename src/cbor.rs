@@ -0,0 +1,296 @@
+use ciborium::Value;
+
+use crate::engine::{CommandStep, EditResult, EditStats, LineEnding, Relocation};
+use crate::lnhash::parse_lnhash;
+use crate::parse::{parse_commands_from_strs, Script, Subcommand};
+use crate::{EditError, ErrorKind, HashMismatch};
+
+/// `script` as CBOR ([RFC 8949](https://www.rfc-editor.org/rfc/rfc8949)): a map of its
+/// `file` fingerprint (or null) and its `commands`, each in the syntax it prints in, so
+/// that another process reads it back with [`script_from_cbor`] without the quoting and
+/// escaping of JSON.
+///
+/// A [`Custom`](Subcommand::Custom) transform has no text form to send and is an error.
+pub fn script_to_cbor(script: &Script) -> Result<Vec<u8>, EditError> {
+    let mut commands = Vec::with_capacity(script.commands.len());
+    for (i, cmd) in script.commands.iter().enumerate() {
+        if is_custom(&cmd.cmd) {
+            let msg = "a custom transform cannot be encoded; only parsed commands can";
+            return Err(EditError::new(msg).at_command(i));
+        }
+        commands.push(Value::Text(cmd.to_string()));
+    }
+    let file = script.fingerprint.clone().map_or(Value::Null, Value::Text);
+    Ok(encode(map([("file", file), ("commands", Value::Array(commands))])))
+}
+
+/// The script [`script_to_cbor`] encoded, with its commands parsed as in a script file.
+pub fn script_from_cbor(bytes: &[u8]) -> Result<Script, EditError> {
+    let value = decode(bytes)?;
+    let fingerprint = match field(&value, "file")? {
+        Value::Null => None,
+        v => Some(text(v)?.to_string()),
+    };
+    let commands: Vec<&str> = array(field(&value, "commands")?)?
+        .iter()
+        .map(text)
+        .collect::<Result<_, _>>()?;
+    Ok(Script { fingerprint, commands: parse_commands_from_strs(&commands)? })
+}
+
+/// `result` as CBOR, every field included: a map keyed by the field names, with line
+/// endings as a byte string (`1` for CRLF) and errors and addresses as their messages and
+/// text. [`result_from_cbor`] gives the result back.
+pub fn result_to_cbor(result: &EditResult) -> Vec<u8> {
+    let endings = result.endings.iter().map(|e| u8::from(*e == LineEnding::CrLf)).collect();
+    let steps = result.steps.iter().map(|s| {
+        let changed = (s.changed.iter())
+            .map(|(n, line)| Value::Array(vec![uint(*n), line.as_str().into()]));
+        Value::Array(vec![
+            uint(s.command),
+            Value::Array(changed.collect()),
+            uints(&s.deleted),
+            uint(s.line_count),
+            stats(&s.stats),
+        ])
+    });
+    let relocated = result.relocated.iter().map(|r| {
+        Value::Array(vec![uint(r.command), r.addr.to_string().into(), uint(r.lineno)])
+    });
+    let skipped = result.skipped.iter().map(|e| {
+        let mismatch = e.mismatch().map_or(Value::Null, |m| {
+            let lineno = m.lineno.map_or(Value::Null, uint);
+            Value::Array(vec![lineno, m.expected.as_str().into(), m.actual.as_str().into()])
+        });
+        Value::Array(vec![
+            e.kind().name().into(),
+            e.message().into(),
+            e.command().map_or(Value::Null, uint),
+            mismatch,
+        ])
+    });
+    encode(map([
+        ("lines", Value::Array(result.lines.iter().map(|l| l.as_str().into()).collect())),
+        ("endings", Value::Bytes(endings)),
+        ("hashes", Value::Array(result.hashes.iter().map(|h| h.as_str().into()).collect())),
+        ("full_hashes", Value::Array(result.full_hashes.iter().map(|&h| h.into()).collect())),
+        ("modified", uints(&result.modified)),
+        ("deleted", uints(&result.deleted)),
+        ("steps", Value::Array(steps.collect())),
+        ("relocated", Value::Array(relocated.collect())),
+        ("skipped", Value::Array(skipped.collect())),
+        ("stats", stats(&result.stats)),
+        ("bom", result.bom.into()),
+    ]))
+}
+
+/// The result [`result_to_cbor`] encoded.
+pub fn result_from_cbor(bytes: &[u8]) -> Result<EditResult, EditError> {
+    let value = decode(bytes)?;
+    let strings = |key: &str| -> Result<Vec<String>, EditError> {
+        array(field(&value, key)?)?.iter().map(|v| Ok(text(v)?.to_string())).collect()
+    };
+    let Value::Bytes(endings) = field(&value, "endings")? else {
+        return Err(invalid("endings must be a byte string"));
+    };
+    let endings = endings
+        .iter()
+        .map(|&e| if e == 1 { LineEnding::CrLf } else { LineEnding::Lf })
+        .collect();
+    let full_hashes = array(field(&value, "full_hashes")?)?
+        .iter()
+        .map(|v| v.as_integer().and_then(|i| u64::try_from(i).ok()).ok_or_else(not_uint))
+        .collect::<Result<_, _>>()?;
+    let steps = array(field(&value, "steps")?)?
+        .iter()
+        .map(|v| {
+            let [command, changed, deleted, line_count, step_stats] = fields(v)?;
+            let changed = array(changed)?
+                .iter()
+                .map(|c| {
+                    let [n, line] = fields(c)?;
+                    Ok((to_usize(n)?, text(line)?.to_string()))
+                })
+                .collect::<Result<_, EditError>>()?;
+            Ok(CommandStep {
+                command: to_usize(command)?,
+                changed,
+                deleted: usizes(deleted)?,
+                line_count: to_usize(line_count)?,
+                stats: read_stats(step_stats)?,
+            })
+        })
+        .collect::<Result<_, EditError>>()?;
+    let relocated = array(field(&value, "relocated")?)?
+        .iter()
+        .map(|v| {
+            let [command, addr, lineno] = fields(v)?;
+            let (command, lineno) = (to_usize(command)?, to_usize(lineno)?);
+            Ok(Relocation { command, addr: parse_lnhash(text(addr)?)?, lineno })
+        })
+        .collect::<Result<_, EditError>>()?;
+    let skipped = array(field(&value, "skipped")?)?
+        .iter()
+        .map(read_error)
+        .collect::<Result<_, EditError>>()?;
+    Ok(EditResult {
+        lines: strings("lines")?,
+        endings,
+        hashes: strings("hashes")?,
+        full_hashes,
+        modified: usizes(field(&value, "modified")?)?,
+        deleted: usizes(field(&value, "deleted")?)?,
+        steps,
+        relocated,
+        skipped,
+        stats: read_stats(field(&value, "stats")?)?,
+        bom: field(&value, "bom")?.as_bool().ok_or_else(|| invalid("bom must be a bool"))?,
+    })
+}
+
+/// Whether `cmd` is, or runs, a custom transform.
+fn is_custom(cmd: &Subcommand) -> bool {
+    match cmd {
+        Subcommand::Custom(_) => true,
+        Subcommand::Global { cmd, .. } => is_custom(cmd),
+        _ => false,
+    }
+}
+
+fn encode(value: Value) -> Vec<u8> {
+    let mut out = Vec::new();
+    ciborium::into_writer(&value, &mut out).expect("writing to a Vec cannot fail");
+    out
+}
+
+fn decode(bytes: &[u8]) -> Result<Value, EditError> {
+    ciborium::from_reader(bytes).map_err(|e| invalid(&e.to_string()))
+}
+
+fn map<const N: usize>(entries: [(&str, Value); N]) -> Value {
+    Value::Map(entries.into_iter().map(|(k, v)| (k.into(), v)).collect())
+}
+
+fn uint(n: usize) -> Value {
+    (n as u64).into()
+}
+
+fn uints(ns: &[usize]) -> Value {
+    Value::Array(ns.iter().map(|&n| uint(n)).collect())
+}
+
+fn stats(s: &EditStats) -> Value {
+    Value::Array([s.added, s.changed, s.deleted, s.substitutions].map(uint).to_vec())
+}
+
+fn read_stats(v: &Value) -> Result<EditStats, EditError> {
+    let [added, changed, deleted, substitutions] = fields(v)?;
+    Ok(EditStats {
+        added: to_usize(added)?,
+        changed: to_usize(changed)?,
+        deleted: to_usize(deleted)?,
+        substitutions: to_usize(substitutions)?,
+    })
+}
+
+fn read_error(v: &Value) -> Result<EditError, EditError> {
+    let [kind, msg, command, mismatch] = fields(v)?;
+    let kind = text(kind)?;
+    let kind = [
+        ErrorKind::Other,
+        ErrorKind::Cancelled,
+        ErrorKind::Parse,
+        ErrorKind::StaleHash,
+        ErrorKind::OutOfRange,
+        ErrorKind::ExpectationFailed,
+    ]
+    .into_iter()
+    .find(|k| k.name() == kind)
+    .ok_or_else(|| invalid(&format!("unknown error kind {kind:?}")))?;
+    let mut err = EditError::with_kind(kind, text(msg)?);
+    if !command.is_null() {
+        err = err.at_command(to_usize(command)?);
+    }
+    if !mismatch.is_null() {
+        let [lineno, expected, actual] = fields(mismatch)?;
+        err = err.with_mismatch(HashMismatch {
+            lineno: if lineno.is_null() { None } else { Some(to_usize(lineno)?) },
+            expected: text(expected)?.to_string(),
+            actual: text(actual)?.to_string(),
+        });
+    }
+    Ok(err)
+}
+
+/// The value under `key` in the map `value`.
+fn field<'a>(value: &'a Value, key: &str) -> Result<&'a Value, EditError> {
+    let entries = value.as_map().ok_or_else(|| invalid("expected a map"))?;
+    entries
+        .iter()
+        .find(|(k, _)| k.as_text() == Some(key))
+        .map(|(_, v)| v)
+        .ok_or_else(|| invalid(&format!("no {key:?} field")))
+}
+
+/// The `N` items of the array `value`.
+fn fields<const N: usize>(value: &Value) -> Result<&[Value; N], EditError> {
+    array(value)?.try_into().map_err(|_| invalid(&format!("expected an array of {N} items")))
+}
+
+fn array(value: &Value) -> Result<&[Value], EditError> {
+    value.as_array().map(|a| a.as_slice()).ok_or_else(|| invalid("expected an array"))
+}
+
+fn text(value: &Value) -> Result<&str, EditError> {
+    value.as_text().ok_or_else(|| invalid("expected a string"))
+}
+
+fn to_usize(value: &Value) -> Result<usize, EditError> {
+    value.as_integer().and_then(|i| usize::try_from(i).ok()).ok_or_else(not_uint)
+}
+
+fn usizes(value: &Value) -> Result<Vec<usize>, EditError> {
+    array(value)?.iter().map(to_usize).collect()
+}
+
+fn not_uint() -> EditError {
+    invalid("expected an unsigned integer")
+}
+
+fn invalid(why: &str) -> EditError {
+    EditError::parse(format!("cbor: {why}"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{edit_text_with, parse_script, EditOptions, LnHash};
+
+    #[test]
+    fn scripts_and_results_round_trip() {
+        let text = "a\r\nb\r\nc\r\n";
+        let addr = |n, line| LnHash::of(n, line).to_string();
+        let script = format!(
+            "@file {}\n{}s/b/B\\/\\\\/g\n{}a\\x\\ny\n{}d\n",
+            crate::file_fingerprint(text),
+            addr(2, "b"),
+            addr(3, "c"),
+            addr(9, "z"),
+        );
+        let script = parse_script(&script).unwrap();
+        let bytes = script_to_cbor(&script).unwrap();
+        let decoded = script_from_cbor(&bytes).unwrap();
+        assert_eq!(decoded.to_string(), script.to_string());
+
+        let opts = EditOptions { trace: true, keep_going: true, ..Default::default() };
+        let result = edit_text_with(text, &script.commands, &opts).unwrap();
+        assert_eq!(result.skipped.len(), 1);
+        let bytes = result_to_cbor(&result);
+        assert_eq!(result_from_cbor(&bytes).unwrap(), result);
+
+        let err = result_from_cbor(&bytes[..bytes.len() / 2]).unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::Parse);
+        let err = script_from_cbor(&encode(map([("file", Value::Null)]))).unwrap_err();
+        assert_eq!(err.message(), "cbor: no \"commands\" field");
+    }
+}
@@ -0,0 +1,373 @@
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::lnhash::{check_hash_width, AddrStyle, HashAlgorithm, LnHashSpec};
+use crate::{Command, EditError, EditOptions, Subcommand};
+
+/// Name of the project config file, looked for in the current directory and its parents.
+pub const PROJECT_CONFIG_FILE: &str = ".exhash.toml";
+
+/// Defaults for the `exhash` and `lnhashview` flags, read from config files. Each field is
+/// `None` unless a file sets it; command-line flags override all of them.
+///
+/// Keys are the flag names without their leading dashes (`hash-width = 6`, `nfc = true`,
+/// `backup = ".bak"`, `max-file-size = "10M"`).
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Config {
+    pub hash_width: Option<usize>,
+    pub seed: Option<String>,
+    pub nfc: Option<bool>,
+    pub ignore_case: Option<bool>,
+    pub versioned: Option<bool>,
+    pub chained: Option<bool>,
+    pub compact: Option<bool>,
+    /// Spaces per `>`/`<` level.
+    pub indent_width: Option<usize>,
+    /// Write files without the byte order mark they start with.
+    pub strip_bom: Option<bool>,
+    /// `auto`, `always` or `never`.
+    pub color: Option<String>,
+    pub backup: Option<String>,
+    pub fuzz: Option<usize>,
+    pub max_file_size: Option<u64>,
+    pub max_commands: Option<usize>,
+    pub max_block_size: Option<u64>,
+    /// The `lnhashview --max-lines` to use when stdout is not a terminal.
+    pub max_lines: Option<usize>,
+}
+
+impl Config {
+    /// Parse a config file. It is the `key = value` subset of TOML: values are strings in
+    /// double or single quotes, integers, or `true`/`false`, and `#` starts a comment.
+    /// Unknown keys are an error, so a misspelt setting is not silently ignored.
+    pub fn parse(text: &str) -> Result<Self, EditError> {
+        let mut config = Config::default();
+        for (n, line) in text.lines().enumerate() {
+            let line = strip_comment(line).trim();
+            if line.is_empty() {
+                continue;
+            }
+            config
+                .set_line(line)
+                .map_err(|e| EditError::parse(format!("line {}: {e}", n + 1)))?;
+        }
+        Ok(config)
+    }
+
+    fn set_line(&mut self, line: &str) -> Result<(), String> {
+        if line.starts_with('[') {
+            return Err(format!("tables are not supported: {line}"));
+        }
+        let (key, value) =
+            line.split_once('=').ok_or_else(|| format!("expected key = value: {line}"))?;
+        let (key, value) = (key.trim(), Value::parse(value.trim())?);
+        match key {
+            "hash-width" => {
+                let w = value.usize(key)?;
+                check_hash_width(w).map_err(|e| e.to_string())?;
+                self.hash_width = Some(w);
+            }
+            "seed" => self.seed = Some(value.string(key)?).filter(|s| !s.is_empty()),
+            "nfc" => self.nfc = Some(value.bool(key)?),
+            "ignore-case" => self.ignore_case = Some(value.bool(key)?),
+            "versioned" => self.versioned = Some(value.bool(key)?),
+            "chained" => self.chained = Some(value.bool(key)?),
+            "compact" => self.compact = Some(value.bool(key)?),
+            "indent-width" => match value.usize(key)? {
+                0 => return Err("indent-width must be at least 1".to_string()),
+                w => self.indent_width = Some(w),
+            },
+            "strip-bom" => self.strip_bom = Some(value.bool(key)?),
+            "color" => {
+                let when = value.string(key)?;
+                if !matches!(when.as_str(), "auto" | "always" | "never") {
+                    return Err("color must be auto, always or never".to_string());
+                }
+                self.color = Some(when);
+            }
+            "backup" => self.backup = Some(value.string(key)?),
+            "fuzz" => self.fuzz = Some(value.usize(key)?),
+            "max-file-size" => self.max_file_size = Some(value.size(key)?),
+            "max-commands" => self.max_commands = Some(value.usize(key)?),
+            "max-block-size" => self.max_block_size = Some(value.size(key)?),
+            "max-lines" => self.max_lines = Some(value.usize(key)?),
+            _ => return Err(format!("unknown setting {key:?}")),
+        }
+        Ok(())
+    }
+
+    /// Read and merge the config files that apply here: the user's
+    /// (`$XDG_CONFIG_HOME/exhash/config.toml`, by default under `~/.config`), then the
+    /// nearest [`PROJECT_CONFIG_FILE`] from the current directory up, whose settings win.
+    /// Missing files are skipped.
+    pub fn load() -> Result<Self, EditError> {
+        let mut config = Config::default();
+        for path in config_paths() {
+            let text = match fs::read_to_string(&path) {
+                Ok(t) => t,
+                Err(e) if e.kind() == std::io::ErrorKind::NotFound => continue,
+                Err(e) => return Err(EditError::new(format!("{}: {e}", path.display()))),
+            };
+            let file = Config::parse(&text)
+                .map_err(|e| EditError::parse(format!("{}: {e}", path.display())))?;
+            config.merge(file);
+        }
+        Ok(config)
+    }
+
+    /// Take every setting `other` has, keeping ours where it has none.
+    pub fn merge(&mut self, other: Config) {
+        macro_rules! take {
+            ($($field:ident),*) => {
+                $(if other.$field.is_some() { self.$field = other.$field; })*
+            };
+        }
+        take!(
+            hash_width, seed, nfc, ignore_case, versioned, chained, compact, indent_width,
+            strip_bom, color, backup, fuzz, max_file_size, max_commands, max_block_size,
+            max_lines
+        );
+    }
+
+    /// The hash options set here on top of the defaults, with a seed from
+    /// [`SEED_ENV_VAR`](crate::SEED_ENV_VAR) taking precedence over a configured one.
+    pub fn hash_spec(&self) -> LnHashSpec {
+        let mut spec = LnHashSpec::from_env();
+        if spec.seed.is_none() {
+            spec.seed = self.seed.clone();
+        }
+        if let Some(w) = self.hash_width {
+            spec.width = w;
+        }
+        spec.normalization.nfc = self.nfc.unwrap_or(false);
+        spec.normalization.lowercase = self.ignore_case.unwrap_or(false);
+        spec.versioned = self.versioned.unwrap_or(false);
+        if self.chained == Some(true) {
+            spec.algorithm = HashAlgorithm::Chained;
+        }
+        spec
+    }
+
+    /// The edit options set here on top of the defaults: the [`hash_spec`](Self::hash_spec),
+    /// `compact` addresses, `fuzz`, `indent-width` and `strip-bom`.
+    pub fn edit_options(&self) -> EditOptions {
+        EditOptions {
+            hash_spec: self.hash_spec(),
+            addr_style: match self.compact {
+                Some(true) => AddrStyle::Compact,
+                _ => AddrStyle::Standard,
+            },
+            fuzz: self.fuzz.unwrap_or(0),
+            indent_width: self.indent_width.unwrap_or(4),
+            strip_bom: self.strip_bom.unwrap_or(false),
+            ..Default::default()
+        }
+    }
+}
+
+/// The config files [`Config::load`] reads, in order; they need not exist.
+pub fn config_paths() -> Vec<PathBuf> {
+    let mut paths = Vec::new();
+    let user_dir = env::var_os("XDG_CONFIG_HOME")
+        .filter(|d| !d.is_empty())
+        .map(PathBuf::from)
+        .or_else(|| env::var_os("HOME").map(|h| Path::new(&h).join(".config")));
+    if let Some(dir) = user_dir {
+        paths.push(dir.join("exhash").join("config.toml"));
+    }
+    if let Ok(cwd) = env::current_dir() {
+        let mut candidates = cwd.ancestors().map(|d| d.join(PROJECT_CONFIG_FILE));
+        if let Some(found) = candidates.find(|p| p.is_file()) {
+            paths.push(found);
+        }
+    }
+    paths
+}
+
+/// Fail if there are more `commands` than `max_commands`, or one of their text blocks (`a`,
+/// `i` or `c`, also under a global) is over `max_block_size` bytes, counting a newline per
+/// line. These are the `max-commands` and `max-block-size` limits.
+pub fn check_limits(
+    commands: &[Command],
+    max_commands: Option<usize>,
+    max_block_size: Option<u64>,
+) -> Result<(), EditError> {
+    if let Some(max) = max_commands.filter(|&max| commands.len() > max) {
+        let msg = format!("{} commands, over max-commands {max}", commands.len());
+        return Err(EditError::new(msg));
+    }
+    let Some(max) = max_block_size else { return Ok(()) };
+    for (k, c) in commands.iter().enumerate() {
+        let mut sub = &c.cmd;
+        while let Subcommand::Global { cmd, .. } = sub {
+            sub = cmd;
+        }
+        let text = match sub {
+            Subcommand::Append(text) | Subcommand::Insert(text) => text,
+            Subcommand::Change(text) => text,
+            _ => continue,
+        };
+        let size: u64 = text.iter().map(|l| l.len() as u64 + 1).sum();
+        if size > max {
+            return Err(EditError::new(format!(
+                "command {} ({}): text block is {size} bytes, over max-block-size {max}",
+                k + 1,
+                c.addr1
+            ))
+            .at_command(k));
+        }
+    }
+    Ok(())
+}
+
+/// Parse a byte count such as `4096`, `64K` or `10M` (powers of 1024).
+pub fn parse_size(s: &str) -> Option<u64> {
+    let (digits, shift) = match s.char_indices().last()? {
+        (i, 'K' | 'k') => (&s[..i], 10),
+        (i, 'M' | 'm') => (&s[..i], 20),
+        (i, 'G' | 'g') => (&s[..i], 30),
+        _ => (s, 0),
+    };
+    digits.parse::<u64>().ok()?.checked_mul(1 << shift)
+}
+
+/// `line` up to a `#` that is not inside a string.
+fn strip_comment(line: &str) -> &str {
+    let mut quote = None;
+    let mut escaped = false;
+    for (i, c) in line.char_indices() {
+        match (quote, c) {
+            (Some('"'), '\\') if !escaped => {
+                escaped = true;
+                continue;
+            }
+            (Some(q), c) if c == q && !escaped => quote = None,
+            (None, '"' | '\'') => quote = Some(c),
+            (None, '#') => return &line[..i],
+            _ => {}
+        }
+        escaped = false;
+    }
+    line
+}
+
+enum Value {
+    Str(String),
+    Int(u64),
+    Bool(bool),
+}
+
+impl Value {
+    fn parse(s: &str) -> Result<Self, String> {
+        if let Some(body) = s.strip_prefix('\'') {
+            return match body.strip_suffix('\'') {
+                Some(body) if !body.contains('\'') => Ok(Value::Str(body.to_string())),
+                _ => Err(format!("bad string: {s}")),
+            };
+        }
+        if let Some(body) = s.strip_prefix('"') {
+            let mut out = String::new();
+            let mut chars = body.chars();
+            while let Some(c) = chars.next() {
+                match c {
+                    '"' if chars.as_str().is_empty() => return Ok(Value::Str(out)),
+                    '"' => break,
+                    '\\' => out.push(match chars.next() {
+                        Some('n') => '\n',
+                        Some('t') => '\t',
+                        Some(c @ ('"' | '\\')) => c,
+                        _ => return Err(format!("bad escape in string: {s}")),
+                    }),
+                    c => out.push(c),
+                }
+            }
+            return Err(format!("bad string: {s}"));
+        }
+        match s {
+            "true" => Ok(Value::Bool(true)),
+            "false" => Ok(Value::Bool(false)),
+            _ => s
+                .replace('_', "")
+                .parse()
+                .map(Value::Int)
+                .map_err(|_| format!("expected a string, integer or boolean: {s}")),
+        }
+    }
+
+    fn string(self, key: &str) -> Result<String, String> {
+        match self {
+            Value::Str(s) => Ok(s),
+            _ => Err(format!("{key} must be a string")),
+        }
+    }
+
+    fn bool(self, key: &str) -> Result<bool, String> {
+        match self {
+            Value::Bool(b) => Ok(b),
+            _ => Err(format!("{key} must be true or false")),
+        }
+    }
+
+    fn usize(self, key: &str) -> Result<usize, String> {
+        match self {
+            Value::Int(n) => usize::try_from(n).map_err(|_| format!("{key} is too large")),
+            _ => Err(format!("{key} must be an integer")),
+        }
+    }
+
+    /// A byte count, as an integer or a string with a K, M or G suffix.
+    fn size(self, key: &str) -> Result<u64, String> {
+        match self {
+            Value::Int(n) => Ok(n),
+            Value::Str(s) => parse_size(&s).ok_or_else(|| format!("{key}: invalid size {s:?}")),
+            Value::Bool(_) => Err(format!("{key} must be a size such as 4096 or \"10M\"")),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_settings_and_merge() {
+        let text = "# defaults\nhash-width = 6\nnfc = true  # comment\nbackup = \".b#k\"\n\
+                    max-file-size = \"2K\"\nindent-width = 2\nstrip-bom = true\n";
+        let mut config = Config::parse(text).unwrap();
+        assert_eq!(config.hash_width, Some(6));
+        assert_eq!(config.nfc, Some(true));
+        assert_eq!(config.backup.as_deref(), Some(".b#k"));
+        assert_eq!(config.max_file_size, Some(2048));
+        assert_eq!(config.indent_width, Some(2));
+        assert_eq!(config.strip_bom, Some(true));
+
+        config.merge(Config::parse("hash-width = 8\nfuzz = 3\n").unwrap());
+        assert_eq!((config.hash_width, config.fuzz, config.nfc), (Some(8), Some(3), Some(true)));
+        let spec = config.hash_spec();
+        assert_eq!((spec.width, spec.normalization.nfc), (8, true));
+        let opts = config.edit_options();
+        assert_eq!((opts.hash_spec.width, opts.fuzz, opts.indent_width), (8, 3, 2));
+        assert!(opts.strip_bom && opts.addr_style == AddrStyle::Standard);
+
+        assert!(Config::parse("hash-width = 5").is_err());
+        assert!(Config::parse("colour = \"always\"").is_err());
+        assert!(Config::parse("nfc = \"yes\"").is_err());
+        assert!(Config::parse("[exhash]").is_err());
+        let err = Config::parse("\nseed = \"unterminated").unwrap_err();
+        assert!(err.message().starts_with("line 2:"), "{err}");
+    }
+
+    #[test]
+    fn check_limits_counts_commands_and_blocks() {
+        let a = crate::LnHash::of(1, "a");
+        let script = format!("{a}d\n{a},{a}g/a/a\nxyz\n.\n");
+        let cmds = crate::parse_script(&script).unwrap().commands;
+        assert!(check_limits(&cmds, Some(2), Some(4)).is_ok());
+        let err = check_limits(&cmds, Some(1), None).unwrap_err();
+        assert_eq!(err.to_string(), "2 commands, over max-commands 1");
+        let err = check_limits(&cmds, None, Some(3)).unwrap_err();
+        assert_eq!(err.command(), Some(1));
+        assert!(err.to_string().contains("text block is 4 bytes"), "{err}");
+    }
+}
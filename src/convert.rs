@@ -0,0 +1,1096 @@
+use std::iter;
+use std::ops::Range;
+
+use regex::{Regex, RegexBuilder};
+
+use crate::diff::replace_commands;
+use crate::engine::{edit_text_with, EditOptions};
+use crate::lnhash::{LnHash, LnHashSpec};
+use crate::parse::{Command, Subcommand, Subst};
+use crate::EditError;
+
+/// The language of a script for [`convert_script`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ScriptDialect {
+    /// A `sed` script, with POSIX basic regexes (plus GNU's `\+`, `\?` and `\|`) and both
+    /// the `a\` and the one-line `a text` forms of `a`, `i` and `c`.
+    Sed,
+    /// An `ed` script, as `ed -s file < script` runs it.
+    Ed,
+}
+
+impl ScriptDialect {
+    fn name(self) -> &'static str {
+        match self {
+            ScriptDialect::Sed => "sed",
+            ScriptDialect::Ed => "ed",
+        }
+    }
+}
+
+/// Translate a `sed` or `ed` script into exhash commands making the same change to
+/// `text`, addressed with `spec`'s hashes of the lines they act on.
+///
+/// The script is run against `text` as it is translated: each of its commands becomes
+/// exhash commands for the lines it selects there (one per run of lines, or per line for
+/// sed's `a` and `i`), each addressed against the text as the commands before it leave
+/// it. The result applies in order, e.g. with `exhash -f`, and fails on a stale hash
+/// rather than editing other lines once `text` has changed.
+///
+/// Only the editing commands translate: sed's `s`, `d`, `a`, `i` and `c`, with line, `$`
+/// and regex addresses, ranges and `!`; and ed's `a`, `i`, `c`, `d`, `s`, `m`, `t`, `j`,
+/// and `g`/`v` running `d` or `s`, with ed's `.`, `$`, `/re/`, `?re?` and offset
+/// addresses. ed's printing, writing and quitting commands are skipped. Anything else
+/// (sed's hold space, branches and blocks, a backreference in a regex, ...) is a
+/// [`Parse`](crate::ErrorKind::Parse) error naming the script line.
+pub fn convert_script(
+    dialect: ScriptDialect,
+    script: &str,
+    text: &str,
+    spec: &LnHashSpec,
+) -> Result<Vec<Command>, EditError> {
+    let mut conv = Converter {
+        opts: EditOptions { hash_spec: spec.clone(), ..Default::default() },
+        text: text.to_string(),
+        lines: text.lines().map(str::to_string).collect(),
+        commands: Vec::new(),
+    };
+    match dialect {
+        ScriptDialect::Sed => run_sed(&mut conv, &parse_sed(script)?)?,
+        ScriptDialect::Ed => run_ed(&mut conv, &parse_ed(script)?)?,
+    }
+    Ok(conv.commands)
+}
+
+/// A script error at `line` (1-based) of the script.
+fn script_error(dialect: ScriptDialect, line: usize, msg: impl std::fmt::Display) -> EditError {
+    EditError::parse(format!("{}: line {line}: {msg}", dialect.name()))
+}
+
+/// The text being converted, as the commands made so far leave it.
+struct Converter {
+    opts: EditOptions,
+    text: String,
+    lines: Vec<String>,
+    commands: Vec<Command>,
+}
+
+impl Converter {
+    fn addrs(&self) -> Vec<LnHash> {
+        self.opts.hash_spec.lnhashes(self.lines.iter().map(|l| l.as_str()))
+    }
+
+    /// Apply `cmd` to the text and add it to the script.
+    fn apply(&mut self, cmd: Command) -> Result<(), EditError> {
+        let result = edit_text_with(&self.text, std::slice::from_ref(&cmd), &self.opts)?;
+        self.text = result.text();
+        self.lines = result.lines;
+        self.commands.push(cmd);
+        Ok(())
+    }
+
+    /// Replace the lines in `range` (0-based; empty to insert before `range.start`) with
+    /// `text`, as an `a`, `c` or `d`.
+    fn replace(&mut self, range: Range<usize>, text: Vec<String>) -> Result<(), EditError> {
+        if range.is_empty() && text.is_empty() {
+            return Ok(());
+        }
+        let mut cmds = replace_commands(&self.addrs(), iter::once((range, text)));
+        self.apply(cmds.remove(0))
+    }
+
+    /// Run `s` on those of the lines at `indices` (in order) that it matches: one command
+    /// per run of them, bottom up, so that every line it changes is verified by hash.
+    fn substitute(&mut self, indices: Vec<usize>, s: &Subst) -> Result<(), EditError> {
+        let re = RegexBuilder::new(&s.pattern)
+            .case_insensitive(s.case_insensitive)
+            .build()
+            .map_err(|e| EditError::parse(format!("invalid regex: {e}")))?;
+        let matching: Vec<Selected> = indices
+            .into_iter()
+            .filter(|&index| re.is_match(&self.lines[index]))
+            .map(|index| Selected { index, range: None, last: false })
+            .collect();
+        for run in runs(&matching, false).into_iter().rev() {
+            self.run(run, Subcommand::Substitute(s.clone()))?;
+        }
+        Ok(())
+    }
+
+    /// Run `cmd` on the lines in `range` (0-based, not empty).
+    fn run(&mut self, range: Range<usize>, cmd: Subcommand) -> Result<(), EditError> {
+        let addrs = self.addrs();
+        let addr2 = (range.len() > 1).then(|| addrs[range.end - 1]);
+        self.apply(Command { addr1: addrs[range.start], has_comma: addr2.is_some(), addr2, cmd })
+    }
+}
+
+/// A POSIX basic regex, as sed and ed take them (with GNU's `\+`, `\?`, `\|`, `\<` and
+/// `\>`), in the syntax of the `regex` crate.
+fn translate_regex(bre: &str) -> Result<String, String> {
+    let chars: Vec<char> = bre.chars().collect();
+    let mut out = String::new();
+    // Where `*` is literal and `^` is an anchor: at the start of the regex or a group.
+    let mut at_start = true;
+    let mut i = 0;
+    while i < chars.len() {
+        let c = chars[i];
+        i += 1;
+        let start = std::mem::replace(&mut at_start, false);
+        match c {
+            '\\' => {
+                let Some(&e) = chars.get(i) else {
+                    return Err("trailing backslash in regex".to_string());
+                };
+                i += 1;
+                match e {
+                    '(' | '|' => {
+                        out.push(e);
+                        at_start = true;
+                    }
+                    ')' | '{' | '}' | '+' | '?' => out.push(e),
+                    '<' | '>' => out.push_str("\\b"),
+                    '1'..='9' => return Err(format!("backreference \\{e} in regex")),
+                    'n' | 't' | 'w' | 'W' | 's' | 'S' | 'b' | 'B' => {
+                        out.push('\\');
+                        out.push(e);
+                    }
+                    _ => out.push_str(&regex::escape(&e.to_string())),
+                }
+            }
+            '^' if start => {
+                out.push('^');
+                at_start = true;
+            }
+            '*' if start => out.push_str("\\*"),
+            '$' => {
+                let rest: String = chars[i..].iter().collect();
+                let anchor = rest.is_empty() || rest.starts_with("\\)") || rest.starts_with("\\|");
+                out.push_str(if anchor { "$" } else { "\\$" });
+            }
+            '.' | '*' => out.push(c),
+            '[' => i = translate_bracket(&chars, i, &mut out)?,
+            _ => out.push_str(&regex::escape(&c.to_string())),
+        }
+    }
+    Ok(out)
+}
+
+/// Copy the bracket expression whose `[` is just before `chars[i]` into `out`, returning
+/// the index after its `]`. Backslashes are literal in POSIX brackets but not in Rust's.
+fn translate_bracket(chars: &[char], mut i: usize, out: &mut String) -> Result<usize, String> {
+    out.push('[');
+    if chars.get(i) == Some(&'^') {
+        out.push('^');
+        i += 1;
+    }
+    // A `]` first is part of the set.
+    if chars.get(i) == Some(&']') {
+        out.push_str("\\]");
+        i += 1;
+    }
+    while let Some(&c) = chars.get(i) {
+        i += 1;
+        match c {
+            ']' => {
+                out.push(']');
+                return Ok(i);
+            }
+            // A character class such as `[:alpha:]` is copied whole.
+            '[' if chars.get(i) == Some(&':') => {
+                let end = (i + 1..chars.len().saturating_sub(1))
+                    .find(|&j| chars[j] == ':' && chars[j + 1] == ']')
+                    .ok_or("unterminated character class in regex")?;
+                out.push('[');
+                out.extend(&chars[i..end + 2]);
+                i = end + 2;
+            }
+            '\\' | '[' | '&' | '~' => {
+                out.push('\\');
+                out.push(c);
+            }
+            _ => out.push(c),
+        }
+    }
+    Err("unterminated [ in regex".to_string())
+}
+
+/// A sed or ed replacement (`&` for the match, `\1` for a group) in the syntax of the
+/// `regex` crate.
+fn translate_replacement(rep: &str) -> Result<String, String> {
+    let mut out = String::new();
+    let mut chars = rep.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' => match chars.next() {
+                Some(d @ '0'..='9') => out.push_str(&format!("${{{d}}}")),
+                Some('n' | '\n') => return Err("a newline in a replacement".to_string()),
+                Some('$') => out.push_str("$$"),
+                Some(c) => out.push(c),
+                None => return Err("trailing backslash in replacement".to_string()),
+            },
+            '&' => out.push_str("${0}"),
+            '$' => out.push_str("$$"),
+            _ => out.push(c),
+        }
+    }
+    Ok(out)
+}
+
+/// The script being parsed, with the position of the next character.
+struct Scanner {
+    chars: Vec<char>,
+    pos: usize,
+}
+
+impl Scanner {
+    fn new(s: &str) -> Self {
+        Self { chars: s.chars().collect(), pos: 0 }
+    }
+
+    fn peek(&self) -> Option<char> {
+        self.chars.get(self.pos).copied()
+    }
+
+    fn next(&mut self) -> Option<char> {
+        let c = self.peek()?;
+        self.pos += 1;
+        Some(c)
+    }
+
+    fn eat(&mut self, c: char) -> bool {
+        let found = self.peek() == Some(c);
+        self.pos += found as usize;
+        found
+    }
+
+    fn skip_blanks(&mut self) {
+        while matches!(self.peek(), Some(' ' | '\t')) {
+            self.pos += 1;
+        }
+    }
+
+    /// The 1-based script line of the next character.
+    fn line(&self) -> usize {
+        1 + self.chars[..self.pos.min(self.chars.len())].iter().filter(|&&c| c == '\n').count()
+    }
+
+    fn number(&mut self) -> Option<usize> {
+        let start = self.pos;
+        while self.peek().is_some_and(|c| c.is_ascii_digit()) {
+            self.pos += 1;
+        }
+        self.chars[start..self.pos].iter().collect::<String>().parse().ok()
+    }
+
+    /// The text up to the next unescaped `delim`, which is consumed; `\delim` stands for
+    /// `delim`, and other escapes are kept for the regex or replacement.
+    fn delimited(&mut self, delim: char) -> Option<String> {
+        let mut out = String::new();
+        loop {
+            match self.next()? {
+                '\n' => return None,
+                '\\' => match self.next()? {
+                    c if c == delim => out.push(c),
+                    c => {
+                        out.push('\\');
+                        out.push(c);
+                    }
+                },
+                c if c == delim => return Some(out),
+                c => out.push(c),
+            }
+        }
+    }
+
+    fn rest_of_line(&mut self) -> String {
+        let mut out = String::new();
+        while let Some(c) = self.next() {
+            if c == '\n' {
+                break;
+            }
+            out.push(c);
+        }
+        out
+    }
+}
+
+/// A regex from a script, translated, with `//` standing for the last one.
+fn script_regex(
+    raw: &str,
+    last: &mut Option<String>,
+    case_insensitive: bool,
+) -> Result<String, String> {
+    let re = if raw.is_empty() {
+        last.clone().ok_or("an empty regex with no regex before it")?
+    } else {
+        let re = translate_regex(raw)?;
+        let re = if case_insensitive { format!("(?i){re}") } else { re };
+        Regex::new(&re).map_err(|e| format!("invalid regex: {e}"))?;
+        re
+    };
+    *last = Some(re.clone());
+    Ok(re)
+}
+
+/// The flags after an `s` command's replacement: `g`, and with `case_flag`, `i`/`I`.
+/// ed's print flags are skipped.
+fn subst_flags(sc: &mut Scanner, dialect: ScriptDialect) -> Result<(bool, bool), String> {
+    let (mut global, mut case_insensitive) = (false, false);
+    while let Some(c) = sc.peek() {
+        match (c, dialect) {
+            ('g', _) => global = true,
+            ('i' | 'I', ScriptDialect::Sed) => case_insensitive = true,
+            ('p' | 'l' | 'n', ScriptDialect::Ed) => {}
+            (c, _) if c.is_ascii_alphanumeric() => {
+                return Err(format!("s flag {c:?} has no exhash equivalent"));
+            }
+            _ => break,
+        }
+        sc.pos += 1;
+    }
+    Ok((global, case_insensitive))
+}
+
+/// An `s` command after its `s`: the delimiter, regex, replacement and flags.
+fn parse_subst(
+    sc: &mut Scanner,
+    dialect: ScriptDialect,
+    last_regex: &mut Option<String>,
+) -> Result<Subst, String> {
+    let delim = match sc.next() {
+        Some(c) if c != '\n' && c != '\\' && c != ' ' => c,
+        _ => return Err("s needs a delimiter, as in s/old/new/".to_string()),
+    };
+    let unterminated = || format!("unterminated s{delim}...{delim}...{delim}");
+    let pattern = sc.delimited(delim).ok_or_else(unterminated)?;
+    let replacement = sc.delimited(delim).ok_or_else(unterminated)?;
+    let (global, case_insensitive) = subst_flags(sc, dialect)?;
+    Ok(Subst {
+        pattern: script_regex(&pattern, last_regex, false)?,
+        replacement: translate_replacement(&replacement)?,
+        global,
+        case_insensitive,
+        columns: None,
+    })
+}
+
+// ---- sed ----
+
+#[derive(Debug, Clone)]
+enum SedAddr {
+    Line(usize),
+    Last,
+    Regex(Regex),
+}
+
+#[derive(Debug, Clone)]
+enum SedAction {
+    Subst(Subst),
+    Delete,
+    Append(Vec<String>),
+    Insert(Vec<String>),
+    Change(Vec<String>),
+}
+
+#[derive(Debug, Clone)]
+struct SedCommand {
+    addr1: Option<SedAddr>,
+    addr2: Option<SedAddr>,
+    negate: bool,
+    action: SedAction,
+}
+
+fn parse_sed(script: &str) -> Result<Vec<SedCommand>, EditError> {
+    let mut sc = Scanner::new(script);
+    let mut last_regex = None;
+    let mut out = Vec::new();
+    loop {
+        while matches!(sc.peek(), Some(' ' | '\t' | '\n' | ';')) {
+            sc.pos += 1;
+        }
+        if sc.eat('#') {
+            sc.rest_of_line();
+            continue;
+        }
+        if sc.peek().is_none() {
+            return Ok(out);
+        }
+        let line = sc.line();
+        let err = |msg| script_error(ScriptDialect::Sed, line, msg);
+        let addr1 = parse_sed_addr(&mut sc, &mut last_regex).map_err(err)?;
+        let mut addr2 = None;
+        if addr1.is_some() && sc.eat(',') {
+            sc.skip_blanks();
+            addr2 = parse_sed_addr(&mut sc, &mut last_regex).map_err(err)?;
+            if addr2.is_none() {
+                return Err(err("expected an address after ','".to_string()));
+            }
+        }
+        sc.skip_blanks();
+        let mut negate = false;
+        while sc.eat('!') {
+            negate = true;
+            sc.skip_blanks();
+        }
+        let action = match sc.next() {
+            Some('s') => SedAction::Subst(
+                parse_subst(&mut sc, ScriptDialect::Sed, &mut last_regex).map_err(err)?,
+            ),
+            Some('d') => SedAction::Delete,
+            Some('a') => SedAction::Append(sed_text(&mut sc)),
+            Some('i') => SedAction::Insert(sed_text(&mut sc)),
+            Some('c') => SedAction::Change(sed_text(&mut sc)),
+            Some(c) if c != '\n' && c != ';' => {
+                return Err(err(format!("command {c:?} has no exhash equivalent")))
+            }
+            _ => return Err(err("missing command".to_string())),
+        };
+        sc.skip_blanks();
+        if !matches!(sc.peek(), None | Some('\n' | ';' | '#')) {
+            return Err(err(format!("unexpected {:?} after the command", sc.peek().unwrap())));
+        }
+        out.push(SedCommand { addr1, addr2, negate, action });
+    }
+}
+
+fn parse_sed_addr(
+    sc: &mut Scanner,
+    last_regex: &mut Option<String>,
+) -> Result<Option<SedAddr>, String> {
+    let delim = match sc.peek() {
+        Some(c) if c.is_ascii_digit() => {
+            return match sc.number() {
+                Some(0) | None => Err("line 0 is not an address".to_string()),
+                Some(n) => Ok(Some(SedAddr::Line(n))),
+            };
+        }
+        Some('$') => {
+            sc.pos += 1;
+            return Ok(Some(SedAddr::Last));
+        }
+        Some('+' | '~') => return Err("addr,+N and addr,~N have no exhash equivalent".into()),
+        Some('/') => '/',
+        Some('\\') => {
+            sc.pos += 1;
+            sc.next().ok_or("unterminated regex address")?
+        }
+        _ => return Ok(None),
+    };
+    sc.pos += 1;
+    let raw = sc.delimited(delim).ok_or("unterminated regex address")?;
+    let case_insensitive = sc.eat('I');
+    let re = script_regex(&raw, last_regex, case_insensitive)?;
+    Ok(Some(SedAddr::Regex(Regex::new(&re).map_err(|e| format!("invalid regex: {e}"))?)))
+}
+
+/// The text of an `a`, `i` or `c`: after `a\` and a newline, or on the same line after
+/// `a`; a line ending in `\` continues it, and other backslashes escape the next character.
+fn sed_text(sc: &mut Scanner) -> Vec<String> {
+    sc.skip_blanks();
+    if sc.eat('\\') {
+        sc.skip_blanks();
+        sc.eat('\n');
+    }
+    let mut lines = vec![String::new()];
+    while let Some(c) = sc.peek().filter(|&c| c != '\n') {
+        sc.pos += 1;
+        match c {
+            '\\' => match sc.next() {
+                Some('\n') => lines.push(String::new()),
+                Some(c) => lines.last_mut().unwrap().push(c),
+                None => break,
+            },
+            c => lines.last_mut().unwrap().push(c),
+        }
+    }
+    lines
+}
+
+/// Where a line of the converted text came from. sed's addresses count and match only
+/// the input's lines, and its `a` text follows any earlier `a` text for the same line.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Origin {
+    Input(usize),
+    /// `i` or `c` text for an input line.
+    Before(usize),
+    /// `a` text for an input line.
+    After(usize),
+}
+
+/// A line a sed command selects: its index, and for a range, which occurrence of the
+/// range it is in and whether it ends it.
+struct Selected {
+    index: usize,
+    range: Option<usize>,
+    last: bool,
+}
+
+fn run_sed(conv: &mut Converter, cmds: &[SedCommand]) -> Result<(), EditError> {
+    let total = conv.lines.len();
+    let mut origins: Vec<Origin> = (1..=total).map(Origin::Input).collect();
+    for cmd in cmds {
+        let selected = sed_select(cmd, &conv.lines, &origins, total);
+        // Bottom up, so that the indices of the lines still to do stay put.
+        match &cmd.action {
+            SedAction::Subst(s) => {
+                let indices = selected.iter().map(|sel| sel.index);
+                conv.substitute(indices.collect(), s)?;
+            }
+            SedAction::Delete => {
+                for run in runs(&selected, false).into_iter().rev() {
+                    conv.replace(run.clone(), Vec::new())?;
+                    origins.drain(run);
+                }
+            }
+            SedAction::Insert(text) => {
+                for sel in selected.iter().rev() {
+                    let Origin::Input(n) = origins[sel.index] else { unreachable!() };
+                    conv.replace(sel.index..sel.index, text.clone())?;
+                    let new = iter::repeat_n(Origin::Before(n), text.len());
+                    origins.splice(sel.index..sel.index, new);
+                }
+            }
+            SedAction::Append(text) => {
+                for sel in selected.iter().rev() {
+                    let Origin::Input(n) = origins[sel.index] else { unreachable!() };
+                    let mut at = sel.index + 1;
+                    while origins.get(at) == Some(&Origin::After(n)) {
+                        at += 1;
+                    }
+                    conv.replace(at..at, text.clone())?;
+                    origins.splice(at..at, iter::repeat_n(Origin::After(n), text.len()));
+                }
+            }
+            SedAction::Change(text) => {
+                // A range is replaced by one copy of the text, where it ends; lines
+                // selected one by one are each replaced.
+                let whole = cmd.addr2.is_some() && !cmd.negate;
+                for run in runs(&selected, !whole).into_iter().rev() {
+                    let Origin::Input(n) = origins[run.start] else { unreachable!() };
+                    let ends = selected.iter().any(|s| s.index == run.end - 1 && s.last);
+                    let new = if ends || !whole { text.clone() } else { Vec::new() };
+                    let count = new.len();
+                    conv.replace(run.clone(), new)?;
+                    origins.splice(run, iter::repeat_n(Origin::Before(n), count));
+                }
+            }
+        }
+    }
+    Ok(())
+}
+
+/// The input lines `cmd`'s address selects, in order, as sed's per-line cycle would see
+/// them after the commands before it.
+fn sed_select(
+    cmd: &SedCommand,
+    lines: &[String],
+    origins: &[Origin],
+    total: usize,
+) -> Vec<Selected> {
+    let matches = |addr: &SedAddr, n: usize, text: &str| match addr {
+        SedAddr::Line(l) => n == *l,
+        SedAddr::Last => n == total,
+        SedAddr::Regex(re) => re.is_match(text),
+    };
+    let mut out = Vec::new();
+    let mut ranges = 0;
+    let mut active = false;
+    for (index, (text, origin)) in lines.iter().zip(origins).enumerate() {
+        let Origin::Input(n) = *origin else { continue };
+        let (mut hit, mut last) = (false, false);
+        match (&cmd.addr1, &cmd.addr2) {
+            (None, _) => hit = true,
+            (Some(a1), None) => hit = matches(a1, n, text),
+            (Some(a1), Some(a2)) => {
+                // A numbered end that was passed (its line deleted) closes the range
+                // without selecting this line.
+                if active && matches!(a2, SedAddr::Line(l) if n > *l) {
+                    active = false;
+                }
+                if active {
+                    hit = true;
+                    last = matches(a2, n, text) || n == total;
+                    active = !last;
+                } else if matches(a1, n, text) {
+                    hit = true;
+                    ranges += 1;
+                    // A numbered end at or before the start selects one line.
+                    last = match a2 {
+                        SedAddr::Line(l) => n >= *l,
+                        SedAddr::Last => n == total,
+                        SedAddr::Regex(_) => n == total,
+                    };
+                    active = !last;
+                }
+            }
+        }
+        if hit != cmd.negate {
+            let range = (cmd.addr2.is_some() && !cmd.negate).then_some(ranges);
+            out.push(Selected { index, range, last: last && !cmd.negate });
+        }
+    }
+    out
+}
+
+/// The runs of consecutive selected lines, in order, not spanning two occurrences of a
+/// range nor the end of one; with `single`, each line is a run.
+fn runs(selected: &[Selected], single: bool) -> Vec<Range<usize>> {
+    let mut out: Vec<Range<usize>> = Vec::new();
+    let mut prev: Option<&Selected> = None;
+    for sel in selected {
+        let joins = prev.is_some_and(|p| {
+            !single && !p.last && p.index + 1 == sel.index && p.range == sel.range
+        });
+        match out.last_mut() {
+            Some(run) if joins => run.end = sel.index + 1,
+            _ => out.push(sel.index..sel.index + 1),
+        }
+        prev = Some(sel);
+    }
+    out
+}
+
+// ---- ed ----
+
+#[derive(Debug, Clone)]
+enum EdBase {
+    Current,
+    Last,
+    Line(usize),
+    Forward(Regex),
+    Backward(Regex),
+}
+
+#[derive(Debug, Clone)]
+struct EdAddr {
+    base: EdBase,
+    offset: isize,
+}
+
+#[derive(Debug, Clone)]
+enum EdAction {
+    Append(Vec<String>),
+    Insert(Vec<String>),
+    Change(Vec<String>),
+    Delete,
+    Subst(Subst),
+    Move(Option<EdAddr>),
+    Copy(Option<EdAddr>),
+    Join,
+    Global { invert: bool, pattern: String, cmd: Subcommand },
+    /// Set the current line (an address alone), or print or write, which changes nothing.
+    Nothing,
+    Quit,
+}
+
+#[derive(Debug, Clone)]
+struct EdCommand {
+    line: usize,
+    /// The addresses given, and whether a `;` between them sets the current line.
+    addr1: Option<EdAddr>,
+    addr2: Option<EdAddr>,
+    semicolon: bool,
+    action: EdAction,
+}
+
+fn parse_ed(script: &str) -> Result<Vec<EdCommand>, EditError> {
+    let mut sc = Scanner::new(script);
+    let mut last_regex = None;
+    let mut out = Vec::new();
+    while sc.peek().is_some() {
+        let line = sc.line();
+        let err = |msg| script_error(ScriptDialect::Ed, line, msg);
+        let (addr1, addr2, semicolon) = parse_ed_range(&mut sc, &mut last_regex).map_err(err)?;
+        let action = match sc.next() {
+            None | Some('\n') => {
+                out.push(EdCommand { line, addr1, addr2, semicolon, action: EdAction::Nothing });
+                continue;
+            }
+            Some(c @ ('a' | 'i' | 'c')) => {
+                sc.rest_of_line();
+                let mut text = Vec::new();
+                loop {
+                    if sc.peek().is_none() {
+                        return Err(err(format!("no '.' line ends the {c} text")));
+                    }
+                    let l = sc.rest_of_line();
+                    if l == "." {
+                        break;
+                    }
+                    text.push(l);
+                }
+                let action = match c {
+                    'a' => EdAction::Append(text),
+                    'i' => EdAction::Insert(text),
+                    _ => EdAction::Change(text),
+                };
+                out.push(EdCommand { line, addr1, addr2, semicolon, action });
+                continue;
+            }
+            Some('d') => EdAction::Delete,
+            Some('j') => EdAction::Join,
+            Some('s') => EdAction::Subst(
+                parse_subst(&mut sc, ScriptDialect::Ed, &mut last_regex).map_err(err)?,
+            ),
+            Some(c @ ('m' | 't')) => {
+                sc.skip_blanks();
+                let dest = parse_ed_addr(&mut sc, &mut last_regex).map_err(err)?;
+                if c == 'm' {
+                    EdAction::Move(dest)
+                } else {
+                    EdAction::Copy(dest)
+                }
+            }
+            Some(c @ ('g' | 'v')) => parse_ed_global(&mut sc, c == 'v', &mut last_regex)
+                .map_err(err)?,
+            Some('p' | 'n' | 'l' | '=' | 'h' | 'H' | 'P') => EdAction::Nothing,
+            Some('w' | 'W') => {
+                let rest = sc.rest_of_line();
+                out.push(EdCommand { line, addr1, addr2, semicolon, action: EdAction::Nothing });
+                if rest.starts_with('q') {
+                    out.push(EdCommand {
+                        line,
+                        addr1: None,
+                        addr2: None,
+                        semicolon: false,
+                        action: EdAction::Quit,
+                    });
+                }
+                continue;
+            }
+            Some('q' | 'Q') => EdAction::Quit,
+            Some(c) => return Err(err(format!("command {c:?} has no exhash equivalent"))),
+        };
+        sc.skip_blanks();
+        match sc.next() {
+            None | Some('\n') => {}
+            Some(c) => return Err(err(format!("unexpected {c:?} after the command"))),
+        }
+        out.push(EdCommand { line, addr1, addr2, semicolon, action });
+    }
+    Ok(out)
+}
+
+/// The addresses of an ed command, and whether `;` separates them.
+type EdRange = (Option<EdAddr>, Option<EdAddr>, bool);
+
+/// The addresses before an ed command: none, one, or two separated by `,` or `;`, where
+/// `,` or `%` alone is `1,$` and `;` alone is `.,$`.
+fn parse_ed_range(sc: &mut Scanner, last_regex: &mut Option<String>) -> Result<EdRange, String> {
+    let at = |base| Some(EdAddr { base, offset: 0 });
+    if sc.eat('%') {
+        return Ok((at(EdBase::Line(1)), at(EdBase::Last), false));
+    }
+    let first = parse_ed_addr(sc, last_regex)?;
+    let semicolon = match sc.peek() {
+        Some(',') => false,
+        Some(';') => true,
+        _ => return Ok((first, None, false)),
+    };
+    sc.pos += 1;
+    let second = parse_ed_addr(sc, last_regex)?;
+    Ok(match (first, second) {
+        (None, None) if semicolon => (at(EdBase::Current), at(EdBase::Last), false),
+        (None, None) => (at(EdBase::Line(1)), at(EdBase::Last), false),
+        (None, Some(b)) if semicolon => (at(EdBase::Current), Some(b), true),
+        (None, Some(b)) => (at(EdBase::Line(1)), Some(b), false),
+        (Some(a), None) => (Some(a.clone()), Some(a), semicolon),
+        (Some(a), Some(b)) => (Some(a), Some(b), semicolon),
+    })
+}
+
+fn parse_ed_addr(
+    sc: &mut Scanner,
+    last_regex: &mut Option<String>,
+) -> Result<Option<EdAddr>, String> {
+    let base = match sc.peek() {
+        Some(c) if c.is_ascii_digit() => EdBase::Line(sc.number().ok_or("invalid line number")?),
+        Some('.') => {
+            sc.pos += 1;
+            EdBase::Current
+        }
+        Some('$') => {
+            sc.pos += 1;
+            EdBase::Last
+        }
+        Some(c @ ('/' | '?')) => {
+            sc.pos += 1;
+            let raw = sc.delimited(c).ok_or("unterminated regex address")?;
+            let re = script_regex(&raw, last_regex, false)?;
+            let re = Regex::new(&re).map_err(|e| format!("invalid regex: {e}"))?;
+            if c == '/' {
+                EdBase::Forward(re)
+            } else {
+                EdBase::Backward(re)
+            }
+        }
+        Some('+' | '-') => EdBase::Current,
+        Some('\'') => return Err("marks have no exhash equivalent".to_string()),
+        _ => return Ok(None),
+    };
+    let mut offset = 0;
+    loop {
+        let sign = match sc.peek() {
+            Some('+') => 1,
+            Some('-') => -1,
+            _ => break,
+        };
+        sc.pos += 1;
+        let n = if sc.peek().is_some_and(|c| c.is_ascii_digit()) {
+            sc.number().ok_or("invalid offset")?
+        } else {
+            1
+        };
+        offset += sign * n as isize;
+    }
+    Ok(Some(EdAddr { base, offset }))
+}
+
+/// A `g/re/cmd` or `v/re/cmd` after its `g` or `v`: exhash runs one `d` or `s` per line.
+fn parse_ed_global(
+    sc: &mut Scanner,
+    invert: bool,
+    last_regex: &mut Option<String>,
+) -> Result<EdAction, String> {
+    let delim = sc.next().filter(|&c| c != '\n' && c != ' ').ok_or("g needs /re/")?;
+    let raw = sc.delimited(delim).ok_or("unterminated g/re/")?;
+    let pattern = script_regex(&raw, last_regex, false)?;
+    // Printing the matching lines changes nothing.
+    match sc.peek() {
+        None | Some('\n') => return Ok(EdAction::Nothing),
+        Some('p' | 'n' | 'l') => {
+            sc.pos += 1;
+            return Ok(EdAction::Nothing);
+        }
+        _ => {}
+    }
+    let cmd = match sc.next() {
+        Some('d') => Subcommand::Delete,
+        Some('s') => Subcommand::Substitute(parse_subst(sc, ScriptDialect::Ed, last_regex)?),
+        c => return Err(format!("g runs only d or s in exhash, not {:?}", c.unwrap_or(' '))),
+    };
+    Ok(EdAction::Global { invert, pattern, cmd })
+}
+
+fn run_ed(conv: &mut Converter, cmds: &[EdCommand]) -> Result<(), EditError> {
+    // ed starts at the last line.
+    let mut current = conv.lines.len();
+    for cmd in cmds {
+        let err = |msg: String| script_error(ScriptDialect::Ed, cmd.line, msg);
+        let len = conv.lines.len();
+        let resolve = |addr: &EdAddr, current: usize| -> Result<usize, EditError> {
+            ed_line(addr, current, &conv.lines).map_err(err)
+        };
+        // The 1-based lines addressed, or `default` relative to the current line.
+        let range = |default: (isize, isize), current: usize| -> Result<(usize, usize), EditError> {
+            let Some(a1) = &cmd.addr1 else {
+                let at = |d: isize| current as isize + d;
+                if at(default.1) > len as isize {
+                    return Err(err(format!("line {} is past the end", at(default.1))));
+                }
+                return Ok((at(default.0).max(0) as usize, at(default.1).max(0) as usize));
+            };
+            let first = resolve(a1, current)?;
+            let second = match &cmd.addr2 {
+                Some(a2) => resolve(a2, if cmd.semicolon { first } else { current })?,
+                None => first,
+            };
+            if second < first {
+                return Err(err(format!("lines {first},{second} are backwards")));
+            }
+            Ok((first, second))
+        };
+        let lines = |(first, second): (usize, usize)| -> Result<Range<usize>, EditError> {
+            if first == 0 {
+                return Err(err("line 0 is only an address for a and i".to_string()));
+            }
+            Ok(first - 1..second)
+        };
+        match &cmd.action {
+            EdAction::Quit => break,
+            EdAction::Nothing => {
+                if cmd.addr1.is_some() {
+                    current = range((0, 0), current)?.1;
+                }
+            }
+            EdAction::Append(text) => {
+                let at = range((0, 0), current)?.1;
+                conv.replace(at..at, text.clone())?;
+                current = at + text.len();
+            }
+            EdAction::Insert(text) => {
+                let at = range((0, 0), current)?.1.max(1) - 1;
+                conv.replace(at..at, text.clone())?;
+                current = (at + text.len()).max(1).min(conv.lines.len());
+            }
+            EdAction::Change(text) => {
+                let r = lines(range((0, 0), current)?)?;
+                let start = r.start;
+                conv.replace(r, text.clone())?;
+                current = match text.len() {
+                    0 => start.min(conv.lines.len()),
+                    n => start + n,
+                };
+            }
+            EdAction::Delete => {
+                let r = lines(range((0, 0), current)?)?;
+                let start = r.start;
+                conv.replace(r, Vec::new())?;
+                current = (start + 1).min(conv.lines.len());
+            }
+            EdAction::Subst(s) => {
+                let r = lines(range((0, 0), current)?)?;
+                current = r.end;
+                conv.substitute(r.collect(), s)?;
+            }
+            EdAction::Join => {
+                let r = lines(range((0, 1), current)?)?;
+                current = r.start + 1;
+                if r.len() > 1 {
+                    let joined = conv.lines[r.clone()].concat();
+                    conv.replace(r, vec![joined])?;
+                }
+            }
+            EdAction::Move(dest) | EdAction::Copy(dest) => {
+                let r = lines(range((0, 0), current)?)?;
+                let dest = match dest {
+                    Some(d) => resolve(d, current)?,
+                    None => current,
+                };
+                let moving = matches!(cmd.action, EdAction::Move(_));
+                if moving && dest >= r.start && dest < r.end {
+                    let msg = format!("cannot move lines {}-{} after {dest}", r.start + 1, r.end);
+                    return Err(err(msg));
+                }
+                let n = r.len();
+                if dest == 0 {
+                    // exhash's m and t take no 0 address: add the lines at the top instead.
+                    let text = conv.lines[r.clone()].to_vec();
+                    conv.replace(0..0, text)?;
+                    if moving {
+                        conv.replace(r.start + n..r.end + n, Vec::new())?;
+                    }
+                    current = n;
+                    continue;
+                }
+                let dest_addr = conv.addrs()[dest - 1];
+                let sub = if moving {
+                    Subcommand::Move { dest: dest_addr }
+                } else {
+                    Subcommand::Copy { dest: dest_addr }
+                };
+                conv.run(r.clone(), sub)?;
+                current = if moving && dest >= r.end { dest } else { dest + n };
+            }
+            EdAction::Global { invert, pattern, cmd: sub } => {
+                let (first, second) = match (&cmd.addr1, &cmd.addr2) {
+                    (None, _) => (1, len),
+                    _ => range((0, 0), current)?,
+                };
+                if len == 0 {
+                    continue;
+                }
+                let sub = Subcommand::Global {
+                    invert: *invert,
+                    pattern: pattern.clone(),
+                    cmd: Box::new(sub.clone()),
+                };
+                conv.run(lines((first, second))?, sub)?;
+                current = conv.lines.len();
+            }
+        }
+    }
+    Ok(())
+}
+
+/// The 1-based line an ed address names, given the current line.
+fn ed_line(addr: &EdAddr, current: usize, lines: &[String]) -> Result<usize, String> {
+    let len = lines.len();
+    let base = match &addr.base {
+        EdBase::Current => current,
+        EdBase::Last => len,
+        EdBase::Line(n) => *n,
+        EdBase::Forward(re) | EdBase::Backward(re) => {
+            // Searches start next to the current line and wrap around.
+            let forward = matches!(addr.base, EdBase::Forward(_));
+            let found = (1..=len).find_map(|k| {
+                let n = if forward {
+                    (current + k - 1) % len + 1
+                } else {
+                    (current + len - k % len - 1) % len + 1
+                };
+                re.is_match(&lines[n - 1]).then_some(n)
+            });
+            found.ok_or_else(|| format!("no line matches {}", re.as_str()))?
+        }
+    };
+    let line = base as isize + addr.offset;
+    if line < 0 || line > len as isize {
+        return Err(format!("line {line} is out of range (the text has {len} lines)"));
+    }
+    Ok(line as usize)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::edit_text;
+
+    fn convert(dialect: ScriptDialect, script: &str, text: &str) -> String {
+        let cmds = convert_script(dialect, script, text, &LnHashSpec::default()).unwrap();
+        // The commands reparse to the same edit.
+        let script: Vec<String> = cmds.iter().map(|c| c.to_string()).collect();
+        let script: Vec<&str> = script.iter().map(|c| c.as_str()).collect();
+        let cmds = crate::parse_commands_from_strs(&script).unwrap();
+        edit_text(text, &cmds).unwrap().text()
+    }
+
+    #[test]
+    fn sed_scripts_convert_to_the_same_edit() {
+        let sed = |script, text| convert(ScriptDialect::Sed, script, text);
+        let text = "one\ntwo\nthree\nfour\nfive\n";
+        assert_eq!(sed("s/o/0/g", text), "0ne\ntw0\nthree\nf0ur\nfive\n");
+        assert_eq!(sed("2,4d", text), "one\nfive\n");
+        assert_eq!(sed("/t/!d; 2a\\\nend", text), "two\nend\nthree\n");
+        // Line numbers are the input's, whatever earlier commands did.
+        assert_eq!(
+            sed("1d\n2s/two/2/\n3i\\\nbefore\\\n3", text),
+            "2\nbefore\n3\nthree\nfour\nfive\n"
+        );
+        assert_eq!(sed("/two/,/four/c\\\nX", text), "one\nX\nfive\n");
+        assert_eq!(sed("2!c changed", "a\nb\nc\n"), "changed\nb\nchanged\n");
+        assert_eq!(sed("1a A1\n1a A2\n2i B", "a\nb\n"), "a\nA1\nA2\nB\nb\n");
+        assert_eq!(sed(r"s/\(t[a-z]*\)o$/[\1]/", text), "one\n[tw]\nthree\nfour\nfive\n");
+        assert_eq!(sed("s|e$|&&|; /^f/s//F/I", text), "onee\ntwo\nthreee\nFour\nFivee\n");
+    }
+
+    #[test]
+    fn ed_scripts_convert_to_the_same_edit() {
+        let ed = |script, text| convert(ScriptDialect::Ed, script, text);
+        let text = "one\ntwo\nthree\nfour\nfive\n";
+        assert_eq!(ed("2d\n.s/three/3/\nw\nq\n", text), "one\n3\nfour\nfive\n");
+        assert_eq!(ed("/two/a\nnew\n.\n$m0\n", text), "five\none\ntwo\nnew\nthree\nfour\n");
+        assert_eq!(ed("1,2j\n$-1,$t0\n,s/o/O/\n", text), "fOur\nfive\nOnetwo\nthree\nfOur\nfive\n");
+        assert_eq!(ed("g/e$/d\nv/f/s/t/T/\n", text), "Two\nfour\n");
+        assert_eq!(ed("?one?;+1c\nX\n.\n", text), "X\nthree\nfour\nfive\n");
+    }
+
+    #[test]
+    fn unsupported_scripts_are_parse_errors() {
+        let spec = LnHashSpec::default();
+        for (dialect, script, msg) in [
+            (ScriptDialect::Sed, "1h", "sed: line 1: command 'h' has no exhash equivalent"),
+            (ScriptDialect::Sed, "\n/x/{d}", "sed: line 2: command '{'"),
+            (ScriptDialect::Sed, r"s/\(a\)\1/b/", "backreference"),
+            (ScriptDialect::Sed, "s/a/b/w out", "s flag 'w'"),
+            (ScriptDialect::Ed, "u", "ed: line 1: command 'u'"),
+            (ScriptDialect::Ed, "a\nno end\n", "no '.' line"),
+            (ScriptDialect::Ed, "9d", "ed: line 1: line 9 is out of range"),
+        ] {
+            let err = convert_script(dialect, script, "a\n", &spec).unwrap_err();
+            assert_eq!(err.kind(), crate::ErrorKind::Parse);
+            assert!(err.message().contains(msg), "{err}");
+        }
+    }
+}
@@ -0,0 +1,349 @@
+use std::ops::Range;
+
+use crate::engine::EditResult;
+use crate::lnhash::{LnHash, LnHashSpec, BOM};
+use crate::parse::{Command, Subcommand};
+
+/// One line of an edit script turning `old` into `new`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum DiffOp {
+    Equal,
+    Delete,
+    Insert,
+}
+
+/// Render a unified diff (as `diff -u` prints it) from `old` to `new`, with `context`
+/// unchanged lines around each change. Returns an empty string if the texts are equal.
+///
+/// Lines keep their endings when compared, so a changed line ending shows up as a change,
+/// and a missing final newline is marked the way `patch` expects.
+pub fn unified_diff(
+    old: &str,
+    new: &str,
+    old_label: &str,
+    new_label: &str,
+    context: usize,
+) -> String {
+    let a: Vec<&str> = old.split_inclusive('\n').collect();
+    let b: Vec<&str> = new.split_inclusive('\n').collect();
+    let ops = diff_ops(&a, &b);
+
+    // Each op with the old/new line indices it starts at.
+    let mut positioned = Vec::with_capacity(ops.len());
+    let (mut i, mut j) = (0, 0);
+    for op in ops {
+        positioned.push((op, i, j));
+        match op {
+            DiffOp::Equal => (i, j) = (i + 1, j + 1),
+            DiffOp::Delete => i += 1,
+            DiffOp::Insert => j += 1,
+        }
+    }
+    let changes: Vec<usize> = positioned
+        .iter()
+        .enumerate()
+        .filter(|(_, (op, _, _))| *op != DiffOp::Equal)
+        .map(|(k, _)| k)
+        .collect();
+    if changes.is_empty() {
+        return String::new();
+    }
+
+    let mut out = format!("--- {old_label}\n+++ {new_label}\n");
+    let mut c = 0;
+    while c < changes.len() {
+        let start = changes[c].saturating_sub(context);
+        let mut end = changes[c] + 1;
+        c += 1;
+        // Merge changes whose context would touch or overlap.
+        while c < changes.len() && changes[c] <= end + 2 * context {
+            end = changes[c] + 1;
+            c += 1;
+        }
+        let end = (end + context).min(positioned.len());
+        let hunk = &positioned[start..end];
+        let old_len = hunk.iter().filter(|(op, _, _)| *op != DiffOp::Insert).count();
+        let new_len = hunk.iter().filter(|(op, _, _)| *op != DiffOp::Delete).count();
+        let (_, old_start, new_start) = hunk[0];
+        out.push_str(&format!(
+            "@@ -{} +{} @@\n",
+            hunk_range(old_start, old_len),
+            hunk_range(new_start, new_len)
+        ));
+        for &(op, i, j) in hunk {
+            let (sigil, line) = match op {
+                DiffOp::Equal => (' ', a[i]),
+                DiffOp::Delete => ('-', a[i]),
+                DiffOp::Insert => ('+', b[j]),
+            };
+            out.push(sigil);
+            out.push_str(line);
+            if !line.ends_with('\n') {
+                out.push_str("\n\\ No newline at end of file\n");
+            }
+        }
+    }
+    out
+}
+
+/// The runs of lines that differ between `old` and `new`, in order, as pairs of the
+/// replaced range of `old` and the range of `new` replacing it (either may be empty).
+/// Consecutive pairs are separated by at least one unchanged line.
+pub fn changed_ranges(old: &[&str], new: &[&str]) -> Vec<(Range<usize>, Range<usize>)> {
+    let mut out: Vec<(Range<usize>, Range<usize>)> = Vec::new();
+    let (mut i, mut j) = (0, 0);
+    let mut in_change = false;
+    for op in diff_ops(old, new) {
+        if op == DiffOp::Equal {
+            (i, j) = (i + 1, j + 1);
+            in_change = false;
+            continue;
+        }
+        if !in_change {
+            out.push((i..i, j..j));
+            in_change = true;
+        }
+        let (a, b) = out.last_mut().unwrap();
+        match op {
+            DiffOp::Delete => {
+                i += 1;
+                a.end = i;
+            }
+            _ => {
+                j += 1;
+                b.end = j;
+            }
+        }
+    }
+    out
+}
+
+/// Commands that turn `from` into `to`, addressed against `from` with `spec`'s hashes:
+/// one `a`, `c` or `d` per run of changed lines, last run first so that each address
+/// still names its line when it is verified. `diff_commands(new, old, spec)` is an undo
+/// script for an edit from `old` to `new`.
+pub fn diff_commands(from: &str, to: &str, spec: &LnHashSpec) -> Vec<Command> {
+    let old: Vec<&str> = from.lines().collect();
+    let new: Vec<&str> = to.lines().collect();
+    let runs = changed_ranges(&old, &new).into_iter().map(|(removed, added)| {
+        (removed, new[added].iter().map(|l| l.to_string()).collect())
+    });
+    replace_commands(&spec.lnhashes(old.iter().copied()), runs)
+}
+
+/// Quickfix lines (`path:lineno: message`, Vim's default `errorformat`) for every line
+/// `result` modified and every line of `original` it deleted, in order of their line
+/// numbers in the result, so that `:cfile` or `:cbuffer` steps through the edit.
+///
+/// A modified line reads `path:N: modified: <text>`. A deleted line is reported where it
+/// used to be, at the line that now follows it, as `path:N: deleted line M: <old text>`
+/// with its old line number `M`.
+pub fn quickfix_lines(path: &str, original: &str, result: &EditResult) -> Vec<String> {
+    let original = original.strip_prefix(BOM).unwrap_or(original);
+    let old: Vec<&str> = original.lines().collect();
+    let new: Vec<&str> = result.lines.iter().map(|l| l.as_str()).collect();
+    // Where each old line went: its new index if it is unchanged, otherwise the start of
+    // the lines that replaced its run.
+    let mut new_index = Vec::with_capacity(old.len());
+    let mut offset: isize = 0;
+    for (removed, added) in changed_ranges(&old, &new) {
+        while new_index.len() < removed.start {
+            new_index.push((new_index.len() as isize + offset) as usize);
+        }
+        new_index.extend(removed.clone().map(|_| added.start));
+        offset = added.end as isize - removed.end as isize;
+    }
+    while new_index.len() < old.len() {
+        new_index.push((new_index.len() as isize + offset) as usize);
+    }
+
+    let last = new.len().max(1);
+    let mut entries: Vec<(usize, bool, String)> = Vec::new();
+    for &m in &result.deleted {
+        let (Some(&at), Some(text)) = (new_index.get(m - 1), old.get(m - 1)) else { continue };
+        entries.push(((at + 1).min(last), false, format!("deleted line {m}: {text}")));
+    }
+    for &n in &result.modified {
+        let Some(text) = new.get(n - 1) else { continue };
+        entries.push((n, true, format!("modified: {text}")));
+    }
+    // A deletion comes before the line that now stands in its place.
+    entries.sort_by_key(|&(n, modified, _)| (n, modified));
+    entries.into_iter().map(|(n, _, msg)| format!("{path}:{n}: {msg}")).collect()
+}
+
+/// Commands replacing each `(range, text)` run of lines, given in order and addressed by
+/// `addrs`, the lnhashes of the lines they are to be verified against: one `a`, `c` or
+/// `d` per run, last run first.
+pub(crate) fn replace_commands(
+    addrs: &[LnHash],
+    runs: impl DoubleEndedIterator<Item = (Range<usize>, Vec<String>)>,
+) -> Vec<Command> {
+    let before_first = LnHash { lineno: 0, hash: 0, ..LnHash::of(0, "") };
+    runs.rev()
+        .map(|(removed, text)| {
+            let (addr1, addr2) = match removed.len() {
+                0 if removed.start == 0 => (before_first, None),
+                0 => (addrs[removed.start - 1], None),
+                1 => (addrs[removed.start], None),
+                _ => (addrs[removed.start], Some(addrs[removed.end - 1])),
+            };
+            let cmd = match (removed.is_empty(), text.is_empty()) {
+                (true, _) => Subcommand::Append(text),
+                (false, true) => Subcommand::Delete,
+                (false, false) => Subcommand::Change(text),
+            };
+            Command { addr1, has_comma: addr2.is_some(), addr2, cmd }
+        })
+        .collect()
+}
+
+/// A hunk header range: `start,len`, 1-based, where an empty range names the line before.
+fn hunk_range(start: usize, len: usize) -> String {
+    match len {
+        0 => format!("{start},0"),
+        1 => format!("{}", start + 1),
+        _ => format!("{},{len}", start + 1),
+    }
+}
+
+/// A shortest edit script from `a` to `b` (Myers' O(ND) algorithm), after trimming the
+/// common prefix and suffix so that small edits to large files stay cheap.
+fn diff_ops(a: &[&str], b: &[&str]) -> Vec<DiffOp> {
+    let prefix = a.iter().zip(b).take_while(|(x, y)| x == y).count();
+    let suffix = a[prefix..]
+        .iter()
+        .rev()
+        .zip(b[prefix..].iter().rev())
+        .take_while(|(x, y)| x == y)
+        .count();
+    let (ma, mb) = (&a[prefix..a.len() - suffix], &b[prefix..b.len() - suffix]);
+
+    let mut ops = vec![DiffOp::Equal; prefix];
+    ops.extend(myers(ma, mb));
+    ops.extend(std::iter::repeat_n(DiffOp::Equal, suffix));
+    ops
+}
+
+fn myers(a: &[&str], b: &[&str]) -> Vec<DiffOp> {
+    let (n, m) = (a.len() as isize, b.len() as isize);
+    let max = n + m;
+    let idx = |k: isize| (k + max) as usize;
+    // v[idx(k)] is the furthest x reached on diagonal k; trace[d] is v before round d.
+    let mut v = vec![0isize; 2 * max as usize + 2];
+    let mut trace = Vec::new();
+    'search: for d in 0..=max {
+        trace.push(v.clone());
+        for k in (-d..=d).step_by(2) {
+            let mut x = if k == -d || (k != d && v[idx(k - 1)] < v[idx(k + 1)]) {
+                v[idx(k + 1)]
+            } else {
+                v[idx(k - 1)] + 1
+            };
+            let mut y = x - k;
+            while x < n && y < m && a[x as usize] == b[y as usize] {
+                x += 1;
+                y += 1;
+            }
+            v[idx(k)] = x;
+            if x >= n && y >= m {
+                break 'search;
+            }
+        }
+    }
+
+    let mut ops = Vec::new();
+    let (mut x, mut y) = (n, m);
+    for (d, v) in trace.iter().enumerate().rev() {
+        let (d, k) = (d as isize, x - y);
+        let prev_k = if k == -d || (k != d && v[idx(k - 1)] < v[idx(k + 1)]) {
+            k + 1
+        } else {
+            k - 1
+        };
+        let prev_x = v[idx(prev_k)];
+        let prev_y = prev_x - prev_k;
+        while x > prev_x && y > prev_y {
+            ops.push(DiffOp::Equal);
+            x -= 1;
+            y -= 1;
+        }
+        if d > 0 {
+            ops.push(if x == prev_x { DiffOp::Insert } else { DiffOp::Delete });
+        }
+        (x, y) = (prev_x, prev_y);
+    }
+    ops.reverse();
+    ops
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn diff_hunks_and_headers() {
+        assert_eq!(unified_diff("a\nb\n", "a\nb\n", "f", "f", 3), "");
+        let old = "1\n2\n3\n4\n5\n6\n7\n8\n9\n";
+        let new = "1\nX\n3\n4\n5\n6\n7\n8\n";
+        assert_eq!(
+            unified_diff(old, new, "f", "f", 1),
+            "--- f\n+++ f\n@@ -1,3 +1,3 @@\n 1\n-2\n+X\n 3\n@@ -8,2 +8 @@\n 8\n-9\n"
+        );
+        // Nearby changes share a hunk.
+        assert_eq!(
+            unified_diff("a\nb\nc\n", "b\nc\nd\n", "x", "y", 3),
+            "--- x\n+++ y\n@@ -1,3 +1,3 @@\n-a\n b\n c\n+d\n"
+        );
+        assert_eq!(unified_diff("", "a\n", "x", "y", 3), "--- x\n+++ y\n@@ -0,0 +1 @@\n+a\n");
+    }
+
+    #[test]
+    fn changed_ranges_pair_old_and_new_runs() {
+        assert_eq!(changed_ranges(&["a", "b"], &["a", "b"]), vec![]);
+        assert_eq!(
+            changed_ranges(&["a", "b", "c", "d"], &["x", "a", "c", "y", "z"]),
+            vec![(0..0, 0..1), (1..2, 2..2), (3..4, 3..5)]
+        );
+    }
+
+    #[test]
+    fn diff_commands_rebuild_the_target() {
+        let spec = LnHashSpec::default();
+        let (old, new) = ("a\nb\nc\nd\n", "top\na\nB\nc\n");
+        let cmds = diff_commands(old, new, &spec);
+        let text: Vec<String> = cmds.iter().map(|c| c.to_string()).collect();
+        assert_eq!(text[2], "0|0000|a\\top");
+        let result = crate::edit_text(old, &cmds).unwrap();
+        assert_eq!(result.lines, ["top", "a", "B", "c"]);
+        assert!(diff_commands(old, old, &spec).is_empty());
+    }
+
+    #[test]
+    fn quickfix_lists_modified_and_deleted_lines() {
+        let old = "a\nb\nc\nd\ne\n";
+        let addr = |n, line| LnHash::of(n, line).to_string();
+        let quickfix = |cmds: &[String]| {
+            let cmds: Vec<&str> = cmds.iter().map(|c| c.as_str()).collect();
+            let cmds = crate::parse_commands_from_strs(&cmds).unwrap();
+            quickfix_lines("f.txt", old, &crate::edit_text(old, &cmds).unwrap())
+        };
+        assert_eq!(
+            quickfix(&[format!("{}d", addr(2, "b")), format!("{}s/d/D/", addr(3, "d"))]),
+            ["f.txt:2: deleted line 2: b", "f.txt:3: modified: D"]
+        );
+        // Deleting the last lines points at the new last line.
+        assert_eq!(
+            quickfix(&[format!("{},{}d", addr(4, "d"), addr(5, "e"))]),
+            ["f.txt:3: deleted line 4: d", "f.txt:3: deleted line 5: e"]
+        );
+    }
+
+    #[test]
+    fn diff_marks_missing_final_newline() {
+        assert_eq!(
+            unified_diff("a\nb", "a\nb\n", "f", "f", 3),
+            "--- f\n+++ f\n@@ -1,2 +1,2 @@\n a\n-b\n\\ No newline at end of file\n+b\n"
+        );
+    }
+}
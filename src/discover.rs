@@ -0,0 +1,185 @@
+use std::collections::BTreeSet;
+use std::path::Path;
+
+use ignore::overrides::OverrideBuilder;
+use ignore::WalkBuilder;
+use regex::Regex;
+
+use crate::file::read_text;
+use crate::lnhash::{file_fingerprint, LnHash, LnHashSpec};
+use crate::parse::{Command, FileScript, Script, Subcommand};
+use crate::EditError;
+
+/// What [`find_in_files`] looks for in each line.
+#[derive(Debug, Clone)]
+pub enum LineQuery {
+    /// Lines the regex matches.
+    Pattern(Regex),
+    /// Lines with this hash under the search's spec, such as the hash of an address whose
+    /// line has moved to another file.
+    Hash(u32),
+}
+
+/// The lines of one file that a [`find_in_files`] search found.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FileMatches {
+    /// The file, as [`discover_files`] names it.
+    pub path: String,
+    /// The file's `@file` fingerprint as it was searched.
+    pub fingerprint: String,
+    /// The address and text of each line found, in order.
+    pub lines: Vec<(LnHash, String)>,
+}
+
+impl FileMatches {
+    /// A script for [`parse_file_scripts`](crate::parse_file_scripts)' multi-file edits
+    /// that runs `cmd` on every line found, last first so that each address still names
+    /// its line. It is headed by the file's fingerprint, so it applies only while the
+    /// file is as it was searched.
+    pub fn script(&self, cmd: &Subcommand) -> FileScript {
+        let commands = (self.lines.iter().rev())
+            .map(|(addr, _)| Command {
+                addr1: *addr,
+                addr2: None,
+                has_comma: false,
+                cmd: cmd.clone(),
+            })
+            .collect();
+        let script = Script { fingerprint: Some(self.fingerprint.clone()), commands };
+        FileScript { path: self.path.clone(), script }
+    }
+}
+
+/// The files matching any of the glob `patterns` (such as `src/**/*.rs`), sorted, leaving
+/// out hidden files and what `.gitignore` and `.ignore` files exclude, as ripgrep does.
+///
+/// Each pattern is walked from its leading components without wildcards (the current
+/// directory if there are none), and the rest is matched below that, anchored there as in
+/// a shell. Files come back relative as their pattern is, without a leading `./`.
+/// Directories that cannot be read are passed over.
+pub fn discover_files(patterns: &[&str]) -> Result<Vec<String>, EditError> {
+    let mut files = BTreeSet::new();
+    for pattern in patterns {
+        let (base, glob) = split_glob(pattern);
+        let mut walk = WalkBuilder::new(&base);
+        // Honor .gitignore files outside a git checkout too, such as in an exported tree.
+        walk.require_git(false);
+        if !glob.is_empty() {
+            let overrides = OverrideBuilder::new(&base)
+                .add(&format!("/{glob}"))
+                .and_then(|o| o.build())
+                .map_err(|e| EditError::parse(format!("glob {pattern}: {e}")))?;
+            walk.overrides(overrides);
+        }
+        for entry in walk.build().flatten() {
+            if entry.file_type().is_some_and(|t| t.is_file()) {
+                let path = entry.path().to_string_lossy();
+                files.insert(path.strip_prefix("./").unwrap_or(&path).to_string());
+            }
+        }
+    }
+    Ok(files.into_iter().collect())
+}
+
+/// Search every file [`discover_files`] finds for `patterns` for the lines `query` picks,
+/// giving their addresses under `spec`. Files with no such line are left out, and so are
+/// binary files and those that are not UTF-8 or cannot be read.
+///
+/// Each file's [`script`](FileMatches::script) turns the matches into a verified edit, and
+/// the scripts of all of them into one multi-file edit.
+pub fn find_in_files(
+    patterns: &[&str],
+    query: &LineQuery,
+    spec: &LnHashSpec,
+) -> Result<Vec<FileMatches>, EditError> {
+    let mut found = Vec::new();
+    for path in discover_files(patterns)? {
+        let Ok((text, _)) = read_text(Path::new(&path), None) else {
+            continue;
+        };
+        let lines: Vec<&str> = text.lines().collect();
+        let addrs = spec.lnhashes(lines.iter().copied());
+        let matches: Vec<(LnHash, String)> = (addrs.into_iter().zip(&lines))
+            .filter(|(addr, line)| match query {
+                LineQuery::Pattern(re) => re.is_match(line),
+                LineQuery::Hash(hash) => addr.hash == *hash,
+            })
+            .map(|(addr, line)| (addr, line.to_string()))
+            .collect();
+        if !matches.is_empty() {
+            found.push(FileMatches { path, fingerprint: file_fingerprint(&text), lines: matches });
+        }
+    }
+    Ok(found)
+}
+
+/// `pattern` split into the directory to walk, made of its leading components without
+/// wildcards, and the glob to match below it (empty when there are no wildcards).
+fn split_glob(pattern: &str) -> (String, String) {
+    let parts: Vec<&str> = pattern.split('/').collect();
+    let literal = parts.iter().take_while(|p| !p.contains(['*', '?', '[', '{'])).count();
+    let base = match parts[..literal].join("/") {
+        b if !b.is_empty() => b,
+        _ if pattern.starts_with('/') => "/".to_string(),
+        _ => ".".to_string(),
+    };
+    (base, parts[literal..].join("/"))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{fs, process};
+
+    use super::*;
+    use crate::parse::Subst;
+    use crate::{edit_text, parse_file_scripts};
+
+    #[test]
+    fn find_in_files_feeds_a_multi_file_edit() {
+        let dir = std::env::temp_dir().join(format!("exhash-discover-{}", process::id()));
+        fs::create_dir_all(dir.join("src/sub")).unwrap();
+        fs::create_dir_all(dir.join("target")).unwrap();
+        fs::write(dir.join(".gitignore"), "target/\n").unwrap();
+        fs::write(dir.join("src/a.rs"), "fn old() {}\nold();\n").unwrap();
+        fs::write(dir.join("src/sub/b.rs"), "other\nold();\n").unwrap();
+        fs::write(dir.join("src/c.txt"), "old\n").unwrap();
+        fs::write(dir.join("src/bin.rs"), "old\0\n").unwrap();
+        fs::write(dir.join("target/d.rs"), "old\n").unwrap();
+        let root = dir.to_string_lossy();
+
+        let files = discover_files(&[&format!("{root}/**/*.rs")]).unwrap();
+        let names: Vec<&str> = files.iter().map(|f| &f[root.len() + 1..]).collect();
+        assert_eq!(names, ["src/a.rs", "src/bin.rs", "src/sub/b.rs"]);
+        // Anchored below the literal part, as a shell would match it.
+        assert_eq!(discover_files(&[&format!("{root}/src/*.rs")]).unwrap().len(), 2);
+        let err = discover_files(&[&format!("{root}/src/{{a")]).unwrap_err();
+        assert_eq!(err.kind(), crate::ErrorKind::Parse);
+
+        let spec = LnHashSpec::default();
+        let pattern = LineQuery::Pattern(Regex::new(r"\bold\(").unwrap());
+        let found = find_in_files(&[&format!("{root}/src/**/*.rs")], &pattern, &spec).unwrap();
+        assert_eq!(found.len(), 2);
+        let line = |n, text: &str| (spec.lnhash(n, text), text.to_string());
+        assert_eq!(found[0].lines, [line(1, "fn old() {}"), line(2, "old();")]);
+        let by_hash = LineQuery::Hash(spec.lnhash(2, "old();").hash);
+        let moved = find_in_files(&[&format!("{root}/src/**/*.rs")], &by_hash, &spec).unwrap();
+        assert_eq!(moved.iter().map(|f| f.lines.len()).sum::<usize>(), 2);
+
+        // The matches become one script per file, which parse and apply as multi-file edits.
+        let subst = Subst {
+            pattern: "old".into(),
+            replacement: "new".into(),
+            global: false,
+            case_insensitive: false,
+            columns: None,
+        };
+        let rename = Subcommand::Substitute(subst);
+        let script: String = found.iter().map(|f| f.script(&rename).to_string()).collect();
+        let scripts = parse_file_scripts(&script).unwrap();
+        let a = fs::read_to_string(dir.join("src/a.rs")).unwrap();
+        assert_eq!(scripts[0].script.fingerprint.as_deref(), Some(&*file_fingerprint(&a)));
+        let result = edit_text(&a, &scripts[0].script.commands).unwrap();
+        assert_eq!(result.text(), "fn new() {}\nnew();\n");
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
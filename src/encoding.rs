@@ -0,0 +1,113 @@
+use encoding_rs::{Encoding, UTF_8, WINDOWS_1252};
+
+use crate::engine::{edit_text_with, EditOptions, EditResult};
+use crate::parse::Command;
+use crate::EditError;
+
+/// Look up an encoding by its WHATWG label (e.g. `"latin1"`, `"windows-1252"`, `"shift_jis"`).
+pub(crate) fn lookup_encoding(label: &str) -> Result<&'static Encoding, EditError> {
+    let enc = Encoding::for_label(label.trim().as_bytes())
+        .ok_or_else(|| EditError::new(format!("unknown encoding: {label:?}")))?;
+    if enc.output_encoding() != enc {
+        return Err(EditError::new(format!(
+            "encoding {} can be read but not written",
+            enc.name()
+        )));
+    }
+    Ok(enc)
+}
+
+/// Check that `label` names an encoding text can be both read and written in, or is
+/// `"auto"`.
+pub fn check_encoding(label: &str) -> Result<(), EditError> {
+    if label.trim() != "auto" {
+        lookup_encoding(label)?;
+    }
+    Ok(())
+}
+
+/// Decode `input` from `encoding`, returning the text and the name of the encoding used.
+///
+/// With `"auto"`, input that is valid UTF-8 is read as UTF-8 and anything else as
+/// windows-1252, which decodes every byte; multi-byte legacy encodings such as Shift_JIS
+/// have to be named. Malformed input is an error rather than being replaced.
+pub fn decode_text(input: &[u8], encoding: &str) -> Result<(String, &'static str), EditError> {
+    let enc = match encoding.trim() {
+        "auto" if std::str::from_utf8(input).is_ok() => UTF_8,
+        "auto" => WINDOWS_1252,
+        label => lookup_encoding(label)?,
+    };
+    let text = enc
+        .decode_without_bom_handling_and_without_replacement(input)
+        .ok_or_else(|| EditError::new(format!("input is not valid {}", enc.name())))?;
+    Ok((text.into_owned(), enc.name()))
+}
+
+/// Encode `text` in `encoding`; text the encoding cannot represent is an error.
+pub fn encode_text(text: &str, encoding: &str) -> Result<Vec<u8>, EditError> {
+    let enc = lookup_encoding(encoding)?;
+    let (bytes, _, had_errors) = enc.encode(text);
+    if had_errors {
+        return Err(EditError::new(format!(
+            "edited text cannot be represented in {}",
+            enc.name()
+        )));
+    }
+    Ok(bytes.into_owned())
+}
+
+/// Decode `input` from `encoding`, apply `commands`, and re-encode the result.
+///
+/// Returns the edit result (as UTF-8 strings) together with the encoded output bytes,
+/// which end with a newline unless the result is empty, and keep the input's line endings
+/// (see [`EditResult::text`]). Malformed input, and edited
+/// text that `encoding` cannot represent, are errors rather than being replaced.
+pub fn edit_bytes(
+    input: &[u8],
+    encoding: &str,
+    commands: &[Command],
+    opts: &EditOptions,
+) -> Result<(EditResult, Vec<u8>), EditError> {
+    let (text, name) = decode_text(input, encoding)?;
+    let result = edit_text_with(&text, commands, opts)?;
+
+    let bytes = encode_text(&result.text(), name)?;
+    Ok((result, bytes))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::lnhash::format_lnhash;
+    use crate::parse::parse_commands_from_script;
+
+    #[test]
+    fn edits_latin1_bytes_in_place() {
+        // "café\nold\n" in Latin-1 (é = 0xe9).
+        let input = b"caf\xe9\nold\n";
+        let script = format!("{}s/old/né/", format_lnhash(2, "old"));
+        let cmds = parse_commands_from_script(&script).unwrap();
+        let (res, bytes) = edit_bytes(input, "latin1", &cmds, &EditOptions::default()).unwrap();
+        assert_eq!(res.lines, vec!["café", "né"]);
+        assert_eq!(bytes, b"caf\xe9\nn\xe9\n");
+    }
+
+    #[test]
+    fn rejects_unrepresentable_output() {
+        let script = format!("{}s/a/\u{3042}/", format_lnhash(1, "a"));
+        let cmds = parse_commands_from_script(&script).unwrap();
+        let err = edit_bytes(b"a\n", "latin1", &cmds, &EditOptions::default()).unwrap_err();
+        assert!(err.message().contains("cannot be represented"));
+        assert!(edit_bytes(b"a\n", "nope", &[], &EditOptions::default()).is_err());
+    }
+
+    #[test]
+    fn auto_detects_utf8_or_falls_back_to_windows_1252() {
+        assert_eq!(decode_text("café".as_bytes(), "auto").unwrap(), ("café".into(), "UTF-8"));
+        assert_eq!(decode_text(b"caf\xe9", "auto").unwrap(), ("café".into(), "windows-1252"));
+        assert_eq!(encode_text("café", "windows-1252").unwrap(), b"caf\xe9");
+        // Shift_JIS for "あ".
+        assert_eq!(decode_text(b"\x82\xa0", "shift-jis").unwrap().0, "\u{3042}");
+        assert!(check_encoding("auto").is_ok() && check_encoding("utf-16le").is_err());
+    }
+}
@@ -1,22 +1,230 @@
+use std::borrow::Cow;
 use std::collections::BTreeSet;
-
-use regex::{Regex, RegexBuilder};
-
-use crate::lnhash::line_hash_u16;
+use std::fmt;
+use std::ops::{Range, RangeInclusive};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::Instant;
+
+use regex::{Captures, Regex, RegexBuilder, Replacer};
+use unicode_segmentation::UnicodeSegmentation;
+use unicode_width::UnicodeWidthStr;
+
+use crate::gap::GapBuffer;
+use crate::lnhash::{file_fingerprint, AddrStyle, LnHash, LnHashSpec, BOM};
 use crate::parse::{Command, Subcommand, Subst};
-use crate::EditError;
+use crate::{EditError, ErrorKind, HashMismatch};
+
+/// Range loops poll for cancellation once per this many lines.
+const CANCEL_CHECK_INTERVAL: usize = 1024;
 
 /// Result of applying an edit script.
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub struct EditResult {
     /// Full edited content, split into lines (without trailing `\n`).
     pub lines: Vec<String>,
+    /// The ending each line of `lines` should be written with: a line from the input
+    /// keeps its own, and a new line takes the input's predominant ending. See
+    /// [`text`](Self::text).
+    pub endings: Vec<LineEnding>,
     /// lnhash for each line in the edited content (e.g. `"42|a3f2|"`).
     pub hashes: Vec<String>,
+    /// Full 64-bit hash of each line in the edited content; `hashes` carry its low bits.
+    pub full_hashes: Vec<u64>,
     /// New-file 1-based line numbers that are new, changed, reordered, or explicitly printed.
     pub modified: Vec<usize>,
     /// Old-file 1-based line numbers that were removed.
     pub deleted: Vec<usize>,
+    /// What each command did, in the order applied; only filled in with
+    /// [`EditOptions::trace`].
+    pub steps: Vec<CommandStep>,
+    /// Stale addresses found on a nearby line with [`EditOptions::fuzz`], in the order
+    /// applied.
+    pub relocated: Vec<Relocation>,
+    /// Why each command skipped with [`EditOptions::keep_going`] failed, in the order
+    /// applied; see [`EditError::command`] for which command it was.
+    pub skipped: Vec<EditError>,
+    /// How the result differs from the input, in lines, plus the substitutions made.
+    pub stats: EditStats,
+    /// Whether [`text`](Self::text) starts with a UTF-8 byte order mark: when the input
+    /// did, unless [`EditOptions::strip_bom`] is set. The mark is never part of `lines`.
+    pub bom: bool,
+}
+
+impl EditResult {
+    /// The edited content as one string, each line followed by its ending from
+    /// [`endings`](Self::endings), so a CRLF file (or one with mixed endings) is written
+    /// back as it was, apart from the lines that were added. A byte order mark
+    /// ([`bom`](Self::bom)) comes first.
+    pub fn text(&self) -> String {
+        let mut out = String::new();
+        if self.bom {
+            out.push(BOM);
+        }
+        for (line, ending) in self.lines.iter().zip(&self.endings) {
+            out.push_str(line);
+            out.push_str(ending.as_str());
+        }
+        out
+    }
+}
+
+/// How a line is terminated.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum LineEnding {
+    /// `\n`.
+    #[default]
+    Lf,
+    /// `\r\n`, as on Windows.
+    CrLf,
+}
+
+impl LineEnding {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            LineEnding::Lf => "\n",
+            LineEnding::CrLf => "\r\n",
+        }
+    }
+
+    /// The ending of each line of `text`, as split by [`str::lines`], and the one most
+    /// lines use (LF on a tie). A last line without an ending is given the predominant one.
+    pub fn detect(text: &str) -> (Vec<LineEnding>, LineEnding) {
+        let endings: Vec<Option<LineEnding>> = text
+            .split_inclusive('\n')
+            .map(|l| match l {
+                l if l.ends_with("\r\n") => Some(LineEnding::CrLf),
+                l if l.ends_with('\n') => Some(LineEnding::Lf),
+                _ => None,
+            })
+            .collect();
+        let crlf = endings.iter().filter(|&&e| e == Some(LineEnding::CrLf)).count();
+        let lf = endings.iter().filter(|&&e| e == Some(LineEnding::Lf)).count();
+        let predominant = if crlf > lf { LineEnding::CrLf } else { LineEnding::Lf };
+        let endings = endings.into_iter().map(|e| e.unwrap_or(predominant)).collect();
+        (endings, predominant)
+    }
+}
+
+/// Line and substitution counts, for a whole edit ([`EditResult::stats`]) or one command
+/// ([`CommandStep::stats`]).
+///
+/// For a whole edit, lines are counted by comparing the result with the input, so a line
+/// added by one command and deleted by a later one counts for neither. For one command,
+/// they count what that command did.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct EditStats {
+    /// Lines that are new: appended, inserted, copied, or written by `c`.
+    pub added: usize,
+    /// Lines whose text was rewritten in place (moved and sorted lines are not counted).
+    pub changed: usize,
+    /// Lines removed.
+    pub deleted: usize,
+    /// Regex replacements made by `s`; one line can take several with the `g` flag.
+    pub substitutions: usize,
+}
+
+impl EditStats {
+    /// The counts accumulated since `earlier`.
+    fn since(&self, earlier: &EditStats) -> EditStats {
+        EditStats {
+            added: self.added - earlier.added,
+            changed: self.changed - earlier.changed,
+            deleted: self.deleted - earlier.deleted,
+            substitutions: self.substitutions - earlier.substitutions,
+        }
+    }
+}
+
+/// A stale address that [`EditOptions::fuzz`] re-anchored to a nearby line.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Relocation {
+    /// Index of the command the address belongs to.
+    pub command: usize,
+    /// The address as written.
+    pub addr: LnHash,
+    /// 1-based line number the command used instead of `addr.lineno`, in the text as it
+    /// stood when the command ran.
+    pub lineno: usize,
+}
+
+/// Corrections for a stale address, found by [`suggest_addresses`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AddressHint {
+    /// Index of the command the address belongs to.
+    pub command: usize,
+    /// The stale address as written.
+    pub addr: LnHash,
+    /// The line `addr` names as it is now, with its current address, if the text has a
+    /// line there.
+    pub current: Option<(LnHash, String)>,
+    /// Nearby lines that `addr`'s hash matches, nearest first, each with `addr` moved to
+    /// it.
+    pub matches: Vec<(LnHash, String)>,
+}
+
+/// The effect of one applied command, recorded with [`EditOptions::trace`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CommandStep {
+    /// Index of the command in the slice passed to [`edit_text_with`].
+    pub command: usize,
+    /// Lines the command changed, added, moved or printed, as 1-based line numbers and
+    /// text right after it ran (later commands may shift or change them again).
+    pub changed: Vec<(usize, String)>,
+    /// Old-file 1-based line numbers the command removed.
+    pub deleted: Vec<usize>,
+    /// Line count after the command ran.
+    pub line_count: usize,
+    /// What the command added, changed, deleted and substituted.
+    pub stats: EditStats,
+}
+
+/// A custom per-line edit run over a verified range by [`Subcommand::Custom`].
+///
+/// This is a library-only extension point: scripts cannot name a transform, but embedders
+/// can build a [`Command`] around one and get the usual hash verification and
+/// modified/deleted reporting. Closures `Fn(usize, &str) -> Option<String>` implement it.
+pub trait LineTransform: LineTransformClone + Send + Sync {
+    /// Name shown in debug output.
+    fn name(&self) -> &str {
+        "custom"
+    }
+
+    /// Transform one line, where `lineno` is its current 1-based position. Returns `None`
+    /// to leave the line unchanged.
+    fn transform(&self, lineno: usize, line: &str) -> Option<String>;
+}
+
+/// Object-safe cloning for boxed [`LineTransform`]s; implemented for every `Clone` transform.
+pub trait LineTransformClone {
+    fn clone_box(&self) -> Box<dyn LineTransform>;
+}
+
+impl<T: LineTransform + Clone + 'static> LineTransformClone for T {
+    fn clone_box(&self) -> Box<dyn LineTransform> {
+        Box::new(self.clone())
+    }
+}
+
+impl Clone for Box<dyn LineTransform> {
+    fn clone(&self) -> Self {
+        self.clone_box()
+    }
+}
+
+impl fmt::Debug for dyn LineTransform {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "LineTransform({})", self.name())
+    }
+}
+
+impl<F> LineTransform for F
+where
+    F: Fn(usize, &str) -> Option<String> + Clone + Send + Sync + 'static,
+{
+    fn transform(&self, lineno: usize, line: &str) -> Option<String> {
+        self(lineno, line)
+    }
 }
 
 #[derive(Debug, Clone)]
@@ -24,16 +232,22 @@ struct Line {
     text: String,
     origin: Option<usize>,
     modified: bool,
+    /// `modified` as it stood before the current command, while tracing.
+    was_modified: bool,
     global_mark: bool,
 }
 
-struct Engine {
-    lines: Vec<Line>,
+#[derive(Clone)]
+struct Engine<'a> {
+    lines: GapBuffer<Line>,
     deleted: BTreeSet<usize>,
+    /// Running totals of what the commands applied so far did.
+    counts: EditStats,
+    opts: &'a EditOptions,
 }
 
-impl Engine {
-    fn new(input_lines: Vec<String>) -> Self {
+impl<'a> Engine<'a> {
+    fn new(input_lines: Vec<String>, opts: &'a EditOptions) -> Self {
         let lines = input_lines
             .into_iter()
             .enumerate()
@@ -41,12 +255,90 @@ impl Engine {
                 text,
                 origin: Some(i + 1),
                 modified: false,
+                was_modified: false,
                 global_mark: false,
             })
             .collect();
         Self {
-            lines,
+            lines: GapBuffer::from_vec(lines),
             deleted: BTreeSet::new(),
+            counts: EditStats::default(),
+            opts,
+        }
+    }
+
+    fn check_cancelled(&self) -> Result<(), EditError> {
+        if let Some(flag) = &self.opts.cancel {
+            if flag.load(Ordering::Relaxed) {
+                return Err(EditError::with_kind(ErrorKind::Cancelled, "edit cancelled"));
+            }
+        }
+        if let Some(deadline) = self.opts.deadline {
+            if Instant::now() >= deadline {
+                return Err(EditError::with_kind(ErrorKind::Cancelled, "edit deadline exceeded"));
+            }
+        }
+        Ok(())
+    }
+
+    /// Cheap cancellation check for the `i`th iteration of a range loop.
+    fn poll_cancelled(&self, i: usize) -> Result<(), EditError> {
+        if i % CANCEL_CHECK_INTERVAL == CANCEL_CHECK_INTERVAL - 1 {
+            self.check_cancelled()
+        } else {
+            Ok(())
+        }
+    }
+
+    /// Command `index` of `commands`, re-anchored first if [`EditOptions::fuzz`] is set.
+    fn command<'c>(
+        &self,
+        index: usize,
+        commands: &'c [Command],
+        relocated: &mut Vec<Relocation>,
+    ) -> Cow<'c, Command> {
+        if self.opts.fuzz == 0 {
+            Cow::Borrowed(&commands[index])
+        } else {
+            Cow::Owned(self.reanchored(index, &commands[index], relocated))
+        }
+    }
+
+    /// Run `apply` as command `index`, recording the lines it touched.
+    fn traced(
+        &mut self,
+        index: usize,
+        apply: impl FnOnce(&mut Self) -> Result<(), EditError>,
+    ) -> Result<CommandStep, EditError> {
+        for l in self.lines.iter_mut() {
+            l.was_modified = std::mem::take(&mut l.modified);
+        }
+        let deleted_before = self.deleted.clone();
+        let counts_before = self.counts;
+        let res = apply(self);
+        let mut changed = Vec::new();
+        for (i, l) in self.lines.iter_mut().enumerate() {
+            if l.modified {
+                changed.push((i + 1, l.text.clone()));
+            }
+            l.modified |= l.was_modified;
+        }
+        res?;
+        Ok(CommandStep {
+            command: index,
+            changed,
+            deleted: self.deleted.difference(&deleted_before).copied().collect(),
+            line_count: self.lines.len(),
+            stats: self.counts.since(&counts_before),
+        })
+    }
+
+    /// Replace the text of the line at 0-based `idx`, marking it modified if it changed.
+    fn set_text(&mut self, idx: usize, text: String) {
+        if text != self.lines[idx].text {
+            self.lines[idx].text = text;
+            self.lines[idx].modified = true;
+            self.counts.changed += 1;
         }
     }
 
@@ -96,25 +388,92 @@ impl Engine {
         }
     }
 
+    /// `cmd` with each stale address moved to the line [`reanchor`](Self::reanchor) finds
+    /// for it, recording the moves in `relocated`. Addresses it cannot place are left as
+    /// they are, to fail verification as usual.
+    fn reanchored(&self, index: usize, cmd: &Command, relocated: &mut Vec<Relocation>) -> Command {
+        let mut cmd = cmd.clone();
+        let mut fix = |addr: &mut LnHash| {
+            let stale = self
+                .verify_lnhash_basic(*addr)
+                .is_err_and(|e| e.kind() == ErrorKind::StaleHash);
+            if let Some(lineno) = stale.then(|| self.reanchor(*addr)).flatten() {
+                relocated.push(Relocation { command: index, addr: *addr, lineno });
+                addr.lineno = lineno;
+            }
+        };
+        if cmd.addr1.lineno != 0 {
+            fix(&mut cmd.addr1);
+        }
+        if let Some(a2) = &mut cmd.addr2 {
+            fix(a2);
+        }
+        let mut sub = &mut cmd.cmd;
+        while let Subcommand::Global { cmd, .. } = sub {
+            sub = cmd;
+        }
+        if let Subcommand::Move { dest } | Subcommand::Copy { dest } = sub {
+            fix(dest);
+        }
+        cmd
+    }
+
+    /// The nearest line within [`EditOptions::fuzz`] lines of a stale `addr` that it
+    /// verifies against, or `None` if there is none or the nearest matches above and below
+    /// are equally close.
+    fn reanchor(&self, addr: LnHash) -> Option<usize> {
+        for d in 1..=self.opts.fuzz {
+            let above = addr.lineno.checked_sub(d).filter(|&n| n > 0);
+            let mut hits = [above, Some(addr.lineno + d)]
+                .into_iter()
+                .flatten()
+                .filter(|&lineno| self.verify_lnhash_basic(LnHash { lineno, ..addr }).is_ok());
+            match (hits.next(), hits.next()) {
+                (Some(n), None) => return Some(n),
+                (Some(_), Some(_)) => return None,
+                _ => {}
+            }
+        }
+        None
+    }
+
+    /// The [`AddressHint`] for command `index`'s stale `addr`, looking `window` lines
+    /// either side for lines it matches.
+    fn hint(&self, index: usize, addr: LnHash, window: usize) -> AddressHint {
+        let address_of = |lineno: usize| {
+            let i = lineno - 1;
+            let prev = i.checked_sub(1).map(|p| self.lines[p].text.as_str());
+            let line = &self.lines[i].text;
+            (self.opts.hash_spec.lnhash_after(lineno, prev, line), line.clone())
+        };
+        let current = (addr.lineno <= self.lines.len()).then(|| address_of(addr.lineno));
+        let mut matches = Vec::new();
+        for d in 1..=window {
+            let above = addr.lineno.checked_sub(d).filter(|&n| n > 0);
+            for lineno in [above, Some(addr.lineno + d)].into_iter().flatten() {
+                let moved = LnHash { lineno, ..addr };
+                if self.verify_lnhash_basic(moved).is_ok() {
+                    matches.push((moved, self.lines[lineno - 1].text.clone()));
+                }
+            }
+        }
+        AddressHint { command: index, addr, current, matches }
+    }
+
     fn verify_lnhash_basic(&self, addr: crate::LnHash) -> Result<(), EditError> {
         if addr.lineno == 0 {
             return Err(EditError::new("address 0 is not allowed here"));
         }
         if addr.lineno > self.lines.len() {
-            return Err(EditError::new(format!(
+            return Err(EditError::with_kind(ErrorKind::OutOfRange, format!(
                 "address out of range: {} > {}",
                 addr.lineno,
                 self.lines.len()
             )));
         }
-        let actual = line_hash_u16(&self.lines[addr.lineno - 1].text);
-        if actual != addr.hash {
-            return Err(EditError::new(format!(
-                "stale lnhash at line {}: expected {:04x}, got {:04x}",
-                addr.lineno, addr.hash, actual
-            )));
-        }
-        Ok(())
+        let i = addr.lineno - 1;
+        let prev = i.checked_sub(1).map(|p| self.lines[p].text.as_str());
+        self.opts.hash_spec.verify(&addr, prev, &self.lines[i].text)
     }
 
     fn apply_subcommand(
@@ -148,6 +507,7 @@ impl Engine {
             Subcommand::Dedent { levels } => self.dedent_range(start, end, *levels),
             Subcommand::Sort => self.sort_range(start, end),
             Subcommand::Print => self.print_range(start, end),
+            Subcommand::Custom(t) => self.transform_range(start, end, t.as_ref()),
         }
     }
 
@@ -161,7 +521,7 @@ impl Engine {
             )));
         }
         if end > self.lines.len() {
-            return Err(EditError::new(format!(
+            return Err(EditError::with_kind(ErrorKind::OutOfRange, format!(
                 "address out of range: {end} > {}",
                 self.lines.len()
             )));
@@ -171,35 +531,64 @@ impl Engine {
 
     fn delete_range(&mut self, start: usize, end: usize) -> Result<(), EditError> {
         let (s, e) = self.resolve_range(start, end)?;
-        let removed: Vec<Line> = self.lines.drain(s..=e).collect();
+        let removed = self.lines.splice(s, e - s + 1, []);
+        self.record_deleted(removed);
+        Ok(())
+    }
+
+    fn record_deleted(&mut self, removed: Vec<Line>) {
+        self.counts.deleted += removed.len();
         for l in removed {
             if let Some(o) = l.origin {
                 self.deleted.insert(o);
             }
         }
-        Ok(())
     }
 
     fn substitute_range(&mut self, start: usize, end: usize, s: &Subst) -> Result<(), EditError> {
         let (s_idx, e_idx) = self.resolve_range(start, end)?;
         let re = build_regex(&s.pattern, s.case_insensitive)?;
+        let mut matched = false;
         for idx in s_idx..=e_idx {
-            let old = self.lines[idx].text.clone();
-            let new = if s.global {
-                re.replace_all(&old, s.replacement.as_str()).to_string()
-            } else {
-                // replace first match
-                if !re.is_match(&old) {
-                    continue;
-                }
-                re.replace(&old, s.replacement.as_str()).to_string()
-            };
-            if new != old {
-                self.lines[idx].text = new;
-                self.lines[idx].modified = true;
-            }
+            self.poll_cancelled(idx)?;
+            matched |= self.substitute_line(idx, &re, s);
         }
-        Ok(())
+        self.check_matched(matched, s)
+    }
+
+    /// With [`EditOptions::strict_substitute`], fail unless `s` `matched` some line.
+    fn check_matched(&self, matched: bool, s: &Subst) -> Result<(), EditError> {
+        if matched || !self.opts.strict_substitute {
+            return Ok(());
+        }
+        Err(EditError::new(format!("substitute pattern {:?} matched nothing", s.pattern)))
+    }
+
+    /// Run `s`, with its pattern compiled as `re`, on the line at 0-based `idx`, returning
+    /// whether the pattern matched. A line the pattern does not match is only searched:
+    /// nothing is allocated for it.
+    ///
+    /// With [`Subst::columns`], the pattern sees only the part of the line in those columns,
+    /// as if it were the whole line (`^` matches at its first column).
+    fn substitute_line(&mut self, idx: usize, re: &Regex, s: &Subst) -> bool {
+        let mut rep = CountingReplacer { replacement: &s.replacement, count: 0 };
+        let limit = if s.global { 0 } else { 1 };
+        let text = &self.lines[idx].text;
+        let span = match &s.columns {
+            Some(columns) => match column_span(text, columns) {
+                Some(span) => span,
+                None => return false,
+            },
+            None => 0..text.len(),
+        };
+        let new = match re.replacen(&text[span.clone()], limit, rep.by_ref()) {
+            Cow::Owned(new) if span.len() == text.len() => new,
+            Cow::Owned(new) => format!("{}{new}{}", &text[..span.start], &text[span.end..]),
+            Cow::Borrowed(_) => return false,
+        };
+        self.counts.substitutions += rep.count;
+        self.set_text(idx, new);
+        true
     }
 
     fn append_after(&mut self, start: usize, end: usize, text: &[String]) -> Result<(), EditError> {
@@ -221,17 +610,8 @@ impl Engine {
             return Ok(());
         }
 
-        let new_lines: Vec<Line> = text
-            .iter()
-            .map(|t| Line {
-                text: t.clone(),
-                origin: None,
-                modified: true,
-                global_mark: false,
-            })
-            .collect();
-
-        self.lines.splice(insert_at..insert_at, new_lines);
+        self.lines.splice(insert_at, 0, new_lines(text));
+        self.counts.added += text.len();
         Ok(())
     }
 
@@ -252,44 +632,16 @@ impl Engine {
             return Ok(());
         }
 
-        let new_lines: Vec<Line> = text
-            .iter()
-            .map(|t| Line {
-                text: t.clone(),
-                origin: None,
-                modified: true,
-                global_mark: false,
-            })
-            .collect();
-
-        self.lines.splice(insert_at..insert_at, new_lines);
+        self.lines.splice(insert_at, 0, new_lines(text));
+        self.counts.added += text.len();
         Ok(())
     }
 
     fn change_range(&mut self, start: usize, end: usize, text: &[String]) -> Result<(), EditError> {
         let (s, e) = self.resolve_range(start, end)?;
-        let removed: Vec<Line> = self.lines.drain(s..=e).collect();
-        for l in removed {
-            if let Some(o) = l.origin {
-                self.deleted.insert(o);
-            }
-        }
-
-        if text.is_empty() {
-            return Ok(());
-        }
-
-        let new_lines: Vec<Line> = text
-            .iter()
-            .map(|t| Line {
-                text: t.clone(),
-                origin: None,
-                modified: true,
-                global_mark: false,
-            })
-            .collect();
-
-        self.lines.splice(s..s, new_lines);
+        let removed = self.lines.splice(s, e - s + 1, new_lines(text));
+        self.counts.added += text.len();
+        self.record_deleted(removed);
         Ok(())
     }
 
@@ -304,15 +656,10 @@ impl Engine {
             return Err(EditError::new("cannot join: no next line"));
         }
         let idx = line - 1;
-        let joined = join_strings(&self.lines[idx].text, &self.lines[idx + 1].text);
-        if joined != self.lines[idx].text {
-            self.lines[idx].text = joined;
-            self.lines[idx].modified = true;
-        }
-        let removed = self.lines.remove(idx + 1);
-        if let Some(o) = removed.origin {
-            self.deleted.insert(o);
-        }
+        let joined = join_strings(&self.lines[idx].text, &self.lines[idx + 1].text, &self.opts.join);
+        self.set_text(idx, joined);
+        let removed = self.lines.splice(idx + 1, 1, []);
+        self.record_deleted(removed);
         Ok(())
     }
 
@@ -323,19 +670,12 @@ impl Engine {
         }
         let mut joined = self.lines[s].text.clone();
         for i in (s + 1)..=e {
-            joined = join_strings(&joined, &self.lines[i].text);
-        }
-        if joined != self.lines[s].text {
-            self.lines[s].text = joined;
-            self.lines[s].modified = true;
+            joined = join_strings(&joined, &self.lines[i].text, &self.opts.join);
         }
+        self.set_text(s, joined);
         // Remove the rest.
-        let removed: Vec<Line> = self.lines.drain((s + 1)..=e).collect();
-        for l in removed {
-            if let Some(o) = l.origin {
-                self.deleted.insert(o);
-            }
-        }
+        let removed = self.lines.splice(s + 1, e - s, []);
+        self.record_deleted(removed);
         Ok(())
     }
 
@@ -345,7 +685,7 @@ impl Engine {
             return Err(EditError::new("destination 0 is not allowed"));
         }
         if dest > self.lines.len() {
-            return Err(EditError::new(format!(
+            return Err(EditError::with_kind(ErrorKind::OutOfRange, format!(
                 "destination out of range: {dest} > {}",
                 self.lines.len()
             )));
@@ -355,7 +695,7 @@ impl Engine {
         }
 
         let seg_len = e - s + 1;
-        let mut seg: Vec<Line> = self.lines.drain(s..=e).collect();
+        let mut seg = self.lines.splice(s, seg_len, []);
         for l in &mut seg {
             l.modified = true;
         }
@@ -367,7 +707,7 @@ impl Engine {
             dest - seg_len
         };
 
-        self.lines.splice(insert_at..insert_at, seg);
+        self.lines.splice(insert_at, 0, seg);
         Ok(())
     }
 
@@ -377,24 +717,24 @@ impl Engine {
             return Err(EditError::new("destination 0 is not allowed"));
         }
         if dest > self.lines.len() {
-            return Err(EditError::new(format!(
+            return Err(EditError::with_kind(ErrorKind::OutOfRange, format!(
                 "destination out of range: {dest} > {}",
                 self.lines.len()
             )));
         }
 
-        let mut seg: Vec<Line> = self.lines[s..=e]
-            .iter()
-            .map(|l| Line {
-                text: l.text.clone(),
+        let seg: Vec<Line> = (s..=e)
+            .map(|idx| Line {
+                text: self.lines[idx].text.clone(),
                 origin: None,
                 modified: true,
+                was_modified: false,
                 global_mark: false,
             })
             .collect();
 
-        let insert_at = dest;
-        self.lines.splice(insert_at..insert_at, seg.drain(..));
+        self.counts.added += seg.len();
+        self.lines.splice(dest, 0, seg);
         Ok(())
     }
 
@@ -403,11 +743,11 @@ impl Engine {
         if levels == 0 {
             return Ok(());
         }
-        let prefix = "    ".repeat(levels);
+        let prefix = " ".repeat(self.opts.indent_width * levels);
         for idx in s..=e {
+            self.poll_cancelled(idx)?;
             let new = format!("{}{}", prefix, self.lines[idx].text);
-            self.lines[idx].text = new;
-            self.lines[idx].modified = true;
+            self.set_text(idx, new);
         }
         Ok(())
     }
@@ -418,12 +758,9 @@ impl Engine {
             return Ok(());
         }
         for idx in s..=e {
-            let old = self.lines[idx].text.clone();
-            let new = dedent(&old, levels);
-            if new != old {
-                self.lines[idx].text = new;
-                self.lines[idx].modified = true;
-            }
+            self.poll_cancelled(idx)?;
+            let new = dedent(&self.lines[idx].text, levels, self.opts.indent_width);
+            self.set_text(idx, new);
         }
         Ok(())
     }
@@ -433,17 +770,33 @@ impl Engine {
         if s == e {
             return Ok(());
         }
-        let before: Vec<String> = self.lines[s..=e].iter().map(|l| l.text.clone()).collect();
-        self.lines[s..=e].sort_by(|a, b| a.text.cmp(&b.text));
-        let after: Vec<String> = self.lines[s..=e].iter().map(|l| l.text.clone()).collect();
-        if before != after {
-            for l in &mut self.lines[s..=e] {
+        let seg = self.lines.range_mut(s, e + 1);
+        let before: Vec<String> = seg.iter().map(|l| l.text.clone()).collect();
+        seg.sort_by(|a, b| a.text.cmp(&b.text));
+        if seg.iter().zip(&before).any(|(l, b)| l.text != *b) {
+            for l in seg.iter_mut() {
                 l.modified = true;
             }
         }
         Ok(())
     }
 
+    fn transform_range(
+        &mut self,
+        start: usize,
+        end: usize,
+        t: &dyn LineTransform,
+    ) -> Result<(), EditError> {
+        let (s, e) = self.resolve_range(start, end)?;
+        for idx in s..=e {
+            self.poll_cancelled(idx)?;
+            if let Some(new) = t.transform(idx + 1, &self.lines[idx].text) {
+                self.set_text(idx, new);
+            }
+        }
+        Ok(())
+    }
+
     fn print_range(&mut self, start: usize, end: usize) -> Result<(), EditError> {
         let (s, e) = self.resolve_range(start, end)?;
         for idx in s..=e {
@@ -464,17 +817,35 @@ impl Engine {
         let re = build_regex(pattern, false)?;
 
         // Clear existing marks.
-        for l in &mut self.lines {
+        for l in self.lines.iter_mut() {
             l.global_mark = false;
         }
 
         for idx in s..=e {
+            self.poll_cancelled(idx)?;
             let m = re.is_match(&self.lines[idx].text);
             self.lines[idx].global_mark = if invert { !m } else { m };
         }
 
+        // A substitution keeps the line count, so the marked lines can be visited in one
+        // pass, with the pattern compiled once rather than per line.
+        if let Subcommand::Substitute(subst) = subcmd {
+            let re = build_regex(&subst.pattern, subst.case_insensitive)?;
+            let mut matched = false;
+            for idx in s..=e {
+                self.poll_cancelled(idx)?;
+                if std::mem::take(&mut self.lines[idx].global_mark) {
+                    matched |= self.substitute_line(idx, &re, subst);
+                }
+            }
+            return self.check_matched(matched, subst);
+        }
+
         let mut idx = 0usize;
+        let mut steps = 0usize;
         while idx < self.lines.len() {
+            self.poll_cancelled(steps)?;
+            steps += 1;
             if self.lines[idx].global_mark {
                 self.lines[idx].global_mark = false;
                 // Apply subcommand to this line (single-line address, no comma).
@@ -487,7 +858,7 @@ impl Engine {
         }
 
         // Ensure marks are cleared.
-        for l in &mut self.lines {
+        for l in self.lines.iter_mut() {
             l.global_mark = false;
         }
 
@@ -495,40 +866,405 @@ impl Engine {
     }
 }
 
+/// Options controlling how an edit script is applied.
+#[derive(Debug, Clone)]
+pub struct EditOptions {
+    /// Apply commands bottom-up (highest address first) instead of in script order, so
+    /// that earlier commands cannot shift the lines later commands refer to. Fails if any
+    /// two commands touch overlapping lines (see [`bottom_up_order`]).
+    pub bottom_up: bool,
+    /// How `j` combines adjacent lines.
+    pub join: JoinPolicy,
+    /// Cancellation flag, checked between commands and periodically inside range loops.
+    /// Setting it makes the edit fail with [`ErrorKind::Cancelled`].
+    pub cancel: Option<Arc<AtomicBool>>,
+    /// Deadline after which the edit fails with [`ErrorKind::Cancelled`].
+    pub deadline: Option<Instant>,
+    /// Textual form of the addresses reported in [`EditResult::hashes`].
+    pub addr_style: AddrStyle,
+    /// How line hashes are computed, both to verify input addresses and to produce
+    /// [`EditResult::hashes`]. Input addresses are verified at whatever width,
+    /// normalization and algorithm they were written with; only the seed must match.
+    pub hash_spec: LnHashSpec,
+    /// Expected [`file_fingerprint`] of the input, checked before any line-level
+    /// verification to catch edits aimed at the wrong file entirely.
+    pub fingerprint: Option<String>,
+    /// Record what each command did in [`EditResult::steps`]. Costs a pass over the
+    /// lines per command.
+    pub trace: bool,
+    /// When an address is stale, look up to this many lines above and below for the line
+    /// it names and use that instead, reporting the move in [`EditResult::relocated`].
+    /// 0 (the default) disables re-anchoring.
+    pub fuzz: usize,
+    /// Skip commands that fail instead of failing the whole edit, collecting their errors
+    /// in [`EditResult::skipped`]. Later commands see the text without the skipped ones,
+    /// even when one failed partway (a global stopping on its third line), at the cost of
+    /// copying the lines before each `g`/`v`. Cancellation still fails the edit.
+    pub keep_going: bool,
+    /// Addresses the result must match once every command has run, so a caller can assert
+    /// the edit came out exactly as intended. Any mismatch fails the edit with
+    /// [`ErrorKind::ExpectationFailed`].
+    pub expect: Vec<LnHash>,
+    /// Expected [`file_fingerprint`] of the result (its lines, each ending in `\n`),
+    /// checked like [`expect`](Self::expect).
+    pub expect_fingerprint: Option<String>,
+    /// Spaces per level for `>` and `<` (4 by default). `<` also removes a leading tab,
+    /// or fewer spaces than this, as one level.
+    pub indent_width: usize,
+    /// Drop the input's byte order mark from [`EditResult::text`] rather than keep it.
+    /// Either way the mark is split off before the edit, so it is not part of line 1's
+    /// text or hash and cannot end up on another line.
+    pub strip_bom: bool,
+    /// Fail an `s` whose pattern matches nothing in its range (under a global, on none of
+    /// the lines the global selects) rather than leave the lines as they are, so a pattern
+    /// that no longer fits the text is noticed.
+    pub strict_substitute: bool,
+}
+
+impl Default for EditOptions {
+    fn default() -> Self {
+        Self {
+            bottom_up: false,
+            join: JoinPolicy::default(),
+            cancel: None,
+            deadline: None,
+            addr_style: AddrStyle::Standard,
+            hash_spec: LnHashSpec::default(),
+            fingerprint: None,
+            trace: false,
+            fuzz: 0,
+            keep_going: false,
+            expect: Vec::new(),
+            expect_fingerprint: None,
+            indent_width: 4,
+            strip_bom: false,
+            strict_substitute: false,
+        }
+    }
+}
+
+/// How the join (`j`) command combines adjacent lines.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub enum JoinPolicy {
+    /// Insert a single space unless the seam already has whitespace on either side, or
+    /// either line is empty.
+    #[default]
+    Smart,
+    /// Always insert a single space.
+    Always,
+    /// Concatenate lines directly, e.g. to rejoin a call split across lines.
+    Never,
+    /// Insert a custom separator.
+    Separator(String),
+}
+
 /// Apply `commands` to the input text.
 ///
 /// Each command's lnhashes are verified against the current text immediately before that
 /// command is applied.
 pub fn edit_text(input: &str, commands: &[Command]) -> Result<EditResult, EditError> {
-    let input_lines: Vec<String> = input.lines().map(|l| l.to_string()).collect();
+    edit_text_with(input, commands, &EditOptions::default())
+}
 
-    let mut eng = Engine::new(input_lines);
-    for c in commands {
-        eng.verify_command(c)?;
-        eng.apply_command(c)?;
+/// Apply `commands` to the input text using `opts`.
+pub fn edit_text_with(
+    input: &str,
+    commands: &[Command],
+    opts: &EditOptions,
+) -> Result<EditResult, EditError> {
+    let (input_lines, order) = prepare(input, commands, opts)?;
+    let mut eng = Engine::new(input_lines, opts);
+    let mut steps = Vec::new();
+    let mut relocated = Vec::new();
+    let mut skipped = Vec::new();
+    for i in order {
+        eng.check_cancelled()?;
+        let relocated_before = relocated.len();
+        let c = eng.command(i, commands, &mut relocated);
+        let c = c.as_ref();
+        // Only a global can fail after it has changed the text (on its third line, say);
+        // every other command fails before touching it. So with `keep_going`, the state to
+        // go back to if the command is skipped is copied only before a global.
+        let global = matches!(c.cmd, Subcommand::Global { .. });
+        let saved = (opts.keep_going && global).then(|| eng.clone());
+        let applied = eng.verify_command(c).and_then(|()| {
+            if opts.trace {
+                steps.push(eng.traced(i, |eng| eng.apply_command(c))?);
+                Ok(())
+            } else {
+                eng.apply_command(c)
+            }
+        });
+        match applied.map_err(|e| e.at_command(i)) {
+            Err(e) if opts.keep_going && e.kind() != ErrorKind::Cancelled => {
+                if let Some(saved) = saved {
+                    eng = saved;
+                }
+                relocated.truncate(relocated_before);
+                skipped.push(e);
+            }
+            res => res?,
+        }
     }
 
-    let lines: Vec<String> = eng.lines.iter().map(|l| l.text.clone()).collect();
-    let hashes: Vec<String> = lines
+    let eng_lines = eng.lines.into_vec();
+    let lines: Vec<String> = eng_lines.iter().map(|l| l.text.clone()).collect();
+    check_expectations(&lines, opts)?;
+    let (hashes, full_hashes): (Vec<String>, Vec<u64>) = opts
+        .hash_spec
+        .hash_lines(lines.iter().map(String::as_str))
+        .into_iter()
+        .map(|(a, full)| (a.format_with(opts.addr_style), full))
+        .unzip();
+
+    let modified: Vec<usize> = eng_lines
         .iter()
         .enumerate()
-        .map(|(i, l)| format!("{}|{:04x}|", i + 1, line_hash_u16(l)))
+        .filter_map(|(i, l)| if l.modified { Some(i + 1) } else { None })
         .collect();
 
-    let modified: Vec<usize> = eng
-        .lines
+    let deleted: Vec<usize> = eng.deleted.into_iter().collect();
+
+    let (input_endings, predominant) = LineEnding::detect(input);
+    let (input, bom) = match input.strip_prefix(BOM) {
+        Some(rest) => (rest, !opts.strip_bom),
+        None => (input, false),
+    };
+    let endings = eng_lines
         .iter()
-        .enumerate()
-        .filter_map(|(i, l)| if l.modified { Some(i + 1) } else { None })
+        .map(|l| l.origin.and_then(|o| input_endings.get(o - 1).copied()).unwrap_or(predominant))
         .collect();
 
-    let deleted: Vec<usize> = eng.deleted.into_iter().collect();
+    let originals: Vec<&str> = input.lines().collect();
+    let stats = EditStats {
+        added: eng_lines.iter().filter(|l| l.origin.is_none()).count(),
+        changed: eng_lines
+            .iter()
+            .filter(|l| l.origin.is_some_and(|o| l.text != originals[o - 1]))
+            .count(),
+        deleted: deleted.len(),
+        substitutions: eng.counts.substitutions,
+    };
 
     Ok(EditResult {
         lines,
+        endings,
         hashes,
+        full_hashes,
         modified,
         deleted,
+        steps,
+        relocated,
+        skipped,
+        stats,
+        bom,
+    })
+}
+
+/// Check every command's lnhashes without producing a result, reporting all failures.
+///
+/// Commands are checked in the order [`edit_text_with`] would apply them, each against the
+/// text as edited by the commands before it, so later addresses see earlier line shifts.
+/// A command that fails is skipped and checking continues, so a single pass lists every
+/// stale address; each error's [`command`](EditError::command) says which command it
+/// belongs to. An empty result means `edit_text_with` would succeed.
+pub fn verify_commands(input: &str, commands: &[Command], opts: &EditOptions) -> Vec<EditError> {
+    let (input_lines, order) = match prepare(input, commands, opts) {
+        Ok(p) => p,
+        Err(e) => return vec![e],
+    };
+    let mut eng = Engine::new(input_lines, opts);
+    let mut errors = Vec::new();
+    for i in order {
+        if let Err(e) = eng.check_cancelled() {
+            errors.push(e);
+            break;
+        }
+        let c = eng.command(i, commands, &mut Vec::new());
+        let c = c.as_ref();
+        if let Err(e) = eng.verify_command(c).and_then(|()| eng.apply_command(c)) {
+            errors.push(e.at_command(i));
+        }
+    }
+    errors
+}
+
+/// Suggest corrected addresses for every stale address in `commands`.
+///
+/// Commands are run as [`verify_commands`] runs them, skipping the ones that fail, so each
+/// stale address is looked up in the text as it stood when its command would have run.
+/// For each one the hint gives that line's current address and text, and the lines within
+/// `window` lines either side whose text the stale hash still matches: usually the line
+/// it meant, moved by an edit made since the address was fetched.
+pub fn suggest_addresses(
+    input: &str,
+    commands: &[Command],
+    opts: &EditOptions,
+    window: usize,
+) -> Vec<AddressHint> {
+    let Ok((input_lines, order)) = prepare(input, commands, opts) else {
+        return Vec::new();
+    };
+    let mut eng = Engine::new(input_lines, opts);
+    let mut hints = Vec::new();
+    for i in order {
+        let c = eng.command(i, commands, &mut Vec::new());
+        let c = c.as_ref();
+        match eng.verify_command(c) {
+            Ok(()) => {
+                let _ = eng.apply_command(c);
+            }
+            Err(e) if e.kind() == ErrorKind::StaleHash => {
+                let mut addrs = vec![c.addr1];
+                addrs.extend(c.addr2);
+                let mut sub = &c.cmd;
+                while let Subcommand::Global { cmd, .. } = sub {
+                    sub = cmd;
+                }
+                if let Subcommand::Move { dest } | Subcommand::Copy { dest } = sub {
+                    addrs.push(*dest);
+                }
+                let stale = addrs.into_iter().filter(|&a| {
+                    let check = eng.verify_lnhash_basic(a);
+                    a.lineno != 0 && check.is_err_and(|e| e.kind() == ErrorKind::StaleHash)
+                });
+                hints.extend(stale.map(|addr| eng.hint(i, addr, window)));
+            }
+            Err(_) => {}
+        }
+    }
+    hints
+}
+
+/// Check the edited `lines` against [`EditOptions::expect`] and
+/// [`EditOptions::expect_fingerprint`].
+fn check_expectations(lines: &[String], opts: &EditOptions) -> Result<(), EditError> {
+    let failed = |msg: String| EditError::with_kind(ErrorKind::ExpectationFailed, msg);
+    for addr in &opts.expect {
+        let Some(line) = addr.lineno.checked_sub(1).and_then(|i| lines.get(i)) else {
+            return Err(failed(format!(
+                "expected {addr}, but the result has {} line(s)",
+                lines.len()
+            )));
+        };
+        let prev = (addr.lineno > 1).then(|| lines[addr.lineno - 2].as_str());
+        if opts.hash_spec.verify(addr, prev, line).is_err() {
+            return Err(failed(format!("expected {addr}, but line {} is {line:?}", addr.lineno)));
+        }
+    }
+    if let Some(expected) = &opts.expect_fingerprint {
+        let mut text = lines.join("\n");
+        if !lines.is_empty() {
+            text.push('\n');
+        }
+        let actual = file_fingerprint(&text);
+        if !expected.eq_ignore_ascii_case(&actual) {
+            return Err(failed(format!(
+                "expected result fingerprint {expected}, got {actual}"
+            )));
+        }
+    }
+    Ok(())
+}
+
+/// Check `opts` and the file fingerprint, split `input` into lines, and pick the order in
+/// which to apply `commands`.
+fn prepare(
+    input: &str,
+    commands: &[Command],
+    opts: &EditOptions,
+) -> Result<(Vec<String>, Vec<usize>), EditError> {
+    opts.hash_spec.validate()?;
+    if let Some(expected) = &opts.fingerprint {
+        let actual = file_fingerprint(input);
+        if !expected.eq_ignore_ascii_case(&actual) {
+            return Err(EditError::stale(format!(
+                "file fingerprint mismatch: expected {expected}, got {actual} (wrong file?)"
+            ))
+            .with_mismatch(HashMismatch { lineno: None, expected: expected.clone(), actual }));
+        }
+    }
+    let input = input.strip_prefix(BOM).unwrap_or(input);
+    let input_lines: Vec<String> = input.lines().map(|l| l.to_string()).collect();
+
+    let order: Vec<usize> = if opts.bottom_up {
+        bottom_up_order(commands)?
+    } else {
+        (0..commands.len()).collect()
+    };
+    Ok((input_lines, order))
+}
+
+/// Compute a bottom-up application order for `commands`, returning command indices.
+///
+/// Commands are ordered by descending address. Because a command only shifts lines at
+/// or below its own span, applying them bottom-up keeps every later address valid. This
+/// only holds when spans are disjoint, so overlapping commands are reported as
+/// order-dependent. Useful as a lint for generated scripts.
+pub fn bottom_up_order(commands: &[Command]) -> Result<Vec<usize>, EditError> {
+    let mut spans: Vec<(usize, usize, usize)> = commands
+        .iter()
+        .enumerate()
+        .map(|(i, c)| {
+            let (lo, hi) = command_span(c);
+            (lo, hi, i)
+        })
+        .collect();
+    spans.sort_by(|a, b| b.0.cmp(&a.0).then(a.2.cmp(&b.2)));
+    for w in spans.windows(2) {
+        let (upper, lower) = (w[0], w[1]);
+        if lower.1 >= upper.0 {
+            let (a, b) = (upper.2.min(lower.2), upper.2.max(lower.2));
+            return Err(EditError::new(format!(
+                "commands {} and {} are order-dependent (overlapping lines)",
+                a + 1,
+                b + 1
+            )));
+        }
+    }
+    Ok(spans.into_iter().map(|(_, _, i)| i).collect())
+}
+
+/// The positions a command touches, in doubled coordinates: line `n` is `2n` and the gap
+/// after line `n` is `2n + 1`. Inserting after a line does not touch the line itself, so
+/// e.g. `s` on line 3 and `a` after line 3 are independent.
+fn command_span(cmd: &Command) -> (usize, usize) {
+    let start = cmd.addr1.lineno;
+    let end = cmd.addr2.map(|a| a.lineno).unwrap_or(start);
+    subcommand_span(start, end, cmd.has_comma, &cmd.cmd)
+}
+
+fn subcommand_span(start: usize, end: usize, has_comma: bool, sub: &Subcommand) -> (usize, usize) {
+    match sub {
+        Subcommand::Append(_) => {
+            let after = if start == 0 { 0 } else { end };
+            (2 * after + 1, 2 * after + 1)
+        }
+        Subcommand::Insert(_) => {
+            let gap = if start == 0 { 1 } else { 2 * start - 1 };
+            (gap, gap)
+        }
+        Subcommand::Join if !has_comma => (2 * start, 2 * start + 2),
+        Subcommand::Move { dest } | Subcommand::Copy { dest } => {
+            let gap = 2 * dest.lineno + 1;
+            ((2 * start).min(gap), (2 * end).max(gap))
+        }
+        Subcommand::Global { cmd, .. } => {
+            // Per-line subcommands may reach past the range (e.g. `a` after its last line).
+            let (lo, hi) = subcommand_span(start, end, false, cmd);
+            ((2 * start).min(lo), (2 * end + 1).max(hi))
+        }
+        _ => (2 * start, 2 * end),
+    }
+}
+
+fn new_lines(text: &[String]) -> impl Iterator<Item = Line> + '_ {
+    text.iter().map(|t| Line {
+        text: t.clone(),
+        origin: None,
+        modified: true,
+        was_modified: false,
+        global_mark: false,
     })
 }
 
@@ -537,13 +1273,57 @@ fn build_regex(pattern: &str, case_insensitive: bool) -> Result<Regex, EditError
         RegexBuilder::new(pattern)
             .case_insensitive(true)
             .build()
-            .map_err(|e| EditError::new(format!("invalid regex: {e}")))
+            .map_err(|e| EditError::parse(format!("invalid regex: {e}")))
     } else {
-        Regex::new(pattern).map_err(|e| EditError::new(format!("invalid regex: {e}")))
+        Regex::new(pattern).map_err(|e| EditError::parse(format!("invalid regex: {e}")))
+    }
+}
+
+/// The bytes of `text` in the display `columns` (1-based, inclusive): the grapheme clusters
+/// wholly inside them, so a wide character straddling either edge is left out. `None` if
+/// there are none. A range built by hand from column 0 starts at column 1.
+fn column_span(text: &str, columns: &RangeInclusive<usize>) -> Option<Range<usize>> {
+    let mut span: Option<Range<usize>> = None;
+    let mut col = 0;
+    for (i, g) in text.grapheme_indices(true) {
+        let next = match g {
+            "\t" => (col / 8 + 1) * 8,
+            g => col + g.width(),
+        };
+        if col < columns.start().saturating_sub(1) {
+            col = next;
+            continue;
+        }
+        if next > *columns.end() {
+            break;
+        }
+        span.get_or_insert(i..i).end = i + g.len();
+        col = next;
     }
+    span
 }
 
-fn join_strings(a: &str, b: &str) -> String {
+/// The replacement of an `s` command, counting the matches it replaces so that a line is
+/// searched only once.
+struct CountingReplacer<'a> {
+    replacement: &'a str,
+    count: usize,
+}
+
+impl Replacer for CountingReplacer<'_> {
+    fn replace_append(&mut self, caps: &Captures<'_>, dst: &mut String) {
+        self.count += 1;
+        caps.expand(self.replacement, dst);
+    }
+}
+
+fn join_strings(a: &str, b: &str, policy: &JoinPolicy) -> String {
+    match policy {
+        JoinPolicy::Smart => {}
+        JoinPolicy::Always => return format!("{a} {b}"),
+        JoinPolicy::Never => return format!("{a}{b}"),
+        JoinPolicy::Separator(sep) => return format!("{a}{sep}{b}"),
+    }
     if a.is_empty() {
         return b.to_string();
     }
@@ -559,36 +1339,31 @@ fn join_strings(a: &str, b: &str) -> String {
     }
 }
 
-fn dedent(line: &str, levels: usize) -> String {
-    let mut s = line.to_string();
+fn dedent(line: &str, levels: usize, width: usize) -> String {
+    let mut s = line;
     for _ in 0..levels {
-        if s.starts_with("    ") {
-            s = s[4..].to_string();
+        if let Some(rest) = s.strip_prefix('\t') {
+            s = rest;
             continue;
         }
-        if s.starts_with('\t') {
-            s = s[1..].to_string();
-            continue;
-        }
-        // Remove up to 4 leading spaces as one level.
-        let mut removed = 0usize;
-        let bytes = s.as_bytes();
-        while removed < 4 && removed < bytes.len() && bytes[removed] == b' ' {
-            removed += 1;
-        }
-        if removed > 0 {
-            s = s[removed..].to_string();
-            continue;
+        // Remove up to `width` leading spaces as one level. Counting chars rather than
+        // bytes, the cut can only fall after a space, never inside a multi-byte character.
+        let spaces = s.chars().take(width).take_while(|&c| c == ' ').count();
+        if spaces == 0 {
+            break;
         }
-        break;
+        s = &s[spaces..];
     }
-    s
+    s.to_string()
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
-    use crate::lnhash::{format_lnhash, line_hash_u16};
+    use crate::lnhash::{
+        format_lnhash, format_lnhash_width, line_hash_u16, line_hash_u64, line_hash_width,
+        HashAlgorithm,
+    };
     use crate::parse::parse_commands_from_script;
 
     fn addr(lineno: usize, line: &str) -> String {
@@ -604,6 +1379,37 @@ mod tests {
         assert!(err.message().contains("stale"));
     }
 
+    #[test]
+    fn result_keeps_line_endings() {
+        let input = "a\r\nb\r\nc\nd\r\n";
+        let script = format!("{}s/b/B/\n{}a\\new\n", addr(2, "b"), addr(3, "c"));
+        let cmds = parse_commands_from_script(&script).unwrap();
+        let res = edit_text(input, &cmds).unwrap();
+        assert_eq!(res.text(), "a\r\nB\r\nc\nnew\r\nd\r\n");
+
+        let (endings, predominant) = LineEnding::detect("a\nb\r\nc");
+        assert_eq!(endings, [LineEnding::Lf, LineEnding::CrLf, LineEnding::Lf]);
+        assert_eq!(predominant, LineEnding::Lf);
+        assert_eq!(edit_text("x", &[]).unwrap().text(), "x\n");
+    }
+
+    #[test]
+    fn byte_order_mark_is_kept_or_stripped() {
+        let input = "\u{feff}a\r\nb\r\n";
+        let script = format!("{}s/a/A/\n0|0000|a\\top\n", addr(1, "a"));
+        let cmds = parse_commands_from_script(&script).unwrap();
+        let res = edit_text(input, &cmds).unwrap();
+        assert_eq!(res.lines, ["top", "A", "b"]);
+        assert!(res.bom);
+        assert_eq!(res.text(), "\u{feff}top\r\nA\r\nb\r\n");
+        assert_eq!(res.stats.changed, 1);
+
+        let opts = EditOptions { strip_bom: true, ..Default::default() };
+        let res = edit_text_with(input, &cmds, &opts).unwrap();
+        assert_eq!(res.text(), "top\r\nA\r\nb\r\n");
+        assert!(!edit_text("a\n", &[]).unwrap().bom);
+    }
+
     #[test]
     fn delete_range_updates_deleted() {
         let input = "a\nb\nc\n";
@@ -624,6 +1430,21 @@ mod tests {
         assert!(res.modified.is_empty());
     }
 
+    #[test]
+    fn substitute_strict_no_match() {
+        let opts = EditOptions { strict_substitute: true, ..Default::default() };
+        let (a, b) = (addr(1, "abc"), addr(2, "abd"));
+        let script = format!("{a},{b}s/d/D/\n{a},{}g/a/s/c/C/\n", addr(2, "abD"));
+        let cmds = parse_commands_from_script(&script).unwrap();
+        let res = edit_text_with("abc\nabd\n", &cmds, &opts).unwrap();
+        assert_eq!(res.lines, vec!["abC", "abD"]);
+        for script in [format!("{a}s/zzz/yyy/"), format!("{a},{b}g/d/s/c/C/")] {
+            let cmds = parse_commands_from_script(&script).unwrap();
+            let err = edit_text_with("abc\nabd\n", &cmds, &opts).unwrap_err();
+            assert!(err.to_string().contains("matched nothing"), "{err}");
+        }
+    }
+
     #[test]
     fn substitute_global_case_insensitive() {
         let input = "Foo foo\n";
@@ -634,6 +1455,58 @@ mod tests {
         assert_eq!(res.modified, vec![1]);
     }
 
+    #[test]
+    fn substitute_counts_matches_and_expands_groups() {
+        let input = "a=1, b=2\nnone\nc=3\n";
+        let script = format!(
+            "{},{}s/([a-z])=([0-9])/${{2}}:$1/g\n",
+            addr(1, "a=1, b=2"),
+            addr(3, "c=3")
+        );
+        let res = edit_text(input, &parse_commands_from_script(&script).unwrap()).unwrap();
+        assert_eq!(res.lines, ["1:a, 2:b", "none", "3:c"]);
+        assert_eq!((res.stats.substitutions, res.stats.changed), (3, 2));
+        assert_eq!(res.modified, [1, 3]);
+
+        // Under g, only the marked lines are rewritten; a no-op replacement counts as a
+        // substitution but leaves the line unmodified.
+        let script = format!("{},{}g/=/s/=/=/\n", addr(1, "a=1, b=2"), addr(3, "c=3"));
+        let res = edit_text(input, &parse_commands_from_script(&script).unwrap()).unwrap();
+        assert_eq!(res.text(), input);
+        assert_eq!(res.stats.substitutions, 2);
+        assert!(res.modified.is_empty());
+        let script = format!("{},{}v/=/s/o/0/g\n", addr(1, "a=1, b=2"), addr(3, "c=3"));
+        let res = edit_text(input, &parse_commands_from_script(&script).unwrap()).unwrap();
+        assert_eq!(res.lines, ["a=1, b=2", "n0ne", "c=3"]);
+        assert_eq!(res.modified, [2]);
+    }
+
+    #[test]
+    fn substitute_within_display_columns() {
+        // Columns: 日 1-2, 本 3-4, space 5, decomposed é 6, x 7, space 8, x 9.
+        let line = "\u{65e5}\u{672c} e\u{301}x x";
+        let run = |s: &str| {
+            let script = format!("{}{s}\n", addr(1, line));
+            edit_text(line, &parse_commands_from_script(&script).unwrap()).unwrap().lines
+        };
+        assert_eq!(run("s/x/Y/g@7-8"), ["\u{65e5}\u{672c} e\u{301}Y x"]);
+        assert_eq!(run("s/x/Y/g@8-"), ["\u{65e5}\u{672c} e\u{301}x Y"]);
+        // The accent stays with its e, and ^ is the first column searched.
+        assert_eq!(run("s/^e/E/@6-6"), ["\u{65e5}\u{672c} E\u{301}x x"]);
+        // A wide character is only searched when both its columns are in range.
+        assert_eq!(run("s/\u{672c}/Z/@4-"), [line]);
+        assert_eq!(run("s/\u{672c}/Z/@3-4"), ["\u{65e5}Z e\u{301}x x"]);
+        assert_eq!(run("s/x/Y/@20-"), [line]);
+
+        // A tab runs to the next multiple of 8.
+        let script = format!("{}s/x/Y/g@9\n", addr(1, "x\tx"));
+        let res = edit_text("x\tx", &parse_commands_from_script(&script).unwrap()).unwrap();
+        assert_eq!(res.lines, ["x\tY"]);
+
+        // `columns` is a public field, so a range from 0 can be built without the parser.
+        assert_eq!(column_span("abc", &(0..=2)), Some(0..2));
+    }
+
     #[test]
     fn insert_before_first_line_using_zero_address() {
         let input = "b\n";
@@ -694,6 +1567,16 @@ mod tests {
         let res = edit_text(input, &cmds).unwrap();
         assert_eq!(res.lines, vec!["        a".to_string(), "b".to_string()]);
         assert_eq!(res.modified, vec![1, 2]);
+
+        let opts = EditOptions { indent_width: 2, ..Default::default() };
+        let res = edit_text_with(input, &cmds, &opts).unwrap();
+        assert_eq!(res.lines, vec!["    a", "  b"]);
+
+        // Only spaces and tabs are indentation: other whitespace, and the multi-byte
+        // characters after it, are left whole.
+        assert_eq!(dedent("  \u{3000}\u{e9}", 2, 4), "\u{3000}\u{e9}");
+        assert_eq!(dedent("\u{a0} x", 1, 4), "\u{a0} x");
+        assert_eq!(dedent("\t  \u{65e5}", 3, 4), "\u{65e5}");
     }
 
     #[test]
@@ -777,6 +1660,218 @@ mod tests {
         assert_eq!(res.modified, vec![1]);
     }
 
+    #[test]
+    fn many_bottom_up_edits_apply_in_one_pass() {
+        let input: String = (1..=2000).map(|i| format!("line {i}\n")).collect();
+        // Walk bottom-up: delete every even line and insert a marker before every odd one.
+        let mut script = String::new();
+        for i in (1..=2000).rev() {
+            let a = addr(i, &format!("line {i}"));
+            if i % 2 == 0 {
+                script.push_str(&format!("{a}d\n"));
+            } else {
+                script.push_str(&format!("{a}i\n# {i}\n.\n"));
+            }
+        }
+        let cmds = parse_commands_from_script(&script).unwrap();
+        let res = edit_text(&input, &cmds).unwrap();
+        assert_eq!(res.lines.len(), 2000);
+        assert_eq!(res.lines[0], "# 1");
+        assert_eq!(res.lines[1], "line 1");
+        assert_eq!(res.lines[1999], "line 1999");
+        assert_eq!(res.deleted.len(), 1000);
+        assert_eq!(res.modified.len(), 1000);
+    }
+
+    #[test]
+    fn bottom_up_applies_commands_against_original_addresses() {
+        let input = "a\nb\nc\nd\n";
+        // Written top-down: without bottom-up, the insert would make the later addresses stale.
+        let script = format!(
+            "{}i\nX\n.\n{}d\n{}s/d/D/\n",
+            addr(1, "a"),
+            addr(3, "c"),
+            addr(4, "d")
+        );
+        let cmds = parse_commands_from_script(&script).unwrap();
+        assert!(edit_text(input, &cmds).is_err());
+        let opts = EditOptions { bottom_up: true, ..Default::default() };
+        let res = edit_text_with(input, &cmds, &opts).unwrap();
+        assert_eq!(res.lines, vec!["X", "a", "b", "D"]);
+        assert_eq!(res.deleted, vec![3]);
+        assert_eq!(bottom_up_order(&cmds).unwrap(), vec![2, 1, 0]);
+    }
+
+    #[test]
+    fn bottom_up_rejects_overlapping_commands() {
+        let script = format!(
+            "{},{}d\n{}s/b/B/\n{}a\nX\n.\n",
+            addr(1, "a"),
+            addr(2, "b"),
+            addr(2, "b"),
+            addr(2, "b")
+        );
+        let cmds = parse_commands_from_script(&script).unwrap();
+        let err = bottom_up_order(&cmds).unwrap_err();
+        assert!(err.message().contains("commands 1 and 2 are order-dependent"));
+        // Appending after a line does not touch the line itself.
+        assert_eq!(bottom_up_order(&cmds[1..]).unwrap(), vec![1, 0]);
+    }
+
+    #[test]
+    fn join_policy_controls_separator() {
+        let input = "foo(a,\n  b)\nx\n";
+        let cmd = format!("{},{}j", addr(1, "foo(a,"), addr(2, "  b)"));
+        let cmds = parse_commands_from_script(&cmd).unwrap();
+        let join = |policy| {
+            let opts = EditOptions { join: policy, ..Default::default() };
+            edit_text_with(input, &cmds, &opts).unwrap().lines[0].clone()
+        };
+        assert_eq!(join(JoinPolicy::Smart), "foo(a,  b)");
+        assert_eq!(join(JoinPolicy::Never), "foo(a,  b)");
+        assert_eq!(join(JoinPolicy::Always), "foo(a,   b)");
+        assert_eq!(join(JoinPolicy::Separator(";".into())), "foo(a,;  b)");
+        assert_eq!(join_strings("f(", "x)", &JoinPolicy::Smart), "f( x)");
+        assert_eq!(join_strings("f(", "x)", &JoinPolicy::Never), "f(x)");
+    }
+
+    #[test]
+    fn custom_transform_runs_over_verified_range() {
+        let input = "a\nb\nc\n";
+        let upper = |_: usize, l: &str| (l != "b").then(|| l.to_uppercase());
+        let script = format!("{},{}d", addr(1, "a"), addr(3, "c"));
+        let mut cmd = parse_commands_from_script(&script).unwrap().remove(0);
+        cmd.cmd = Subcommand::Custom(Box::new(upper));
+        let res = edit_text(input, &[cmd.clone()]).unwrap();
+        assert_eq!(res.lines, vec!["A", "b", "C"]);
+        assert_eq!(res.modified, vec![1, 3]);
+
+        // Addresses are still verified before the transform runs.
+        cmd.addr1.hash ^= 1;
+        assert!(edit_text(input, &[cmd]).unwrap_err().message().contains("stale"));
+    }
+
+    #[test]
+    fn cancelled_edit_returns_cancelled_error() {
+        let input = "a\n";
+        let cmds = parse_commands_from_script(&format!("{}d", addr(1, "a"))).unwrap();
+        let flag = Arc::new(AtomicBool::new(true));
+        let opts = EditOptions { cancel: Some(flag.clone()), ..Default::default() };
+        let err = edit_text_with(input, &cmds, &opts).unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::Cancelled);
+        flag.store(false, Ordering::Relaxed);
+        assert!(edit_text_with(input, &cmds, &opts).is_ok());
+
+        let opts = EditOptions { deadline: Some(Instant::now()), ..Default::default() };
+        let err = edit_text_with(input, &cmds, &opts).unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::Cancelled);
+    }
+
+    #[test]
+    fn cancellation_is_polled_inside_range_loops() {
+        let input: String = (0..5000).map(|i| format!("{i}\n")).collect();
+        let script = format!("{},{}s/^/x/", addr(1, "0"), addr(5000, "4999"));
+        let cmds = parse_commands_from_script(&script).unwrap();
+        let flag = Arc::new(AtomicBool::new(false));
+        let f = flag.clone();
+        // A transform that requests cancellation part-way through the range.
+        let trip = move |n: usize, _: &str| {
+            if n == 2000 {
+                f.store(true, Ordering::Relaxed);
+            }
+            None
+        };
+        let mut cmd = cmds[0].clone();
+        cmd.cmd = Subcommand::Custom(Box::new(trip));
+        let opts = EditOptions { cancel: Some(flag), ..Default::default() };
+        let err = edit_text_with(&input, &[cmd], &opts).unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::Cancelled);
+    }
+
+    #[test]
+    fn compact_style_applies_to_result_hashes() {
+        let input = "a\nb\n";
+        let cmds = parse_commands_from_script(&format!("{}d", format_lnhash(1, "a"))).unwrap();
+        let opts = EditOptions { addr_style: AddrStyle::Compact, ..Default::default() };
+        let res = edit_text_with(input, &cmds, &opts).unwrap();
+        assert_eq!(res.hashes, vec![format!("1:{:04x}", line_hash_u16("b"))]);
+    }
+
+    #[test]
+    fn seeded_addresses_only_verify_under_their_seed() {
+        let input = "a\nb\n";
+        let spec = LnHashSpec::seeded("proj");
+        let seeded = parse_commands_from_script(&format!("{}d", spec.lnhash(2, "b"))).unwrap();
+        let opts = EditOptions { hash_spec: spec.clone(), ..Default::default() };
+        let res = edit_text_with(input, &seeded, &opts).unwrap();
+        assert_eq!(res.lines, vec!["a"]);
+        assert_eq!(res.hashes, vec![spec.lnhash(1, "a").to_string()]);
+
+        let err = edit_text(input, &seeded).unwrap_err();
+        assert!(err.message().contains("but no seed is set"), "{err}");
+        let other = EditOptions { hash_spec: LnHashSpec::seeded("other"), ..Default::default() };
+        let err = edit_text_with(input, &seeded, &other).unwrap_err();
+        assert!(err.message().contains("but the current seed is"), "{err}");
+        let plain = parse_commands_from_script(&format!("{}d", format_lnhash(2, "b"))).unwrap();
+        let err = edit_text_with(input, &plain, &opts).unwrap_err();
+        assert!(err.message().contains("is unseeded"), "{err}");
+    }
+
+    #[test]
+    fn result_reports_full_hashes() {
+        let res = edit_text_with("a\nb\n", &[], &EditOptions::default()).unwrap();
+        assert_eq!(res.full_hashes, vec![line_hash_u64("a"), line_hash_u64("b")]);
+    }
+
+    #[test]
+    fn chained_addresses_pick_the_right_duplicate() {
+        let input = "if a {\n}\nif b {\n}\n";
+        let spec = LnHashSpec { algorithm: HashAlgorithm::Chained, ..Default::default() };
+        let addrs = spec.lnhashes(input.lines());
+        let opts = EditOptions { hash_spec: spec, ..Default::default() };
+
+        let cmds = parse_commands_from_script(&format!("{}d", addrs[3])).unwrap();
+        let res = edit_text_with(input, &cmds, &opts).unwrap();
+        assert_eq!(res.lines, vec!["if a {", "}", "if b {"]);
+        assert_eq!(res.hashes[1], addrs[1].to_string());
+
+        // The address of the second `}` does not verify against the first.
+        let wrong = format!("2|prev:{:04x}|d", addrs[3].hash);
+        let err = edit_text(input, &parse_commands_from_script(&wrong).unwrap()).unwrap_err();
+        assert!(err.message().contains("stale lnhash at line 2"), "{err}");
+    }
+
+    #[test]
+    fn wide_addresses_verify_at_their_own_width() {
+        let input = "}\n}\n";
+        let wide = format_lnhash_width(2, "}", 8);
+        let cmds = parse_commands_from_script(&format!("{wide}d")).unwrap();
+        let hash_spec = LnHashSpec { width: 6, ..Default::default() };
+        let opts = EditOptions { hash_spec, ..Default::default() };
+        let res = edit_text_with(input, &cmds, &opts).unwrap();
+        assert_eq!(res.hashes, vec![format_lnhash_width(1, "}", 6)]);
+
+        let stale = format!("2|{:08x}|d", line_hash_width("{", 8));
+        let cmds = parse_commands_from_script(&stale).unwrap();
+        let err = edit_text(input, &cmds).unwrap_err();
+        assert!(err.message().contains(&format!("got {:08x}", line_hash_width("}", 8))));
+    }
+
+    #[test]
+    fn fingerprint_is_checked_before_commands() {
+        let input = "a\nb\n";
+        let script = format!("@file {}\n{}d\n", file_fingerprint(input), addr(1, "a"));
+        let parsed = crate::parse::parse_script(&script).unwrap();
+        let opts = EditOptions { fingerprint: parsed.fingerprint.clone(), ..Default::default() };
+        assert!(edit_text_with(input, &parsed.commands, &opts).is_ok());
+        // Same first line, different file.
+        let err = edit_text_with("a\nc\n", &parsed.commands, &opts).unwrap_err();
+        assert!(err.message().contains("fingerprint mismatch"));
+        let mismatch = err.mismatch().unwrap();
+        assert_eq!(mismatch.lineno, None);
+        assert_eq!(mismatch.actual, file_fingerprint("a\nc\n"));
+    }
+
     #[test]
     fn multi_command_rechecks_hashes_after_each_command() {
         let input = "a\nb\nc\n";
@@ -789,5 +1884,185 @@ mod tests {
         let cmds = parse_commands_from_script(&script).unwrap();
         let err = edit_text(input, &cmds).unwrap_err();
         assert!(err.message().contains("stale lnhash at line 3"));
+        assert_eq!(err.command(), Some(1));
+        let mismatch = err.mismatch().unwrap();
+        assert_eq!(mismatch.lineno, Some(3));
+        assert_eq!(mismatch.expected, format!("{:04x}", line_hash_u16("c")));
+        assert_eq!(mismatch.actual, format!("{:04x}", line_hash_u16("b")));
+    }
+
+    #[test]
+    fn trace_records_each_command() {
+        let script = format!("{}s/a/A/\n{}d\n{}a\nx\ny\n.\n", addr(1, "a"), addr(2, "b"), addr(2, "c"));
+        let cmds = parse_commands_from_script(&script).unwrap();
+        let opts = EditOptions { trace: true, ..Default::default() };
+        let res = edit_text_with("a\nb\nc\n", &cmds, &opts).unwrap();
+        let step = |command, changed: &[(usize, &str)], deleted: &[usize], line_count| CommandStep {
+            command,
+            changed: changed.iter().map(|&(n, t)| (n, t.to_string())).collect(),
+            deleted: deleted.to_vec(),
+            line_count,
+            stats: EditStats::default(),
+        };
+        let stats = |added, changed, deleted, substitutions| EditStats {
+            added,
+            changed,
+            deleted,
+            substitutions,
+        };
+        assert_eq!(
+            res.steps,
+            vec![
+                CommandStep { stats: stats(0, 1, 0, 1), ..step(0, &[(1, "A")], &[], 3) },
+                CommandStep { stats: stats(0, 0, 1, 0), ..step(1, &[], &[2], 2) },
+                CommandStep { stats: stats(2, 0, 0, 0), ..step(2, &[(3, "x"), (4, "y")], &[], 4) },
+            ]
+        );
+        assert_eq!(res.modified, vec![1, 3, 4]);
+        assert!(edit_text("a\n", &[]).unwrap().steps.is_empty());
+    }
+
+    #[test]
+    fn error_kinds_classify_failures() {
+        let kind = |script: &str| {
+            let cmds = parse_commands_from_script(script).map_err(|e| e.kind())?;
+            edit_text("a\nb\n", &cmds).map(|_| ()).map_err(|e| e.kind())
+        };
+        assert_eq!(kind(&format!("{}d", addr(1, "x"))), Err(ErrorKind::StaleHash));
+        assert_eq!(kind(&format!("{}d", addr(3, "a"))), Err(ErrorKind::OutOfRange));
+        assert_eq!(kind(&format!("{}m{}", addr(1, "a"), addr(5, "b"))), Err(ErrorKind::OutOfRange));
+        assert_eq!(kind("1|abcd|q"), Err(ErrorKind::Parse));
+        assert_eq!(kind(&format!("{}s/(/x/", addr(1, "a"))), Err(ErrorKind::Parse));
+    }
+
+    #[test]
+    fn verify_commands_lists_every_stale_command() {
+        let input = "a\nb\nc\n";
+        let script = format!(
+            "{}d\n{}s/b/B/\n2|0000|d\n{}d\n",
+            addr(3, "x"),
+            addr(2, "b"),
+            addr(3, "c")
+        );
+        let cmds = parse_commands_from_script(&script).unwrap();
+        let errs = verify_commands(input, &cmds, &EditOptions::default());
+        let failed: Vec<_> = errs.iter().map(|e| e.command()).collect();
+        assert_eq!(failed, vec![Some(0), Some(2)]);
+        assert!(errs[0].message().contains("stale lnhash at line 3"), "{}", errs[0]);
+        assert!(verify_commands(input, &cmds[3..], &EditOptions::default()).is_empty());
+    }
+
+    #[test]
+    fn suggest_addresses_finds_the_moved_line() {
+        let input = "new\na\nb\nc\n";
+        let cmds = parse_commands_from_script(&format!("{}s/b/B/\n", addr(2, "b"))).unwrap();
+        let hints = suggest_addresses(input, &cmds, &EditOptions::default(), 5);
+        assert_eq!(hints.len(), 1);
+        let (hint, spec) = (&hints[0], LnHashSpec::default());
+        assert_eq!(hint.current, Some((spec.lnhash(2, "a"), "a".to_string())));
+        assert_eq!(hint.matches, vec![(spec.lnhash(3, "b"), "b".to_string())]);
+        let fixed = Command { addr1: hint.matches[0].0, ..cmds[0].clone() };
+        assert_eq!(edit_text(input, &[fixed]).unwrap().lines, ["new", "a", "B", "c"]);
+
+        // Fresh addresses need no hints; a line outside the window is not found.
+        assert!(suggest_addresses("a\nb\n", &cmds, &EditOptions::default(), 5).is_empty());
+        let hints = suggest_addresses("x\nx\na\nb\n", &cmds, &EditOptions::default(), 1);
+        assert!(hints[0].matches.is_empty());
+    }
+
+    #[test]
+    fn stats_compare_result_with_input() {
+        let input = "a a\nb\nc\n";
+        let script = format!(
+            "{}s/a/x/g\n{}a\nnew\n.\n{}d\n{}d\n{}s/c/c/\n",
+            addr(1, "a a"),
+            addr(1, "x x"),
+            addr(2, "new"),
+            addr(2, "b"),
+            addr(2, "c")
+        );
+        let cmds = parse_commands_from_script(&script).unwrap();
+        let res = edit_text(input, &cmds).unwrap();
+        assert_eq!(res.lines, vec!["x x", "c"]);
+        // "new" was added and deleted again; `s/c/c/` substitutes without changing the line.
+        let expected = EditStats { added: 0, changed: 1, deleted: 1, substitutions: 3 };
+        assert_eq!(res.stats, expected);
+    }
+
+    #[test]
+    fn expectations_are_checked_against_the_result() {
+        let cmds = parse_commands_from_script(&format!("{}s/a/A/", addr(1, "a"))).unwrap();
+        let with = |expect: Vec<LnHash>, fp: Option<&str>| EditOptions {
+            expect,
+            expect_fingerprint: fp.map(String::from),
+            ..Default::default()
+        };
+        let good = with(vec![LnHash::of(1, "A"), LnHash::of(2, "b")], None);
+        assert!(edit_text_with("a\nb\n", &cmds, &good).is_ok());
+        let fp = file_fingerprint("A\nb\n");
+        assert!(edit_text_with("a\nb\n", &cmds, &with(vec![], Some(&fp))).is_ok());
+
+        for opts in [
+            with(vec![LnHash::of(1, "a")], None),
+            with(vec![LnHash::of(3, "b")], None),
+            with(vec![], Some(&file_fingerprint("a\nb\n"))),
+        ] {
+            let err = edit_text_with("a\nb\n", &cmds, &opts).unwrap_err();
+            assert_eq!(err.kind(), ErrorKind::ExpectationFailed, "{err}");
+        }
+    }
+
+    #[test]
+    fn keep_going_skips_failing_commands() {
+        let input = "a\nb\nc\n";
+        let (stale, b, c) = (addr(3, "x"), addr(2, "b"), addr(3, "c"));
+        let script = format!("{stale}d\n{b}s/b/B/\n{c}m9|0000|\n");
+        let cmds = parse_commands_from_script(&script).unwrap();
+        let opts = EditOptions { keep_going: true, ..Default::default() };
+        let res = edit_text_with(input, &cmds, &opts).unwrap();
+        assert_eq!(res.lines, vec!["a", "B", "c"]);
+        let skipped: Vec<_> = res.skipped.iter().map(|e| (e.command(), e.kind())).collect();
+        let expected = [(Some(0), ErrorKind::StaleHash), (Some(2), ErrorKind::OutOfRange)];
+        assert_eq!(skipped, expected);
+        assert!(edit_text(input, &cmds).is_err());
+        // Commands other than globals fail before changing anything, so nothing is copied
+        // to go back to, and tracing leaves what the earlier commands changed marked.
+        let traced = EditOptions { trace: true, ..opts.clone() };
+        let res = edit_text_with(input, &cmds, &traced).unwrap();
+        assert_eq!(res.lines, vec!["a", "B", "c"]);
+        assert_eq!((res.modified, res.steps.len()), (vec![2], 1));
+
+        // The global joins "a1" with "b", then fails on "a2", the last line; the join it
+        // already made is undone along with it.
+        let input = "a1\nb\na2\n";
+        let script = format!("{},{}g/a/j\n{}s/b/B/\n", addr(1, "a1"), addr(3, "a2"), addr(2, "b"));
+        let cmds = parse_commands_from_script(&script).unwrap();
+        let res = edit_text_with(input, &cmds, &opts).unwrap();
+        assert_eq!(res.lines, vec!["a1", "B", "a2"]);
+        assert_eq!((res.skipped.len(), res.modified.as_slice()), (1, &[2][..]));
+        assert_eq!(res.stats, EditStats { changed: 1, substitutions: 1, ..Default::default() });
+    }
+
+    #[test]
+    fn fuzz_reanchors_drifted_addresses() {
+        // Two lines were inserted above "a" since its address was taken.
+        let input = "x\ny\na\nb\n";
+        let script = format!("{}s/a/A/\n{}m{}\n", addr(1, "a"), addr(2, "b"), addr(1, "x"));
+        let cmds = parse_commands_from_script(&script).unwrap();
+        let fuzz = |fuzz| EditOptions { fuzz, ..Default::default() };
+
+        assert_eq!(edit_text(input, &cmds).unwrap_err().kind(), ErrorKind::StaleHash);
+        assert!(edit_text_with(input, &cmds, &fuzz(1)).is_err());
+        let res = edit_text_with(input, &cmds, &fuzz(2)).unwrap();
+        assert_eq!(res.lines, vec!["x", "b", "y", "A"]);
+        let moved: Vec<_> =
+            res.relocated.iter().map(|r| (r.command, r.addr.lineno, r.lineno)).collect();
+        assert_eq!(moved, vec![(0, 1, 3), (1, 2, 4)]);
+        assert!(verify_commands(input, &cmds, &fuzz(2)).is_empty());
+
+        // Equally close matches above and below are ambiguous.
+        let cmds = parse_commands_from_script(&format!("{}d", addr(2, "a"))).unwrap();
+        let err = edit_text_with("a\nq\na\n", &cmds, &fuzz(3)).unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::StaleHash);
     }
 }
@@ -0,0 +1,181 @@
+use std::ffi::{c_char, CStr, CString};
+use std::ptr;
+
+use serde_json::{json, Value};
+
+/// Apply `ncommands` command strings to `text` and return the outcome as a JSON object:
+/// `{"ok":true,"lines":[...],"hashes":[...],"modified":[...],"deleted":[...],"text":"..."}`,
+/// or `{"ok":false,"error":{"message":"...","kind":"stale_hash","command":1,...}}` with the
+/// index of the failing command, or null, and for a stale address its `lineno` and the
+/// `expected` and `actual` hashes, as the servers report errors. Commands are as for the
+/// Python `exhash()`, including an optional `@file <fingerprint>` first command.
+///
+/// The result must be released with [`exhash_free_result`].
+///
+/// # Safety
+///
+/// `text` must be a NUL-terminated string, and `commands` must point to `ncommands` of
+/// them (it may be null if `ncommands` is 0).
+#[no_mangle]
+pub unsafe extern "C" fn exhash_edit(
+    text: *const c_char,
+    commands: *const *const c_char,
+    ncommands: usize,
+) -> *mut c_char {
+    let cmds = if ncommands == 0 {
+        Vec::new()
+    } else if commands.is_null() {
+        return into_c(failed(crate::EditError::new("commands is null")));
+    } else {
+        std::slice::from_raw_parts(commands, ncommands).to_vec()
+    };
+    let text = match str_arg(text, "text") {
+        Ok(t) => t,
+        Err(msg) => return into_c(failed(crate::EditError::new(msg))),
+    };
+    let mut cmd_refs = Vec::with_capacity(cmds.len());
+    for (k, &c) in cmds.iter().enumerate() {
+        match str_arg(c, &format!("command {}", k + 1)) {
+            Ok(c) => cmd_refs.push(c),
+            Err(msg) => return into_c(failed(crate::EditError::new(msg))),
+        }
+    }
+    into_c(edit(text, cmd_refs).unwrap_or_else(failed))
+}
+
+/// The lnhash view of `text`, one `lineno|hash|  content` line per line of it, each
+/// ending in `\n`: what the `lnhashview` CLI prints. Returns null if `text` is null or not
+/// UTF-8.
+///
+/// The result must be released with [`exhash_free_result`].
+///
+/// # Safety
+///
+/// `text` must be null or a NUL-terminated string.
+#[no_mangle]
+pub unsafe extern "C" fn exhash_lnhashview(text: *const c_char) -> *mut c_char {
+    let Ok(text) = str_arg(text, "text") else { return ptr::null_mut() };
+    let view = crate::LnHashSpec::default().view_lines(text, crate::AddrStyle::Standard);
+    CString::new(view.iter().map(|l| format!("{l}\n")).collect::<String>())
+        .map_or(ptr::null_mut(), CString::into_raw)
+}
+
+/// Release a string returned by [`exhash_edit`] or [`exhash_lnhashview`]. Null is ignored.
+///
+/// # Safety
+///
+/// `result` must be null or a string from one of those functions, not already released.
+#[no_mangle]
+pub unsafe extern "C" fn exhash_free_result(result: *mut c_char) {
+    if !result.is_null() {
+        drop(CString::from_raw(result));
+    }
+}
+
+fn edit(text: &str, cmds: Vec<&str>) -> Result<Value, crate::EditError> {
+    let (parsed, fingerprint) = crate::parse_commands_with_header(&cmds)?;
+    let opts = crate::EditOptions { fingerprint, ..Default::default() };
+    let mut result = crate::result_json(&crate::edit_text_with(text, &parsed, &opts)?);
+    result["ok"] = Value::Bool(true);
+    Ok(result)
+}
+
+/// Borrow the C string `s` as UTF-8, naming it `what` in the error.
+unsafe fn str_arg<'a>(s: *const c_char, what: &str) -> Result<&'a str, String> {
+    if s.is_null() {
+        return Err(format!("{what} is null"));
+    }
+    CStr::from_ptr(s).to_str().map_err(|_| format!("{what} is not valid UTF-8"))
+}
+
+/// The outcome of an edit that failed with `e`.
+fn failed(e: crate::EditError) -> Value {
+    json!({ "ok": false, "error": crate::error_json(&e) })
+}
+
+/// Hand `result` to the caller as a C string. It has no NUL to reject: JSON escapes them.
+fn into_c(result: Value) -> *mut c_char {
+    CString::new(result.to_string()).map_or(ptr::null_mut(), CString::into_raw)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    unsafe fn take(s: *mut c_char) -> String {
+        let out = CStr::from_ptr(s).to_str().unwrap().to_string();
+        exhash_free_result(s);
+        out
+    }
+
+    unsafe fn edit_json(s: *mut c_char) -> Value {
+        serde_json::from_str(&take(s)).unwrap()
+    }
+
+    #[test]
+    fn edit_and_view_through_the_c_api() {
+        let text = CString::new("foo\nbar\n").unwrap();
+        let addr = crate::LnHash::of(1, "foo").to_string();
+        let cmd = CString::new(format!("{addr}s/foo/b\"z/")).unwrap();
+        unsafe {
+            let view = take(exhash_lnhashview(text.as_ptr()));
+            assert_eq!(view, format!("{addr}  foo\n{}  bar\n", crate::LnHash::of(2, "bar")));
+
+            let res = edit_json(exhash_edit(text.as_ptr(), &cmd.as_ptr(), 1));
+            let hashes = [crate::LnHash::of(1, "b\"z"), crate::LnHash::of(2, "bar")];
+            let expected = json!({
+                "ok": true,
+                "lines": ["b\"z", "bar"],
+                "hashes": hashes.map(|h| h.to_string()),
+                "modified": [1],
+                "deleted": [],
+                "text": "b\"z\nbar\n",
+            });
+            assert_eq!(res, expected);
+
+            let cmds = [cmd.as_ptr(), cmd.as_ptr()];
+            let res = edit_json(exhash_edit(text.as_ptr(), cmds.as_ptr(), 2));
+            let error = &res["error"];
+            assert_eq!((&res["ok"], &error["kind"]), (&json!(false), &json!("stale_hash")));
+            assert_eq!((&error["command"], &error["lineno"]), (&json!(1), &json!(1)));
+            let res = edit_json(exhash_edit(ptr::null(), ptr::null(), 0));
+            assert_eq!(res["error"]["message"], "text is null");
+            assert_eq!(res["error"]["kind"], "other");
+            assert!(exhash_lnhashview(ptr::null()).is_null());
+            exhash_free_result(ptr::null_mut());
+        }
+    }
+
+    /// Every function exported here is declared in include/exhash.h with the same
+    /// signature, so the header cannot drift from the code.
+    #[test]
+    fn header_declares_the_exported_functions() {
+        let collapse = |s: &str| s.split_whitespace().collect::<Vec<_>>().join(" ");
+        let header = collapse(include_str!("../include/exhash.h"));
+        let c_type = |ty: &str| match ty {
+            "*const c_char" => "const char *",
+            "*const *const c_char" => "const char *const *",
+            "*mut c_char" => "char *",
+            "usize" => "size_t ",
+            "" => "void ",
+            ty => panic!("no C type for {ty}"),
+        };
+        let mut exported = 0;
+        for item in include_str!("ffi.rs").split("pub unsafe extern \"C\" fn ").skip(1) {
+            let signature = collapse(item.split('{').next().unwrap());
+            let (name, rest) = signature.split_once('(').unwrap();
+            let (params, ret) = rest.rsplit_once(')').unwrap();
+            let params: Vec<String> = (params.split(',').map(str::trim))
+                .filter(|p| !p.is_empty())
+                .map(|p| p.split_once(": ").unwrap())
+                .map(|(param, ty)| format!("{}{param}", c_type(ty)))
+                .collect();
+            let ret = c_type(ret.trim().trim_start_matches("->").trim());
+            let decl = format!("{ret}{name}({});", params.join(", "));
+            assert!(header.contains(&decl), "include/exhash.h does not declare {decl}");
+            exported += 1;
+        }
+        let declared = regex::Regex::new(r"\bexhash_\w+\(").unwrap().find_iter(&header).count();
+        assert_eq!((exported, declared), (3, 3));
+    }
+}
@@ -0,0 +1,462 @@
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::process;
+
+use crate::engine::{edit_text_with, EditOptions, EditResult};
+use crate::parse::Command;
+use crate::EditError;
+
+/// How [`edit_file`] reads and writes the file.
+#[derive(Debug, Clone, Default)]
+pub struct FileOptions {
+    /// Edit and return the result without writing anything.
+    pub dry_run: bool,
+    /// Copy the original to `<path><suffix>` before replacing it.
+    pub backup: Option<String>,
+    /// The file's encoding as a WHATWG label, or `"auto"` (needs the `encoding` feature).
+    /// `None` reads and writes UTF-8.
+    pub encoding: Option<String>,
+}
+
+/// Read `path`, apply `commands`, and replace the file with the result via
+/// [`write_atomic`], as the `exhash` CLI does. Line endings and the file's encoding are
+/// kept. Binary files (with a NUL byte) and, without an encoding, non-UTF-8 files are
+/// rejected; nothing is written if the edit fails.
+///
+/// Returns the edit result together with the text the file held before.
+pub fn edit_file(
+    path: impl AsRef<Path>,
+    commands: &[Command],
+    opts: &EditOptions,
+    file_opts: &FileOptions,
+) -> Result<(EditResult, String), EditError> {
+    let path = path.as_ref();
+    let (text, encoding) = read_text(path, file_opts.encoding.as_deref())?;
+    let result = edit_text_with(&text, commands, opts)?;
+    if file_opts.dry_run {
+        return Ok((result, text));
+    }
+    let new_bytes = encode(&result.text(), encoding)?;
+    if let Some(suffix) = &file_opts.backup {
+        let mut bak = path.as_os_str().to_owned();
+        bak.push(suffix);
+        fs::copy(path, &bak).map_err(|e| {
+            EditError::new(format!("failed to write backup {}: {e}", Path::new(&bak).display()))
+        })?;
+    }
+    write_atomic(path, &new_bytes)
+        .map_err(|e| EditError::new(format!("failed to write {}: {e}", path.display())))?;
+    Ok((result, text))
+}
+
+/// Read `path` as text in `encoding` (UTF-8 if `None`), rejecting binary files. Returns the
+/// text and the encoding to write it back in (`None` for UTF-8).
+pub(crate) fn read_text(
+    path: &Path,
+    encoding: Option<&str>,
+) -> Result<(String, Option<&'static str>), EditError> {
+    let bytes = fs::read(path)
+        .map_err(|e| EditError::new(format!("failed to read {}: {e}", path.display())))?;
+    if bytes.contains(&0) {
+        return Err(EditError::new("binary file rejected (NUL byte found)"));
+    }
+    decode(bytes, encoding)
+}
+
+/// `bytes` as text, and the encoding to write it back in (`None` for UTF-8).
+pub(crate) fn decode(
+    bytes: Vec<u8>,
+    encoding: Option<&str>,
+) -> Result<(String, Option<&'static str>), EditError> {
+    #[cfg(feature = "encoding")]
+    if let Some(label) = encoding {
+        return crate::encoding::decode_text(&bytes, label).map(|(text, name)| (text, Some(name)));
+    }
+    #[cfg(not(feature = "encoding"))]
+    if encoding.is_some() {
+        return Err(EditError::new("reading other encodings needs the `encoding` feature"));
+    }
+    String::from_utf8(bytes)
+        .map(|s| (s, None))
+        .map_err(|_| EditError::new("non-UTF8 file rejected (give its encoding)"))
+}
+
+fn encode(text: &str, encoding: Option<&str>) -> Result<Vec<u8>, EditError> {
+    #[cfg(feature = "encoding")]
+    if let Some(name) = encoding {
+        return crate::encoding::encode_text(text, name);
+    }
+    #[cfg(not(feature = "encoding"))]
+    let _ = encoding;
+    Ok(text.as_bytes().to_vec())
+}
+
+/// Replace `path` with `content` so that readers see either the old file or the new one.
+///
+/// The text goes to a temp file beside `path`, which takes on the original's owner,
+/// permissions and (on Linux) extended attributes before it is renamed into place; the
+/// directory is then fsynced so the rename itself survives a crash. A temp file beside
+/// the target can only be on another filesystem when `path` is itself a mount point (a
+/// bind-mounted file, say), where rename fails: then the file is rewritten in place,
+/// with a copy of the original kept beside it until the new text is synced.
+pub fn write_atomic(path: &Path, content: &[u8]) -> io::Result<()> {
+    let (dir, file_name) = split_path(path);
+    let original = fs::metadata(path).ok();
+    let tmp_path = stage_beside(path, content)?;
+
+    match fs::rename(&tmp_path, path) {
+        Ok(()) => {
+            sync_dir(dir);
+            Ok(())
+        }
+        Err(e) => {
+            let _ = fs::remove_file(&tmp_path);
+            let cross_device =
+                matches!(e.kind(), io::ErrorKind::CrossesDevices | io::ErrorKind::ResourceBusy);
+            if cross_device && original.is_some() {
+                write_in_place(path, dir, &file_name, content)
+            } else {
+                Err(e)
+            }
+        }
+    }
+}
+
+/// Write several files as one transaction: all are staged and synced before any is
+/// renamed into place, and if a rename fails, the files already replaced are put back.
+///
+/// Each original is kept under a hard link (or, where links are unsupported, a copy)
+/// until every rename has succeeded. There is no in-place fallback here, since a file
+/// rewritten in place could not be restored atomically.
+pub fn write_all_atomic(files: &[(PathBuf, Vec<u8>)]) -> io::Result<()> {
+    let mut staged: Vec<PathBuf> = Vec::new();
+    for (path, content) in files {
+        match stage_beside(path, content) {
+            Ok(tmp) => staged.push(tmp),
+            Err(e) => {
+                for tmp in &staged {
+                    let _ = fs::remove_file(tmp);
+                }
+                return Err(io::Error::new(e.kind(), format!("{}: {e}", path.display())));
+            }
+        }
+    }
+
+    // The originals of the files renamed so far (None where there was none).
+    let mut replaced: Vec<Option<PathBuf>> = Vec::new();
+    let mut failure = None;
+    for (k, (path, _)) in files.iter().enumerate() {
+        let kept = match keep_original(path) {
+            Ok(kept) => kept,
+            Err(e) => {
+                failure = Some((k, e));
+                break;
+            }
+        };
+        if let Err(e) = fs::rename(&staged[k], path) {
+            if let Some(orig) = kept {
+                let _ = fs::remove_file(orig);
+            }
+            failure = Some((k, e));
+            break;
+        }
+        replaced.push(kept);
+    }
+
+    let Some((k, e)) = failure else {
+        for orig in replaced.iter().flatten() {
+            let _ = fs::remove_file(orig);
+        }
+        for (path, _) in files {
+            sync_dir(split_path(path).0);
+        }
+        return Ok(());
+    };
+    for tmp in &staged[k..] {
+        let _ = fs::remove_file(tmp);
+    }
+    let mut unrestored = Vec::new();
+    for ((path, _), orig) in files.iter().zip(replaced) {
+        let restored = match orig {
+            Some(orig) => fs::rename(&orig, path),
+            None => fs::remove_file(path),
+        };
+        if restored.is_err() {
+            unrestored.push(path.display().to_string());
+        }
+    }
+    let failed = files[k].0.display();
+    let msg = if unrestored.is_empty() {
+        format!("{failed}: {e}; no file was changed")
+    } else {
+        format!("{failed}: {e}; could not restore {}", unrestored.join(", "))
+    };
+    Err(io::Error::new(e.kind(), msg))
+}
+
+/// Write `content` to a fresh temp file beside `path`, with the original's metadata, and
+/// sync it. Returns the temp file's path; nothing is left behind on failure.
+fn stage_beside(path: &Path, content: &[u8]) -> io::Result<PathBuf> {
+    let (dir, file_name) = split_path(path);
+    let original = fs::metadata(path).ok();
+    let (tmp_path, mut tmp) = create_beside(dir, &file_name, "tmp")?;
+    let written = (|| {
+        use std::io::Write;
+        tmp.write_all(content)?;
+        if let Some(meta) = &original {
+            copy_metadata(path, meta, &tmp_path, &tmp);
+        }
+        tmp.sync_all()
+    })();
+    drop(tmp);
+    match written {
+        Ok(()) => Ok(tmp_path),
+        Err(e) => {
+            let _ = fs::remove_file(&tmp_path);
+            Err(e)
+        }
+    }
+}
+
+/// Keep `path`'s current contents under a new name beside it, so that it can be restored
+/// after `path` is renamed over. Returns None if `path` does not exist.
+fn keep_original(path: &Path) -> io::Result<Option<PathBuf>> {
+    if !path.exists() {
+        return Ok(None);
+    }
+    let (dir, file_name) = split_path(path);
+    let (orig, f) = create_beside(dir, &file_name, "orig")?;
+    drop(f);
+    let _ = fs::remove_file(&orig);
+    if fs::hard_link(path, &orig).is_ok() {
+        return Ok(Some(orig));
+    }
+    match fs::copy(path, &orig) {
+        Ok(_) => Ok(Some(orig)),
+        Err(e) => {
+            let _ = fs::remove_file(&orig);
+            Err(e)
+        }
+    }
+}
+
+/// The directory holding `path` (`.` for a bare name) and its file name.
+pub fn split_path(path: &Path) -> (&Path, String) {
+    let dir = path
+        .parent()
+        .filter(|d| !d.as_os_str().is_empty())
+        .unwrap_or(Path::new("."));
+    let file_name = path
+        .file_name()
+        .map(|s| s.to_string_lossy().to_string())
+        .unwrap_or_else(|| "file".to_string());
+    (dir, file_name)
+}
+
+/// Sync `dir` so that renames into it survive a crash. Not every filesystem can fsync a
+/// directory; the rename has happened regardless.
+fn sync_dir(dir: &Path) {
+    #[cfg(unix)]
+    let _ = fs::File::open(dir).and_then(|d| d.sync_all());
+    #[cfg(not(unix))]
+    let _ = dir;
+}
+
+/// Create a fresh `.<file_name>.exhash.<kind>.<pid>.<n>` file in `dir`.
+pub fn create_beside(dir: &Path, file_name: &str, kind: &str) -> io::Result<(PathBuf, fs::File)> {
+    let pid = process::id();
+    for attempt in 0u64.. {
+        let candidate = dir.join(format!(".{file_name}.exhash.{kind}.{pid}.{attempt}"));
+        match fs::OpenOptions::new().write(true).create_new(true).open(&candidate) {
+            Ok(f) => return Ok((candidate, f)),
+            Err(e) if e.kind() == io::ErrorKind::AlreadyExists => continue,
+            Err(e) => return Err(e),
+        }
+    }
+    unreachable!()
+}
+
+/// Give the temp file the original's owner, permissions and extended attributes, as far
+/// as this process is allowed to: a failure here never blocks the edit.
+fn copy_metadata(original: &Path, meta: &fs::Metadata, tmp_path: &Path, tmp: &fs::File) {
+    // Ownership first: chown may clear setuid/setgid bits, which the chmod then restores.
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::{fchown, MetadataExt};
+        if fchown(tmp, Some(meta.uid()), Some(meta.gid())).is_err() {
+            // Without privilege we may still move the file into one of our own groups.
+            let _ = fchown(tmp, None, Some(meta.gid()));
+        }
+    }
+    let _ = tmp.set_permissions(meta.permissions());
+    #[cfg(target_os = "linux")]
+    xattr::copy(original, tmp_path);
+    #[cfg(not(target_os = "linux"))]
+    let _ = (original, tmp_path);
+}
+
+/// The fallback when `path` cannot be renamed over: truncate and rewrite it in place. The
+/// original is copied aside first and restored if the write fails part way.
+fn write_in_place(path: &Path, dir: &Path, file_name: &str, content: &[u8]) -> io::Result<()> {
+    let (bak_path, bak) = create_beside(dir, file_name, "orig")?;
+    drop(bak);
+    if let Err(e) = fs::copy(path, &bak_path).and_then(|_| fs::File::open(&bak_path)?.sync_all()) {
+        let _ = fs::remove_file(&bak_path);
+        return Err(e);
+    }
+    let written = (|| {
+        use std::io::Write;
+        let mut f = fs::OpenOptions::new().write(true).truncate(true).open(path)?;
+        f.write_all(content)?;
+        f.sync_all()
+    })();
+    match written {
+        Ok(()) => {
+            let _ = fs::remove_file(&bak_path);
+            Ok(())
+        }
+        Err(e) if fs::copy(&bak_path, path).is_ok() => {
+            let _ = fs::remove_file(&bak_path);
+            Err(e)
+        }
+        Err(e) => Err(io::Error::new(
+            e.kind(),
+            format!("{e}; the original is kept at {}", bak_path.display()),
+        )),
+    }
+}
+
+/// Extended attributes, via the C library's xattr calls (std has no wrapper for them).
+#[cfg(target_os = "linux")]
+mod xattr {
+    use std::ffi::{c_char, c_int, c_void, CString};
+    use std::os::unix::ffi::OsStrExt;
+    use std::path::Path;
+
+    extern "C" {
+        fn listxattr(path: *const c_char, list: *mut c_char, size: usize) -> isize;
+        fn getxattr(path: *const c_char, name: *const c_char, value: *mut c_void, size: usize)
+            -> isize;
+        fn setxattr(
+            path: *const c_char,
+            name: *const c_char,
+            value: *const c_void,
+            size: usize,
+            flags: c_int,
+        ) -> c_int;
+    }
+
+    /// Copy every extended attribute of `from` onto `to`, skipping any that cannot be read
+    /// or set (`security.*` without privilege, or a filesystem without xattr support).
+    pub fn copy(from: &Path, to: &Path) {
+        let (Ok(from), Ok(to)) = (c_path(from), c_path(to)) else {
+            return;
+        };
+        // SAFETY: the paths are NUL-terminated, and `read` passes a buffer of `len` bytes.
+        let Some(names) = read(|buf, len| unsafe { listxattr(from.as_ptr(), buf.cast(), len) })
+        else {
+            return;
+        };
+        for name in names.split(|&b| b == 0).filter(|n| !n.is_empty()) {
+            let Ok(name) = CString::new(name) else { continue };
+            // SAFETY: as above, with `name` NUL-terminated too.
+            let value =
+                read(|buf, len| unsafe { getxattr(from.as_ptr(), name.as_ptr(), buf.cast(), len) });
+            if let Some(value) = value {
+                let (ptr, len) = (value.as_ptr().cast(), value.len());
+                // SAFETY: `value` is a live buffer of `len` bytes.
+                unsafe { setxattr(to.as_ptr(), name.as_ptr(), ptr, len, 0) };
+            }
+        }
+    }
+
+    /// Set the attribute `name` of `path` to `value`.
+    #[cfg(test)]
+    pub fn set(path: &Path, name: &str, value: &[u8]) -> std::io::Result<()> {
+        let (path, name) = (c_path(path)?, CString::new(name)?);
+        let (ptr, len) = (value.as_ptr().cast(), value.len());
+        // SAFETY: the path and name are NUL-terminated, and `value` is `len` bytes.
+        match unsafe { setxattr(path.as_ptr(), name.as_ptr(), ptr, len, 0) } {
+            0 => Ok(()),
+            _ => Err(std::io::Error::last_os_error()),
+        }
+    }
+
+    /// The value of the attribute `name` of `path`, if it has one.
+    #[cfg(test)]
+    pub fn get(path: &Path, name: &str) -> Option<Vec<u8>> {
+        let (path, name) = (c_path(path).ok()?, CString::new(name).ok()?);
+        // SAFETY: as in `copy`.
+        read(|buf, len| unsafe { getxattr(path.as_ptr(), name.as_ptr(), buf.cast(), len) })
+    }
+
+    fn c_path(path: &Path) -> Result<CString, std::ffi::NulError> {
+        CString::new(path.as_os_str().as_bytes())
+    }
+
+    /// Run a size-probing xattr call: first with no buffer to learn the length, then again
+    /// into a buffer of that size. `None` if either call fails.
+    fn read(call: impl Fn(*mut u8, usize) -> isize) -> Option<Vec<u8>> {
+        let len = usize::try_from(call(std::ptr::null_mut(), 0)).ok()?;
+        let mut buf = vec![0u8; len];
+        if len > 0 {
+            let got = usize::try_from(call(buf.as_mut_ptr(), len)).ok()?;
+            buf.truncate(got);
+        }
+        Some(buf)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn edit_file_writes_atomically_with_a_backup() {
+        let dir = std::env::temp_dir().join(format!("exhash-file-{}", process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("f.txt");
+        fs::write(&path, "a\r\nb\r\n").unwrap();
+        let opts = EditOptions::default();
+        let delete_b = format!("{}d", opts.hash_spec.lnhash(2, "b"));
+        let commands = crate::parse_commands_from_strs(&[delete_b.as_str()]).unwrap();
+
+        let dry = FileOptions { dry_run: true, ..Default::default() };
+        let (result, original) = edit_file(&path, &commands, &opts, &dry).unwrap();
+        assert_eq!((result.lines, original.as_str()), (vec!["a".to_string()], "a\r\nb\r\n"));
+        assert_eq!(fs::read_to_string(&path).unwrap(), "a\r\nb\r\n");
+
+        let backup = FileOptions { backup: Some(".bak".to_string()), ..Default::default() };
+        edit_file(&path, &commands, &opts, &backup).unwrap();
+        assert_eq!(fs::read_to_string(&path).unwrap(), "a\r\n");
+        assert_eq!(fs::read_to_string(dir.join("f.txt.bak")).unwrap(), "a\r\nb\r\n");
+        assert!(edit_file(&path, &commands, &opts, &backup).is_err());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    #[cfg(target_os = "linux")]
+    fn edit_file_keeps_extended_attributes() {
+        let dir = std::env::temp_dir().join(format!("exhash-xattr-{}", process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("f.txt");
+        fs::write(&path, "a\nb\n").unwrap();
+        match xattr::set(&path, "user.exhash.test", b"kept") {
+            // ENOTSUP (EOPNOTSUPP): the filesystem has no user attributes to keep.
+            Err(e) if e.raw_os_error() == Some(95) => {
+                fs::remove_dir_all(&dir).unwrap();
+                eprintln!("skipped: {} does not support user xattrs", dir.display());
+                return;
+            }
+            set => set.unwrap(),
+        }
+        let opts = EditOptions::default();
+        let delete_b = format!("{}d", opts.hash_spec.lnhash(2, "b"));
+        let commands = crate::parse_commands_from_strs(&[delete_b.as_str()]).unwrap();
+        edit_file(&path, &commands, &opts, &FileOptions::default()).unwrap();
+        assert_eq!(fs::read_to_string(&path).unwrap(), "a\n");
+        assert_eq!(xattr::get(&path, "user.exhash.test").as_deref(), Some(&b"kept"[..]));
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,129 @@
+use std::ops::{Index, IndexMut};
+
+/// A gap buffer over lines.
+///
+/// Elements before the gap live in `front`; elements after it live in `back` in reverse
+/// order, so the element just after the gap is `back.last()`. Edits at the gap are O(1)
+/// and moving the gap costs O(distance), so scripts that walk through a file (top-down
+/// or bottom-up) avoid the O(n) shift of a `Vec::splice` per command.
+#[derive(Debug, Clone)]
+pub(crate) struct GapBuffer<T> {
+    front: Vec<T>,
+    back: Vec<T>,
+}
+
+impl<T> GapBuffer<T> {
+    pub(crate) fn from_vec(items: Vec<T>) -> Self {
+        Self {
+            front: items,
+            back: Vec::new(),
+        }
+    }
+
+    pub(crate) fn len(&self) -> usize {
+        self.front.len() + self.back.len()
+    }
+
+    fn move_gap(&mut self, pos: usize) {
+        while self.front.len() > pos {
+            let item = self.front.pop().unwrap();
+            self.back.push(item);
+        }
+        while self.front.len() < pos {
+            let item = self.back.pop().unwrap();
+            self.front.push(item);
+        }
+    }
+
+    /// Remove `count` elements starting at `pos` and insert `items` in their place,
+    /// returning the removed elements in order.
+    pub(crate) fn splice(
+        &mut self,
+        pos: usize,
+        count: usize,
+        items: impl IntoIterator<Item = T>,
+    ) -> Vec<T> {
+        assert!(pos + count <= self.len(), "splice out of bounds");
+        self.move_gap(pos);
+        let removed: Vec<T> = (0..count).map(|_| self.back.pop().unwrap()).collect();
+        self.front.extend(items);
+        removed
+    }
+
+    /// Borrow `start..end` as a contiguous mutable slice.
+    pub(crate) fn range_mut(&mut self, start: usize, end: usize) -> &mut [T] {
+        self.move_gap(end);
+        &mut self.front[start..end]
+    }
+
+    pub(crate) fn iter_mut(&mut self) -> impl Iterator<Item = &mut T> {
+        self.front.iter_mut().chain(self.back.iter_mut().rev())
+    }
+
+    pub(crate) fn into_vec(mut self) -> Vec<T> {
+        let len = self.len();
+        self.move_gap(len);
+        self.front
+    }
+}
+
+impl<T> Index<usize> for GapBuffer<T> {
+    type Output = T;
+
+    fn index(&self, idx: usize) -> &T {
+        if idx < self.front.len() {
+            &self.front[idx]
+        } else {
+            let back_idx = idx - self.front.len();
+            assert!(back_idx < self.back.len(), "index out of bounds");
+            &self.back[self.back.len() - 1 - back_idx]
+        }
+    }
+}
+
+impl<T> IndexMut<usize> for GapBuffer<T> {
+    fn index_mut(&mut self, idx: usize) -> &mut T {
+        if idx < self.front.len() {
+            &mut self.front[idx]
+        } else {
+            let back_idx = idx - self.front.len();
+            assert!(back_idx < self.back.len(), "index out of bounds");
+            let n = self.back.len();
+            &mut self.back[n - 1 - back_idx]
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn splice_matches_vec_semantics() {
+        let mut gb = GapBuffer::from_vec(vec![1, 2, 3, 4, 5]);
+        let mut v = vec![1, 2, 3, 4, 5];
+
+        let removed = gb.splice(1, 2, [20, 30, 40]);
+        let expected: Vec<i32> = v.splice(1..3, [20, 30, 40]).collect();
+        assert_eq!(removed, expected);
+
+        gb.splice(5, 0, [9]);
+        v.splice(5..5, [9]);
+        gb.splice(0, 1, []);
+        v.splice(0..1, []);
+
+        assert_eq!(gb.len(), v.len());
+        for i in 0..v.len() {
+            assert_eq!(gb[i], v[i]);
+        }
+        assert_eq!(gb.into_vec(), v);
+    }
+
+    #[test]
+    fn range_mut_is_contiguous_after_gap_moves() {
+        let mut gb = GapBuffer::from_vec(vec![5, 4, 3, 2, 1]);
+        gb.splice(1, 0, [0]); // gap now sits after index 1
+        gb.range_mut(2, 6).sort();
+        assert_eq!(gb.into_vec(), vec![5, 0, 1, 2, 3, 4]);
+    }
+}
@@ -0,0 +1,192 @@
+use std::fmt::Write;
+
+use crate::diff::changed_ranges;
+use crate::lnhash::{AddrStyle, LnHash, LnHashSpec};
+
+/// The rules for the tables below, scoped to their `exhash` class so that a page or notebook
+/// showing them keeps its own look.
+const STYLE: &str = "<style>\
+.exhash{border-collapse:collapse;font-family:monospace;font-size:0.9em}\
+.exhash td{padding:0 0.6em;white-space:pre;vertical-align:top;text-align:left}\
+.exhash td.exhash-addr{color:#6e7781;text-align:right}\
+.exhash tr.exhash-hl{background:#fff8c5}\
+.exhash tr.exhash-add{background:#dafbe1}\
+.exhash tr.exhash-del{background:#ffebe9}\
+.exhash tr.exhash-gap td{color:#6e7781;text-align:center}\
+</style>";
+
+/// `text`'s lines as an HTML table of addresses and content, as `lnhashview` prints them,
+/// with the 1-based lines in `highlight` marked. The table comes with its own `<style>`, so
+/// it can be dropped into a page or returned from a notebook's `_repr_html_` as it is.
+pub fn html_view(text: &str, spec: &LnHashSpec, style: AddrStyle, highlight: &[usize]) -> String {
+    let linenos: Vec<usize> = (1..=text.lines().count()).collect();
+    html_view_lines(text, spec, style, &linenos, highlight)
+}
+
+/// [`html_view`] for the 1-based lines in `linenos` only, in that order, with a `⋯` row
+/// wherever one does not follow on from the line before. Numbers past the end are skipped.
+pub fn html_view_lines(
+    text: &str,
+    spec: &LnHashSpec,
+    style: AddrStyle,
+    linenos: &[usize],
+    highlight: &[usize],
+) -> String {
+    let lines: Vec<&str> = text.lines().collect();
+    let addrs = spec.lnhashes(lines.iter().copied());
+    let mut out = format!("{STYLE}<table class=\"exhash\">\n");
+    let mut prev = None;
+    for &n in linenos.iter().filter(|&&n| (1..=lines.len()).contains(&n)) {
+        if prev.is_some_and(|p| p + 1 != n) {
+            out.push_str("<tr class=\"exhash-gap\"><td colspan=\"2\">⋯</td></tr>\n");
+        }
+        prev = Some(n);
+        let class = if highlight.contains(&n) { " class=\"exhash-hl\"" } else { "" };
+        let _ = writeln!(
+            out,
+            "<tr{class}>{}<td class=\"exhash-text\">{}</td></tr>",
+            addr_cell(Some(&addrs[n - 1]), style),
+            escape(lines[n - 1])
+        );
+    }
+    out.push_str("</table>\n");
+    out
+}
+
+/// The changes from `old` to `new` as an HTML table, with `context` unchanged lines around
+/// each: a row per line, giving its address in `old` (for unchanged and removed lines) and
+/// in `new` (for unchanged and added ones), with removed and added lines colored. Returns an
+/// empty string if the texts have the same lines, as [`unified_diff`](crate::unified_diff)
+/// does.
+pub fn html_diff(
+    old: &str,
+    new: &str,
+    spec: &LnHashSpec,
+    style: AddrStyle,
+    context: usize,
+) -> String {
+    let old: Vec<&str> = old.lines().collect();
+    let new: Vec<&str> = new.lines().collect();
+    let changes = changed_ranges(&old, &new);
+    if changes.is_empty() {
+        return String::new();
+    }
+    let old_addrs = spec.lnhashes(old.iter().copied());
+    let new_addrs = spec.lnhashes(new.iter().copied());
+
+    // Every line of both texts in diff order: (old index, new index), one of them `None`
+    // for a removed or added line.
+    let mut rows: Vec<(Option<usize>, Option<usize>)> = Vec::new();
+    let (mut i, mut j) = (0, 0);
+    for (removed, added) in changes {
+        rows.extend((i..removed.start).zip(j..).map(|(i, j)| (Some(i), Some(j))));
+        rows.extend(removed.clone().map(|i| (Some(i), None)));
+        rows.extend(added.clone().map(|j| (None, Some(j))));
+        (i, j) = (removed.end, added.end);
+    }
+    rows.extend((i..old.len()).zip(j..).map(|(i, j)| (Some(i), Some(j))));
+
+    // How far each row is from the nearest change, to keep `context` unchanged lines.
+    let is_change = |row: &(Option<usize>, Option<usize>)| row.0.is_none() || row.1.is_none();
+    let mut distance = vec![usize::MAX; rows.len()];
+    let mut last = None;
+    for k in 0..rows.len() {
+        last = if is_change(&rows[k]) { Some(k) } else { last };
+        distance[k] = last.map_or(usize::MAX, |l| k - l);
+    }
+    last = None;
+    for k in (0..rows.len()).rev() {
+        last = if is_change(&rows[k]) { Some(k) } else { last };
+        distance[k] = distance[k].min(last.map_or(usize::MAX, |l| l - k));
+    }
+
+    let mut out = format!("{STYLE}<table class=\"exhash exhash-diff\">\n");
+    let mut prev = None;
+    for (k, &(i, j)) in rows.iter().enumerate().filter(|&(k, _)| distance[k] <= context) {
+        if prev.is_some_and(|p| p + 1 != k) {
+            out.push_str("<tr class=\"exhash-gap\"><td colspan=\"4\">⋯</td></tr>\n");
+        }
+        prev = Some(k);
+        let (class, marker, line) = match (i, j) {
+            (Some(i), None) => (" class=\"exhash-del\"", "-", old[i]),
+            (None, Some(j)) => (" class=\"exhash-add\"", "+", new[j]),
+            (_, j) => ("", " ", new[j.unwrap_or_default()]),
+        };
+        let _ = writeln!(
+            out,
+            "<tr{class}>{}{}<td class=\"exhash-mark\">{marker}</td>\
+             <td class=\"exhash-text\">{}</td></tr>",
+            addr_cell(i.map(|i| &old_addrs[i]), style),
+            addr_cell(j.map(|j| &new_addrs[j]), style),
+            escape(line)
+        );
+    }
+    out.push_str("</table>\n");
+    out
+}
+
+/// A table cell holding `addr`, or an empty one.
+fn addr_cell(addr: Option<&LnHash>, style: AddrStyle) -> String {
+    let addr = addr.map(|a| escape(&a.format_with(style))).unwrap_or_default();
+    format!("<td class=\"exhash-addr\">{addr}</td>")
+}
+
+/// `text` with the characters HTML gives a meaning to escaped.
+fn escape(text: &str) -> String {
+    let mut out = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => out.push_str("&amp;"),
+            '<' => out.push_str("&lt;"),
+            '>' => out.push_str("&gt;"),
+            '"' => out.push_str("&quot;"),
+            '\'' => out.push_str("&#39;"),
+            c => out.push(c),
+        }
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn html_view_and_diff_mark_lines_and_escape_text() {
+        let spec = LnHashSpec::default();
+        let addr = |n, line| spec.lnhash(n, line).to_string();
+
+        let html = html_view("a\n<b & c>\nd\n", &spec, AddrStyle::Standard, &[2]);
+        assert!(html.starts_with("<style>"));
+        let row = format!(
+            "<tr class=\"exhash-hl\"><td class=\"exhash-addr\">{}</td>\
+             <td class=\"exhash-text\">&lt;b &amp; c&gt;</td></tr>",
+            addr(2, "<b & c>")
+        );
+        assert!(html.contains(&row), "{html}");
+        assert_eq!(html.matches("<tr").count(), 3);
+        let html = html_view_lines("a\nb\nc\nd\n", &spec, AddrStyle::Standard, &[1, 3, 4, 9], &[]);
+        assert_eq!(html.matches("<tr").count(), 4);
+        assert_eq!(html.matches("<tr class=\"exhash-gap\">").count(), 1, "{html}");
+
+        let old = "1\n2\n3\n4\n5\n6\n7\n8\n";
+        let new = "1\n2\nthree\n4\n5\n6\n7\n8\nnine\n";
+        let html = html_diff(old, new, &spec, AddrStyle::Standard, 1);
+        let removed = format!(
+            "<tr class=\"exhash-del\"><td class=\"exhash-addr\">{}</td>\
+             <td class=\"exhash-addr\"></td><td class=\"exhash-mark\">-</td>",
+            addr(3, "3")
+        );
+        assert!(html.contains(&removed), "{html}");
+        let added = format!(
+            "<td class=\"exhash-addr\">{}</td><td class=\"exhash-mark\">+</td>\
+             <td class=\"exhash-text\">nine</td>",
+            addr(9, "nine")
+        );
+        assert!(html.contains(&added), "{html}");
+        // Lines 2-4 and 8-9 with one line of context, and a gap between.
+        let rows = html.lines().filter(|l| l.starts_with("<tr")).count();
+        assert_eq!(rows, 4 + 1 + 2);
+        assert_eq!(html_diff(old, old, &spec, AddrStyle::Standard, 3), "");
+    }
+}
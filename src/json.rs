@@ -0,0 +1,90 @@
+use std::fmt;
+
+use serde_json::{json, Map, Value};
+
+use crate::engine::EditResult;
+use crate::lnhash::{file_fingerprint, AddrStyle, LnHashSpec};
+use crate::EditError;
+
+/// An edit error as JSON: its message and kind, the command that failed, and for a stale
+/// address the line and the hash expected and found, as the servers report errors.
+pub fn error_json(e: &EditError) -> Value {
+    let mismatch = e.mismatch();
+    json!({
+        "message": e.message(),
+        "kind": e.kind().name(),
+        "command": e.command(),
+        "lineno": mismatch.and_then(|m| m.lineno),
+        "expected": mismatch.map(|m| &m.expected),
+        "actual": mismatch.map(|m| &m.actual),
+    })
+}
+
+/// An edit's result as JSON: its `lines`, `hashes`, `modified` and `deleted`, and the
+/// result `text` with its line endings.
+pub fn result_json(res: &EditResult) -> Value {
+    json!({
+        "lines": res.lines,
+        "hashes": res.hashes,
+        "modified": res.modified,
+        "deleted": res.deleted,
+        "text": res.text(),
+    })
+}
+
+/// Lines `start..=end` of `text` (by default all of them) as JSON rows of `lineno`, `hash`
+/// and `text`, with the text's `fingerprint` for an `@file` header.
+pub fn view_json(
+    text: &str,
+    spec: &LnHashSpec,
+    style: AddrStyle,
+    start: Option<usize>,
+    end: Option<usize>,
+) -> Value {
+    let lines: Vec<&str> = text.lines().collect();
+    let start = start.unwrap_or(1).max(1);
+    let end = end.unwrap_or(lines.len()).min(lines.len());
+    let addrs = spec.lnhashes(lines.iter().copied());
+    let rows: Vec<Value> = (start..=end)
+        .map(|n| {
+            let hash = addrs[n - 1].format_with(style);
+            json!({ "lineno": n, "hash": hash, "text": lines[n - 1] })
+        })
+        .collect();
+    json!({ "fingerprint": file_fingerprint(text), "lines": rows })
+}
+
+/// A request parameter that is missing or of the wrong type; each server turns it into
+/// its own error.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ParamError(pub String);
+
+impl fmt::Display for ParamError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+impl std::error::Error for ParamError {}
+
+/// The string parameter `name`.
+pub fn str_param<'a>(params: &'a Map<String, Value>, name: &str) -> Result<&'a str, ParamError> {
+    params
+        .get(name)
+        .and_then(Value::as_str)
+        .ok_or_else(|| ParamError(format!("{name} must be a string")))
+}
+
+/// The line number parameter `name`, or `None` if it is missing or null.
+pub fn usize_param(
+    params: &Map<String, Value>,
+    name: &str,
+) -> Result<Option<usize>, ParamError> {
+    match params.get(name) {
+        None | Some(Value::Null) => Ok(None),
+        Some(v) => v
+            .as_u64()
+            .map(|n| Some(n as usize))
+            .ok_or_else(|| ParamError(format!("{name} must be a line number"))),
+    }
+}
@@ -3,30 +3,178 @@
 //! This crate provides the string-based editing engine and command parsing for the
 //! `exhash` and `lnhashview` CLIs.
 
+#[cfg(feature = "cbor")]
+mod cbor;
+mod config;
+mod convert;
+#[cfg(feature = "glob")]
+mod discover;
+#[cfg(feature = "encoding")]
+mod encoding;
+mod diff;
 mod engine;
+mod file;
+mod gap;
+mod html;
+#[cfg(feature = "serde_json")]
+mod json;
 mod lnhash;
 mod parse;
+mod patch;
 
 #[cfg(feature = "pyo3")]
 mod python;
+#[cfg(feature = "wasm")]
+mod wasm;
+#[cfg(feature = "ffi")]
+mod ffi;
+#[cfg(feature = "mlua")]
+mod lua;
+#[cfg(feature = "lsp-types")]
+mod lsp;
+#[cfg(feature = "treesitter")]
+mod symbols;
 
-pub use engine::{edit_text, EditResult};
-pub use lnhash::{format_lnhash, line_hash_u16, parse_lnhash, LnHash};
-pub use parse::{parse_commands_from_args, parse_commands_from_script, parse_commands_from_strs, Command, Subcommand};
+#[cfg(feature = "cbor")]
+pub use cbor::{result_from_cbor, result_to_cbor, script_from_cbor, script_to_cbor};
+pub use config::{check_limits, config_paths, parse_size, Config, PROJECT_CONFIG_FILE};
+pub use convert::{convert_script, ScriptDialect};
+#[cfg(feature = "glob")]
+pub use discover::{discover_files, find_in_files, FileMatches, LineQuery};
+pub use diff::{changed_ranges, diff_commands, quickfix_lines, unified_diff};
+pub use engine::{
+    bottom_up_order, edit_text, edit_text_with, suggest_addresses, verify_commands, AddressHint,
+    CommandStep, EditOptions, EditResult, EditStats, JoinPolicy, LineEnding, LineTransform,
+    LineTransformClone, Relocation,
+};
+#[cfg(feature = "encoding")]
+pub use encoding::{check_encoding, decode_text, edit_bytes, encode_text};
+pub use file::{
+    create_beside, edit_file, split_path, write_all_atomic, write_atomic, FileOptions,
+};
+pub use html::{html_diff, html_view, html_view_lines};
+#[cfg(feature = "serde_json")]
+pub use json::{error_json, result_json, str_param, usize_param, view_json, ParamError};
+#[cfg(feature = "legacy-hash")]
+pub use lnhash::legacy_line_hash_u16;
+#[cfg(feature = "lsp-types")]
+pub use lsp::{lsp_text_edits, lsp_text_edits_with, lsp_workspace_edit};
+pub use lnhash::{
+    check_hash_width, file_fingerprint, format_lnhash, format_lnhash_width, hash_collisions, hash_collisions_with,
+    line_hash_u16, line_hash_u64, line_hash_width, lnhash_lines, lnhash_view_lines, parse_lnhash, AddrStyle,
+    HashAlgorithm, HashReport, HashVersion, LineGroup, LnHash, LnHashSpec, Normalization, DEFAULT_HASH_WIDTH,
+    SEED_ENV_VAR,
+};
+pub use parse::{
+    parse_commands_from_args, parse_commands_from_script, parse_commands_from_strs,
+    parse_commands_with_blocks, parse_commands_with_header, parse_file_header, parse_file_scripts,
+    parse_script, Command, FileScript, Script, Subcommand,
+};
+pub use patch::{parse_patch, FilePatch};
+#[cfg(feature = "treesitter")]
+pub use symbols::{
+    find_symbols, is_symbol_address, resolve_symbol, resolve_symbol_address, Symbol, SymbolKind,
+    SymbolLanguage,
+};
 
-#[derive(Debug, Clone)]
+/// Broad category of an [`EditError`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ErrorKind {
+    /// Any failure without a more specific kind.
+    Other,
+    /// The edit was cancelled via [`EditOptions::cancel`] or ran past [`EditOptions::deadline`].
+    Cancelled,
+    /// A command, script or address could not be parsed, or a regex did not compile.
+    Parse,
+    /// An lnhash, its seed, or an `@file` fingerprint no longer matches the text: the
+    /// addresses are out of date and should be fetched again.
+    StaleHash,
+    /// An address or destination lies past the end of the text.
+    OutOfRange,
+    /// The edit ran, but its result does not match [`EditOptions::expect`] or
+    /// [`EditOptions::expect_fingerprint`].
+    ExpectationFailed,
+}
+
+impl ErrorKind {
+    /// The kind in snake case (`stale_hash`), as JSON output and the bindings report it.
+    pub fn name(self) -> &'static str {
+        match self {
+            ErrorKind::Other => "other",
+            ErrorKind::Cancelled => "cancelled",
+            ErrorKind::Parse => "parse",
+            ErrorKind::StaleHash => "stale_hash",
+            ErrorKind::OutOfRange => "out_of_range",
+            ErrorKind::ExpectationFailed => "expectation_failed",
+        }
+    }
+}
+
+/// The hash a [`ErrorKind::StaleHash`] error expected and the one the text has now.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct HashMismatch {
+    /// The line whose lnhash failed, or `None` for an `@file` fingerprint.
+    pub lineno: Option<usize>,
+    /// The hash from the address or header, in hex.
+    pub expected: String,
+    /// The hash of the current text, in hex at the same width.
+    pub actual: String,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub struct EditError {
     msg: String,
+    kind: ErrorKind,
+    command: Option<usize>,
+    mismatch: Option<HashMismatch>,
 }
 
 impl EditError {
     pub(crate) fn new(msg: impl Into<String>) -> Self {
-        Self { msg: msg.into() }
+        Self::with_kind(ErrorKind::Other, msg)
+    }
+
+    pub(crate) fn parse(msg: impl Into<String>) -> Self {
+        Self::with_kind(ErrorKind::Parse, msg)
+    }
+
+    pub(crate) fn stale(msg: impl Into<String>) -> Self {
+        Self::with_kind(ErrorKind::StaleHash, msg)
+    }
+
+    pub(crate) fn with_kind(kind: ErrorKind, msg: impl Into<String>) -> Self {
+        Self { msg: msg.into(), kind, command: None, mismatch: None }
+    }
+
+    pub(crate) fn with_mismatch(mut self, mismatch: HashMismatch) -> Self {
+        self.mismatch = Some(mismatch);
+        self
+    }
+
+    pub(crate) fn at_command(mut self, index: usize) -> Self {
+        self.command = Some(index);
+        self
     }
 
     pub fn message(&self) -> &str {
         &self.msg
     }
+
+    pub fn kind(&self) -> ErrorKind {
+        self.kind
+    }
+
+    /// Index (into the slice passed to [`edit_text_with`]) of the command that failed
+    /// verification or could not be applied, if the error belongs to one command.
+    pub fn command(&self) -> Option<usize> {
+        self.command
+    }
+
+    /// For a hash that no longer matches the text, the two hashes. Other stale-hash
+    /// errors, such as a seed mismatch, have none.
+    pub fn mismatch(&self) -> Option<&HashMismatch> {
+        self.mismatch.as_ref()
+    }
 }
 
 impl std::fmt::Display for EditError {
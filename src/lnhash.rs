@@ -1,35 +1,672 @@
-use std::collections::hash_map::DefaultHasher;
-use std::hash::{Hash, Hasher};
+use std::borrow::Cow;
+use std::collections::HashMap;
+use std::fmt::{self, Write as _};
+use std::str::FromStr;
 
-use crate::EditError;
+use unicode_normalization::{is_nfc, UnicodeNormalization};
+
+use crate::{EditError, HashMismatch};
+
+const FNV_OFFSET_BASIS: u64 = 0xcbf2_9ce4_8422_2325;
+const FNV_PRIME: u64 = 0x0000_0100_0000_01b3;
+
+/// Default number of hex digits in an lnhash.
+pub const DEFAULT_HASH_WIDTH: usize = 4;
+
+/// Environment variable the CLIs read the hash seed from (see [`LnHashSpec::from_env`]).
+pub const SEED_ENV_VAR: &str = "EXHASH_SEED";
+
+/// How line hashes are computed: the single configuration point shared by hashing,
+/// address parsing and verification, the engine, the CLIs and the Python bindings.
+///
+/// The default spec is the plain 4-digit FNV-1a lnhash. `width` sets the number of hex
+/// digits in the addresses it produces (4, 6 or 8); see [`LnHashSpec::validate`].
+/// A `seed` (for example a project name) is mixed into every line hash, placing addresses
+/// in their own namespace: an address made under one seed fails verification under any
+/// other seed, or under none, instead of being replayed against another project's file.
+/// Seeded addresses carry a tag derived from the seed, e.g. `12|s1a2b:a3f2|`, so the
+/// mismatch is reported as such rather than as a stale hash.
+///
+/// `normalization` is applied to each line before hashing. It is recorded in the
+/// addresses this spec produces (e.g. `12|nfc:a3f2|`), and an address is always verified
+/// with the normalization it was made with, whatever this spec's setting.
+///
+/// With `versioned`, produced addresses also name the hash algorithm, e.g. `12|v2:a3f2|`,
+/// so that they keep verifying (or fail clearly) if the default algorithm ever changes.
+///
+/// `algorithm` picks between hashing each line alone and chaining it to the line before
+/// (see [`HashAlgorithm::Chained`]).
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LnHashSpec {
+    pub algorithm: HashAlgorithm,
+    pub width: usize,
+    pub normalization: Normalization,
+    pub seed: Option<String>,
+    pub versioned: bool,
+}
+
+impl Default for LnHashSpec {
+    fn default() -> Self {
+        Self {
+            algorithm: HashAlgorithm::default(),
+            width: DEFAULT_HASH_WIDTH,
+            normalization: Normalization::default(),
+            seed: None,
+            versioned: false,
+        }
+    }
+}
+
+/// Which inputs a line hash covers.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum HashAlgorithm {
+    /// The line's own content.
+    #[default]
+    Line,
+    /// The line's content, continuing from 16 bits of the previous line's own hash. Identical
+    /// lines (`}`, `- item`) in different places get different addresses, so a stale address
+    /// cannot silently match another occurrence; in exchange, editing a line also
+    /// invalidates the address of the line after it. Tagged `prev`.
+    Chained,
+}
+
+/// A version of the line hash function, as named by an address's `vN:` tag.
+///
+/// Untagged addresses use [`HashVersion::CURRENT`] (and, with the `legacy-hash` feature,
+/// may also match [`HashVersion::V1`]).
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum HashVersion {
+    /// The pre-FNV hash: low 16 bits of `DefaultHasher`. Verifying it needs the
+    /// `legacy-hash` feature.
+    V1,
+    /// 64-bit FNV-1a, truncated to the address width.
+    V2,
+}
+
+impl HashVersion {
+    /// The algorithm used to produce new addresses.
+    pub const CURRENT: Self = HashVersion::V2;
+
+    fn number(self) -> u32 {
+        match self {
+            HashVersion::V1 => 1,
+            HashVersion::V2 => 2,
+        }
+    }
+}
+
+/// Unicode normalization applied to a line before it is hashed.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Normalization {
+    /// Normalize to NFC, so that composed and decomposed spellings of the same text
+    /// (`é` as one code point, or `e` plus a combining accent) hash alike. Tagged `nfc`.
+    pub nfc: bool,
+    /// Lowercase the line (after NFC, if both are set), so that edits which only change
+    /// case do not invalidate addresses. Tagged `lc`.
+    pub lowercase: bool,
+}
+
+impl Normalization {
+    fn apply<'a>(&self, line: &'a str) -> Cow<'a, str> {
+        let mut line = Cow::Borrowed(line);
+        if self.nfc && !is_nfc(&line) {
+            line = Cow::Owned(line.nfc().collect());
+        }
+        if self.lowercase && line.chars().any(char::is_uppercase) {
+            line = Cow::Owned(line.to_lowercase());
+        }
+        line
+    }
+}
+
+impl LnHashSpec {
+    /// A spec using `seed`.
+    pub fn seeded(seed: impl Into<String>) -> Self {
+        Self {
+            seed: Some(seed.into()),
+            ..Self::default()
+        }
+    }
+
+    /// The default spec, seeded from [`SEED_ENV_VAR`] if it is set and non-empty.
+    pub fn from_env() -> Self {
+        match std::env::var(SEED_ENV_VAR) {
+            Ok(seed) if !seed.is_empty() => Self::seeded(seed),
+            _ => Self::default(),
+        }
+    }
+
+    /// The 16-bit id shown in addresses made with this spec's seed.
+    pub fn seed_id(&self) -> Option<u16> {
+        self.seed.as_deref().map(|s| (fnv1a64(s.as_bytes()) & 0xffff) as u16)
+    }
+
+    /// Check that this spec's `width` is supported.
+    pub fn validate(&self) -> Result<(), EditError> {
+        check_hash_width(self.width)
+    }
+
+    /// Hash `line` under this spec.
+    ///
+    /// A chained spec hashes `line` as the first line of a file; see
+    /// [`line_hash_after`](Self::line_hash_after).
+    pub fn line_hash(&self, line: &str) -> u32 {
+        self.line_hash_after(None, line)
+    }
+
+    /// Hash `line`, which follows `prev` (`None` for the first line). `prev` only matters
+    /// for a chained spec.
+    pub fn line_hash_after(&self, prev: Option<&str>, line: &str) -> u32 {
+        low_bits(self.line_hash_u64_after(prev, line), self.width)
+    }
+
+    /// The full 64-bit hash of `line`, following `prev`, before truncation to `width`.
+    pub fn line_hash_u64_after(&self, prev: Option<&str>, line: &str) -> u64 {
+        self.full_hash(self.normalization, self.algorithm, prev, line)
+    }
+
+    /// The untruncated hash of `line` under this spec's seed and the given mode.
+    fn full_hash(
+        &self,
+        normalization: Normalization,
+        algorithm: HashAlgorithm,
+        prev: Option<&str>,
+        line: &str,
+    ) -> u64 {
+        let basis = self.basis();
+        let line = normalization.apply(line);
+        if algorithm == HashAlgorithm::Line {
+            return fnv1a64_update(basis, line.as_bytes());
+        }
+        let prev = prev.map(|p| fnv1a64_update(basis, normalization.apply(p).as_bytes()));
+        fnv1a64_update(context_state(basis, prev), line.as_bytes())
+    }
+
+    /// The FNV state every line hash starts from: the offset basis, advanced over the seed.
+    fn basis(&self) -> u64 {
+        match &self.seed {
+            // 0xff never occurs in UTF-8, so no seed/line split is ambiguous.
+            Some(seed) => {
+                fnv1a64_update(fnv1a64_update(FNV_OFFSET_BASIS, seed.as_bytes()), &[0xff])
+            }
+            None => FNV_OFFSET_BASIS,
+        }
+    }
+
+    /// The addresses of `lines`, numbered from 1, in a single pass. A byte order mark at
+    /// the start of the first line is left out of its hash, as for every address of line 1.
+    ///
+    /// Equivalent to calling [`lnhash_after`](Self::lnhash_after) on each line, but the
+    /// seed, and for a chained spec each line's own hash, is computed only once.
+    pub fn lnhashes<'a>(&self, lines: impl IntoIterator<Item = &'a str>) -> Vec<LnHash> {
+        self.hash_lines(lines).into_iter().map(|(addr, _)| addr).collect()
+    }
+
+    /// Like [`lnhashes`](Self::lnhashes), but pairs each address with the full 64-bit
+    /// hash it was truncated from, for callers that want a collision-resistant value.
+    pub fn hash_lines<'a>(&self, lines: impl IntoIterator<Item = &'a str>) -> Vec<(LnHash, u64)> {
+        let (basis, seed, normalization) = (self.basis(), self.seed_id(), self.normalization);
+        let (version, algorithm, width) = (self.version(), self.algorithm, self.width);
+        let mut prev = None;
+        lines
+            .into_iter()
+            .enumerate()
+            .map(|(i, line)| {
+                let line = normalization.apply(hashed_line(i + 1, line));
+                let own = fnv1a64_update(basis, line.as_bytes());
+                let full = match algorithm {
+                    HashAlgorithm::Line => own,
+                    HashAlgorithm::Chained => {
+                        fnv1a64_update(context_state(basis, prev), line.as_bytes())
+                    }
+                };
+                prev = Some(own);
+                let addr = LnHash {
+                    lineno: i + 1,
+                    hash: low_bits(full, width),
+                    width,
+                    seed,
+                    normalization,
+                    version,
+                    algorithm,
+                };
+                (addr, full)
+            })
+            .collect()
+    }
+
+    /// The lines of `text` as `lnhashview` prints them: `<address>  <content>`.
+    pub fn view_lines(&self, text: &str, style: AddrStyle) -> Vec<String> {
+        self.lnhashes(text.lines())
+            .iter()
+            .zip(text.lines())
+            .map(|(addr, line)| format!("{}  {line}", addr.format_with(style)))
+            .collect()
+    }
+
+    /// The address of `line` at 1-based `lineno` under this spec.
+    ///
+    /// A chained spec hashes `line` as if nothing preceded it; use
+    /// [`lnhash_after`](Self::lnhash_after) for lines after the first.
+    pub fn lnhash(&self, lineno: usize, line: &str) -> LnHash {
+        self.lnhash_after(lineno, None, line)
+    }
+
+    /// The address of `line` at 1-based `lineno`, which follows `prev`.
+    pub fn lnhash_after(&self, lineno: usize, prev: Option<&str>, line: &str) -> LnHash {
+        LnHash {
+            lineno,
+            hash: self.line_hash_after(
+                prev.map(|p| hashed_line(lineno.saturating_sub(1), p)),
+                hashed_line(lineno, line),
+            ),
+            width: self.width,
+            seed: self.seed_id(),
+            normalization: self.normalization,
+            version: self.version(),
+            algorithm: self.algorithm,
+        }
+    }
+
+    /// Parse an address, rejecting it up front if it was made under a different seed.
+    pub fn parse_lnhash(&self, s: &str) -> Result<LnHash, EditError> {
+        let addr = parse_lnhash(s)?;
+        self.check_seed(&addr)?;
+        Ok(addr)
+    }
+
+    fn version(&self) -> Option<HashVersion> {
+        self.versioned.then_some(HashVersion::CURRENT)
+    }
+
+    /// Check that `addr` was made under this spec's seed (or, like this spec, under none).
+    fn check_seed(&self, addr: &LnHash) -> Result<(), EditError> {
+        let n = addr.lineno;
+        match (addr.seed, self.seed_id()) {
+            (Some(a), None) => Err(EditError::stale(format!(
+                "lnhash at line {n} was made with seed s{a:04x}, but no seed is set"
+            ))),
+            (None, Some(s)) => Err(EditError::stale(format!(
+                "lnhash at line {n} is unseeded, but seed s{s:04x} is set"
+            ))),
+            (Some(a), Some(s)) if a != s => Err(EditError::stale(format!(
+                "lnhash at line {n} was made with seed s{a:04x}, but the current seed is s{s:04x}"
+            ))),
+            _ => Ok(()),
+        }
+    }
+
+    /// Check that `addr` is a valid address for `line`, which follows `prev`, under this
+    /// spec.
+    ///
+    /// Only the seed must match the spec: the width, normalization, algorithm and version
+    /// are taken from the address itself.
+    pub fn verify(
+        &self,
+        addr: &LnHash,
+        prev: Option<&str>,
+        line: &str,
+    ) -> Result<(), EditError> {
+        self.check_seed(addr)?;
+        let n = addr.lineno;
+        let w = addr.width;
+        let prev = prev.map(|p| hashed_line(n.saturating_sub(1), p));
+        let line = hashed_line(n, line);
+        let plain = self.seed.is_none()
+            && addr.normalization == Normalization::default()
+            && addr.algorithm == HashAlgorithm::Line;
+        let v2 = || low_bits(self.full_hash(addr.normalization, addr.algorithm, prev, line), w);
+        let (matches, actual) = match addr.version {
+            Some(HashVersion::V1) => {
+                let actual = v1_hash(line, n)?;
+                (actual == addr.hash, actual)
+            }
+            Some(HashVersion::V2) => {
+                let actual = v2();
+                (actual == addr.hash, actual)
+            }
+            None => {
+                let actual = v2();
+                let matches = if plain {
+                    hash_matches(line, addr.hash, w)
+                } else {
+                    actual == addr.hash
+                };
+                (matches, actual)
+            }
+        };
+        if !matches {
+            let hint = if !addr.normalization.nfc && !is_nfc(line) {
+                " (line is not in Unicode NFC form; NFC hashing may help)".to_string()
+            } else if addr.algorithm == HashAlgorithm::Chained && n > 1 {
+                format!(" (chained hash; line {} may have changed instead)", n - 1)
+            } else {
+                String::new()
+            };
+            let mismatch = HashMismatch {
+                lineno: Some(n),
+                expected: format!("{:0w$x}", addr.hash),
+                actual: format!("{actual:0w$x}"),
+            };
+            return Err(EditError::stale(format!(
+                "stale lnhash at line {n}: expected {}, got {}{hint}",
+                mismatch.expected, mismatch.actual
+            ))
+            .with_mismatch(mismatch));
+        }
+        Ok(())
+    }
+}
+
+/// The FNV state a chained line hash continues from: `basis`, advanced over 16 bits of the
+/// previous line's own hash (zero for the first line).
+fn context_state(basis: u64, prev: Option<u64>) -> u64 {
+    let ctx = prev.map_or(0, |h| h as u16);
+    fnv1a64_update(fnv1a64_update(basis, &ctx.to_le_bytes()), &[0xff])
+}
+
+/// Textual form of an lnhash address.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum AddrStyle {
+    /// `lineno|hash|`, e.g. `1234|a3f2|`.
+    #[default]
+    Standard,
+    /// `lineno:hash` with the line number in base 36, e.g. `ya:a3f2`. Shorter, for
+    /// contexts that display thousands of addresses.
+    Compact,
+}
 
 /// A verified line address: a 1-based line number paired with a short content hash.
-#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+///
+/// Displays as, and parses from, `lineno|hash|`, with any tags ahead of the hash, e.g.
+/// `lineno|v2:sNNNN:hash|`; [`parse_lnhash`] also accepts the compact form (see
+/// [`AddrStyle::Compact`]).
+/// Ordering is by line number first.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
 pub struct LnHash {
     pub lineno: usize,
-    pub hash: u16,
+    /// The low `4 * width` bits of the line hash.
+    pub hash: u32,
+    /// Number of hex digits in the hash: 4, 6 or 8.
+    pub width: usize,
+    /// Id of the seed the hash was made with (see [`LnHashSpec::seed_id`]).
+    pub seed: Option<u16>,
+    /// Normalization applied to the line before hashing.
+    pub normalization: Normalization,
+    /// Hash algorithm named by the address, if it is tagged with one.
+    pub version: Option<HashVersion>,
+    /// Which inputs the hash covers.
+    pub algorithm: HashAlgorithm,
+}
+
+impl LnHash {
+    /// The address of `line` at 1-based `lineno`, with a 4-digit hash.
+    pub fn of(lineno: usize, line: &str) -> Self {
+        Self::of_width(lineno, line, DEFAULT_HASH_WIDTH)
+    }
+
+    /// The address of `line` at 1-based `lineno`, with a `width`-digit hash.
+    pub fn of_width(lineno: usize, line: &str, width: usize) -> Self {
+        Self {
+            lineno,
+            hash: line_hash_width(hashed_line(lineno, line), width),
+            width,
+            seed: None,
+            normalization: Normalization::default(),
+            version: None,
+            algorithm: HashAlgorithm::Line,
+        }
+    }
+
+    /// Render this address in the given style.
+    pub fn format_with(&self, style: AddrStyle) -> String {
+        match style {
+            AddrStyle::Standard => self.to_string(),
+            AddrStyle::Compact => format!("{}:{}", to_base36(self.lineno), self.hash_field()),
+        }
+    }
+
+    /// The hash with any tags, as written between the `|` delimiters (`a3f2`,
+    /// `nfc:a3f2`).
+    pub fn hash_field(&self) -> String {
+        let mut field = String::new();
+        if let Some(v) = self.version {
+            let _ = write!(field, "v{}:", v.number());
+        }
+        if let Some(id) = self.seed {
+            let _ = write!(field, "s{id:04x}:");
+        }
+        if self.normalization.nfc {
+            field.push_str("nfc:");
+        }
+        if self.normalization.lowercase {
+            field.push_str("lc:");
+        }
+        if self.algorithm == HashAlgorithm::Chained {
+            field.push_str("prev:");
+        }
+        let _ = write!(field, "{:0w$x}", self.hash, w = self.width);
+        field
+    }
+}
+
+fn to_base36(mut n: usize) -> String {
+    const DIGITS: &[u8; 36] = b"0123456789abcdefghijklmnopqrstuvwxyz";
+    let mut out = Vec::new();
+    loop {
+        out.push(DIGITS[n % 36]);
+        n /= 36;
+        if n == 0 {
+            break;
+        }
+    }
+    out.reverse();
+    String::from_utf8(out).unwrap()
+}
+
+impl fmt::Display for LnHash {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}|{}|", self.lineno, self.hash_field())
+    }
+}
+
+impl FromStr for LnHash {
+    type Err = EditError;
+
+    fn from_str(s: &str) -> Result<Self, EditError> {
+        parse_lnhash(s)
+    }
+}
+
+/// Feed `bytes` into a running 64-bit FNV-1a state.
+fn fnv1a64_update(mut h: u64, bytes: &[u8]) -> u64 {
+    for &b in bytes {
+        h ^= b as u64;
+        h = h.wrapping_mul(FNV_PRIME);
+    }
+    h
+}
+
+/// 64-bit FNV-1a over `bytes`.
+fn fnv1a64(bytes: &[u8]) -> u64 {
+    fnv1a64_update(FNV_OFFSET_BASIS, bytes)
+}
+
+/// Fingerprint a whole text: 64-bit FNV-1a over each line followed by `\n`, as 16 hex
+/// digits.
+///
+/// Lines are split the same way the engine splits them, so LF and CRLF copies of a file
+/// (and copies with or without a final newline) share a fingerprint. A leading byte order
+/// mark is not part of the first line, so it does not count either.
+pub fn file_fingerprint(text: &str) -> String {
+    let text = text.strip_prefix(BOM).unwrap_or(text);
+    let h = text.lines().fold(FNV_OFFSET_BASIS, |h, line| {
+        fnv1a64_update(fnv1a64_update(h, line.as_bytes()), b"\n")
+    });
+    format!("{h:016x}")
+}
+
+/// The byte order mark (U+FEFF) some editors write at the start of a UTF-8 file.
+pub(crate) const BOM: char = '\u{feff}';
+
+/// `line` as it is hashed at 1-based `lineno`: line 1 without a leading [`BOM`], which
+/// says how the file is encoded rather than what its first line holds.
+fn hashed_line(lineno: usize, line: &str) -> &str {
+    match lineno {
+        1 => line.strip_prefix(BOM).unwrap_or(line),
+        _ => line,
+    }
+}
+
+/// Check that `width` is a supported hash width (4, 6 or 8 hex digits).
+pub fn check_hash_width(width: usize) -> Result<(), EditError> {
+    match width {
+        4 | 6 | 8 => Ok(()),
+        _ => Err(EditError::new(format!(
+            "hash width must be 4, 6 or 8 hex chars, got {width}"
+        ))),
+    }
+}
+
+/// Compute the lnhash of a line's content truncated to `width` hex digits.
+///
+/// This is the low `4 * width` bits of the same FNV-1a hash as [`line_hash_u16`], so the
+/// 4-digit form of every width agrees.
+pub fn line_hash_width(line: &str, width: usize) -> u32 {
+    low_bits(line_hash_u64(line), width)
+}
+
+/// The full 64-bit FNV-1a hash of a line's content, of which lnhashes are the low bits.
+///
+/// Addresses keep the short form; this is for tools (deduplication, indexing) that want a
+/// collision-resistant value for the same line.
+pub fn line_hash_u64(line: &str) -> u64 {
+    fnv1a64(line.as_bytes())
+}
+
+fn low_bits(h: u64, width: usize) -> u32 {
+    let bits = 4 * width.min(8) as u32;
+    (h & ((1u64 << bits) - 1)) as u32
 }
 
 /// Compute the 16-bit lnhash of a line's content.
 ///
-/// The hash is the low 16 bits of `std::collections::hash_map::DefaultHasher` (SipHash-1-3)
-/// over the UTF-8 line content (excluding the line ending).
+/// The hash is the low 16 bits of 64-bit FNV-1a over the UTF-8 line content (excluding
+/// the line ending). Unlike `DefaultHasher`, this is fixed across Rust releases and
+/// platforms, so addresses stay valid between builds.
 pub fn line_hash_u16(line: &str) -> u16 {
+    (line_hash_u64(line) & 0xffff) as u16
+}
+
+/// The pre-FNV lnhash: the low 16 bits of `DefaultHasher` (SipHash-1-3) over `line`.
+///
+/// `DefaultHasher` is not guaranteed stable across Rust releases, so this only matches
+/// addresses produced by a build using the same standard library.
+#[cfg(feature = "legacy-hash")]
+pub fn legacy_line_hash_u16(line: &str) -> u16 {
+    use std::collections::hash_map::DefaultHasher;
+    use std::hash::{Hash, Hasher};
+
     let mut h = DefaultHasher::new();
     line.hash(&mut h);
     (h.finish() & 0xffff) as u16
 }
 
+/// Whether `hash` is a valid `width`-digit lnhash for `line`. With the `legacy-hash`
+/// feature, 4-digit hashes from older builds are accepted too.
+pub(crate) fn hash_matches(line: &str, hash: u32, width: usize) -> bool {
+    if line_hash_width(line, width) == hash {
+        return true;
+    }
+    #[cfg(feature = "legacy-hash")]
+    if width == 4 && legacy_line_hash_u16(line) as u32 == hash {
+        return true;
+    }
+    false
+}
+
+/// The default 4-digit addresses of every line of `text`.
+pub fn lnhash_lines(text: &str) -> Vec<LnHash> {
+    LnHashSpec::default().lnhashes(text.lines())
+}
+
+/// Every line of `text` as `lnhashview` prints it by default: `lineno|hash|  content`.
+pub fn lnhash_view_lines(text: &str) -> Vec<String> {
+    LnHashSpec::default().view_lines(text, AddrStyle::Standard)
+}
+
 /// Format a line address as `lineno|hash|`.
 pub fn format_lnhash(lineno: usize, line: &str) -> String {
-    format!("{}|{:04x}|", lineno, line_hash_u16(line))
+    LnHash::of(lineno, line).to_string()
+}
+
+/// Format a line address as `lineno|hash|` with a `width`-digit hash.
+pub fn format_lnhash_width(lineno: usize, line: &str, width: usize) -> String {
+    LnHash::of_width(lineno, line, width).to_string()
+}
+
+/// Lines sharing one hash value.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LineGroup {
+    pub hash: u32,
+    /// 1-based line numbers, ascending.
+    pub linenos: Vec<usize>,
+}
+
+/// Where a text's lnhashes cannot tell lines apart.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct HashReport {
+    /// Groups of lines with different content but the same hash.
+    pub collisions: Vec<LineGroup>,
+    /// Groups of lines with identical content (and therefore identical hashes).
+    pub duplicates: Vec<LineGroup>,
+}
+
+/// Scan `text` for hash collisions and duplicate lines at the given hash `width`.
+///
+/// Groups are ordered by their first line number.
+pub fn hash_collisions(text: &str, width: usize) -> HashReport {
+    hash_collisions_with(text, &LnHashSpec { width, ..Default::default() })
+}
+
+/// Like [`hash_collisions`], hashing under `spec`.
+pub fn hash_collisions_with(text: &str, spec: &LnHashSpec) -> HashReport {
+    let mut by_hash: HashMap<u32, Vec<(usize, &str)>> = HashMap::new();
+    for (addr, line) in spec.lnhashes(text.lines()).into_iter().zip(text.lines()) {
+        by_hash.entry(addr.hash).or_default().push((addr.lineno, line));
+    }
+
+    let mut report = HashReport::default();
+    for (hash, lines) in by_hash {
+        if lines.len() < 2 {
+            continue;
+        }
+        let mut by_content: HashMap<&str, Vec<usize>> = HashMap::new();
+        for &(lineno, line) in &lines {
+            by_content.entry(line).or_default().push(lineno);
+        }
+        if by_content.len() > 1 {
+            report.collisions.push(LineGroup {
+                hash,
+                linenos: lines.iter().map(|&(n, _)| n).collect(),
+            });
+        }
+        for (_, linenos) in by_content {
+            if linenos.len() > 1 {
+                report.duplicates.push(LineGroup { hash, linenos });
+            }
+        }
+    }
+    report.collisions.sort_by_key(|g| g.linenos[0]);
+    report.duplicates.sort_by_key(|g| g.linenos[0]);
+    report
 }
 
 /// Parse a `lineno|hash|` address.
 pub fn parse_lnhash(s: &str) -> Result<LnHash, EditError> {
     let (lh, rest) = parse_lnhash_prefix(s)?;
     if !rest.is_empty() {
-        return Err(EditError::new(format!(
+        return Err(EditError::parse(format!(
             "invalid lnhash: trailing characters after address: {:?}",
             rest
         )));
@@ -38,41 +675,196 @@ pub fn parse_lnhash(s: &str) -> Result<LnHash, EditError> {
 }
 
 /// Parse a `lineno|hash|` from the start of `input`, returning the address and the remaining suffix.
+///
+/// The compact `lineno:hash` form is accepted too. It has no closing delimiter, so its
+/// hash is taken as the longest valid width (8, 6 or 4) among the lowercase hex digits
+/// that follow; commands that start with a hex letter (`a`, `c`, `d`) remain unambiguous
+/// because no command continues with further hex digits.
 pub fn parse_lnhash_prefix(input: &str) -> Result<(LnHash, &str), EditError> {
+    let head_len = input
+        .find(|c: char| !c.is_ascii_alphanumeric())
+        .unwrap_or(input.len());
+    if input[head_len..].starts_with(':') {
+        return parse_compact_prefix(&input[..head_len], &input[head_len + 1..]);
+    }
+
     let mut it = input.splitn(2, '|');
     let lineno_str = it
         .next()
-        .ok_or_else(|| EditError::new("invalid lnhash: missing line number"))?;
+        .ok_or_else(|| EditError::parse("invalid lnhash: missing line number"))?;
     let rest = it
         .next()
-        .ok_or_else(|| EditError::new("invalid lnhash: missing '|' after line number"))?;
+        .ok_or_else(|| EditError::parse("invalid lnhash: missing '|' after line number"))?;
 
     if lineno_str.is_empty() {
-        return Err(EditError::new("invalid lnhash: empty line number"));
+        return Err(EditError::parse("invalid lnhash: empty line number"));
     }
     let lineno: usize = lineno_str
         .parse()
-        .map_err(|_| EditError::new(format!("invalid lnhash: bad line number: {lineno_str:?}")))?;
+        .map_err(|_| EditError::parse(format!("invalid lnhash: bad line number: {lineno_str:?}")))?;
 
-    // Now parse hash|suffix
+    // Now parse [tag:]...hash|suffix
     let mut it2 = rest.splitn(2, '|');
-    let hash_str = it2
+    let field = it2
         .next()
-        .ok_or_else(|| EditError::new("invalid lnhash: missing hash"))?;
+        .ok_or_else(|| EditError::parse("invalid lnhash: missing hash"))?;
     let suffix = it2
         .next()
-        .ok_or_else(|| EditError::new("invalid lnhash: missing trailing '|' after hash"))?;
+        .ok_or_else(|| EditError::parse("invalid lnhash: missing trailing '|' after hash"))?;
 
-    if hash_str.len() != 4 {
-        return Err(EditError::new(format!(
-            "invalid lnhash: hash must be 4 hex chars, got {hash_str:?}"
+    let (tags, hash_str) = match field.rsplit_once(':') {
+        Some((tags, hash_str)) => (Some(tags), hash_str),
+        None => (None, field),
+    };
+    let mut lh = LnHash {
+        lineno,
+        hash: 0,
+        width: 0,
+        seed: None,
+        normalization: Normalization::default(),
+        version: None,
+        algorithm: HashAlgorithm::Line,
+    };
+    for tag in tags.into_iter().flat_map(|t| t.split(':')) {
+        apply_tag(&mut lh, tag)?;
+    }
+
+    let width = hash_str.len();
+    if check_hash_width(width).is_err() {
+        return Err(EditError::parse(format!(
+            "invalid lnhash: hash must be 4, 6 or 8 hex chars, got {hash_str:?}"
         )));
     }
 
-    let hash = u16::from_str_radix(hash_str, 16)
-        .map_err(|_| EditError::new(format!("invalid lnhash: bad hash: {hash_str:?}")))?;
+    lh.hash = u32::from_str_radix(hash_str, 16)
+        .map_err(|_| EditError::parse(format!("invalid lnhash: bad hash: {hash_str:?}")))?;
+    lh.width = width;
+
+    check_tags(&lh)?;
+    Ok((lh, suffix))
+}
+
+/// Record one `tag:` from an address's hash field on `lh`.
+fn apply_tag(lh: &mut LnHash, tag: &str) -> Result<(), EditError> {
+    let bad = || EditError::parse(format!("invalid lnhash: bad tag: {tag:?}"));
+    if let Some(num) = tag.strip_prefix('v') {
+        let version = match num.parse::<u32>() {
+            Ok(1) => HashVersion::V1,
+            Ok(2) => HashVersion::V2,
+            Ok(_) => {
+                return Err(EditError::parse(format!(
+                    "invalid lnhash: unsupported hash version {tag:?} (made by a newer exhash?)"
+                )));
+            }
+            Err(_) => return Err(bad()),
+        };
+        if lh.version.replace(version).is_some() {
+            return Err(bad());
+        }
+        return Ok(());
+    }
+    let flag = match tag {
+        "nfc" => Some(&mut lh.normalization.nfc),
+        "lc" => Some(&mut lh.normalization.lowercase),
+        "prev" => {
+            if lh.algorithm == HashAlgorithm::Chained {
+                return Err(bad());
+            }
+            lh.algorithm = HashAlgorithm::Chained;
+            return Ok(());
+        }
+        _ => None,
+    };
+    if let Some(flag) = flag {
+        if std::mem::replace(flag, true) {
+            return Err(bad());
+        }
+        return Ok(());
+    }
+    let id = tag
+        .strip_prefix('s')
+        .filter(|id| id.len() == 4)
+        .and_then(|id| u16::from_str_radix(id, 16).ok())
+        .ok_or_else(bad)?;
+    if lh.seed.replace(id).is_some() {
+        return Err(bad());
+    }
+    Ok(())
+}
 
-    Ok((LnHash { lineno, hash }, suffix))
+/// Reject tag combinations no hash was ever made with, once the whole address is parsed.
+fn check_tags(lh: &LnHash) -> Result<(), EditError> {
+    let v1_only = lh.width == 4
+        && lh.seed.is_none()
+        && lh.normalization == Normalization::default()
+        && lh.algorithm == HashAlgorithm::Line;
+    if lh.version == Some(HashVersion::V1) && !v1_only {
+        return Err(EditError::parse(
+            "invalid lnhash: v1 hashes are 4 hex chars and take no other tags",
+        ));
+    }
+    Ok(())
+}
+
+/// The v1 (pre-FNV) hash of `line`, for verifying the address at line `n`.
+#[cfg(feature = "legacy-hash")]
+fn v1_hash(line: &str, _n: usize) -> Result<u32, EditError> {
+    Ok(legacy_line_hash_u16(line) as u32)
+}
+
+#[cfg(not(feature = "legacy-hash"))]
+fn v1_hash(_line: &str, n: usize) -> Result<u32, EditError> {
+    Err(EditError::new(format!(
+        "lnhash at line {n} uses hash v1 (pre-FNV), which needs the legacy-hash feature"
+    )))
+}
+
+/// Whether `s` could be a tag rather than the start of a hash. Every tag starts with a
+/// letter past `f`, so it can never be mistaken for hex digits.
+fn is_tag(s: &str) -> bool {
+    s.starts_with(|c: char| c.is_ascii_lowercase() && c > 'f')
+}
+
+fn parse_compact_prefix<'a>(lineno_str: &str, mut rest: &'a str) -> Result<(LnHash, &'a str), EditError> {
+    if lineno_str.is_empty() {
+        return Err(EditError::parse("invalid lnhash: empty line number"));
+    }
+    let lineno = usize::from_str_radix(lineno_str, 36)
+        .map_err(|_| EditError::parse(format!("invalid lnhash: bad line number: {lineno_str:?}")))?;
+    let mut lh = LnHash {
+        lineno,
+        hash: 0,
+        width: 0,
+        seed: None,
+        normalization: Normalization::default(),
+        version: None,
+        algorithm: HashAlgorithm::Line,
+    };
+    loop {
+        let tag_len = rest.find(|c: char| !c.is_ascii_alphanumeric()).unwrap_or(rest.len());
+        if !is_tag(rest) || !rest[tag_len..].starts_with(':') {
+            break;
+        }
+        apply_tag(&mut lh, &rest[..tag_len])?;
+        rest = &rest[tag_len + 1..];
+    }
+    let run = rest
+        .bytes()
+        .take_while(|b| b.is_ascii_digit() || (b'a'..=b'f').contains(b))
+        .count();
+    let width = [8, 6, 4]
+        .into_iter()
+        .find(|&w| w <= run)
+        .ok_or_else(|| {
+            EditError::parse(format!(
+                "invalid lnhash: hash must be 4, 6 or 8 hex chars, got {:?}",
+                &rest[..run]
+            ))
+        })?;
+    lh.hash = u32::from_str_radix(&rest[..width], 16).unwrap();
+    lh.width = width;
+    check_tags(&lh)?;
+    Ok((lh, &rest[width..]))
 }
 
 #[cfg(test)]
@@ -87,7 +879,282 @@ mod tests {
         assert!(addr.ends_with('|'));
         let parsed = parse_lnhash(&addr).unwrap();
         assert_eq!(parsed.lineno, 12);
-        assert_eq!(parsed.hash, line_hash_u16(line));
+        assert_eq!(parsed.hash, line_hash_u16(line) as u32);
+        assert_eq!(parsed.width, 4);
+    }
+
+    #[test]
+    fn wide_hashes_roundtrip_and_share_low_bits() {
+        let line = "}";
+        for width in [4, 6, 8] {
+            let addr = format_lnhash_width(7, line, width);
+            let parsed = parse_lnhash(&addr).unwrap();
+            assert_eq!(parsed.width, width);
+            assert_eq!(parsed.hash, line_hash_width(line, width));
+            assert!(hash_matches(line, parsed.hash, width));
+            assert_eq!(parsed.hash & 0xffff, line_hash_u16(line) as u32);
+        }
+        assert!(parse_lnhash("1|abcde|").is_err());
+        assert!(check_hash_width(5).is_err());
+    }
+
+    #[test]
+    fn line_hash_is_low_bits_of_fnv1a() {
+        // Reference FNV-1a 64 values: "" = cbf29ce484222325, "a" = af63dc4c8601ec8c.
+        assert_eq!(line_hash_u16(""), 0x2325);
+        assert_eq!(line_hash_u16("a"), 0xec8c);
+        assert!(hash_matches("a", 0xec8c, 4));
+        assert!(!hash_matches("a", 0xec8d, 4));
+        assert_eq!(line_hash_width("a", 8), 0x8601ec8c);
+        assert_eq!(line_hash_u64("a"), 0xaf63dc4c8601ec8c);
+
+        let spec = LnHashSpec { algorithm: HashAlgorithm::Chained, ..LnHashSpec::seeded("p") };
+        let lines = ["a", "}", "}"];
+        for (i, (addr, full)) in spec.hash_lines(lines).into_iter().enumerate() {
+            let prev = i.checked_sub(1).map(|p| lines[p]);
+            assert_eq!(full, spec.line_hash_u64_after(prev, lines[i]));
+            assert_eq!(addr.hash, low_bits(full, 4));
+        }
+    }
+
+    #[test]
+    fn collision_report_groups_lines() {
+        // Find two distinct lines that collide at 16 bits.
+        let target = line_hash_u16("x0");
+        let other = (1..)
+            .map(|i| format!("x{i}"))
+            .find(|l| line_hash_u16(l) == target)
+            .unwrap();
+        let text = format!("x0\n}}\n{other}\n}}\nx0\n");
+        let report = hash_collisions(&text, 4);
+        assert_eq!(report.collisions.len(), 1);
+        assert_eq!(report.collisions[0].linenos, vec![1, 3, 5]);
+        let dups: Vec<Vec<usize>> = report.duplicates.iter().map(|g| g.linenos.clone()).collect();
+        assert_eq!(dups, vec![vec![1, 5], vec![2, 4]]);
+        // Wider hashes tell the colliding lines apart.
+        assert!(hash_collisions(&text, 8).collisions.is_empty());
+    }
+
+    #[test]
+    fn file_fingerprint_ignores_line_ending_style() {
+        let fp = file_fingerprint("a\nb\n");
+        assert_eq!(fp.len(), 16);
+        assert_eq!(fp, file_fingerprint("a\r\nb"));
+        assert_ne!(fp, file_fingerprint("a\nb\nc\n"));
+        assert_eq!(file_fingerprint(""), format!("{FNV_OFFSET_BASIS:016x}"));
+    }
+
+    #[test]
+    fn byte_order_mark_is_not_hashed() {
+        let spec = LnHashSpec { algorithm: HashAlgorithm::Chained, ..Default::default() };
+        let text = "\u{feff}a\nb\n";
+        assert_eq!(spec.lnhashes(text.lines()), spec.lnhashes("a\nb\n".lines()));
+        assert_eq!(spec.lnhash(1, "\u{feff}a"), spec.lnhash(1, "a"));
+        assert_eq!(LnHash::of(1, "\u{feff}a"), LnHash::of(1, "a"));
+        assert!(spec.verify(&spec.lnhash(1, "a"), None, "\u{feff}a").is_ok());
+        let second = spec.lnhash_after(2, Some("a"), "b");
+        assert!(spec.verify(&second, Some("\u{feff}a"), "b").is_ok());
+        assert_eq!(file_fingerprint(text), file_fingerprint("a\nb\n"));
+        // Only at the start of the file: anywhere else U+FEFF is text like any other.
+        assert_ne!(spec.lnhash(2, "\u{feff}b"), spec.lnhash(2, "b"));
+    }
+
+    #[test]
+    fn lnhash_std_traits() {
+        let a = LnHash::of(3, "x");
+        let parsed: LnHash = a.to_string().parse().unwrap();
+        assert_eq!(parsed, a);
+        assert_eq!(a.to_string(), format_lnhash(3, "x"));
+        assert!("3|zz|".parse::<LnHash>().is_err());
+
+        let mut addrs = [LnHash::of(10, "b"), LnHash::of(2, "z"), LnHash::of_width(2, "a", 8)];
+        addrs.sort();
+        assert_eq!(addrs.iter().map(|a| a.lineno).collect::<Vec<_>>(), vec![2, 2, 10]);
+        let set: std::collections::HashSet<LnHash> = addrs.iter().copied().collect();
+        assert_eq!(set.len(), 3);
+    }
+
+    #[test]
+    fn compact_form_roundtrips() {
+        let a = LnHash::of(1234, "x");
+        let compact = a.format_with(AddrStyle::Compact);
+        assert_eq!(compact, format!("ya:{:04x}", a.hash));
+        assert_eq!(parse_lnhash(&compact).unwrap(), a);
+        assert_eq!(a.format_with(AddrStyle::Standard), a.to_string());
+
+        let wide = LnHash::of_width(0, "x", 6);
+        assert_eq!(parse_lnhash(&wide.format_with(AddrStyle::Compact)).unwrap(), wide);
+
+        // Commands starting with a hex letter are split off the hash.
+        let (lh, rest) = parse_lnhash_prefix("c:00ffd").unwrap();
+        assert_eq!((lh.lineno, lh.hash, lh.width), (12, 0x00ff, 4));
+        assert_eq!(rest, "d");
+        let (lh, rest) = parse_lnhash_prefix("c:00ff00a").unwrap();
+        assert_eq!((lh.hash, lh.width, rest), (0x00ff00, 6, "a"));
+        assert!(parse_lnhash("c:0ff").is_err());
+    }
+
+    #[test]
+    fn seed_changes_hash_and_tags_address() {
+        let spec = LnHashSpec::seeded("proj");
+        let id = spec.seed_id().unwrap();
+        let wide = LnHashSpec { width: 8, ..spec.clone() };
+        assert_ne!(wide.line_hash("x"), line_hash_width("x", 8));
+        let plain = LnHashSpec { width: 8, ..Default::default() };
+        assert_eq!(plain.line_hash("x"), line_hash_width("x", 8));
+
+        let a = spec.lnhash(12, "x");
+        assert_eq!(a.to_string(), format!("12|s{id:04x}:{:04x}|", a.hash));
+        assert_eq!(parse_lnhash(&a.to_string()).unwrap(), a);
+        let compact = a.format_with(AddrStyle::Compact);
+        assert_eq!(compact, format!("c:s{id:04x}:{:04x}", a.hash));
+        let cmd = format!("{compact}d");
+        let (lh, rest) = parse_lnhash_prefix(&cmd).unwrap();
+        assert_eq!((lh, rest), (a, "d"));
+
+        assert!(spec.verify(&a, None, "x").is_ok());
+        assert!(spec.verify(&a, None, "y").is_err());
+        assert!(LnHashSpec::default().verify(&a, None, "x").is_err());
+        assert!(parse_lnhash("1|zz:abcd|").is_err());
+        assert!(parse_lnhash("1|s0001:s0002:abcd|").is_err());
+    }
+
+    #[test]
+    fn nfc_hashes_composed_and_decomposed_alike() {
+        let (composed, decomposed) = ("caf\u{e9}", "cafe\u{301}");
+        assert_ne!(line_hash_width(composed, 8), line_hash_width(decomposed, 8));
+        let spec = LnHashSpec {
+            normalization: Normalization { nfc: true, ..Default::default() },
+            ..Default::default()
+        };
+        assert_eq!(spec.line_hash(composed), spec.line_hash(decomposed));
+
+        let a = spec.lnhash(3, decomposed);
+        assert_eq!(a.to_string(), format!("3|nfc:{:04x}|", a.hash));
+        assert_eq!(parse_lnhash(&a.to_string()).unwrap(), a);
+        // The tag, not the verifying spec, decides how the line is normalized.
+        assert!(LnHashSpec::default().verify(&a, None, composed).is_ok());
+        let plain = LnHash::of(3, composed);
+        let err = LnHashSpec::default().verify(&plain, None, decomposed).unwrap_err();
+        assert!(err.message().contains("not in Unicode NFC form"), "{err}");
+
+        let both = LnHashSpec { seed: Some("p".into()), ..spec };
+        let b = both.lnhash(3, composed);
+        assert!(b.to_string().starts_with("3|s"));
+        assert_eq!(parse_lnhash(&b.format_with(AddrStyle::Compact)).unwrap(), b);
+        assert!(parse_lnhash("3|nfc:nfc:abcd|").is_err());
+    }
+
+    #[test]
+    fn bulk_hashing_matches_per_line() {
+        let text = "a\n}\ncaf\u{e9}\n";
+        let addrs = lnhash_lines(text);
+        let expected: Vec<LnHash> =
+            text.lines().enumerate().map(|(i, l)| LnHash::of(i + 1, l)).collect();
+        assert_eq!(addrs, expected);
+        assert_eq!(lnhash_view_lines(text)[1], format!("{}  }}", format_lnhash(2, "}")));
+
+        let spec = LnHashSpec {
+            seed: Some("p".into()),
+            normalization: Normalization { nfc: true, ..Default::default() },
+            versioned: true,
+            algorithm: HashAlgorithm::Chained,
+            width: 6,
+        };
+        let bulk = spec.lnhashes(text.lines());
+        let lines: Vec<&str> = text.lines().collect();
+        for (i, line) in lines.iter().enumerate() {
+            let prev = i.checked_sub(1).map(|p| lines[p]);
+            assert_eq!(bulk[i], spec.lnhash_after(i + 1, prev, line));
+        }
+        let view = spec.view_lines(text, AddrStyle::Compact);
+        assert_eq!(view[0], format!("{}  a", bulk[0].format_with(AddrStyle::Compact)));
+    }
+
+    #[test]
+    fn lowercase_mode_ignores_case_and_is_tagged() {
+        let spec = LnHashSpec {
+            normalization: Normalization { lowercase: true, ..Default::default() },
+            ..Default::default()
+        };
+        assert_eq!(spec.line_hash("SELECT * FROM t"), spec.line_hash("select * from t"));
+        assert_eq!(spec.line_hash("ÉTÉ"), spec.line_hash("été"));
+
+        let a = spec.lnhash(2, "MAX_SIZE = 1");
+        assert_eq!(a.to_string(), format!("2|lc:{:04x}|", a.hash));
+        assert_eq!(parse_lnhash(&a.to_string()).unwrap(), a);
+        assert!(LnHashSpec::default().verify(&a, None, "max_size = 1").is_ok());
+        assert!(LnHashSpec::default().verify(&a, None, "max_size = 2").is_err());
+        // An untagged address still sees case changes.
+        let plain = LnHash::of(2, "MAX_SIZE = 1");
+        assert!(LnHashSpec::default().verify(&plain, None, "max_size = 1").is_err());
+
+        let nfc = Normalization { nfc: true, lowercase: true };
+        let both = LnHashSpec { normalization: nfc, ..Default::default() }.lnhash(1, "É");
+        assert_eq!(both.to_string(), format!("1|nfc:lc:{:04x}|", both.hash));
+        assert!(parse_lnhash("1|lc:lc:abcd|").is_err());
+    }
+
+    #[test]
+    fn chained_hashes_tell_repeated_lines_apart() {
+        let spec = LnHashSpec { algorithm: HashAlgorithm::Chained, ..Default::default() };
+        let text = "a:\n}\nb:\n}\n";
+        let addrs = spec.lnhashes(text.lines());
+        assert_ne!(addrs[1].hash, addrs[3].hash);
+        assert_eq!(addrs[1], spec.lnhash_after(2, Some("a:"), "}"));
+        assert_eq!(addrs[0], spec.lnhash(1, "a:"));
+        assert_eq!(addrs[3].to_string(), format!("4|prev:{:04x}|", addrs[3].hash));
+        assert_eq!(parse_lnhash(&addrs[3].to_string()).unwrap(), addrs[3]);
+
+        // Verification follows the tag and checks the line's context.
+        let plain = LnHashSpec::default();
+        assert!(plain.verify(&addrs[3], Some("b:"), "}").is_ok());
+        let err = plain.verify(&addrs[3], Some("a:"), "}").unwrap_err();
+        assert!(err.message().contains("line 3 may have changed"), "{err}");
+        assert!(parse_lnhash("1|v1:prev:abcd|").is_err());
+    }
+
+    #[test]
+    fn version_tag_dispatches_verification() {
+        let spec = LnHashSpec { versioned: true, width: 6, ..Default::default() };
+        let a = spec.lnhash(5, "x");
+        assert_eq!(a.version, Some(HashVersion::V2));
+        assert_eq!(a.to_string(), format!("5|v2:{:06x}|", line_hash_width("x", 6)));
+        assert_eq!(parse_lnhash(&a.to_string()).unwrap(), a);
+        assert!(LnHashSpec::default().verify(&a, None, "x").is_ok());
+        assert!(LnHashSpec::default().verify(&a, None, "y").is_err());
+
+        let err = parse_lnhash("5|v9:abcd|").unwrap_err();
+        assert!(err.message().contains("unsupported hash version"), "{err}");
+        assert!(parse_lnhash("5|v1:abcdef|").is_err());
+        assert!(parse_lnhash("5|v1:nfc:abcd|").is_err());
+
+        let v1: LnHash = "5|v1:abcd|".parse().unwrap();
+        assert_eq!(v1.version, Some(HashVersion::V1));
+        #[cfg(not(feature = "legacy-hash"))]
+        {
+            let err = LnHashSpec::default().verify(&v1, None, "x").unwrap_err();
+            assert!(err.message().contains("legacy-hash"), "{err}");
+        }
+        #[cfg(feature = "legacy-hash")]
+        {
+            let legacy = format!("5|v1:{:04x}|", legacy_line_hash_u16("x"));
+            let v1: LnHash = legacy.parse().unwrap();
+            assert!(LnHashSpec::default().verify(&v1, None, "x").is_ok());
+        }
+    }
+
+    #[test]
+    fn spec_validates_width_and_checks_seed_on_parse() {
+        assert!(LnHashSpec { width: 5, ..Default::default() }.validate().is_err());
+        let spec = LnHashSpec { width: 8, ..LnHashSpec::seeded("proj") };
+        assert!(spec.validate().is_ok());
+        let a = spec.lnhash(7, "x");
+        assert_eq!(a.width, 8);
+        assert_eq!(spec.parse_lnhash(&a.to_string()).unwrap(), a);
+        let err = LnHashSpec::seeded("other").parse_lnhash(&a.to_string()).unwrap_err();
+        assert!(err.message().contains("current seed"), "{err}");
+        assert!(spec.parse_lnhash(&format_lnhash(7, "x")).is_err());
     }
 
     #[test]
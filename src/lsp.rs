@@ -0,0 +1,136 @@
+use std::collections::HashMap;
+
+use lsp_types::{Position, Range, TextEdit, Uri, WorkspaceEdit};
+
+use crate::diff::changed_ranges;
+use crate::engine::{edit_text_with, EditOptions, EditResult};
+use crate::parse::Command;
+use crate::EditError;
+
+/// The LSP [`TextEdit`]s that turn `original` into `result`'s [`text`](EditResult::text),
+/// in document order and without overlaps, as a `textDocument/applyEdit` or a formatting
+/// response takes them.
+///
+/// There is one edit per run of changed lines, narrowed to the characters that differ, so
+/// that an editor keeps its cursors, marks and folds on the text around a change. Positions
+/// count characters in UTF-16 code units, the LSP default encoding.
+pub fn lsp_text_edits(original: &str, result: &EditResult) -> Vec<TextEdit> {
+    let old: Vec<&str> = original.split_inclusive('\n').collect();
+    let new_lines: Vec<String> = result
+        .lines
+        .iter()
+        .zip(&result.endings)
+        .map(|(line, ending)| format!("{line}{}", ending.as_str()))
+        .collect();
+    let new: Vec<&str> = new_lines.iter().map(|l| l.as_str()).collect();
+
+    let mut edits = Vec::new();
+    for (removed, added) in changed_ranges(&old, &new) {
+        let old_text = old[removed.clone()].concat();
+        let new_text = new[added].concat();
+        let (prefix, suffix) = common_affixes(&old_text, &new_text);
+        let line = removed.start as u32;
+        edits.push(TextEdit {
+            range: Range {
+                start: position_after(line, &old_text[..prefix]),
+                end: position_after(line, &old_text[..old_text.len() - suffix]),
+            },
+            new_text: new_text[prefix..new_text.len() - suffix].to_string(),
+        });
+    }
+    edits
+}
+
+/// Apply `commands` to `original` as [`edit_text_with`] does, returning the change as
+/// [`lsp_text_edits`].
+pub fn lsp_text_edits_with(
+    original: &str,
+    commands: &[Command],
+    opts: &EditOptions,
+) -> Result<Vec<TextEdit>, EditError> {
+    let result = edit_text_with(original, commands, opts)?;
+    Ok(lsp_text_edits(original, &result))
+}
+
+/// A [`WorkspaceEdit`] making [`lsp_text_edits`] to the document at `uri`.
+pub fn lsp_workspace_edit(uri: Uri, original: &str, result: &EditResult) -> WorkspaceEdit {
+    WorkspaceEdit::new(HashMap::from([(uri, lsp_text_edits(original, result))]))
+}
+
+/// The lengths in bytes of the longest common prefix and suffix of `a` and `b`, not
+/// overlapping in either, and neither splitting a `\r\n`.
+fn common_affixes(a: &str, b: &str) -> (usize, usize) {
+    let mut prefix: usize =
+        a.chars().zip(b.chars()).take_while(|(x, y)| x == y).map(|(c, _)| c.len_utf8()).sum();
+    if a[..prefix].ends_with('\r') {
+        prefix -= 1;
+    }
+    let (a_rest, b_rest) = (&a[prefix..], &b[prefix..]);
+    let mut suffix: usize = a_rest
+        .chars()
+        .rev()
+        .zip(b_rest.chars().rev())
+        .take_while(|(x, y)| x == y)
+        .map(|(c, _)| c.len_utf8())
+        .sum();
+    if a_rest[a_rest.len() - suffix..].starts_with('\n')
+        && a_rest[..a_rest.len() - suffix].ends_with('\r')
+    {
+        suffix -= 1;
+    }
+    (prefix, suffix)
+}
+
+/// The position just past `text`, which starts at the beginning of `line`.
+fn position_after(line: u32, text: &str) -> Position {
+    let last = text.rfind('\n').map_or(0, |i| i + 1);
+    Position {
+        line: line + text.matches('\n').count() as u32,
+        character: text[last..].encode_utf16().count() as u32,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{parse_commands_from_strs, LnHash};
+
+    fn edits(original: &str, cmds: &[&str]) -> Vec<(u32, u32, u32, u32, String)> {
+        let cmds = parse_commands_from_strs(cmds).unwrap();
+        let edits = lsp_text_edits_with(original, &cmds, &EditOptions::default()).unwrap();
+        edits
+            .into_iter()
+            .map(|e| {
+                let Range { start, end } = e.range;
+                (start.line, start.character, end.line, end.character, e.new_text)
+            })
+            .collect()
+    }
+
+    #[test]
+    fn text_edits_cover_only_what_changed() {
+        let addr = |n, line| LnHash::of(n, line).to_string();
+
+        // A substitution after a wide character replaces just the changed characters,
+        // counted in UTF-16 code units.
+        let cmd = format!("{}s/foo/bar/", addr(2, "😀 foo x"));
+        assert_eq!(edits("a\n😀 foo x\nc\n", &[&cmd]), [(1, 3, 1, 6, "bar".to_string())]);
+
+        let del = format!("{}d", addr(1, "a"));
+        let app = format!("{}a\nnew", addr(2, "c"));
+        assert_eq!(
+            edits("a\nb\r\nc\n", &[&del, &app]),
+            [(0, 0, 1, 0, String::new()), (3, 0, 3, 0, "new\n".to_string())]
+        );
+
+        // A last line without a newline gets one, as the file would be written.
+        let cmd = format!("{}s/b/B/", addr(2, "b"));
+        assert_eq!(edits("a\nb", &[&cmd]), [(1, 0, 1, 1, "B\n".to_string())]);
+
+        let uri: Uri = "file:///f.txt".parse().unwrap();
+        let result = edit_text_with("a\n", &[], &EditOptions::default()).unwrap();
+        let edit = lsp_workspace_edit(uri.clone(), "a\n", &result);
+        let changes: Vec<_> = edit.changes.unwrap().into_iter().collect();
+        assert_eq!(changes, [(uri, Vec::new())]);
+    }
+}
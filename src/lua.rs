@@ -0,0 +1,78 @@
+use mlua::prelude::*;
+
+/// A buffer's lines as text, each ending in `\n`.
+fn buffer_text(lines: &[String]) -> String {
+    lines.iter().flat_map(|l| [l.as_str(), "\n"]).collect()
+}
+
+/// The values `edit` returns: the result table, or `nil`, the error message and its kind.
+type Edited<'lua> = (Option<LuaTable<'lua>>, Option<String>, Option<&'static str>);
+
+/// Apply `cmds` to the buffer `lines`.
+fn edit(lua: &Lua, lines: Vec<String>, cmds: Vec<String>) -> LuaResult<Edited<'_>> {
+    let text = buffer_text(&lines);
+    let cmd_refs: Vec<&str> = cmds.iter().map(|s| s.as_str()).collect();
+    let res = crate::parse_commands_with_header(&cmd_refs).and_then(|(parsed, fingerprint)| {
+        let opts = crate::EditOptions { fingerprint, ..Default::default() };
+        crate::edit_text_with(&text, &parsed, &opts)
+    });
+    let res = match res {
+        Ok(res) => res,
+        Err(e) => return Ok((None, Some(e.to_string()), Some(e.kind().name()))),
+    };
+
+    let old: Vec<&str> = lines.iter().map(|l| l.as_str()).collect();
+    let new: Vec<&str> = res.lines.iter().map(|l| l.as_str()).collect();
+    let changes = lua.create_table()?;
+    for (removed, added) in crate::changed_ranges(&old, &new).into_iter().rev() {
+        let change = lua.create_table()?;
+        change.set("start", removed.start)?;
+        change.set("stop", removed.end)?;
+        change.set("lines", lua.create_sequence_from(new[added].iter().copied())?)?;
+        changes.push(change)?;
+    }
+    let t = lua.create_table()?;
+    t.set("lines", lua.create_sequence_from(res.lines)?)?;
+    t.set("hashes", lua.create_sequence_from(res.hashes)?)?;
+    t.set("modified", lua.create_sequence_from(res.modified)?)?;
+    t.set("deleted", lua.create_sequence_from(res.deleted)?)?;
+    t.set("changes", changes)?;
+    Ok((Some(t), None, None))
+}
+
+/// The `exhash` Lua module, for Neovim: `require("exhash")` loads it from the shared library
+/// renamed to `exhash.so` on the `package.cpath`.
+///
+/// - `lnhashview(lines)`: the view of a buffer's lines, as `lineno|hash|  content` strings.
+/// - `lnhashes(lines)`: the address of each line.
+/// - `edit(lines, cmds)`: apply the commands (strings as the Python `exhash()` takes) to the
+///   lines, returning `lines`, `hashes`, `modified` and `deleted` as in Python, and
+///   `changes`: each run of changed lines as `{start, stop, lines}`, 0-based and end
+///   exclusive, last first, so that `nvim_buf_set_lines(buf, c.start, c.stop, true, c.lines)`
+///   for each in turn edits the buffer in place. A failed edit returns `nil`, the error
+///   message and its kind (`"stale_hash"`, `"parse"`, ...).
+#[mlua::lua_module]
+fn exhash(lua: &Lua) -> LuaResult<LuaTable<'_>> {
+    let m = lua.create_table()?;
+    m.set(
+        "lnhashview",
+        lua.create_function(|_, lines: Vec<String>| {
+            let text = buffer_text(&lines);
+            Ok(crate::LnHashSpec::default().view_lines(&text, crate::AddrStyle::Standard))
+        })?,
+    )?;
+    m.set(
+        "lnhashes",
+        lua.create_function(|_, lines: Vec<String>| {
+            let addrs = crate::LnHashSpec::default().lnhashes(lines.iter().map(|l| l.as_str()));
+            Ok(addrs.iter().map(|a| a.to_string()).collect::<Vec<_>>())
+        })?,
+    )?;
+    m.set(
+        "edit",
+        lua.create_function(|lua, (lines, cmds): (Vec<String>, Vec<String>)| {
+            edit(lua, lines, cmds)
+        })?,
+    )?;
+    Ok(m)
+}
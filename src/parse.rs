@@ -1,5 +1,8 @@
+use std::fmt;
 use std::io::BufRead;
+use std::ops::RangeInclusive;
 
+use crate::engine::LineTransform;
 use crate::lnhash::{parse_lnhash, parse_lnhash_prefix, LnHash};
 use crate::EditError;
 
@@ -33,6 +36,9 @@ pub enum Subcommand {
     Dedent { levels: usize },
     Sort,
     Print,
+    /// A library-supplied transform applied to each line in the range. Never produced
+    /// by the parsers.
+    Custom(Box<dyn LineTransform>),
 }
 
 #[derive(Debug, Clone)]
@@ -41,6 +47,116 @@ pub struct Subst {
     pub replacement: String,
     pub global: bool,
     pub case_insensitive: bool,
+    /// With the `@FROM-TO` flag, the display columns (1-based, inclusive) the substitution
+    /// is confined to; an open end is 1 or `usize::MAX`. Columns count as a terminal shows
+    /// them (wide characters take two, combining marks none, a tab moves to the next
+    /// multiple of 8), and only characters wholly inside them are searched.
+    pub columns: Option<RangeInclusive<usize>>,
+}
+
+/// Commands print in the syntax they are parsed from, e.g. `12|a3f2|,15|b1c3|s/foo/bar/g`,
+/// with any `a`/`i`/`c` text in the inline `a\text` form; parsing the output gives the
+/// command back.
+impl fmt::Display for Command {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.addr1)?;
+        if let Some(addr2) = &self.addr2 {
+            write!(f, ",{addr2}")?;
+        }
+        write!(f, "{}", self.cmd)
+    }
+}
+
+impl fmt::Display for Subcommand {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let delimited = |s: &str| s.replace('\\', "\\\\").replace('/', "\\/");
+        let text = |name: char, lines: &[String]| {
+            if lines.is_empty() {
+                return name.to_string();
+            }
+            let escaped: Vec<String> = lines.iter().map(|l| l.replace('\\', "\\\\")).collect();
+            format!("{name}\\{}", escaped.join("\\n"))
+        };
+        match self {
+            Subcommand::Delete => write!(f, "d"),
+            Subcommand::Substitute(s) => write!(
+                f,
+                "s/{}/{}/{}{}{}",
+                delimited(&s.pattern),
+                delimited(&s.replacement),
+                if s.global { "g" } else { "" },
+                if s.case_insensitive { "i" } else { "" },
+                s.columns.as_ref().map_or_else(String::new, |c| match *c.end() {
+                    usize::MAX => format!("@{}-", c.start()),
+                    end => format!("@{}-{end}", c.start()),
+                })
+            ),
+            Subcommand::Append(lines) => write!(f, "{}", text('a', lines)),
+            Subcommand::Insert(lines) => write!(f, "{}", text('i', lines)),
+            Subcommand::Change(lines) => write!(f, "{}", text('c', lines)),
+            Subcommand::Join => write!(f, "j"),
+            Subcommand::Move { dest } => write!(f, "m {dest}"),
+            Subcommand::Copy { dest } => write!(f, "t {dest}"),
+            Subcommand::Global { invert, pattern, cmd } => {
+                write!(f, "{}/{}/{cmd}", if *invert { "v" } else { "g" }, delimited(pattern))
+            }
+            Subcommand::Indent { levels } => write!(f, ">{levels}"),
+            Subcommand::Dedent { levels } => write!(f, "<{levels}"),
+            Subcommand::Sort => write!(f, "sort"),
+            Subcommand::Print => write!(f, "p"),
+            Subcommand::Custom(t) => write!(f, "{t:?}"),
+        }
+    }
+}
+
+/// An ex-style script together with its optional `@file <fingerprint>` header.
+#[derive(Debug, Clone)]
+pub struct Script {
+    /// Expected [`file_fingerprint`](crate::file_fingerprint) of the file being edited.
+    pub fingerprint: Option<String>,
+    pub commands: Vec<Command>,
+}
+
+/// One file's section of a multi-file script: the `@path` it names and its commands.
+#[derive(Debug, Clone)]
+pub struct FileScript {
+    pub path: String,
+    pub script: Script,
+}
+
+/// Scripts print as [`parse_script`] reads them: the `@file` header, if any, then one
+/// command per line.
+impl fmt::Display for Script {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if let Some(fp) = &self.fingerprint {
+            writeln!(f, "@file {fp}")?;
+        }
+        for cmd in &self.commands {
+            writeln!(f, "{cmd}")?;
+        }
+        Ok(())
+    }
+}
+
+/// An `@path` line and the file's script, as one section of what [`parse_file_scripts`]
+/// reads.
+impl fmt::Display for FileScript {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "@path {}", self.path)?;
+        write!(f, "{}", self.script)
+    }
+}
+
+/// Parse an `@file <fingerprint>` header line, returning `None` if `line` is not a header.
+pub fn parse_file_header(line: &str) -> Option<Result<String, EditError>> {
+    let rest = line.trim().strip_prefix("@file")?;
+    let fp = rest.trim();
+    if fp.len() != 16 || !fp.chars().all(|c| c.is_ascii_hexdigit()) {
+        return Some(Err(EditError::parse(format!(
+            "invalid @file header: fingerprint must be 16 hex chars, got {fp:?}"
+        ))));
+    }
+    Some(Ok(fp.to_ascii_lowercase()))
 }
 
 /// Parse commands from CLI argv, reading any multiline text blocks from `stdin`.
@@ -58,6 +174,36 @@ pub fn parse_commands_from_args(
     Ok(out)
 }
 
+/// Parse commands from CLI argv, taking text blocks from `blocks` instead of stdin.
+///
+/// Each `a`/`i`/`c` command (including one under `g`) takes the next block in order; a
+/// block's lines are split on `\n`, ignoring one trailing newline, and need no `.`
+/// terminator. Running out of blocks, or leaving some unused, is an error.
+pub fn parse_commands_with_blocks(
+    args: &[String],
+    blocks: &[String],
+) -> Result<Vec<Command>, EditError> {
+    let mut blocks = blocks.iter();
+    let mut out = Vec::with_capacity(args.len());
+    for a in args {
+        let cmd = parse_command_with_text(a, || {
+            let block = blocks.next().ok_or_else(|| {
+                EditError::parse(format!("no text block left for command {:?}", a.trim()))
+            })?;
+            let block = block.strip_suffix('\n').unwrap_or(block);
+            Ok(block.split('\n').map(|l| l.strip_suffix('\r').unwrap_or(l).to_string()).collect())
+        })?;
+        out.push(cmd);
+    }
+    if blocks.len() > 0 {
+        return Err(EditError::parse(format!(
+            "{} text block(s) left over; only a, i and c commands take one",
+            blocks.len()
+        )));
+    }
+    Ok(out)
+}
+
 /// Parse commands from a list of individual command strings (for programmatic APIs).
 ///
 /// Each string is one command. For `a`/`i`/`c`, lines after the first are the text
@@ -72,26 +218,35 @@ pub fn parse_commands_from_strs(cmds: &[&str]) -> Result<Vec<Command>, EditError
     Ok(out)
 }
 
+/// Parse command strings as [`parse_commands_from_strs`] does, taking a first string of
+/// `@file <fingerprint>` as a header: returns the commands and the header's fingerprint.
+pub fn parse_commands_with_header(
+    cmds: &[&str],
+) -> Result<(Vec<Command>, Option<String>), EditError> {
+    let (fingerprint, cmds) = match cmds.split_first() {
+        Some((first, rest)) => match parse_file_header(first) {
+            Some(header) => (Some(header?), rest),
+            None => (None, cmds),
+        },
+        None => (None, cmds),
+    };
+    Ok((parse_commands_from_strs(cmds)?, fingerprint))
+}
+
 fn parse_command_with_text_from_str(input: &str) -> Result<Command, EditError> {
     let mut lines = input.split('\n');
     let first = lines.next().unwrap(); // split always yields at least one
     let remaining: Vec<String> = lines.map(|l| l.strip_suffix('\r').unwrap_or(l).to_string()).collect();
     let has_text = !remaining.is_empty();
+    let mut text_used = false;
     let cmd = parse_command_with_text(first, || {
-        if has_text { Ok(remaining.clone()) }
-        else { Ok(vec![]) }
+        text_used = true;
+        Ok(remaining.clone())
     })?;
-    // For non-text commands, extra lines are an error
-    if has_text {
-        match &cmd.cmd {
-            Subcommand::Append(_) | Subcommand::Insert(_) | Subcommand::Change(_) => {}
-            Subcommand::Global { cmd: sub, .. } => match sub.as_ref() {
-                Subcommand::Append(_) | Subcommand::Insert(_) | Subcommand::Change(_) => {}
-                _ if has_text => return Err(EditError::new("unexpected multiline input for this command")),
-                _ => {}
-            },
-            _ => return Err(EditError::new("unexpected multiline input for this command")),
-        }
+    // Extra lines are an error unless an a/i/c took them as its block (an inline
+    // `a\text` takes none).
+    if has_text && !text_used {
+        return Err(EditError::parse("unexpected multiline input for this command"));
     }
     Ok(cmd)
 }
@@ -100,30 +255,97 @@ fn parse_command_with_text_from_str(input: &str) -> Result<Command, EditError> {
 ///
 /// Commands are separated by newlines. For `a`/`i`/`c` (and for global subcommands
 /// that are `a`/`i`/`c`), the following lines up to a `.` line (dot on its own line)
-/// are taken as the text block.
+/// are taken as the text block. Scripts with an `@file` header must be parsed with
+/// [`parse_script`] so the fingerprint is not silently dropped.
 pub fn parse_commands_from_script(script: &str) -> Result<Vec<Command>, EditError> {
+    let parsed = parse_script(script)?;
+    if parsed.fingerprint.is_some() {
+        return Err(EditError::parse(
+            "script has an @file header; parse it with parse_script to verify the fingerprint",
+        ));
+    }
+    Ok(parsed.commands)
+}
+
+/// Parse an ex-style script, including an optional leading `@file <fingerprint>` header.
+///
+/// Scripts with `@path` sections must be parsed with [`parse_file_scripts`].
+pub fn parse_script(script: &str) -> Result<Script, EditError> {
+    let mut sections = parse_sections(script)?;
+    if sections.iter().any(|(path, _)| path.is_some()) {
+        return Err(EditError::parse(
+            "script has @path sections; parse it with parse_file_scripts",
+        ));
+    }
+    Ok(sections.pop().map_or(Script { fingerprint: None, commands: Vec::new() }, |(_, s)| s))
+}
+
+/// Parse a multi-file script: each `@path <file>` line starts the commands for that file,
+/// which may open with their own `@file <fingerprint>` header. Every command must belong
+/// to a section, and no file may be named twice.
+pub fn parse_file_scripts(script: &str) -> Result<Vec<FileScript>, EditError> {
+    let mut out: Vec<FileScript> = Vec::new();
+    for (path, script) in parse_sections(script)? {
+        let Some(path) = path else {
+            return Err(EditError::parse("multi-file script must start with an @path line"));
+        };
+        if out.iter().any(|f| f.path == path) {
+            return Err(EditError::parse(format!("@path {path} appears more than once")));
+        }
+        out.push(FileScript { path, script });
+    }
+    Ok(out)
+}
+
+/// Split a script into its `@path` sections (the first has no path when the script does
+/// not start with one), parsing each section's header and commands. An empty script has
+/// no sections.
+fn parse_sections(script: &str) -> Result<Vec<(Option<String>, Script)>, EditError> {
     let mut lines = script
         .split('\n')
         .map(|l| l.strip_suffix('\r').unwrap_or(l))
         .peekable();
 
-    let mut out = Vec::new();
+    let mut sections: Vec<(Option<String>, Script)> = Vec::new();
     while let Some(line) = lines.next() {
-        let line = line.trim();
-        if line.is_empty() {
+        if line.trim().is_empty() {
+            continue;
+        }
+        if let Some(path) = line.trim().strip_prefix("@path ") {
+            let path = path.trim();
+            if path.is_empty() {
+                return Err(EditError::parse("@path needs a file name"));
+            }
+            if sections.last().is_some_and(|(p, _)| p.is_none()) {
+                return Err(EditError::parse("commands before the first @path line"));
+            }
+            let empty = Script { fingerprint: None, commands: Vec::new() };
+            sections.push((Some(path.to_string()), empty));
+            continue;
+        }
+        if sections.is_empty() {
+            sections.push((None, Script { fingerprint: None, commands: Vec::new() }));
+        }
+        let (_, current) = sections.last_mut().unwrap();
+        if let Some(fp) = parse_file_header(line) {
+            if !current.commands.is_empty() || current.fingerprint.is_some() {
+                return Err(EditError::parse("@file header must come first, and only once"));
+            }
+            current.fingerprint = Some(fp?);
             continue;
         }
         let cmd = parse_command_with_text(line, || read_text_block_from_iter(&mut lines))?;
-        out.push(cmd);
+        current.commands.push(cmd);
     }
-    Ok(out)
+    Ok(sections)
 }
 
 fn parse_command_with_text<F>(line: &str, mut read_text: F) -> Result<Command, EditError>
 where
     F: FnMut() -> Result<Vec<String>, EditError>,
 {
-    let line = line.trim();
+    // Only leading whitespace is dropped: trailing spaces may belong to inline text.
+    let line = line.trim_start();
     let (addr1, mut rest) = parse_lnhash_prefix(line)?;
     let mut has_comma = false;
     let mut addr2: Option<LnHash> = None;
@@ -135,16 +357,16 @@ where
         rest = r2;
     }
 
-    let rest = rest.trim();
-    if rest.is_empty() {
-        return Err(EditError::new("missing command"));
+    let rest = rest.trim_start();
+    if rest.trim_end().is_empty() {
+        return Err(EditError::parse("missing command"));
     }
 
     let (cmd, trailing) = parse_subcommand_with_text(rest, &mut read_text)?;
 
     // No trailing junk for a top-level command.
     if !trailing.trim().is_empty() {
-        return Err(EditError::new(format!(
+        return Err(EditError::parse(format!(
             "unexpected trailing characters: {:?}",
             trailing
         )));
@@ -153,15 +375,15 @@ where
     // Enforce 0|0000| rules.
     if addr1.lineno == 0 {
         if addr1.hash != 0 {
-            return Err(EditError::new("0|0000| must have hash 0000"));
+            return Err(EditError::parse("0|0000| must have hash 0000"));
         }
         if has_comma || addr2.is_some() {
-            return Err(EditError::new("0|0000| is not allowed in ranges"));
+            return Err(EditError::parse("0|0000| is not allowed in ranges"));
         }
         match cmd {
             Subcommand::Append(_) | Subcommand::Insert(_) => {}
             _ => {
-                return Err(EditError::new(
+                return Err(EditError::parse(
                     "0|0000| is only allowed with i or a",
                 ))
             }
@@ -169,10 +391,10 @@ where
     }
     if let Some(a2) = addr2 {
         if a2.lineno == 0 {
-            return Err(EditError::new("0|0000| is not allowed in ranges"));
+            return Err(EditError::parse("0|0000| is not allowed in ranges"));
         }
         if addr1.lineno == 0 {
-            return Err(EditError::new("0|0000| is not allowed in ranges"));
+            return Err(EditError::parse("0|0000| is not allowed in ranges"));
         }
     }
 
@@ -192,20 +414,19 @@ where
     F: FnMut() -> Result<Vec<String>, EditError>,
 {
     let s = input.trim_start();
-    if s.starts_with("sort") {
-        let trailing = &s[4..];
+    if let Some(trailing) = s.strip_prefix("sort") {
         return Ok((Subcommand::Sort, trailing));
     }
 
     // g! must be checked before g
-    if s.starts_with("g!") {
-        return parse_global(&s[2..], true, read_text);
+    if let Some(rest) = s.strip_prefix("g!") {
+        return parse_global(rest, true, read_text);
     }
 
     let mut chars = s.chars();
     let c = chars
         .next()
-        .ok_or_else(|| EditError::new("missing command"))?;
+        .ok_or_else(|| EditError::parse("missing command"))?;
     let rest = chars.as_str();
 
     match c {
@@ -216,23 +437,23 @@ where
             let (subst, trailing) = parse_substitute(rest)?;
             Ok((Subcommand::Substitute(subst), trailing))
         }
-        'a' => {
-            let text = read_text()?;
-            Ok((Subcommand::Append(text), rest))
-        }
-        'i' => {
-            let text = read_text()?;
-            Ok((Subcommand::Insert(text), rest))
-        }
-        'c' => {
-            let text = read_text()?;
-            Ok((Subcommand::Change(text), rest))
+        'a' | 'i' | 'c' => {
+            let (text, trailing) = match rest.strip_prefix('\\') {
+                Some(inline) => (parse_inline_text(inline), ""),
+                None => (read_text()?, rest),
+            };
+            let cmd = match c {
+                'a' => Subcommand::Append(text),
+                'i' => Subcommand::Insert(text),
+                _ => Subcommand::Change(text),
+            };
+            Ok((cmd, trailing))
         }
         'm' => {
             let dest_str = rest.trim();
             let dest = parse_lnhash(dest_str)?;
             if dest.lineno == 0 {
-                return Err(EditError::new(
+                return Err(EditError::parse(
                     "destination 0|0000| is not allowed for m",
                 ));
             }
@@ -242,7 +463,7 @@ where
             let dest_str = rest.trim();
             let dest = parse_lnhash(dest_str)?;
             if dest.lineno == 0 {
-                return Err(EditError::new(
+                return Err(EditError::parse(
                     "destination 0|0000| is not allowed for t",
                 ));
             }
@@ -258,8 +479,39 @@ where
             let levels = parse_optional_usize(rest)?;
             Ok((Subcommand::Dedent { levels }, ""))
         }
-        _ => Err(EditError::new(format!("unknown command: {c}"))),
+        _ => Err(EditError::parse(format!("unknown command: {c}"))),
+    }
+}
+
+/// Decode the text of an inline `a\text` command: `\n` (or a real newline) starts a new
+/// line, `\\` is a backslash, and any other backslash is kept as written.
+fn parse_inline_text(s: &str) -> Vec<String> {
+    let mut lines = vec![String::new()];
+    let mut chars = s.chars();
+    while let Some(ch) = chars.next() {
+        let line = lines.last_mut().unwrap();
+        if ch == '\n' {
+            if line.ends_with('\r') {
+                line.pop();
+            }
+            lines.push(String::new());
+            continue;
+        }
+        if ch != '\\' {
+            line.push(ch);
+            continue;
+        }
+        match chars.next() {
+            Some('n') => lines.push(String::new()),
+            Some('\\') => line.push('\\'),
+            Some(other) => {
+                line.push('\\');
+                line.push(other);
+            }
+            None => line.push('\\'),
+        }
     }
+    lines
 }
 
 fn parse_optional_usize(s: &str) -> Result<usize, EditError> {
@@ -268,7 +520,7 @@ fn parse_optional_usize(s: &str) -> Result<usize, EditError> {
         return Ok(1);
     }
     s.parse::<usize>()
-        .map_err(|_| EditError::new(format!("invalid number: {s:?}")))
+        .map_err(|_| EditError::parse(format!("invalid number: {s:?}")))
 }
 
 fn parse_global<'a, F>(
@@ -281,16 +533,16 @@ where
 {
     let rest = rest.trim_start();
     if !rest.starts_with('/') {
-        return Err(EditError::new("global requires /pat/cmd"));
+        return Err(EditError::parse("global requires /pat/cmd"));
     }
     let (pat, after_pat) = parse_delimited(rest, '/')?;
     let cmd_str = after_pat.trim_start();
     if cmd_str.is_empty() {
-        return Err(EditError::new("global requires a subcommand"));
+        return Err(EditError::parse("global requires a subcommand"));
     }
     let (subcmd, trailing) = parse_subcommand_with_text(cmd_str, read_text)?;
     if !trailing.trim().is_empty() {
-        return Err(EditError::new(format!(
+        return Err(EditError::parse(format!(
             "unexpected trailing characters in global subcommand: {:?}",
             trailing
         )));
@@ -308,7 +560,7 @@ where
 fn parse_substitute(rest: &str) -> Result<(Subst, &str), EditError> {
     let rest = rest.trim_start();
     if !rest.starts_with('/') {
-        return Err(EditError::new("substitute requires /pat/rep/[flags]"));
+        return Err(EditError::parse("substitute requires /pat/rep/[flags]"));
     }
 
     let (pat, after_pat) = parse_delimited(rest, '/')?;
@@ -317,12 +569,16 @@ fn parse_substitute(rest: &str) -> Result<(Subst, &str), EditError> {
     let mut global = false;
     let mut case_insensitive = false;
 
-    for ch in after_rep.trim().chars() {
+    let (flags, columns) = match after_rep.trim().split_once('@') {
+        Some((flags, columns)) => (flags, Some(parse_columns(columns)?)),
+        None => (after_rep.trim(), None),
+    };
+    for ch in flags.chars() {
         match ch {
             'g' => global = true,
             'i' => case_insensitive = true,
             _ => {
-                return Err(EditError::new(format!(
+                return Err(EditError::parse(format!(
                     "unknown substitute flag: {ch}"
                 )))
             }
@@ -330,7 +586,7 @@ fn parse_substitute(rest: &str) -> Result<(Subst, &str), EditError> {
     }
 
     if pat.is_empty() {
-        return Err(EditError::new("substitute pattern may not be empty"));
+        return Err(EditError::parse("substitute pattern may not be empty"));
     }
 
     Ok((
@@ -339,21 +595,40 @@ fn parse_substitute(rest: &str) -> Result<(Subst, &str), EditError> {
             replacement: rep,
             global,
             case_insensitive,
+            columns,
         },
         "",
     ))
 }
 
+/// Parse the `FROM-TO` of a substitute's `@` flag: display columns, 1-based and inclusive,
+/// with either end left open (`@5-`, `@-20`) or a single column (`@5`).
+fn parse_columns(spec: &str) -> Result<RangeInclusive<usize>, EditError> {
+    let invalid = || EditError::parse(format!("invalid substitute columns: @{spec}"));
+    let column = |s: &str, open: usize| match s {
+        "" => Ok(open),
+        s => s.parse::<usize>().map_err(|_| invalid()),
+    };
+    let (from, to) = match spec.split_once('-') {
+        Some((from, to)) => (column(from, 1)?, column(to, usize::MAX)?),
+        None => (column(spec, 0)?, column(spec, 0)?),
+    };
+    if from == 0 || from > to {
+        return Err(invalid());
+    }
+    Ok(from..=to)
+}
+
 /// Parse a `/.../` delimited string from the start of `input`.
 ///
 /// Returns (decoded, rest_after_closing_delim).
-fn parse_delimited<'a>(input: &'a str, delim: char) -> Result<(String, &'a str), EditError> {
+fn parse_delimited(input: &str, delim: char) -> Result<(String, &str), EditError> {
     let mut chars = input.chars();
     let first = chars
         .next()
-        .ok_or_else(|| EditError::new("missing delimiter"))?;
+        .ok_or_else(|| EditError::parse("missing delimiter"))?;
     if first != delim {
-        return Err(EditError::new("missing delimiter"));
+        return Err(EditError::parse("missing delimiter"));
     }
 
     let mut out = String::new();
@@ -378,13 +653,13 @@ fn parse_delimited<'a>(input: &'a str, delim: char) -> Result<(String, &'a str),
         out.push(ch);
     }
 
-    Err(EditError::new("unterminated delimited string"))
+    Err(EditError::parse("unterminated delimited string"))
 }
 
 /// Scan for the next unescaped `delim`, returning (content, rest_after_delim).
 /// Unlike `parse_delimited`, does not expect a leading delimiter.
 /// If no delimiter is found, returns all remaining input as content (allows optional trailing delim).
-fn scan_to_delim<'a>(input: &'a str, delim: char) -> Result<(String, &'a str), EditError> {
+fn scan_to_delim(input: &str, delim: char) -> Result<(String, &str), EditError> {
     let mut out = String::new();
     let mut escaped = false;
     let mut consumed = 0;
@@ -407,7 +682,7 @@ fn read_text_block_from_bufread(stdin: &mut impl BufRead) -> Result<Vec<String>,
             .read_line(&mut buf)
             .map_err(|e| EditError::new(format!("failed to read stdin: {e}")))?;
         if n == 0 {
-            return Err(EditError::new("unexpected EOF while reading text block"));
+            return Err(EditError::parse("unexpected EOF while reading text block"));
         }
         // Trim \n, then optional \r.
         if buf.ends_with('\n') {
@@ -434,7 +709,7 @@ fn read_text_block_from_iter<'a>(
     let mut out = Vec::new();
     loop {
         match it.next() {
-            None => return Err(EditError::new("unexpected EOF while reading text block")),
+            None => return Err(EditError::parse("unexpected EOF while reading text block")),
             Some(line) => {
                 let line = line.strip_suffix('\r').unwrap_or(line);
                 if line == "." {
@@ -465,11 +740,9 @@ mod tests {
         let l1 = "a";
         let l2 = "b";
         let cmd = format!(
-            "{}{},{}{}d",
-            1,
-            format!("|{:04x}|", line_hash_u16(l1)),
-            2,
-            format!("|{:04x}|", line_hash_u16(l2))
+            "1|{:04x}|,2|{:04x}|d",
+            line_hash_u16(l1),
+            line_hash_u16(l2)
         );
         let parsed = parse_commands_from_script(&cmd).unwrap();
         assert_eq!(parsed.len(), 1);
@@ -492,6 +765,100 @@ mod tests {
         }
     }
 
+    #[test]
+    fn text_blocks_from_separate_list() {
+        let args = vec![format!("{}a", addr(1, "x")), format!("{}d", addr(1, "x"))];
+        let blocks = vec!["p\n..\nq\n".to_string()];
+        let cmds = parse_commands_with_blocks(&args, &blocks).unwrap();
+        match &cmds[0].cmd {
+            Subcommand::Append(t) => assert_eq!(t, &["p", "..", "q"]),
+            _ => panic!("expected append"),
+        }
+        assert!(parse_commands_with_blocks(&args, &[]).is_err());
+        assert!(parse_commands_with_blocks(&args[1..], &blocks).is_err());
+    }
+
+    #[test]
+    fn inline_text_needs_no_block() {
+        let args = vec![
+            format!("{}a\\one\\ntwo  ", addr(1, "x")),
+            format!("{}g/x/c\\back\\\\slash \\t", addr(1, "x")),
+        ];
+        let cmds = parse_commands_from_args(&args, &mut std::io::empty()).unwrap();
+        match &cmds[0].cmd {
+            Subcommand::Append(t) => assert_eq!(t, &["one", "two  "]),
+            _ => panic!("expected append"),
+        }
+        match &cmds[1].cmd {
+            Subcommand::Global { cmd, .. } => match cmd.as_ref() {
+                Subcommand::Change(t) => assert_eq!(t, &["back\\slash \\t"]),
+                _ => panic!("expected change"),
+            },
+            _ => panic!("expected global"),
+        }
+        let script = format!("{}i\\first\n{}d\n", addr(1, "x"), addr(1, "x"));
+        assert_eq!(parse_commands_from_script(&script).unwrap().len(), 2);
+        let inline = format!("{}a\\x\nleftover", addr(1, "x"));
+        assert!(parse_commands_from_strs(&[&inline]).is_err());
+    }
+
+    #[test]
+    fn parse_script_reads_file_header() {
+        let script = format!("@file 0123456789ABCDEF\n{}d\n", addr(1, "x"));
+        let parsed = parse_script(&script).unwrap();
+        assert_eq!(parsed.fingerprint.as_deref(), Some("0123456789abcdef"));
+        assert_eq!(parsed.commands.len(), 1);
+        assert!(parse_commands_from_script(&script).is_err());
+        assert!(parse_script(&format!("{}d\n@file 0123456789abcdef", addr(1, "x"))).is_err());
+        assert!(parse_file_header("@file xyz").unwrap().is_err());
+        assert!(parse_file_header("1|abcd|d").is_none());
+
+        let del = format!("{}d", addr(1, "x"));
+        let (cmds, fp) = parse_commands_with_header(&["@file 0123456789ABCDEF", &del]).unwrap();
+        assert_eq!((cmds.len(), fp.as_deref()), (1, Some("0123456789abcdef")));
+        assert_eq!(parse_commands_with_header(&[&del]).unwrap().1, None);
+        assert!(parse_commands_with_header(&["@file xyz", &del]).is_err());
+    }
+
+    #[test]
+    fn parse_multi_file_script() {
+        let script = format!(
+            "@path a.txt\n@file 0123456789abcdef\n{0}d\n\n@path b.txt\n{0}a\nnew\n.\n",
+            addr(1, "x")
+        );
+        let files = parse_file_scripts(&script).unwrap();
+        assert_eq!(files.len(), 2);
+        assert_eq!(files[0].path, "a.txt");
+        assert!(files[0].script.fingerprint.is_some());
+        assert_eq!((files[1].path.as_str(), files[1].script.commands.len()), ("b.txt", 1));
+        assert!(parse_script(&script).is_err());
+        assert!(parse_file_scripts(&format!("{}d\n@path a.txt\n", addr(1, "x"))).is_err());
+        assert!(parse_file_scripts("@path a.txt\n@path a.txt\n").is_err());
+    }
+
+    #[test]
+    fn commands_display_as_parseable_text() {
+        let cmds = [
+            format!("{},{}s/a\\/b/c\\\\d/gi", addr(1, "x"), addr(2, "y")),
+            format!("{}g/x/a\\one\\ntw\\\\o", addr(1, "x")),
+            format!("{}m {}", addr(1, "x"), addr(3, "z")),
+            format!("{}>2", addr(1, "x")),
+            format!("{}s/a/b/g@3-10", addr(1, "x")),
+            format!("{}s/a/b/@5-", addr(1, "x")),
+        ];
+        for c in &cmds {
+            let parsed = parse_commands_from_strs(&[c]).unwrap();
+            assert_eq!(&parsed[0].to_string(), c);
+        }
+        let parse = |flags: &str| {
+            parse_commands_from_strs(&[&format!("{}s/a/b/{flags}", addr(1, "x"))])
+        };
+        assert_eq!(parse("@-4").unwrap()[0].to_string(), format!("{}s/a/b/@1-4", addr(1, "x")));
+        for bad in ["@0-3", "@5-2", "@x", "@"] {
+            assert!(parse(bad).is_err(), "{bad}");
+        }
+    }
+
     #[test]
     fn parse_global_with_subst() {
         let cmd = format!("{}g/foo/s/bar/baz/", addr(1, "x"));
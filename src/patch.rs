@@ -0,0 +1,253 @@
+use std::ops::Range;
+
+use crate::diff::replace_commands;
+use crate::lnhash::{file_fingerprint, LnHashSpec};
+use crate::parse::{Command, Script};
+use crate::EditError;
+
+/// One file's section of a unified diff, as [`parse_patch`] reads it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FilePatch {
+    /// The path on the `---` line, without git's `a/` prefix; `None` for `/dev/null`.
+    pub old_path: Option<String>,
+    /// The path on the `+++` line, without git's `b/` prefix; `None` for `/dev/null`.
+    pub new_path: Option<String>,
+    hunks: Vec<Hunk>,
+}
+
+/// One `@@` hunk: where it starts in the old file (0-based) and its lines, each with its
+/// `' '`, `'-'` or `'+'` marker.
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Hunk {
+    header: String,
+    old_start: usize,
+    lines: Vec<(char, String)>,
+}
+
+impl FilePatch {
+    /// Commands applying the patch to `text`, addressed with `spec`'s hashes of `text`'s
+    /// lines: one `a`, `c` or `d` per run of changed lines, last first, as
+    /// [`diff_commands`](crate::diff_commands) makes them.
+    ///
+    /// Each hunk must apply exactly where its header puts it: a context or removed line
+    /// that differs from `text` is a [`StaleHash`](crate::ErrorKind::StaleHash) error, as
+    /// the patch was made against another version of the file. The commands then verify
+    /// the lines they touch when applied, so the file cannot change in between unnoticed.
+    pub fn commands(&self, text: &str, spec: &LnHashSpec) -> Result<Vec<Command>, EditError> {
+        let lines: Vec<&str> = text.lines().collect();
+        let mut runs: Vec<(Range<usize>, Vec<String>)> = Vec::new();
+        let mut pos = 0;
+        for hunk in &self.hunks {
+            if hunk.old_start < pos {
+                let msg = format!("patch: hunk {} overlaps the one before", hunk.header);
+                return Err(EditError::parse(msg));
+            }
+            pos = hunk.old_start;
+            // The run being built: the old lines it replaces and the new lines.
+            let mut run: Option<(Range<usize>, Vec<String>)> = None;
+            for (marker, line) in &hunk.lines {
+                if *marker != '+' {
+                    let Some(&actual) = lines.get(pos) else {
+                        return Err(EditError::stale(format!(
+                            "hunk {} does not apply: the file ends at line {}",
+                            hunk.header,
+                            lines.len()
+                        )));
+                    };
+                    if actual != line {
+                        return Err(EditError::stale(format!(
+                            "hunk {} does not apply: line {} is {actual:?}, the patch expects \
+                             {line:?}",
+                            hunk.header,
+                            pos + 1
+                        )));
+                    }
+                }
+                match marker {
+                    ' ' => runs.extend(run.take()),
+                    '-' => run.get_or_insert_with(|| (pos..pos, Vec::new())).0.end = pos + 1,
+                    _ => run.get_or_insert_with(|| (pos..pos, Vec::new())).1.push(line.clone()),
+                }
+                if *marker != '+' {
+                    pos += 1;
+                }
+            }
+            runs.extend(run);
+        }
+        Ok(replace_commands(&spec.lnhashes(lines.iter().copied()), runs.into_iter()))
+    }
+
+    /// The [`commands`](Self::commands) as a script headed by `text`'s `@file`
+    /// fingerprint: printed, it replays the patch on that exact file with `exhash -f`.
+    pub fn script(&self, text: &str, spec: &LnHashSpec) -> Result<Script, EditError> {
+        let commands = self.commands(text, spec)?;
+        Ok(Script { fingerprint: Some(file_fingerprint(text)), commands })
+    }
+}
+
+/// Parse a unified diff, such as `git diff` or `diff -u` prints, into its files' patches,
+/// in order. Lines outside the `---`/`+++` headers and hunks (`diff --git`, `index`, ...)
+/// are skipped.
+pub fn parse_patch(patch: &str) -> Result<Vec<FilePatch>, EditError> {
+    let mut files: Vec<FilePatch> = Vec::new();
+    let mut lines = patch.lines().map(|l| l.strip_suffix('\r').unwrap_or(l)).peekable();
+    while let Some(line) = lines.next() {
+        if let Some(old) = line.strip_prefix("--- ") {
+            let Some(new) = lines.next().and_then(|l| l.strip_prefix("+++ ")) else {
+                return Err(EditError::parse(format!("patch: no +++ line after {line:?}")));
+            };
+            files.push(FilePatch {
+                old_path: patch_path(old, "a/"),
+                new_path: patch_path(new, "b/"),
+                hunks: Vec::new(),
+            });
+            continue;
+        }
+        if !line.starts_with("@@ ") {
+            continue;
+        }
+        let Some(file) = files.last_mut() else {
+            return Err(EditError::parse(format!("patch: hunk {line:?} before any --- line")));
+        };
+        let (old_start, mut old_len, mut new_len) = parse_hunk_header(line)?;
+        let mut hunk = Hunk { header: hunk_header(line), old_start, lines: Vec::new() };
+        while old_len + new_len > 0 {
+            let Some(line) = lines.next() else {
+                return Err(EditError::parse(format!("patch: hunk {} is cut short", hunk.header)));
+            };
+            // Some tools strip the space that marks an empty context line.
+            let (marker, text) = match line.chars().next() {
+                None => (' ', ""),
+                Some(c) => (c, &line[1..]),
+            };
+            let (old, new) = match marker {
+                ' ' => (1, 1),
+                '-' => (1, 0),
+                '+' => (0, 1),
+                '\\' => continue,
+                _ => {
+                    return Err(EditError::parse(format!(
+                        "patch: hunk {} is cut short at {line:?}",
+                        hunk.header
+                    )))
+                }
+            };
+            if old > old_len || new > new_len {
+                return Err(EditError::parse(format!(
+                    "patch: hunk {} has more lines than its header says",
+                    hunk.header
+                )));
+            }
+            (old_len, new_len) = (old_len - old, new_len - new);
+            hunk.lines.push((marker, text.to_string()));
+        }
+        // A "\ No newline at end of file" after the last line belongs to this hunk.
+        if lines.peek().is_some_and(|l| l.starts_with('\\')) {
+            lines.next();
+        }
+        file.hunks.push(hunk);
+    }
+    Ok(files)
+}
+
+/// A `---`/`+++` path without its timestamp and git `prefix`, or `None` for `/dev/null`.
+fn patch_path(field: &str, prefix: &str) -> Option<String> {
+    let path = field.split('\t').next().unwrap_or(field).trim_end();
+    if path == "/dev/null" {
+        return None;
+    }
+    Some(path.strip_prefix(prefix).unwrap_or(path).to_string())
+}
+
+/// The `@@ -a,b +c,d @@` part of a hunk header line, for messages.
+fn hunk_header(line: &str) -> String {
+    match line[3..].find(" @@") {
+        Some(end) => line[..end + 6].to_string(),
+        None => line.to_string(),
+    }
+}
+
+/// The 0-based old start and the old and new line counts of a hunk header.
+fn parse_hunk_header(line: &str) -> Result<(usize, usize, usize), EditError> {
+    let bad = || EditError::parse(format!("patch: invalid hunk header {line:?}"));
+    let mut fields = line[3..].split(' ');
+    let range = |field: Option<&str>, sign: char| -> Option<(usize, usize)> {
+        let field = field?.strip_prefix(sign)?;
+        match field.split_once(',') {
+            Some((start, len)) => Some((start.parse().ok()?, len.parse().ok()?)),
+            None => Some((field.parse().ok()?, 1)),
+        }
+    };
+    let (old_start, old_len) = range(fields.next(), '-').ok_or_else(bad)?;
+    let (_, new_len) = range(fields.next(), '+').ok_or_else(bad)?;
+    // An empty old range names the line before it, so `-0,0` and `-3,0` start at 0 and 3.
+    let old_start = match old_len {
+        0 => old_start,
+        _ => old_start.checked_sub(1).ok_or_else(bad)?,
+    };
+    Ok((old_start, old_len, new_len))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{diff_commands, edit_text, unified_diff, ErrorKind};
+
+    #[test]
+    fn patch_applies_as_verified_commands() {
+        let spec = LnHashSpec::default();
+        let old = "a\nb\nc\nd\ne\nf\ng\nh\ni\nj\n";
+        let new = "top\na\nB\nc\nd\ne\nf\ng\ni\nj\nk\n";
+        let diff = unified_diff(old, new, "a/f.txt", "b/f.txt", 1);
+        let patch = format!("diff --git a/f.txt b/f.txt\nindex 1..2\n{diff}");
+        let files = parse_patch(&patch).unwrap();
+        assert_eq!(files.len(), 1);
+        assert_eq!(files[0].old_path.as_deref(), Some("f.txt"));
+        assert_eq!(files[0].new_path.as_deref(), Some("f.txt"));
+        let cmds = files[0].commands(old, &spec).unwrap();
+        assert_eq!(cmds.len(), 4);
+        assert_eq!(edit_text(old, &cmds).unwrap().text(), new);
+
+        // As a script, the commands reparse as they were, pinned to the old text.
+        let script = files[0].script(old, &spec).unwrap().to_string();
+        let parsed = crate::parse_script(&script).unwrap();
+        assert_eq!(parsed.fingerprint, Some(crate::file_fingerprint(old)));
+        let text: Vec<String> = parsed.commands.iter().map(|c| c.to_string()).collect();
+        assert_eq!(text, cmds.iter().map(|c| c.to_string()).collect::<Vec<_>>());
+
+        // The same patch no longer applies once a line it relies on has changed.
+        let err = files[0].commands(&old.replace('b', "x"), &spec).unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::StaleHash);
+        assert!(err.message().contains("line 2 is \"x\", the patch expects \"b\""), "{err}");
+        assert!(files[0].commands("a\n", &spec).unwrap_err().message().contains("file ends"));
+    }
+
+    #[test]
+    fn patch_parsing() {
+        let spec = LnHashSpec::default();
+        let patch = "--- old.txt\t2024-01-01\n+++ /dev/null\n@@ -1,2 +0,0 @@\n-a\n-b\n\
+                     \\ No newline at end of file\n";
+        let files = parse_patch(patch).unwrap();
+        assert_eq!(files[0].old_path.as_deref(), Some("old.txt"));
+        assert_eq!(files[0].new_path, None);
+        let script = |cmds: Vec<Command>| cmds.iter().map(|c| c.to_string()).collect::<Vec<_>>();
+        let cmds = files[0].commands("a\nb", &spec).unwrap();
+        assert_eq!(script(cmds), script(diff_commands("a\nb", "", &spec)));
+
+        // An empty context line may have lost its space.
+        let patch = "--- f\n+++ f\n@@ -1,3 +1,3 @@\n x\n\n-y\n+z\n";
+        let cmds = parse_patch(patch).unwrap()[0].commands("x\n\ny\n", &spec).unwrap();
+        assert_eq!(edit_text("x\n\ny\n", &cmds).unwrap().text(), "x\n\nz\n");
+
+        for (patch, msg) in [
+            ("@@ -1 +1 @@\n-a\n+b\n", "before any --- line"),
+            ("--- f\n+++ f\n@@ -1,2 +1 @@\n-a\n", "cut short"),
+            ("--- f\n+++ f\n@@ -x +1 @@\n", "invalid hunk header"),
+            ("--- f\n+++ f\n@@ -1 +1 @@\n-a\n-b\n", "more lines than its header"),
+        ] {
+            let err = parse_patch(patch).unwrap_err();
+            assert_eq!(err.kind(), ErrorKind::Parse);
+            assert!(err.message().contains(msg), "{err}");
+        }
+    }
+}
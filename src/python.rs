@@ -1,7 +1,52 @@
-use pyo3::exceptions::PyValueError;
+use std::collections::BTreeSet;
+
+use pyo3::create_exception;
+use pyo3::exceptions::{PyTypeError, PyValueError};
 use pyo3::prelude::*;
+use pyo3::types::{PyBytes, PyDict};
+
+create_exception!(
+    exhash,
+    StaleHashError,
+    PyValueError,
+    "An lnhash or `@file` fingerprint no longer matches the text."
+);
+create_exception!(
+    exhash,
+    ParseError,
+    PyValueError,
+    "A command, script or address could not be parsed."
+);
+create_exception!(
+    exhash,
+    AddressRangeError,
+    PyValueError,
+    "An address or destination lies past the end of the text."
+);
+
+/// The Python exception for `err`: one of the classes above where its kind has one, else
+/// a plain `ValueError`. A stale hash carries `lineno`, `expected` and `actual`.
+fn edit_error(err: crate::EditError) -> PyErr {
+    let msg = err.to_string();
+    let py_err = match err.kind() {
+        crate::ErrorKind::StaleHash => StaleHashError::new_err(msg),
+        crate::ErrorKind::Parse => ParseError::new_err(msg),
+        crate::ErrorKind::OutOfRange => AddressRangeError::new_err(msg),
+        _ => return PyValueError::new_err(msg),
+    };
+    if err.kind() == crate::ErrorKind::StaleHash {
+        Python::with_gil(|py| {
+            let value = py_err.value(py);
+            let mismatch = err.mismatch();
+            let _ = value.setattr("lineno", mismatch.and_then(|m| m.lineno));
+            let _ = value.setattr("expected", mismatch.map(|m| m.expected.clone()));
+            let _ = value.setattr("actual", mismatch.map(|m| m.actual.clone()));
+        });
+    }
+    py_err
+}
 
-#[pyclass]
+#[pyclass(name = "EditResult", module = "exhash.exhash")]
 #[derive(Clone)]
 struct EditResultPy {
     #[pyo3(get)]
@@ -9,49 +54,1222 @@ struct EditResultPy {
     #[pyo3(get)]
     hashes: Vec<String>,
     #[pyo3(get)]
+    full_hashes: Vec<u64>,
+    #[pyo3(get)]
     modified: Vec<usize>,
     #[pyo3(get)]
     deleted: Vec<usize>,
+    /// The edited text and the result text, with their line endings, for diffs.
+    input: String,
+    output: String,
+    /// The file edited, if any: the default name in a patch.
+    path: Option<String>,
+    /// How the lines were hashed, to address the input's lines in an HTML diff.
+    hash_spec: crate::LnHashSpec,
+    /// What each command did, with its script form, when the edit was traced.
+    steps: Vec<(String, crate::CommandStep)>,
+}
+
+impl EditResultPy {
+    fn new(
+        r: crate::EditResult,
+        commands: &[crate::Command],
+        input: String,
+        path: Option<String>,
+        hash_spec: &crate::LnHashSpec,
+    ) -> Self {
+        let output = r.text();
+        let steps = r.steps.into_iter().map(|s| (commands[s.command].to_string(), s)).collect();
+        Self {
+            lines: r.lines,
+            hashes: r.hashes,
+            full_hashes: r.full_hashes,
+            modified: r.modified,
+            deleted: r.deleted,
+            input,
+            output,
+            path,
+            hash_spec: hash_spec.clone(),
+            steps,
+        }
+    }
+
+    /// Call `on_command` with a dict describing each command the edit applied, in order.
+    fn notify(&self, py: Python<'_>, on_command: Option<&Bound<'_, PyAny>>) -> PyResult<()> {
+        let Some(on_command) = on_command else { return Ok(()) };
+        for (text, step) in &self.steps {
+            let stats = PyDict::new(py);
+            stats.set_item("added", step.stats.added)?;
+            stats.set_item("changed", step.stats.changed)?;
+            stats.set_item("deleted", step.stats.deleted)?;
+            stats.set_item("substitutions", step.stats.substitutions)?;
+            let d = PyDict::new(py);
+            d.set_item("command", step.command)?;
+            d.set_item("text", text)?;
+            d.set_item("changed", &step.changed)?;
+            d.set_item("deleted", &step.deleted)?;
+            d.set_item("line_count", step.line_count)?;
+            d.set_item("stats", stats)?;
+            on_command.call1((d,))?;
+        }
+        Ok(())
+    }
+}
+
+/// A pickled `EditResult`: its fields, the input and output text, the path, and the hash
+/// spec as `(chained, width, nfc, lowercase, seed, versioned)`.
+type EditResultState = (
+    Vec<String>,
+    Vec<String>,
+    Vec<u64>,
+    Vec<usize>,
+    Vec<usize>,
+    String,
+    String,
+    Option<String>,
+    (bool, usize, bool, bool, Option<String>, bool),
+);
+
+/// Whether an edit must be traced for an `on_command` callback, failing before the edit if
+/// what was passed cannot be called.
+fn tracing(on_command: Option<&Bound<'_, PyAny>>) -> PyResult<bool> {
+    match on_command {
+        Some(f) if !f.is_callable() => Err(PyTypeError::new_err("on_command must be callable")),
+        f => Ok(f.is_some()),
+    }
+}
+
+#[pymethods]
+impl EditResultPy {
+    /// A unified diff from `original` (by default, the text that was edited) to the result.
+    #[pyo3(signature = (original=None, context=3, from_label="a", to_label="b"))]
+    fn unified_diff(
+        &self,
+        original: Option<&str>,
+        context: usize,
+        from_label: &str,
+        to_label: &str,
+    ) -> String {
+        let original = original.unwrap_or(&self.input);
+        crate::unified_diff(original, &self.output, from_label, to_label, context)
+    }
+
+    /// The edit as a patch for `patch -p1` or `git apply`, naming `path` (by default the
+    /// file edited, or `file`).
+    #[pyo3(signature = (path=None))]
+    fn to_patch(&self, path: Option<&str>) -> String {
+        let path = path.or(self.path.as_deref()).unwrap_or("file");
+        let (a, b) = (format!("a/{path}"), format!("b/{path}"));
+        crate::unified_diff(&self.input, &self.output, &a, &b, 3)
+    }
+
+    /// The edit as an HTML table for notebooks: the lines it changed, with their addresses
+    /// before and after and three lines of context, or if no line changed, the result with
+    /// the printed lines highlighted.
+    fn _repr_html_(&self) -> String {
+        let style = crate::AddrStyle::Standard;
+        match crate::html_diff(&self.input, &self.output, &self.hash_spec, style, 3) {
+            table if table.is_empty() => {
+                crate::html_view(&self.output, &self.hash_spec, style, &self.modified)
+            }
+            table => table,
+        }
+    }
+
+    /// The result text, with the input's line endings.
+    #[getter]
+    fn text(&self) -> &str {
+        &self.output
+    }
+
+    /// The result as a plain dict of the fields and `text`, e.g. for `json.dumps`.
+    fn to_dict<'py>(&self, py: Python<'py>) -> PyResult<Bound<'py, PyDict>> {
+        let d = PyDict::new(py);
+        d.set_item("lines", &self.lines)?;
+        d.set_item("hashes", &self.hashes)?;
+        d.set_item("full_hashes", &self.full_hashes)?;
+        d.set_item("modified", &self.modified)?;
+        d.set_item("deleted", &self.deleted)?;
+        d.set_item("text", &self.output)?;
+        Ok(d)
+    }
+
+    fn __len__(&self) -> usize {
+        self.lines.len()
+    }
+
+    /// Pickle (and `copy.deepcopy`) the result by its state. The traced steps are left out:
+    /// they only feed `on_command`, which has run by the time the result is returned.
+    fn __reduce__<'py>(
+        slf: &Bound<'py, Self>,
+    ) -> PyResult<(Bound<'py, PyAny>, (EditResultState,))> {
+        let r = slf.borrow();
+        let spec = &r.hash_spec;
+        let state = (
+            r.lines.clone(),
+            r.hashes.clone(),
+            r.full_hashes.clone(),
+            r.modified.clone(),
+            r.deleted.clone(),
+            r.input.clone(),
+            r.output.clone(),
+            r.path.clone(),
+            (
+                spec.algorithm == crate::HashAlgorithm::Chained,
+                spec.width,
+                spec.normalization.nfc,
+                spec.normalization.lowercase,
+                spec.seed.clone(),
+                spec.versioned,
+            ),
+        );
+        Ok((slf.get_type().getattr("_from_state")?, (state,)))
+    }
+
+    #[staticmethod]
+    fn _from_state(state: EditResultState) -> Self {
+        let (lines, hashes, full_hashes, modified, deleted, input, output, path, spec) = state;
+        let (chained, width, nfc, lowercase, seed, versioned) = spec;
+        let algorithm = match chained {
+            true => crate::HashAlgorithm::Chained,
+            false => crate::HashAlgorithm::Line,
+        };
+        let normalization = crate::Normalization { nfc, lowercase };
+        Self {
+            lines,
+            hashes,
+            full_hashes,
+            modified,
+            deleted,
+            input,
+            output,
+            path,
+            hash_spec: crate::LnHashSpec { algorithm, width, normalization, seed, versioned },
+            steps: Vec::new(),
+        }
+    }
+
+    /// The result lines as `(lineno, hash, text)` tuples.
+    fn __iter__<'py>(&self, py: Python<'py>) -> PyResult<Bound<'py, pyo3::types::PyIterator>> {
+        let rows: Vec<(usize, &str, &str)> = (self.hashes.iter().zip(&self.lines))
+            .enumerate()
+            .map(|(i, (hash, line))| (i + 1, hash.as_str(), line.as_str()))
+            .collect();
+        rows.into_pyobject(py)?.try_iter()
+    }
+
+    fn __repr__(&self) -> String {
+        format!(
+            "EditResult({} lines, modified={:?}, deleted={:?})",
+            self.lines.len(),
+            self.modified,
+            self.deleted
+        )
+    }
+}
+
+/// A text argument: a `str`, `bytes` in the given encoding, or a path to a file to read.
+#[derive(FromPyObject)]
+enum Input<'py> {
+    Text(String),
+    Bytes(Bound<'py, PyBytes>),
+    Path(std::path::PathBuf),
+}
+
+impl Input<'_> {
+    /// The text, decoding bytes and files from `encoding` (a WHATWG label, `"auto"`, or
+    /// UTF-8 if `None`). A `str` is taken as it is.
+    fn text(self, encoding: Option<&str>) -> PyResult<String> {
+        let text = match self {
+            Input::Text(s) => return Ok(s),
+            Input::Bytes(b) => crate::file::decode(b.as_bytes().to_vec(), encoding),
+            Input::Path(p) => crate::file::read_text(&p, encoding),
+        };
+        text.map(|(t, _)| t).map_err(edit_error)
+    }
+}
+
+/// A function's `**kwargs`. The keywords most functions share (the hashing ones, `encoding`,
+/// and an edit's `options` and `on_command`) are parsed here once rather than listed in
+/// every signature; each is removed as it is read, and [`done`](Self::done) rejects the
+/// rest as Python would.
+struct Keywords<'py>(Option<Bound<'py, PyDict>>);
+
+impl<'py> Keywords<'py> {
+    fn new(kwargs: Option<&Bound<'py, PyDict>>) -> PyResult<Self> {
+        Ok(Self(kwargs.map(|k| k.copy()).transpose()?))
+    }
+
+    /// The keyword `key`, if it was given and is not None.
+    fn take<T: FromPyObject<'py>>(&self, key: &str) -> PyResult<Option<T>> {
+        let Some(value) = self.0.as_ref().map(|d| d.get_item(key)).transpose()?.flatten() else {
+            return Ok(None);
+        };
+        self.0.as_ref().map(|d| d.del_item(key)).transpose()?;
+        if value.is_none() {
+            return Ok(None);
+        }
+        let invalid = |e: PyErr| {
+            PyTypeError::new_err(format!("argument '{key}': {}", e.value(value.py())))
+        };
+        value.extract().map(Some).map_err(invalid)
+    }
+
+    fn take_or<T: FromPyObject<'py>>(&self, key: &str, default: T) -> PyResult<T> {
+        Ok(self.take(key)?.unwrap_or(default))
+    }
+
+    /// The hash spec from the hashing keywords: `width`, `seed`, `nfc`, `ignore_case`,
+    /// `versioned` and `chained`.
+    fn hash_spec(&self) -> PyResult<crate::LnHashSpec> {
+        let algorithm = match self.take_or("chained", false)? {
+            true => crate::HashAlgorithm::Chained,
+            false => crate::HashAlgorithm::Line,
+        };
+        let spec = crate::LnHashSpec {
+            algorithm,
+            width: self.take_or("width", crate::DEFAULT_HASH_WIDTH)?,
+            normalization: crate::Normalization {
+                nfc: self.take_or("nfc", false)?,
+                lowercase: self.take_or("ignore_case", false)?,
+            },
+            seed: self.take::<String>("seed")?.filter(|s| !s.is_empty()),
+            versioned: self.take_or("versioned", false)?,
+        };
+        spec.validate().map_err(edit_error)?;
+        Ok(spec)
+    }
+
+    /// An edit's `options`, or the defaults with the hashing keywords (see
+    /// [`EditOptionsPy::resolve`]).
+    fn edit_options(&self) -> PyResult<EditOptionsPy> {
+        let hash_spec = self.hash_spec()?;
+        EditOptionsPy::resolve(self.take("options")?, hash_spec)
+    }
+
+    /// The `on_command` callback, checked to be callable before the edit.
+    fn on_command(&self) -> PyResult<Option<Bound<'py, PyAny>>> {
+        let on_command = self.take("on_command")?;
+        tracing(on_command.as_ref())?;
+        Ok(on_command)
+    }
+
+    /// Fail if a keyword was given that `function` does not take.
+    fn done(self, function: &str) -> PyResult<()> {
+        match self.0.and_then(|d| d.keys().iter().next()) {
+            Some(key) => Err(PyTypeError::new_err(format!(
+                "{function}() got an unexpected keyword argument '{key}'"
+            ))),
+            None => Ok(()),
+        }
+    }
+}
+
+fn addr_style(compact: bool) -> crate::AddrStyle {
+    if compact { crate::AddrStyle::Compact } else { crate::AddrStyle::Standard }
+}
+
+/// How an edit is applied, as the `exhash` CLI takes it from its flags and config files.
+#[pyclass(name = "EditOptions", frozen)]
+#[derive(Clone)]
+struct EditOptionsPy {
+    hash_spec: crate::LnHashSpec,
+    #[pyo3(get)]
+    compact: bool,
+    #[pyo3(get)]
+    indent_width: usize,
+    #[pyo3(get)]
+    fuzz: usize,
+    #[pyo3(get)]
+    bottom_up: bool,
+    join: crate::JoinPolicy,
+    #[pyo3(get)]
+    max_file_size: Option<u64>,
+    #[pyo3(get)]
+    max_commands: Option<usize>,
+    #[pyo3(get)]
+    max_block_size: Option<u64>,
+    #[pyo3(get)]
+    strict_substitute: bool,
+    /// Record each command's effect, for an `on_command` callback.
+    trace: bool,
+}
+
+impl EditOptionsPy {
+    fn with_spec(hash_spec: crate::LnHashSpec) -> Self {
+        Self {
+            hash_spec,
+            compact: false,
+            indent_width: 4,
+            fuzz: 0,
+            bottom_up: false,
+            join: crate::JoinPolicy::default(),
+            max_file_size: None,
+            max_commands: None,
+            max_block_size: None,
+            strict_substitute: false,
+            trace: false,
+        }
+    }
+
+    /// `options` if given, else the defaults with the spec from a function's hashing keywords,
+    /// which must then be left alone.
+    fn resolve(options: Option<Self>, hash_spec: crate::LnHashSpec) -> PyResult<Self> {
+        match options {
+            Some(_) if hash_spec != crate::LnHashSpec::default() => Err(PyValueError::new_err(
+                "pass hashing options in `options`, not as keyword arguments alongside it",
+            )),
+            Some(options) => Ok(options),
+            None => Ok(Self::with_spec(hash_spec)),
+        }
+    }
+
+    fn edit_options(&self) -> crate::EditOptions {
+        crate::EditOptions {
+            hash_spec: self.hash_spec.clone(),
+            addr_style: addr_style(self.compact),
+            indent_width: self.indent_width,
+            fuzz: self.fuzz,
+            bottom_up: self.bottom_up,
+            join: self.join.clone(),
+            strict_substitute: self.strict_substitute,
+            trace: self.trace,
+            ..Default::default()
+        }
+    }
+
+    /// Fail if `what`, of `size` bytes, is over `max_file_size`.
+    fn check_size(&self, what: &str, size: u64) -> Result<(), crate::EditError> {
+        match self.max_file_size {
+            Some(max) if size > max => Err(crate::EditError::new(format!(
+                "{what} is {size} bytes, over max_file_size {max}"
+            ))),
+            _ => Ok(()),
+        }
+    }
+
+    /// Fail if there are more `commands` than `max_commands`, or one of their text blocks is
+    /// over `max_block_size`, as `--max-commands` and `--max-block-size` do.
+    fn check_commands(&self, commands: &[crate::Command]) -> Result<(), crate::EditError> {
+        crate::check_limits(commands, self.max_commands, self.max_block_size)
+    }
 }
 
+#[pymethods]
+impl EditOptionsPy {
+    #[new]
+    #[pyo3(
+        signature = (**kwargs),
+        text_signature = "(width=4, compact=False, seed=None, nfc=False, ignore_case=False, \
+            versioned=False, chained=False, indent_width=4, fuzz=0, bottom_up=False, \
+            join='smart', join_sep=None, max_file_size=None, max_commands=None, \
+            max_block_size=None, strict_substitute=False)"
+    )]
+    fn new(kwargs: Option<&Bound<'_, PyDict>>) -> PyResult<Self> {
+        let kw = Keywords::new(kwargs)?;
+        let indent_width = kw.take_or("indent_width", 4)?;
+        if indent_width == 0 {
+            return Err(PyValueError::new_err("indent_width must be at least 1"));
+        }
+        let join = match (kw.take_or("join", "smart".to_string())?.as_str(), kw.take("join_sep")?) {
+            (_, Some(sep)) => crate::JoinPolicy::Separator(sep),
+            ("smart", None) => crate::JoinPolicy::Smart,
+            ("always", None) => crate::JoinPolicy::Always,
+            ("never", None) => crate::JoinPolicy::Never,
+            _ => return Err(PyValueError::new_err("join must be smart, always or never")),
+        };
+        let options = Self {
+            compact: kw.take_or("compact", false)?,
+            indent_width,
+            fuzz: kw.take_or("fuzz", 0)?,
+            bottom_up: kw.take_or("bottom_up", false)?,
+            join,
+            max_file_size: kw.take("max_file_size")?,
+            max_commands: kw.take("max_commands")?,
+            max_block_size: kw.take("max_block_size")?,
+            strict_substitute: kw.take_or("strict_substitute", false)?,
+            ..Self::with_spec(kw.hash_spec()?)
+        };
+        kw.done("EditOptions")?;
+        Ok(options)
+    }
+
+    /// The options the `exhash` CLI starts from here: the settings of the user's and the
+    /// project's config files, and a seed from `EXHASH_SEED`.
+    #[staticmethod]
+    fn from_config() -> PyResult<Self> {
+        let config = crate::Config::load().map_err(edit_error)?;
+        let opts = config.edit_options();
+        Ok(Self {
+            compact: opts.addr_style == crate::AddrStyle::Compact,
+            indent_width: opts.indent_width,
+            fuzz: opts.fuzz,
+            max_file_size: config.max_file_size,
+            max_commands: config.max_commands,
+            max_block_size: config.max_block_size,
+            ..Self::with_spec(opts.hash_spec)
+        })
+    }
+
+    #[getter]
+    fn width(&self) -> usize {
+        self.hash_spec.width
+    }
+
+    #[getter]
+    fn seed(&self) -> Option<&str> {
+        self.hash_spec.seed.as_deref()
+    }
+
+    /// `smart`, `always` or `never`, or the separator given as `join_sep`.
+    #[getter]
+    fn join(&self) -> &str {
+        match &self.join {
+            crate::JoinPolicy::Smart => "smart",
+            crate::JoinPolicy::Always => "always",
+            crate::JoinPolicy::Never => "never",
+            crate::JoinPolicy::Separator(sep) => sep,
+        }
+    }
 
-impl From<crate::EditResult> for EditResultPy {
-    fn from(r: crate::EditResult) -> Self {
-        Self { lines: r.lines, hashes: r.hashes, modified: r.modified, deleted: r.deleted }
+    fn __repr__(&self) -> String {
+        format!(
+            "EditOptions(width={}, compact={}, indent_width={}, fuzz={}, bottom_up={}, join={:?})",
+            self.hash_spec.width,
+            self.compact,
+            self.indent_width,
+            self.fuzz,
+            self.bottom_up,
+            self.join()
+        )
     }
 }
 
 #[pyfunction]
-fn line_hash(line: &str) -> String { format!("{:04x}", crate::line_hash_u16(line)) }
+#[pyo3(signature = (line, **kwargs))]
+fn line_hash(line: &str, kwargs: Option<&Bound<'_, PyDict>>) -> PyResult<String> {
+    let kw = Keywords::new(kwargs)?;
+    let spec = kw.hash_spec()?;
+    kw.done("line_hash")?;
+    Ok(format!("{:0width$x}", spec.line_hash(line), width = spec.width))
+}
 
 #[pyfunction]
-fn lnhash(lineno: usize, line: &str) -> String { crate::format_lnhash(lineno, line) }
+#[pyo3(signature = (line, **kwargs))]
+fn line_hash_u64(line: &str, kwargs: Option<&Bound<'_, PyDict>>) -> PyResult<u64> {
+    let kw = Keywords::new(kwargs)?;
+    let spec = kw.hash_spec()?;
+    kw.done("line_hash_u64")?;
+    Ok(spec.line_hash_u64_after(None, line))
+}
 
 #[pyfunction]
-fn lnhashview(text: &str) -> Vec<String> {
-    text.lines()
-        .enumerate()
-        .map(|(i, l)| format!("{}  {}", crate::format_lnhash(i + 1, l), l))
-        .collect()
+#[pyo3(signature = (lineno, line, prev=None, **kwargs))]
+fn lnhash(
+    lineno: usize,
+    line: &str,
+    prev: Option<&str>,
+    kwargs: Option<&Bound<'_, PyDict>>,
+) -> PyResult<String> {
+    let kw = Keywords::new(kwargs)?;
+    let (spec, compact) = (kw.hash_spec()?, kw.take_or("compact", false)?);
+    kw.done("lnhash")?;
+    let addr = spec.lnhash_after(lineno, prev, line);
+    Ok(addr.format_with(addr_style(compact)))
+}
+
+#[pyfunction]
+#[pyo3(signature = (text, encoding=None))]
+fn file_fingerprint(text: Input<'_>, encoding: Option<&str>) -> PyResult<String> {
+    Ok(crate::file_fingerprint(&text.text(encoding)?))
+}
+
+#[pyfunction]
+fn parse_lnhash(s: &str) -> PyResult<LnHashPy> {
+    LnHashPy::parse(s)
+}
+
+/// Format an address from its parts: a plain `width`-digit `hash` with no tags.
+#[pyfunction]
+#[pyo3(signature = (lineno, hash, width=4, compact=false))]
+fn format_lnhash(lineno: usize, hash: u32, width: usize, compact: bool) -> PyResult<String> {
+    crate::check_hash_width(width).map_err(edit_error)?;
+    if width < 8 && hash >> (4 * width) != 0 {
+        return Err(PyValueError::new_err(format!("hash {hash:#x} is wider than {width} digits")));
+    }
+    let addr = crate::LnHash { lineno, hash, width, ..crate::LnHash::of(lineno, "") };
+    Ok(addr.format_with(addr_style(compact)))
+}
+
+#[pyfunction]
+#[pyo3(signature = (text, **kwargs))]
+fn lnhashes(text: Input<'_>, kwargs: Option<&Bound<'_, PyDict>>) -> PyResult<Vec<LnHashPy>> {
+    let kw = Keywords::new(kwargs)?;
+    let (spec, encoding) = (kw.hash_spec()?, kw.take::<String>("encoding")?);
+    kw.done("lnhashes")?;
+    let text = text.text(encoding.as_deref())?;
+    Ok(spec.lnhashes(text.lines()).into_iter().map(LnHashPy).collect())
+}
+
+/// What `lnhashview` returns: the view's lines, or with `rows`, a dict for each.
+#[derive(IntoPyObject)]
+enum View {
+    Lines(Vec<String>),
+    Rows(Vec<ViewRow>),
+}
+
+#[derive(IntoPyObject)]
+struct ViewRow {
+    lineno: usize,
+    hash: String,
+    text: String,
+    /// Shown only as context around a match.
+    context: bool,
 }
 
 #[pyfunction]
-#[pyo3(name = "exhash", signature = (text, *cmds))]
-fn py_exhash(text: &str, cmds: Vec<String>) -> PyResult<EditResultPy> {
+#[pyo3(signature = (text, start=None, end=None, pattern=None, context=0, rows=false, **kwargs))]
+fn lnhashview(
+    text: Input<'_>,
+    start: Option<usize>,
+    end: Option<usize>,
+    pattern: Option<&str>,
+    context: usize,
+    rows: bool,
+    kwargs: Option<&Bound<'_, PyDict>>,
+) -> PyResult<View> {
+    let kw = Keywords::new(kwargs)?;
+    let (spec, compact) = (kw.hash_spec()?, kw.take_or("compact", false)?);
+    let encoding = kw.take::<String>("encoding")?;
+    kw.done("lnhashview")?;
+    let style = addr_style(compact);
+    let text = text.text(encoding.as_deref())?;
+    let lines: Vec<&str> = text.lines().collect();
+    let re = pattern
+        .map(regex::Regex::new)
+        .transpose()
+        .map_err(|e| ParseError::new_err(format!("invalid pattern: {e}")))?;
+    let first = start.unwrap_or(1).max(1);
+    let last = end.unwrap_or(lines.len()).min(lines.len());
+    // The lines to show, each flagged if it is only there as context around a match.
+    let shown: Vec<(usize, bool)> = match &re {
+        None => (first..=last).map(|n| (n, false)).collect(),
+        Some(re) => {
+            let matched: BTreeSet<usize> =
+                (first..=last).filter(|&n| re.is_match(lines[n - 1])).collect();
+            let mut shown = BTreeSet::new();
+            for &n in &matched {
+                shown.extend(n.saturating_sub(context).max(first)..=(n + context).min(last));
+            }
+            shown.into_iter().map(|n| (n, !matched.contains(&n))).collect()
+        }
+    };
+    let addrs = spec.lnhashes(lines.iter().copied());
+    if !rows {
+        let view = shown
+            .iter()
+            .map(|&(n, _)| format!("{}  {}", addrs[n - 1].format_with(style), lines[n - 1]))
+            .collect();
+        return Ok(View::Lines(view));
+    }
+    let rows = shown
+        .into_iter()
+        .map(|(n, context)| ViewRow {
+            lineno: n,
+            hash: addrs[n - 1].format_with(style),
+            text: lines[n - 1].to_string(),
+            context,
+        })
+        .collect();
+    Ok(View::Rows(rows))
+}
+
+#[pyfunction]
+#[pyo3(name = "exhash", signature = (text, *cmds, **kwargs))]
+fn py_exhash(
+    py: Python<'_>,
+    text: Input<'_>,
+    cmds: Vec<String>,
+    kwargs: Option<&Bound<'_, PyDict>>,
+) -> PyResult<EditResultPy> {
+    let kw = Keywords::new(kwargs)?;
+    let on_command = kw.on_command()?;
+    let options = EditOptionsPy { trace: on_command.is_some(), ..kw.edit_options()? };
+    let encoding = kw.take::<String>("encoding")?;
+    kw.done("exhash")?;
+    let text = text.text(encoding.as_deref())?;
+    let res = py.allow_threads(|| edit_cmds(&text, &cmds, &options)).map_err(edit_error)?;
+    res.notify(py, on_command.as_ref())?;
+    Ok(res)
+}
+
+/// Parse command strings within `options`' limits, taking a leading `@file <fingerprint>`
+/// header into the edit options.
+fn parse_cmds(
+    cmds: &[String],
+    options: &EditOptionsPy,
+) -> Result<(Vec<crate::Command>, crate::EditOptions), crate::EditError> {
     let cmd_refs: Vec<&str> = cmds.iter().map(|s| s.as_str()).collect();
-    let parsed = crate::parse_commands_from_strs(&cmd_refs)
-        .map_err(|e| PyValueError::new_err(e.to_string()))?;
-    let res = crate::edit_text(text, &parsed)
-        .map_err(|e| PyValueError::new_err(e.to_string()))?;
-    Ok(res.into())
+    let (commands, fingerprint) = crate::parse_commands_with_header(&cmd_refs)?;
+    options.check_commands(&commands)?;
+    Ok((commands, crate::EditOptions { fingerprint, ..options.edit_options() }))
+}
+
+/// Parse `cmds` and apply them to `text`; safe to run without the GIL.
+fn edit_cmds(
+    text: &str,
+    cmds: &[String],
+    options: &EditOptionsPy,
+) -> Result<EditResultPy, crate::EditError> {
+    options.check_size("text", text.len() as u64)?;
+    let (parsed, opts) = parse_cmds(cmds, options)?;
+    let res = crate::edit_text_with(text, &parsed, &opts)?;
+    Ok(EditResultPy::new(res, &parsed, text.to_string(), None, &opts.hash_spec))
+}
+
+/// Check `cmds` against `text` without applying them: every stale or out-of-range address,
+/// as `exhash --verify-only --json` reports them, and with `suggest`, nearby lines that
+/// stale hashes still match.
+#[pyfunction]
+#[pyo3(name = "verify_only", signature = (text, *cmds, suggest=false, window=20, **kwargs))]
+fn py_verify_only<'py>(
+    py: Python<'py>,
+    text: Input<'_>,
+    cmds: Vec<String>,
+    suggest: bool,
+    window: usize,
+    kwargs: Option<&Bound<'_, PyDict>>,
+) -> PyResult<Bound<'py, PyDict>> {
+    let kw = Keywords::new(kwargs)?;
+    let (options, encoding) = (kw.edit_options()?, kw.take::<String>("encoding")?);
+    kw.done("verify_only")?;
+    let text = text.text(encoding.as_deref())?;
+    let (commands, opts) = parse_cmds(&cmds, &options).map_err(edit_error)?;
+    let (errors, hints) = py.allow_threads(|| {
+        let errors = crate::verify_commands(&text, &commands, &opts);
+        let hints = if suggest {
+            crate::suggest_addresses(&text, &commands, &opts, window)
+        } else {
+            Vec::new()
+        };
+        (errors, hints)
+    });
+
+    let line = |(addr, text): &(crate::LnHash, String)| -> PyResult<Bound<'py, PyDict>> {
+        let d = PyDict::new(py);
+        d.set_item("lineno", addr.lineno)?;
+        d.set_item("hash", addr.to_string())?;
+        d.set_item("text", text)?;
+        Ok(d)
+    };
+    let mut error_dicts = Vec::new();
+    for e in &errors {
+        let d = PyDict::new(py);
+        d.set_item("message", e.message())?;
+        d.set_item("kind", e.kind().name())?;
+        d.set_item("command", e.command())?;
+        d.set_item("text", e.command().and_then(|k| commands.get(k)).map(|c| c.to_string()))?;
+        let mismatch = e.mismatch();
+        d.set_item("lineno", mismatch.and_then(|m| m.lineno))?;
+        d.set_item("expected", mismatch.map(|m| m.expected.as_str()))?;
+        d.set_item("actual", mismatch.map(|m| m.actual.as_str()))?;
+        error_dicts.push(d);
+    }
+    let mut hint_dicts = Vec::new();
+    for h in &hints {
+        let d = PyDict::new(py);
+        d.set_item("command", h.command)?;
+        d.set_item("addr", h.addr.to_string())?;
+        d.set_item("current", h.current.as_ref().map(line).transpose()?)?;
+        d.set_item("matches", h.matches.iter().map(line).collect::<PyResult<Vec<_>>>()?)?;
+        hint_dicts.push(d);
+    }
+    let report = PyDict::new(py);
+    report.set_item("ok", errors.is_empty())?;
+    report.set_item("errors", error_dicts)?;
+    report.set_item("suggestions", hint_dicts)?;
+    Ok(report)
+}
+
+/// Apply each `(text, cmds)` edit, on as many threads as there are CPUs and without the
+/// GIL. With `return_exceptions`, a failed edit's exception takes its place in the list;
+/// otherwise the first failure is raised.
+#[pyfunction]
+#[pyo3(name = "exhash_many", signature = (items, return_exceptions=false, **kwargs))]
+fn py_exhash_many(
+    py: Python<'_>,
+    items: Vec<(Input<'_>, Vec<String>)>,
+    return_exceptions: bool,
+    kwargs: Option<&Bound<'_, PyDict>>,
+) -> PyResult<Vec<PyObject>> {
+    let kw = Keywords::new(kwargs)?;
+    let (options, encoding) = (kw.edit_options()?, kw.take::<String>("encoding")?);
+    kw.done("exhash_many")?;
+    let items = items
+        .into_iter()
+        .map(|(text, cmds)| Ok((text.text(encoding.as_deref())?, cmds)))
+        .collect::<PyResult<Vec<_>>>()?;
+    let threads = std::thread::available_parallelism().map_or(1, |n| n.get());
+    let chunk = items.len().div_ceil(threads).max(1);
+    let results: Vec<Result<EditResultPy, crate::EditError>> = py.allow_threads(|| {
+        std::thread::scope(|s| {
+            let workers: Vec<_> = items
+                .chunks(chunk)
+                .map(|part| {
+                    let options = &options;
+                    s.spawn(move || {
+                        part.iter()
+                            .map(|(text, cmds)| edit_cmds(text, cmds, options))
+                            .collect::<Vec<_>>()
+                    })
+                })
+                .collect();
+            workers.into_iter().flat_map(|w| w.join().expect("edit thread panicked")).collect()
+        })
+    });
+    results
+        .into_iter()
+        .map(|r| match r {
+            Ok(res) => Ok(Py::new(py, res)?.into_any()),
+            Err(e) if return_exceptions => Ok(edit_error(e).into_value(py).into_any()),
+            Err(e) => Err(edit_error(e)),
+        })
+        .collect()
+}
+
+#[pyfunction]
+#[pyo3(name = "exhash_script", signature = (text, script, **kwargs))]
+fn py_exhash_script(
+    py: Python<'_>,
+    text: Input<'_>,
+    script: &str,
+    kwargs: Option<&Bound<'_, PyDict>>,
+) -> PyResult<EditResultPy> {
+    let kw = Keywords::new(kwargs)?;
+    let on_command = kw.on_command()?;
+    let options = EditOptionsPy { trace: on_command.is_some(), ..kw.edit_options()? };
+    let encoding = kw.take::<String>("encoding")?;
+    kw.done("exhash_script")?;
+    let text = text.text(encoding.as_deref())?;
+    let res = py.allow_threads(|| {
+        options.check_size("text", text.len() as u64)?;
+        let script = crate::parse_script(script)?;
+        options.check_commands(&script.commands)?;
+        let fingerprint = script.fingerprint;
+        let opts = crate::EditOptions { fingerprint, ..options.edit_options() };
+        let res = crate::edit_text_with(&text, &script.commands, &opts)?;
+        Ok(EditResultPy::new(res, &script.commands, text.clone(), None, &opts.hash_spec))
+    });
+    let res = res.map_err(edit_error)?;
+    res.notify(py, on_command.as_ref())?;
+    Ok(res)
+}
+
+#[pyfunction]
+#[pyo3(name = "edit_file", signature = (path, *cmds, dry_run=false, backup=None, **kwargs))]
+fn py_edit_file(
+    py: Python<'_>,
+    path: std::path::PathBuf,
+    cmds: Vec<String>,
+    dry_run: bool,
+    backup: Option<String>,
+    kwargs: Option<&Bound<'_, PyDict>>,
+) -> PyResult<EditResultPy> {
+    let kw = Keywords::new(kwargs)?;
+    let on_command = kw.on_command()?;
+    let options = EditOptionsPy { trace: on_command.is_some(), ..kw.edit_options()? };
+    let encoding = kw.take("encoding")?;
+    kw.done("edit_file")?;
+    let file_opts = crate::FileOptions { dry_run, backup, encoding };
+    let res = py.allow_threads(|| {
+        // Checked before reading; a missing file is reported when it is read.
+        if let Ok(meta) = std::fs::metadata(&path) {
+            options.check_size(&path.display().to_string(), meta.len())?;
+        }
+        let (parsed, opts) = parse_cmds(&cmds, &options)?;
+        let (res, original) = crate::edit_file(&path, &parsed, &opts, &file_opts)?;
+        let path = Some(path.to_string_lossy().into_owned());
+        Ok(EditResultPy::new(res, &parsed, original, path, &opts.hash_spec))
+    });
+    let res = res.map_err(edit_error)?;
+    res.notify(py, on_command.as_ref())?;
+    Ok(res)
+}
+
+/// An lnhash address.
+#[pyclass(name = "LnHash", frozen, eq, hash)]
+#[derive(Clone, PartialEq, Eq, Hash)]
+struct LnHashPy(crate::LnHash);
+
+#[pymethods]
+impl LnHashPy {
+    /// The address of `line` at 1-based `lineno`, as `lnhash()` makes it.
+    #[new]
+    #[pyo3(
+        signature = (lineno, line, prev=None, **kwargs),
+        text_signature = "(lineno, line, prev=None, width=4, seed=None, nfc=False, \
+            ignore_case=False, versioned=False, chained=False)"
+    )]
+    fn new(
+        lineno: usize,
+        line: &str,
+        prev: Option<&str>,
+        kwargs: Option<&Bound<'_, PyDict>>,
+    ) -> PyResult<Self> {
+        let kw = Keywords::new(kwargs)?;
+        let spec = kw.hash_spec()?;
+        kw.done("LnHash")?;
+        Ok(Self(spec.lnhash_after(lineno, prev, line)))
+    }
+
+    /// Parse an address in either the `lineno|hash|` or the compact form.
+    #[staticmethod]
+    fn parse(s: &str) -> PyResult<Self> {
+        crate::parse_lnhash(s).map(Self).map_err(edit_error)
+    }
+
+    #[getter]
+    fn lineno(&self) -> usize {
+        self.0.lineno
+    }
+
+    #[getter]
+    fn hash(&self) -> u32 {
+        self.0.hash
+    }
+
+    #[getter]
+    fn width(&self) -> usize {
+        self.0.width
+    }
+
+    /// The address as written, `lineno|hash|` with any tags.
+    #[getter]
+    fn formatted(&self) -> String {
+        self.0.to_string()
+    }
+
+    #[pyo3(signature = (compact=false))]
+    fn format(&self, compact: bool) -> String {
+        self.0.format_with(addr_style(compact))
+    }
+
+    fn __str__(&self) -> String {
+        self.0.to_string()
+    }
+
+    fn __repr__(&self) -> String {
+        format!("LnHash({:?})", self.0.to_string())
+    }
+}
+
+/// An address argument: an `LnHash` or its text.
+#[derive(FromPyObject)]
+enum AddrArg {
+    Addr(LnHashPy),
+    Text(String),
+}
+
+impl AddrArg {
+    fn lnhash(self) -> PyResult<crate::LnHash> {
+        match self {
+            AddrArg::Addr(a) => Ok(a.0),
+            AddrArg::Text(s) => crate::parse_lnhash(&s).map_err(edit_error),
+        }
+    }
+}
+
+/// A text block argument: one string, split at newlines, or a list of lines.
+#[derive(FromPyObject)]
+enum TextArg {
+    Text(String),
+    Lines(Vec<String>),
+}
+
+impl TextArg {
+    fn lines(self) -> Vec<String> {
+        let lines = match self {
+            TextArg::Text(s) => vec![s],
+            TextArg::Lines(lines) => lines,
+        };
+        lines.iter().flat_map(|l| l.lines()).map(str::to_string).collect()
+    }
+}
+
+/// What a command does to its lines, without the address.
+#[pyclass(name = "Subcommand", frozen)]
+#[derive(Clone)]
+struct SubcommandPy(crate::Subcommand);
+
+#[pymethods]
+impl SubcommandPy {
+    #[staticmethod]
+    fn delete() -> Self {
+        Self(crate::Subcommand::Delete)
+    }
+
+    #[staticmethod]
+    #[pyo3(signature = (pattern, replacement, global_=false, ignore_case=false))]
+    fn substitute(pattern: String, replacement: String, global_: bool, ignore_case: bool) -> Self {
+        Self(crate::Subcommand::Substitute(crate::parse::Subst {
+            pattern,
+            replacement,
+            global: global_,
+            case_insensitive: ignore_case,
+            columns: None,
+        }))
+    }
+
+    #[staticmethod]
+    fn append(text: TextArg) -> Self {
+        Self(crate::Subcommand::Append(text.lines()))
+    }
+
+    #[staticmethod]
+    fn insert(text: TextArg) -> Self {
+        Self(crate::Subcommand::Insert(text.lines()))
+    }
+
+    #[staticmethod]
+    fn change(text: TextArg) -> Self {
+        Self(crate::Subcommand::Change(text.lines()))
+    }
+
+    #[staticmethod]
+    fn join() -> Self {
+        Self(crate::Subcommand::Join)
+    }
+
+    #[staticmethod]
+    #[pyo3(name = "move")]
+    fn move_(dest: AddrArg) -> PyResult<Self> {
+        Ok(Self(crate::Subcommand::Move { dest: dest.lnhash()? }))
+    }
+
+    #[staticmethod]
+    fn copy(dest: AddrArg) -> PyResult<Self> {
+        Ok(Self(crate::Subcommand::Copy { dest: dest.lnhash()? }))
+    }
+
+    #[staticmethod]
+    #[pyo3(signature = (pattern, cmd, invert=false))]
+    fn global_(pattern: String, cmd: SubcommandPy, invert: bool) -> Self {
+        Self(crate::Subcommand::Global { invert, pattern, cmd: Box::new(cmd.0) })
+    }
+
+    #[staticmethod]
+    #[pyo3(signature = (levels=1))]
+    fn indent(levels: usize) -> Self {
+        Self(crate::Subcommand::Indent { levels })
+    }
+
+    #[staticmethod]
+    #[pyo3(signature = (levels=1))]
+    fn dedent(levels: usize) -> Self {
+        Self(crate::Subcommand::Dedent { levels })
+    }
+
+    #[staticmethod]
+    fn sort() -> Self {
+        Self(crate::Subcommand::Sort)
+    }
+
+    #[staticmethod]
+    fn print() -> Self {
+        Self(crate::Subcommand::Print)
+    }
+
+    fn __str__(&self) -> String {
+        self.0.to_string()
+    }
+
+    fn __repr__(&self) -> String {
+        format!("Subcommand({:?})", self.0.to_string())
+    }
+}
+
+/// A command: a subcommand on the line at `addr`, or on `addr` through `end`. Its `str()`
+/// is script text that parses back to the same command.
+#[pyclass(name = "Command", frozen)]
+#[derive(Clone)]
+struct CommandPy(crate::Command);
+
+impl CommandPy {
+    fn build(addr: AddrArg, cmd: crate::Subcommand, end: Option<AddrArg>) -> PyResult<Self> {
+        let addr2 = end.map(AddrArg::lnhash).transpose()?;
+        let addr1 = addr.lnhash()?;
+        Ok(Self(crate::Command { addr1, addr2, has_comma: addr2.is_some(), cmd }))
+    }
+}
+
+#[pymethods]
+impl CommandPy {
+    #[new]
+    #[pyo3(signature = (addr, cmd, end=None))]
+    fn new(addr: AddrArg, cmd: SubcommandPy, end: Option<AddrArg>) -> PyResult<Self> {
+        Self::build(addr, cmd.0, end)
+    }
+
+    /// Parse one command from script text, e.g. `12|a3f2|s/foo/bar/`.
+    #[staticmethod]
+    fn parse(s: &str) -> PyResult<Self> {
+        let mut commands = crate::parse_commands_from_strs(&[s]).map_err(edit_error)?;
+        match commands.len() {
+            1 => Ok(Self(commands.remove(0))),
+            n => Err(ParseError::new_err(format!("expected one command, got {n}"))),
+        }
+    }
+
+    #[staticmethod]
+    #[pyo3(signature = (addr, pattern, replacement, global_=false, ignore_case=false, end=None))]
+    fn substitute(
+        addr: AddrArg,
+        pattern: String,
+        replacement: String,
+        global_: bool,
+        ignore_case: bool,
+        end: Option<AddrArg>,
+    ) -> PyResult<Self> {
+        let sub = SubcommandPy::substitute(pattern, replacement, global_, ignore_case);
+        Self::build(addr, sub.0, end)
+    }
+
+    #[staticmethod]
+    #[pyo3(signature = (addr, end=None))]
+    fn delete(addr: AddrArg, end: Option<AddrArg>) -> PyResult<Self> {
+        Self::build(addr, crate::Subcommand::Delete, end)
+    }
+
+    #[staticmethod]
+    fn append(addr: AddrArg, text: TextArg) -> PyResult<Self> {
+        Self::build(addr, crate::Subcommand::Append(text.lines()), None)
+    }
+
+    #[staticmethod]
+    fn insert(addr: AddrArg, text: TextArg) -> PyResult<Self> {
+        Self::build(addr, crate::Subcommand::Insert(text.lines()), None)
+    }
+
+    #[staticmethod]
+    #[pyo3(signature = (addr, text, end=None))]
+    fn change(addr: AddrArg, text: TextArg, end: Option<AddrArg>) -> PyResult<Self> {
+        Self::build(addr, crate::Subcommand::Change(text.lines()), end)
+    }
+
+    #[getter]
+    fn addr(&self) -> LnHashPy {
+        LnHashPy(self.0.addr1)
+    }
+
+    #[getter]
+    fn end(&self) -> Option<LnHashPy> {
+        self.0.addr2.map(LnHashPy)
+    }
+
+    #[getter]
+    fn cmd(&self) -> SubcommandPy {
+        SubcommandPy(self.0.cmd.clone())
+    }
+
+    fn __str__(&self) -> String {
+        self.0.to_string()
+    }
+
+    fn __repr__(&self) -> String {
+        format!("Command({:?})", self.0.to_string())
+    }
+}
+
+/// A text buffer edited one batch of commands at a time, with undo.
+#[pyclass(name = "EditSession")]
+struct EditSessionPy {
+    text: String,
+    /// The text before each applied batch, most recent last.
+    history: Vec<String>,
+    options: EditOptionsPy,
+}
+
+#[pymethods]
+impl EditSessionPy {
+    #[new]
+    #[pyo3(signature = (text, **kwargs))]
+    fn new(text: Input<'_>, kwargs: Option<&Bound<'_, PyDict>>) -> PyResult<Self> {
+        let kw = Keywords::new(kwargs)?;
+        let compact = kw.take_or("compact", false)?;
+        let mut options = kw.edit_options()?;
+        options.compact |= compact;
+        let encoding = kw.take::<String>("encoding")?;
+        kw.done("EditSession")?;
+        let text = text.text(encoding.as_deref())?;
+        Ok(Self { text, history: Vec::new(), options })
+    }
+
+    #[getter]
+    fn text(&self) -> &str {
+        &self.text
+    }
+
+    /// The lnhash view of lines `start` to `end` (1-based, inclusive; default all).
+    #[pyo3(signature = (start=None, end=None))]
+    fn view(&self, start: Option<usize>, end: Option<usize>) -> Vec<String> {
+        let style = addr_style(self.options.compact);
+        let lines = self.options.hash_spec.view_lines(&self.text, style);
+        let end = end.unwrap_or(lines.len()).min(lines.len());
+        let start = start.unwrap_or(1).max(1);
+        lines.get(start - 1..end).unwrap_or_default().to_vec()
+    }
+
+    /// Apply `cmds` to the buffer. A failed batch leaves it unchanged.
+    #[pyo3(signature = (*cmds, on_command=None))]
+    fn apply(
+        &mut self,
+        py: Python<'_>,
+        cmds: Vec<String>,
+        on_command: Option<Bound<'_, PyAny>>,
+    ) -> PyResult<EditResultPy> {
+        let trace = tracing(on_command.as_ref())?;
+        let (text, options) = (&self.text, EditOptionsPy { trace, ..self.options.clone() });
+        let res = py.allow_threads(|| edit_cmds(text, &cmds, &options)).map_err(edit_error)?;
+        res.notify(py, on_command.as_ref())?;
+        self.history.push(std::mem::replace(&mut self.text, res.output.clone()));
+        Ok(res)
+    }
+
+    /// Revert the last applied batch. Returns false if there is nothing to undo.
+    fn undo(&mut self) -> bool {
+        match self.history.pop() {
+            Some(text) => {
+                self.text = text;
+                true
+            }
+            None => false,
+        }
+    }
 }
 
 #[pymodule]
 fn exhash(m: &Bound<'_, PyModule>) -> PyResult<()> {
+    m.add_class::<EditOptionsPy>()?;
     m.add_class::<EditResultPy>()?;
+    m.add_class::<EditSessionPy>()?;
+    m.add_class::<LnHashPy>()?;
+    m.add_class::<SubcommandPy>()?;
+    m.add_class::<CommandPy>()?;
+    m.add("StaleHashError", m.py().get_type::<StaleHashError>())?;
+    m.add("ParseError", m.py().get_type::<ParseError>())?;
+    m.add("AddressRangeError", m.py().get_type::<AddressRangeError>())?;
     m.add_function(wrap_pyfunction!(line_hash, m)?)?;
+    m.add_function(wrap_pyfunction!(line_hash_u64, m)?)?;
     m.add_function(wrap_pyfunction!(lnhash, m)?)?;
+    m.add_function(wrap_pyfunction!(file_fingerprint, m)?)?;
     m.add_function(wrap_pyfunction!(lnhashview, m)?)?;
+    m.add_function(wrap_pyfunction!(parse_lnhash, m)?)?;
+    m.add_function(wrap_pyfunction!(format_lnhash, m)?)?;
+    m.add_function(wrap_pyfunction!(lnhashes, m)?)?;
     m.add_function(wrap_pyfunction!(py_exhash, m)?)?;
+    m.add_function(wrap_pyfunction!(py_exhash_script, m)?)?;
+    m.add_function(wrap_pyfunction!(py_exhash_many, m)?)?;
+    m.add_function(wrap_pyfunction!(py_verify_only, m)?)?;
+    m.add_function(wrap_pyfunction!(py_edit_file, m)?)?;
     Ok(())
 }
@@ -0,0 +1,331 @@
+use std::fmt;
+use std::ops::RangeInclusive;
+use std::path::Path;
+
+use tree_sitter::{Node, Parser};
+
+use crate::lnhash::{LnHash, LnHashSpec};
+use crate::EditError;
+
+/// A language whose definitions [`find_symbols`] can find.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SymbolLanguage {
+    Rust,
+    Python,
+}
+
+impl SymbolLanguage {
+    /// The language of a file named `path`, by its extension (`.rs`, `.py` or `.pyi`).
+    pub fn from_path(path: impl AsRef<Path>) -> Option<Self> {
+        match path.as_ref().extension()?.to_str()? {
+            "rs" => Some(Self::Rust),
+            "py" | "pyi" => Some(Self::Python),
+            _ => None,
+        }
+    }
+
+    /// The language called `name`: `rust` or `python`.
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name.to_ascii_lowercase().as_str() {
+            "rust" | "rs" => Some(Self::Rust),
+            "python" | "py" => Some(Self::Python),
+            _ => None,
+        }
+    }
+
+    fn grammar(self) -> tree_sitter::Language {
+        match self {
+            Self::Rust => tree_sitter_rust::LANGUAGE.into(),
+            Self::Python => tree_sitter_python::LANGUAGE.into(),
+        }
+    }
+}
+
+/// What a [`Symbol`] defines, named in an address as `@fn:`, `@class:`, `@impl:` or `@mod:`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SymbolKind {
+    /// A function or method.
+    Fn,
+    /// A Python class, or a Rust struct, enum, union or trait.
+    Class,
+    /// A Rust `impl` block, named by the type it is for.
+    Impl,
+    /// A Rust module with a body.
+    Mod,
+}
+
+impl SymbolKind {
+    /// The kind as addresses name it (`fn`).
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::Fn => "fn",
+            Self::Class => "class",
+            Self::Impl => "impl",
+            Self::Mod => "mod",
+        }
+    }
+
+    fn from_name(name: &str) -> Option<Self> {
+        [Self::Fn, Self::Class, Self::Impl, Self::Mod].into_iter().find(|k| k.name() == name)
+    }
+}
+
+/// A definition found in a text, as [`find_symbols`] lists them.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Symbol {
+    pub kind: SymbolKind,
+    /// The name, after the names of the definitions it is in, such as `Parser.parse` for
+    /// a method in `impl Parser` or `class Parser`.
+    pub path: String,
+    /// The 1-based lines it spans, from its first decorator, attribute or doc comment.
+    pub lines: RangeInclusive<usize>,
+}
+
+/// Symbols print as the address naming them, e.g. `@fn:Parser.parse`.
+impl fmt::Display for Symbol {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "@{}:{}", self.kind.name(), self.path)
+    }
+}
+
+/// Every definition in `text` that an address can name, in order of where they start.
+///
+/// Text that does not parse still gives the definitions tree-sitter could make out around
+/// the errors.
+pub fn find_symbols(text: &str, language: SymbolLanguage) -> Result<Vec<Symbol>, EditError> {
+    let mut parser = Parser::new();
+    parser
+        .set_language(&language.grammar())
+        .map_err(|e| EditError::new(format!("tree-sitter: {e}")))?;
+    let tree =
+        parser.parse(text, None).ok_or_else(|| EditError::new("tree-sitter: parsing failed"))?;
+    let mut symbols = Vec::new();
+    collect(tree.root_node(), text, &mut Vec::new(), &mut symbols);
+    Ok(symbols)
+}
+
+/// Add the definitions in `node` to `symbols`, inside the definitions named by `outer`.
+fn collect(node: Node, text: &str, outer: &mut Vec<String>, symbols: &mut Vec<Symbol>) {
+    let mut cursor = node.walk();
+    for child in node.named_children(&mut cursor) {
+        let Some((kind, name)) = definition(child, text) else {
+            collect(child, text, outer, symbols);
+            continue;
+        };
+        let first = leading_lines(child).start_position().row;
+        let end = child.end_position();
+        // A node ending at the start of a line ends on the line before.
+        let last = if end.column == 0 && end.row > first { end.row - 1 } else { end.row };
+        outer.push(name);
+        symbols.push(Symbol { kind, path: outer.join("."), lines: first + 1..=last + 1 });
+        collect(child, text, outer, symbols);
+        outer.pop();
+    }
+}
+
+/// The kind and name of the definition `node` is, if it is one.
+fn definition(node: Node, text: &str) -> Option<(SymbolKind, String)> {
+    let kind = match node.kind() {
+        "function_item" | "function_signature_item" | "function_definition" => SymbolKind::Fn,
+        "struct_item" | "enum_item" | "union_item" | "trait_item" | "class_definition" => {
+            SymbolKind::Class
+        }
+        "impl_item" => SymbolKind::Impl,
+        "mod_item" if node.child_by_field_name("body").is_some() => SymbolKind::Mod,
+        _ => return None,
+    };
+    let name = match kind {
+        // `impl<T> Trait for a::Type<T>` is named `Type`.
+        SymbolKind::Impl => {
+            let ty = &text[node.child_by_field_name("type")?.byte_range()];
+            let ty = ty.split('<').next().unwrap_or(ty);
+            ty.rsplit("::").next().unwrap_or(ty).trim().to_string()
+        }
+        _ => text[node.child_by_field_name("name")?.byte_range()].to_string(),
+    };
+    Some((kind, name))
+}
+
+/// The first node of the definition `node`: its Python decorators, or the Rust attributes
+/// and doc comments just before it.
+fn leading_lines(node: Node) -> Node {
+    if let Some(parent) = node.parent().filter(|p| p.kind() == "decorated_definition") {
+        return parent;
+    }
+    let mut first = node;
+    while let Some(prev) = first.prev_named_sibling() {
+        let doc = matches!(prev.kind(), "line_comment" | "block_comment")
+            && prev.child_by_field_name("outer").is_some();
+        if prev.kind() != "attribute_item" && !doc {
+            break;
+        }
+        first = prev;
+    }
+    first
+}
+
+/// The first and last lines' addresses under `spec` of the definition `symbol` names in
+/// `text`, such as `@fn:parse_global` or `@fn:Parser.parse`.
+///
+/// A name matches a definition whose [`path`](Symbol::path) ends with it. None matching is
+/// a [`StaleHash`](crate::ErrorKind::StaleHash) error, as for an address whose line has
+/// gone; more than one is a [`Parse`](crate::ErrorKind::Parse) error listing them, to be
+/// told apart by naming what they are in.
+pub fn resolve_symbol(
+    symbol: &str,
+    text: &str,
+    language: SymbolLanguage,
+    spec: &LnHashSpec,
+) -> Result<(LnHash, LnHash), EditError> {
+    let (kind, name) = parse_symbol(symbol)?;
+    let matches: Vec<Symbol> = find_symbols(text, language)?
+        .into_iter()
+        .filter(|s| {
+            s.kind == kind
+                && (s.path == name || s.path.strip_suffix(name).is_some_and(|p| p.ends_with('.')))
+        })
+        .collect();
+    let found = match &matches[..] {
+        [found] => found,
+        [] => return Err(EditError::stale(format!("no {} named {name} found", kind.name()))),
+        _ => {
+            let all: Vec<String> = matches
+                .iter()
+                .map(|s| format!("{s} (lines {}-{})", s.lines.start(), s.lines.end()))
+                .collect();
+            let msg = format!("{symbol} is ambiguous: {}", all.join(", "));
+            return Err(EditError::parse(msg));
+        }
+    };
+    let addrs = spec.lnhashes(text.lines());
+    Ok((addrs[found.lines.start() - 1], addrs[found.lines.end() - 1]))
+}
+
+/// `cmd` with a leading symbol address, as in `@fn:parse_global d`, replaced by the range
+/// of lnhash addresses [`resolve_symbol`] gives for it, so that it parses as any other
+/// command and is verified like one. The name ends at the first whitespace, so one is
+/// needed before the command. A command without a symbol address comes back as it is.
+pub fn resolve_symbol_address(
+    cmd: &str,
+    text: &str,
+    language: SymbolLanguage,
+    spec: &LnHashSpec,
+) -> Result<String, EditError> {
+    let trimmed = cmd.trim_start();
+    if !is_symbol_address(trimmed) {
+        return Ok(cmd.to_string());
+    }
+    let end = trimmed.find(char::is_whitespace).unwrap_or(trimmed.len());
+    let (symbol, rest) = trimmed.split_at(end);
+    let (first, last) = resolve_symbol(symbol, text, language, spec)?;
+    Ok(match first == last {
+        true => format!("{first}{rest}"),
+        false => format!("{first},{last}{rest}"),
+    })
+}
+
+/// Whether `cmd` starts with a symbol address (`@kind:`), rather than an lnhash or a header
+/// such as `@file`.
+pub fn is_symbol_address(cmd: &str) -> bool {
+    let Some(rest) = cmd.strip_prefix('@') else { return false };
+    let word = rest.split(char::is_whitespace).next().unwrap_or_default();
+    word.contains(':')
+}
+
+/// The kind and name of an address such as `@fn:parse_global`.
+fn parse_symbol(symbol: &str) -> Result<(SymbolKind, &str), EditError> {
+    let bad = |why: &str| EditError::parse(format!("invalid symbol address {symbol:?}: {why}"));
+    let (kind, name) =
+        symbol.strip_prefix('@').and_then(|s| s.split_once(':')).ok_or_else(|| bad("no @kind:"))?;
+    let kind =
+        SymbolKind::from_name(kind).ok_or_else(|| bad("the kind is fn, class, impl or mod"))?;
+    if name.is_empty() || name.split('.').any(str::is_empty) {
+        return Err(bad("no name"));
+    }
+    Ok((kind, name))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{edit_text, parse_commands_from_strs, ErrorKind};
+
+    const RUST: &str = "\
+use std::fmt;
+
+/// A parser.
+#[derive(Debug)]
+pub struct Parser {
+    pos: usize,
+}
+
+impl<'a> fmt::Display for crate::Parser<'a> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, \"{}\", self.pos)
+    }
+}
+
+impl Parser {
+    // Not a doc comment.
+    fn new() -> Self {
+        Parser { pos: 0 }
+    }
+}
+
+fn new() {}
+";
+
+    #[test]
+    fn symbols_resolve_to_verified_ranges() {
+        let spec = LnHashSpec::default();
+        let symbols = find_symbols(RUST, SymbolLanguage::Rust).unwrap();
+        let listed: Vec<String> = symbols
+            .iter()
+            .map(|s| format!("{s} {}-{}", s.lines.start(), s.lines.end()))
+            .collect();
+        assert_eq!(
+            listed,
+            [
+                "@class:Parser 3-7",
+                "@impl:Parser 9-13",
+                "@fn:Parser.fmt 10-12",
+                "@impl:Parser 15-20",
+                "@fn:Parser.new 17-19",
+                "@fn:new 22-22",
+            ]
+        );
+
+        let lines: Vec<&str> = RUST.lines().collect();
+        let (first, last) =
+            resolve_symbol("@fn:Parser.new", RUST, SymbolLanguage::Rust, &spec).unwrap();
+        assert_eq!((first, last), (spec.lnhash(17, lines[16]), spec.lnhash(19, lines[18])));
+        let cmd = "@fn:Parser.fmt s/self.pos/self.pos + 1/";
+        let cmd = resolve_symbol_address(cmd, RUST, SymbolLanguage::Rust, &spec).unwrap();
+        assert!(cmd.starts_with(&format!("{},", spec.lnhash(10, lines[9]))), "{cmd}");
+        let cmds = parse_commands_from_strs(&[&cmd]).unwrap();
+        assert!(edit_text(RUST, &cmds).unwrap().text().contains("self.pos + 1)"));
+        let same = "12|abcd|d";
+        assert_eq!(resolve_symbol_address(same, RUST, SymbolLanguage::Rust, &spec).unwrap(), same);
+
+        let resolve = |s: &str| resolve_symbol(s, RUST, SymbolLanguage::Rust, &spec).unwrap_err();
+        let err = resolve("@fn:new");
+        assert_eq!(err.kind(), ErrorKind::Parse);
+        assert!(err.message().contains("@fn:Parser.new (lines 17-19), @fn:new (lines 22-22)"));
+        assert_eq!(resolve("@fn:parse").kind(), ErrorKind::StaleHash);
+        assert!(resolve("@var:x").message().contains("the kind is fn, class, impl or mod"));
+    }
+
+    #[test]
+    fn python_symbols_include_decorators() {
+        let text = "import x\n\n@dataclass\nclass A:\n    @property\n    def f(self):\n        \
+                    return 1\n\n    def g(self):\n        pass\n";
+        let symbols = find_symbols(text, SymbolLanguage::Python).unwrap();
+        let listed: Vec<String> = symbols
+            .iter()
+            .map(|s| format!("{s} {}-{}", s.lines.start(), s.lines.end()))
+            .collect();
+        assert_eq!(listed, ["@class:A 3-10", "@fn:A.f 5-7", "@fn:A.g 9-10"]);
+        assert_eq!(SymbolLanguage::from_path("src/a.pyi"), Some(SymbolLanguage::Python));
+        assert_eq!(SymbolLanguage::from_name("Rust"), Some(SymbolLanguage::Rust));
+    }
+}
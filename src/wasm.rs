@@ -0,0 +1,112 @@
+use wasm_bindgen::prelude::*;
+
+fn js_error(err: crate::EditError) -> JsError {
+    JsError::new(&err.to_string())
+}
+
+/// The outcome of `editText`.
+#[wasm_bindgen(js_name = EditResult)]
+pub struct EditResultJs {
+    lines: Vec<String>,
+    hashes: Vec<String>,
+    modified: Vec<usize>,
+    deleted: Vec<usize>,
+    text: String,
+}
+
+#[wasm_bindgen(js_class = EditResult)]
+impl EditResultJs {
+    /// The output lines.
+    #[wasm_bindgen(getter)]
+    pub fn lines(&self) -> Vec<String> {
+        self.lines.clone()
+    }
+
+    /// The lnhash of each output line.
+    #[wasm_bindgen(getter)]
+    pub fn hashes(&self) -> Vec<String> {
+        self.hashes.clone()
+    }
+
+    /// 1-based line numbers of modified and added lines.
+    #[wasm_bindgen(getter)]
+    pub fn modified(&self) -> Vec<usize> {
+        self.modified.clone()
+    }
+
+    /// 1-based line numbers of removed lines, in the original text.
+    #[wasm_bindgen(getter)]
+    pub fn deleted(&self) -> Vec<usize> {
+        self.deleted.clone()
+    }
+
+    /// The output text, with the input's line endings.
+    #[wasm_bindgen(getter)]
+    pub fn text(&self) -> String {
+        self.text.clone()
+    }
+}
+
+/// A parsed command, from `parseCommands`.
+#[wasm_bindgen(js_name = Command)]
+pub struct CommandJs(crate::Command);
+
+#[wasm_bindgen(js_class = Command)]
+impl CommandJs {
+    /// The first (or only) address, as written.
+    #[wasm_bindgen(getter)]
+    pub fn addr(&self) -> String {
+        self.0.addr1.to_string()
+    }
+
+    /// The end of the range, if the command has one.
+    #[wasm_bindgen(getter)]
+    pub fn end(&self) -> Option<String> {
+        self.0.addr2.map(|a| a.to_string())
+    }
+
+    /// The operation without its addresses, e.g. `s/foo/bar/g`.
+    #[wasm_bindgen(getter)]
+    pub fn op(&self) -> String {
+        self.0.cmd.to_string()
+    }
+
+    /// The command in script form, which parses back to the same command.
+    #[wasm_bindgen(js_name = toString)]
+    pub fn script(&self) -> String {
+        self.0.to_string()
+    }
+}
+
+/// Apply `commands` to `text`, as the Python `exhash()` does: each string is one command,
+/// with the text block of an `a`, `i` or `c` on the lines after it, and a first command of
+/// `@file <fingerprint>` checks the whole text before any address.
+#[wasm_bindgen(js_name = editText)]
+pub fn edit_text(text: &str, commands: Vec<String>) -> Result<EditResultJs, JsError> {
+    let cmd_refs: Vec<&str> = commands.iter().map(|s| s.as_str()).collect();
+    let (parsed, fingerprint) = crate::parse_commands_with_header(&cmd_refs).map_err(js_error)?;
+    let opts = crate::EditOptions { fingerprint, ..Default::default() };
+    let res = crate::edit_text_with(text, &parsed, &opts).map_err(js_error)?;
+    Ok(EditResultJs {
+        text: res.text(),
+        lines: res.lines,
+        hashes: res.hashes,
+        modified: res.modified,
+        deleted: res.deleted,
+    })
+}
+
+/// The lines of `text` as `lineno|hash|  content`, the addresses `editText` takes.
+#[wasm_bindgen(js_name = lnhashView)]
+pub fn lnhash_view(text: &str) -> Vec<String> {
+    crate::LnHashSpec::default().view_lines(text, crate::AddrStyle::Standard)
+}
+
+/// Parse command strings as `editText` takes them, without applying them, to check their
+/// syntax or inspect their addresses.
+#[wasm_bindgen(js_name = parseCommands)]
+pub fn parse_commands(commands: Vec<String>) -> Result<Vec<CommandJs>, JsError> {
+    let cmd_refs: Vec<&str> = commands.iter().map(|s| s.as_str()).collect();
+    let parsed = crate::parse_commands_from_strs(&cmd_refs).map_err(js_error)?;
+    Ok(parsed.into_iter().map(CommandJs).collect())
+}
@@ -35,7 +35,7 @@ fn lnhashview_basic_and_range() {
     let out = Command::new(bin).arg(&file).output().unwrap();
     assert!(out.status.success());
     let stdout = String::from_utf8(out.stdout).unwrap();
-    let expected_lines = vec![
+    let expected_lines = [
         format!("{}  alpha", format_lnhash(1, "alpha")),
         format!("{}  beta", format_lnhash(2, "beta")),
         format!("{}  ", format_lnhash(3, "")),
@@ -53,7 +53,7 @@ fn lnhashview_basic_and_range() {
         .unwrap();
     assert!(out.status.success());
     let stdout = String::from_utf8(out.stdout).unwrap();
-    let expected = vec![
+    let expected = [
         format!("{}  beta", format_lnhash(2, "beta")),
         format!("{}  ", format_lnhash(3, "")),
     ]
@@ -62,6 +62,330 @@ fn lnhashview_basic_and_range() {
     assert_eq!(stdout, expected);
 }
 
+#[test]
+fn lnhashview_colors_addresses_and_matches() {
+    let dir = mk_temp_dir("lnhashview_color");
+    let file = dir.join("f.txt");
+    write_file(&file, "a foo\n");
+    let bin = env!("CARGO_BIN_EXE_lnhashview");
+
+    let args = ["--color=always", "--grep", "fo+"];
+    let out = Command::new(bin).args(args).arg(&file).output().unwrap();
+    let addr = format_lnhash(1, "a foo");
+    let hash = addr.trim_end_matches('|').rsplit('|').next().unwrap();
+    let expected = format!("\x1b[32m1\x1b[0m|\x1b[33m{hash}\x1b[0m|  a \x1b[1;31mfoo\x1b[0m\n");
+    assert_eq!(String::from_utf8(out.stdout).unwrap(), expected);
+
+    // Not a terminal: no color unless asked for.
+    let out = Command::new(bin).arg(&file).output().unwrap();
+    assert!(!out.stdout.contains(&0x1b));
+}
+
+#[test]
+fn lnhashview_around_shows_context_for_a_line_or_address() {
+    let dir = mk_temp_dir("lnhashview_around");
+    let file = dir.join("f.txt");
+    write_file(&file, "new\na\nb\nc\nd\ne\n");
+    let bin = env!("CARGO_BIN_EXE_lnhashview");
+    let line = |n: usize, text: &str| format!("{}  {text}\n", format_lnhash(n, text));
+
+    let out = Command::new(bin).args(["--around", "2", "-C", "1"]).arg(&file).output().unwrap();
+    let expected = line(1, "new") + &line(2, "a") + &line(3, "b");
+    assert_eq!(String::from_utf8(out.stdout).unwrap(), expected);
+
+    // "c" was line 3 before "new" was inserted; its old address still finds it.
+    let old = format_lnhash(3, "c");
+    let out = Command::new(bin).args(["--around", &old, "-C", "0"]).arg(&file).output().unwrap();
+    assert_eq!(String::from_utf8(out.stdout).unwrap(), line(4, "c"));
+
+    let missing = format_lnhash(3, "zzz");
+    let out = Command::new(bin).args(["--around", &missing]).arg(&file).output().unwrap();
+    assert_eq!(out.status.code(), Some(2));
+
+    // A line number outside the file is an error, not an empty or clipped view.
+    write_file(&file, "a\nb\nc\nd\ne\n");
+    for args in [&["--around", "0"][..], &["--around", "6", "-C", "1"], &["--around", "99"]] {
+        let out = Command::new(bin).args(args).arg(&file).output().unwrap();
+        assert_eq!(out.status.code(), Some(2), "{args:?}");
+        assert!(out.stdout.is_empty(), "{args:?}");
+        let stderr = String::from_utf8(out.stderr).unwrap();
+        let n = args[1];
+        let expected = format!("line {n} is beyond EOF (file has 5 line(s))");
+        assert!(stderr.contains(&expected), "{stderr}");
+    }
+}
+
+#[test]
+fn lnhashview_diff_lines_up_files_by_hash() {
+    let dir = mk_temp_dir("lnhashview_diff");
+    let (old, new) = (dir.join("old.txt"), dir.join("new.txt"));
+    write_file(&old, "a\nb\nc\nd\ne\n");
+    write_file(&new, "new\na\nc\nd\nb\n");
+    let bin = env!("CARGO_BIN_EXE_lnhashview");
+
+    let out = Command::new(bin).arg("--diff").args([&old, &new]).output().unwrap();
+    assert_eq!(out.status.code(), Some(1));
+    let expected = format!(
+        "--- {}\n+++ {}\n+ {}  new\n~ {} -> {}  b\n- {}  e\n\
+         1 removed, 1 added, 1 moved, 3 unchanged (1 renumbered)\n",
+        old.display(),
+        new.display(),
+        format_lnhash(1, "new"),
+        format_lnhash(2, "b"),
+        format_lnhash(5, "b"),
+        format_lnhash(5, "e")
+    );
+    assert_eq!(String::from_utf8(out.stdout).unwrap(), expected);
+
+    let out = Command::new(bin).arg("--diff").args([&old, &old]).output().unwrap();
+    assert!(out.status.success());
+}
+
+#[test]
+fn lnhashview_html_renders_views_and_diffs() {
+    let dir = mk_temp_dir("lnhashview_html");
+    let (old, new) = (dir.join("old.txt"), dir.join("new.txt"));
+    write_file(&old, "a\n<b>\nc\nd\n");
+    write_file(&new, "a\n<B>\nc\nd\n");
+    let bin = env!("CARGO_BIN_EXE_lnhashview");
+    let addr =
+        |lineno, line| format!("<td class=\"exhash-addr\">{}</td>", format_lnhash(lineno, line));
+
+    let out = Command::new(bin).args(["--html", "--grep", "b"]).arg(&old).output().unwrap();
+    assert!(out.status.success());
+    let page = String::from_utf8(out.stdout).unwrap();
+    assert!(page.starts_with("<!DOCTYPE html>") && page.ends_with("</html>\n"), "{page}");
+    let text = "<td class=\"exhash-text\">&lt;b&gt;";
+    let row = format!("<tr class=\"exhash-hl\">{}{text}", addr(2, "<b>"));
+    assert!(page.contains(&row), "{page}");
+    assert_eq!(page.matches("<tr").count(), 1);
+
+    let out = Command::new(bin).args(["--html", "--diff", "-C", "0"]).args([&old, &new]).output();
+    let out = out.unwrap();
+    assert_eq!(out.status.code(), Some(1));
+    let page = String::from_utf8(out.stdout).unwrap();
+    assert!(page.contains(&format!("{}<td class=\"exhash-addr\"></td>", addr(2, "<b>"))));
+    assert!(page.contains(&format!("<td class=\"exhash-addr\"></td>{}", addr(2, "<B>"))));
+    assert_eq!(page.matches("<tr").count(), 2);
+
+    let out = Command::new(bin).args(["--html", "--plain"]).arg(&old).output().unwrap();
+    assert_eq!(out.status.code(), Some(2));
+}
+
+#[test]
+fn lnhashview_format_template() {
+    let dir = mk_temp_dir("lnhashview_format");
+    let file = dir.join("f.txt");
+    write_file(&file, "a\n{b}\n");
+    let bin = env!("CARGO_BIN_EXE_lnhashview");
+
+    let template = "{lineno}:{hash}\\t{text} {{{addr}}}";
+    let out = Command::new(bin).args(["--format", template]).arg(&file).arg("2").output().unwrap();
+    assert!(out.status.success());
+    let addr = format_lnhash(2, "{b}");
+    let hash = addr.trim_end_matches('|').rsplit('|').next().unwrap();
+    assert_eq!(String::from_utf8(out.stdout).unwrap(), format!("2:{hash}\t{{b}} {{{addr}}}\n"));
+
+    let out = Command::new(bin).args(["--format", "{line}"]).arg(&file).output().unwrap();
+    assert_eq!(out.status.code(), Some(2));
+
+    write_file(&file, "\t  h\u{e9}llo\n");
+    let out = Command::new(bin).arg("--lengths").arg(&file).output().unwrap();
+    let addr = format_lnhash(1, "\t  h\u{e9}llo");
+    let expected = format!("{addr}  8c 9b 15w 3i  \t  h\u{e9}llo\n");
+    assert_eq!(String::from_utf8(out.stdout).unwrap(), expected);
+    // A decomposed é is still one character and one column, and CJK characters take two.
+    write_file(&file, "h\u{65}\u{301}llo\n\u{65e5}\u{672c}\n");
+    let template = "{chars} {bytes} {width} {indent}";
+    let out = Command::new(bin).args(["--format", template]).arg(&file).output().unwrap();
+    assert_eq!(String::from_utf8(out.stdout).unwrap(), "5 7 5 0\n2 6 4 0\n");
+}
+
+#[test]
+fn lnhashview_hashes_only_skipping_blank_lines() {
+    let dir = mk_temp_dir("lnhashview_hashes_only");
+    let file = dir.join("f.txt");
+    write_file(&file, "a\n\n  \nb\n");
+    let bin = env!("CARGO_BIN_EXE_lnhashview");
+
+    let out = Command::new(bin).arg("--hashes-only").arg(&file).arg("1").arg("2").output().unwrap();
+    let expected = format!("{}\n{}\n", format_lnhash(1, "a"), format_lnhash(2, ""));
+    assert_eq!(String::from_utf8(out.stdout).unwrap(), expected);
+
+    let skipping = ["--hashes-only", "--skip-blank"];
+    let out = Command::new(bin).args(skipping).arg(&file).output().unwrap();
+    let expected = format!("{}\n{}\n", format_lnhash(1, "a"), format_lnhash(4, "b"));
+    assert_eq!(String::from_utf8(out.stdout).unwrap(), expected);
+}
+
+#[test]
+fn lnhashview_shows_whitespace_like_cat() {
+    let dir = mk_temp_dir("lnhashview_show");
+    let file = dir.join("f.txt");
+    let text = "a\tb  \nx\x1by\u{a0}\n";
+    write_file(&file, text);
+    let bin = env!("CARGO_BIN_EXE_lnhashview");
+    let view = |flag: &str| {
+        let out = Command::new(bin).arg(flag).arg(&file).output().unwrap();
+        assert!(out.status.success());
+        String::from_utf8(out.stdout).unwrap()
+    };
+    // The hashes are those of the lines as written.
+    let (a1, a2) = (format_lnhash(1, "a\tb  "), format_lnhash(2, "x\x1by\u{a0}"));
+    assert_eq!(view("-A"), format!("{a1}  a^Ib  $\n{a2}  x^[y<U+00A0>$\n"));
+    assert_eq!(view("--show-tabs"), format!("{a1}  a^Ib  \n{a2}  x\x1by\u{a0}\n"));
+    assert_eq!(view("--show-ends"), format!("{a1}  a\tb  $\n{a2}  x\x1by\u{a0}$\n"));
+}
+
+#[test]
+fn lnhashview_negative_ranges_count_from_the_end() {
+    let dir = mk_temp_dir("lnhashview_tail");
+    let file = dir.join("f.txt");
+    write_file(&file, "a\nb\nc\nd\n");
+    let bin = env!("CARGO_BIN_EXE_lnhashview");
+    let view = |range: &[&str]| {
+        let out = Command::new(bin).arg(&file).args(range).output().unwrap();
+        assert!(out.status.success(), "{}", String::from_utf8_lossy(&out.stderr));
+        let stdout = String::from_utf8(out.stdout).unwrap();
+        stdout.lines().map(|l| l.split("  ").nth(1).unwrap().to_string()).collect::<Vec<_>>()
+    };
+    assert_eq!(view(&["-2"]), ["c", "d"]);
+    assert_eq!(view(&["-10"]), ["a", "b", "c", "d"]);
+    assert_eq!(view(&["2", "-2"]), ["b", "c"]);
+    assert_eq!(view(&["-3", "-3"]), ["b"]);
+
+    // Several ranges print in turn, each after a "--" line; a number left over is one line.
+    let line = |n: usize, text: &str| format!("{}  {text}\n", format_lnhash(n, text));
+    let out = Command::new(bin).arg(&file).args(["3", "4", "1", "1", "-1"]).output().unwrap();
+    let expected = [line(3, "c"), line(4, "d"), "--\n".into(), line(1, "a"), "--\n".into()].concat()
+        + &line(4, "d");
+    assert_eq!(String::from_utf8(out.stdout).unwrap(), expected);
+}
+
+#[test]
+fn lnhashview_grep_shows_matches_with_context() {
+    let dir = mk_temp_dir("lnhashview_grep");
+    let file = dir.join("f.txt");
+    write_file(&file, "a\nfoo\nb\nc\nd\nfood\n");
+    let bin = env!("CARGO_BIN_EXE_lnhashview");
+    let line = |n: usize, text: &str| format!("{}  {text}\n", format_lnhash(n, text));
+
+    let out = Command::new(bin).args(["--grep", "^foo"]).arg(&file).output().unwrap();
+    assert!(out.status.success());
+    let expected = line(2, "foo") + "--\n" + &line(6, "food");
+    assert_eq!(String::from_utf8(out.stdout).unwrap(), expected);
+
+    let out = Command::new(bin).args(["--grep", "foo", "-C", "1"]).arg(&file).output().unwrap();
+    let expected = [line(1, "a"), line(2, "foo"), line(3, "b"), "--\n".to_string()].concat()
+        + &line(5, "d")
+        + &line(6, "food");
+    assert_eq!(String::from_utf8(out.stdout).unwrap(), expected);
+
+    let in_range = ["3", "5"];
+    let out = Command::new(bin).args(["--grep", "foo"]).arg(&file).args(in_range).output().unwrap();
+    assert_eq!(out.status.code(), Some(1));
+    assert!(out.stdout.is_empty());
+
+    let plain = ["--grep", "foo", "-C", "1", "--plain=nohash"];
+    let out = Command::new(bin).args(plain).arg(&file).args(["1", "3"]).output().unwrap();
+    let path = file.display();
+    let expected = format!("{path}-1-a\n{path}:2:foo\n{path}-3-b\n");
+    assert_eq!(String::from_utf8(out.stdout).unwrap(), expected);
+    let out = Command::new(bin).args(["--plain", "--grep", "food"]).arg(&file).output().unwrap();
+    let hash = format_lnhash(6, "food").trim_end_matches('|')[2..].to_string();
+    assert_eq!(String::from_utf8(out.stdout).unwrap(), format!("{path}:6:{hash}:food\n"));
+
+    let capped = ["--grep", "o", "--max-lines", "1"];
+    let out = Command::new(bin).args(capped).arg(&file).output().unwrap();
+    let notice = "… 1 more line (use start_line/end_line to view, from line 6)\n";
+    assert_eq!(String::from_utf8(out.stdout).unwrap(), line(2, "foo") + notice);
+}
+
+#[cfg(feature = "glob")]
+#[test]
+fn lnhashview_glob_scans_a_tree() {
+    let dir = mk_temp_dir("lnhashview_glob");
+    fs::create_dir_all(dir.join("src/sub")).unwrap();
+    fs::create_dir_all(dir.join("target")).unwrap();
+    write_file(&dir.join(".gitignore"), "target/\n");
+    write_file(&dir.join("src/main.rs"), "fn main() {}\n// TODO: one\n");
+    write_file(&dir.join("src/sub/lib.rs"), "// TODO: two\n");
+    write_file(&dir.join("src/notes.txt"), "TODO: not rust\n");
+    write_file(&dir.join("src/blob.rs"), "TODO\0\n");
+    write_file(&dir.join("target/gen.rs"), "// TODO: ignored\n");
+    let bin = env!("CARGO_BIN_EXE_lnhashview");
+    let line =
+        |file: &str, n: usize, text: &str| format!("{file}:{}  {text}\n", format_lnhash(n, text));
+
+    let view = |args: &[&str]| Command::new(bin).current_dir(&dir).args(args).output().unwrap();
+    let out = view(&["--glob", "src/**/*.rs", "--grep", "TODO"]);
+    assert!(out.status.success());
+    let expected = line("src/main.rs", 2, "// TODO: one") + "--\n"
+        + &line("src/sub/lib.rs", 1, "// TODO: two");
+    assert_eq!(String::from_utf8(out.stdout).unwrap(), expected);
+
+    let out = view(&["--glob", "**/*.rs", "--grep", "TODO", "--format", "{file} {lineno}"]);
+    assert_eq!(String::from_utf8(out.stdout).unwrap(), "src/main.rs 2\n--\nsrc/sub/lib.rs 1\n");
+
+    let out = view(&["--glob", "*.rs", "--grep", "TODO"]);
+    assert_eq!(out.status.code(), Some(1));
+}
+
+#[test]
+fn lnhashview_watch_redraws_when_the_file_changes() {
+    let dir = mk_temp_dir("lnhashview_watch");
+    let file = dir.join("f.txt");
+    write_file(&file, "one\n");
+    let bin = env!("CARGO_BIN_EXE_lnhashview");
+    let mut child =
+        Command::new(bin).arg("--watch").arg(&file).stdout(Stdio::piped()).spawn().unwrap();
+    let mut stdout = child.stdout.take().unwrap();
+    let (tx, rx) = std::sync::mpsc::channel();
+    std::thread::spawn(move || {
+        let mut buf = [0; 256];
+        while let Ok(n @ 1..) = std::io::Read::read(&mut stdout, &mut buf) {
+            let _ = tx.send(String::from_utf8_lossy(&buf[..n]).into_owned());
+        }
+    });
+    let mut seen = String::new();
+    let mut wait_for = |text: &str| {
+        while !seen.contains(text) {
+            match rx.recv_timeout(std::time::Duration::from_secs(10)) {
+                Ok(chunk) => seen.push_str(&chunk),
+                Err(_) => panic!("no {text:?} in {seen:?}"),
+            }
+        }
+    };
+    wait_for(&format!("{}  one\n", format_lnhash(1, "one")));
+    // Replaced in one step, so that there is a single change to redraw for.
+    write_file(&dir.join("f.tmp"), "zero\none\n");
+    fs::rename(dir.join("f.tmp"), &file).unwrap();
+    let zero = format!("{}  zero\n", format_lnhash(1, "zero"));
+    wait_for(&format!("\x1b[H\x1b[2J{zero}{}  one\n", format_lnhash(2, "one")));
+    child.kill().unwrap();
+    child.wait().unwrap();
+}
+
+#[test]
+fn lnhashview_reads_stdin() {
+    let bin = env!("CARGO_BIN_EXE_lnhashview");
+    let view = |args: &[&str]| {
+        let mut child = Command::new(bin)
+            .args(args)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .spawn()
+            .unwrap();
+        child.stdin.take().unwrap().write_all(b"alpha\nbeta\n").unwrap();
+        let out = child.wait_with_output().unwrap();
+        assert!(out.status.success());
+        String::from_utf8(out.stdout).unwrap()
+    };
+    let beta = format!("{}  beta\n", format_lnhash(2, "beta"));
+    assert_eq!(view(&["-", "2"]), beta);
+    assert_eq!(view(&[]), format!("{}  alpha\n{beta}", format_lnhash(1, "alpha")));
+}
+
 #[test]
 fn exhash_inplace_substitute_and_stdout_modified_only() {
     let dir = mk_temp_dir("exhash_subst");
@@ -236,3 +560,1390 @@ fn exhash_stdin_mode_edits_and_prints_full_file() {
     );
     assert_eq!(stdout, expected);
 }
+
+#[test]
+fn exhash_bottom_up_uses_original_addresses() {
+    let dir = mk_temp_dir("exhash_bottom_up");
+    let file = dir.join("f.txt");
+    write_file(&file, "a\nb\nc\n");
+
+    let cmd1 = format!("{}d", format_lnhash(1, "a"));
+    let cmd2 = format!("{}s/c/C/", format_lnhash(3, "c"));
+
+    let bin = env!("CARGO_BIN_EXE_exhash");
+    let out = Command::new(bin)
+        .arg("--bottom-up")
+        .arg(&file)
+        .arg(cmd1)
+        .arg(cmd2)
+        .output()
+        .unwrap();
+    assert!(out.status.success());
+    assert_eq!(read_file(&file), "b\nC\n");
+}
+
+#[test]
+fn lnhashview_emit_cmd_feeds_exhash() {
+    let dir = mk_temp_dir("lnhashview_emit_cmd");
+    let file = dir.join("f.txt");
+    write_file(&file, "foo\nbar\nboo\n");
+    let view = env!("CARGO_BIN_EXE_lnhashview");
+
+    let emit = ["--grep", "o", "-C", "1", "--emit-cmd", "s/o/0/g"];
+    let out = Command::new(view).args(emit).arg(&file).output().unwrap();
+    let cmds = String::from_utf8(out.stdout).unwrap();
+    let (foo, boo) = (format_lnhash(1, "foo"), format_lnhash(3, "boo"));
+    assert_eq!(cmds, format!("{foo}s/o/0/g\n{boo}s/o/0/g\n"));
+    let out = Command::new(env!("CARGO_BIN_EXE_exhash")).arg(&file).args(cmds.lines()).output();
+    assert!(out.unwrap().status.success());
+    assert_eq!(read_file(&file), "f00\nbar\nb00\n");
+
+    let out = Command::new(view).args(["--emit-cmd", "q"]).arg(&file).output().unwrap();
+    assert_eq!(out.status.code(), Some(2));
+}
+
+#[test]
+fn lnhashview_hash_width() {
+    let dir = mk_temp_dir("lnhashview_width");
+    let file = dir.join("f.txt");
+    write_file(&file, "}\n");
+
+    let bin = env!("CARGO_BIN_EXE_lnhashview");
+    let out = Command::new(bin)
+        .arg("--hash-width")
+        .arg("8")
+        .arg(&file)
+        .output()
+        .unwrap();
+    assert!(out.status.success());
+    let stdout = String::from_utf8(out.stdout).unwrap();
+    assert_eq!(stdout, format!("{}  }}\n", exhash::format_lnhash_width(1, "}", 8)));
+}
+
+#[test]
+fn compact_addresses_roundtrip_through_exhash() {
+    let dir = mk_temp_dir("compact");
+    let file = dir.join("f.txt");
+    let text: String = (1..=12).map(|i| format!("line{i}\n")).collect();
+    write_file(&file, &text);
+
+    let out = Command::new(env!("CARGO_BIN_EXE_lnhashview"))
+        .arg("--compact")
+        .arg(&file)
+        .arg("12")
+        .output()
+        .unwrap();
+    assert!(out.status.success());
+    let stdout = String::from_utf8(out.stdout).unwrap();
+    let addr = stdout.split_whitespace().next().unwrap();
+    assert!(addr.starts_with("c:"), "{stdout}");
+
+    let out = Command::new(env!("CARGO_BIN_EXE_exhash"))
+        .args(["--json", "--compact", "--dry-run"])
+        .arg(&file)
+        .arg(format!("{addr}s/line/LINE/"))
+        .output()
+        .unwrap();
+    assert!(out.status.success(), "{}", String::from_utf8_lossy(&out.stderr));
+    let stdout = String::from_utf8(out.stdout).unwrap();
+    assert!(stdout.contains(&format!(r#""commands":[{{"addr":"{addr}""#)), "{stdout}");
+
+    let out = Command::new(env!("CARGO_BIN_EXE_exhash"))
+        .arg(&file)
+        .arg(format!("{addr}s/line/LINE/"))
+        .output()
+        .unwrap();
+    assert!(out.status.success(), "{}", String::from_utf8_lossy(&out.stderr));
+    assert!(read_file(&file).ends_with("LINE12\n"));
+}
+
+#[test]
+fn seed_from_env_or_flag_namespaces_addresses() {
+    let dir = mk_temp_dir("seed");
+    let file = dir.join("f.txt");
+    write_file(&file, "a\nb\n");
+
+    let out = Command::new(env!("CARGO_BIN_EXE_lnhashview"))
+        .env("EXHASH_SEED", "proj")
+        .arg(&file)
+        .arg("2")
+        .output()
+        .unwrap();
+    assert!(out.status.success());
+    let stdout = String::from_utf8(out.stdout).unwrap();
+    let addr = stdout.split_whitespace().next().unwrap().to_string();
+    assert_eq!(addr, exhash::LnHashSpec::seeded("proj").lnhash(2, "b").to_string());
+
+    let bin = env!("CARGO_BIN_EXE_exhash");
+    let out = Command::new(bin)
+        .env_remove("EXHASH_SEED")
+        .arg(&file)
+        .arg(format!("{addr}d"))
+        .output()
+        .unwrap();
+    assert!(!out.status.success());
+    assert!(String::from_utf8_lossy(&out.stderr).contains("no seed is set"));
+
+    let out = Command::new(bin)
+        .env_remove("EXHASH_SEED")
+        .arg("--seed")
+        .arg("proj")
+        .arg(&file)
+        .arg(format!("{addr}d"))
+        .output()
+        .unwrap();
+    assert!(out.status.success(), "{}", String::from_utf8_lossy(&out.stderr));
+    assert_eq!(read_file(&file), "a\n");
+}
+
+#[test]
+fn lnhashview_collisions_lists_duplicates() {
+    let dir = mk_temp_dir("lnhashview_collisions");
+    let file = dir.join("f.txt");
+    write_file(&file, "}\na\n}\n");
+
+    let bin = env!("CARGO_BIN_EXE_lnhashview");
+    let out = Command::new(bin).arg("--collisions").arg(&file).output().unwrap();
+    assert!(out.status.success());
+    let stdout = String::from_utf8(out.stdout).unwrap();
+    let expected = format!(
+        "duplicate {:04x}:\n  {}  }}\n  {}  }}\n0 collision group(s), 1 duplicate group(s)\n",
+        exhash::line_hash_u16("}"),
+        format_lnhash(1, "}"),
+        format_lnhash(3, "}")
+    );
+    assert_eq!(stdout, expected);
+
+    write_file(&file, "}\n\nlonger\n}\n");
+    let out = Command::new(bin).arg("--stats").arg(&file).output().unwrap();
+    let expected = "lines         4\nblank lines   1\nlongest line  6 characters (line 3)\n\
+                    duplicates    1 group(s), 2 lines\n\
+                    collisions    0 group(s), 0 lines (4-digit hashes)\n";
+    assert_eq!(String::from_utf8(out.stdout).unwrap(), expected);
+}
+
+#[test]
+fn exhash_file_fingerprint_header_rejects_wrong_file() {
+    let dir = mk_temp_dir("exhash_fingerprint");
+    let file = dir.join("f.txt");
+    write_file(&file, "a\nb\n");
+
+    let header = format!("@file {}", exhash::file_fingerprint("a\nc\n"));
+    let cmd = format!("{}d", format_lnhash(1, "a"));
+
+    let bin = env!("CARGO_BIN_EXE_exhash");
+    let out = Command::new(bin).arg(&file).arg(header).arg(cmd).output().unwrap();
+    assert!(!out.status.success());
+    assert!(String::from_utf8(out.stderr).unwrap().contains("fingerprint mismatch"));
+    assert_eq!(read_file(&file), "a\nb\n");
+}
+
+#[test]
+fn exhash_script_file_with_inline_text_blocks() {
+    let dir = mk_temp_dir("exhash_script");
+    let file = dir.join("f.txt");
+    write_file(&file, "a\nb\n");
+    let script = dir.join("edits.ex");
+    let header = format!("@file {}", exhash::file_fingerprint("a\nb\n"));
+    let (a1, a2) = (format_lnhash(1, "a"), format_lnhash(2, "b"));
+    write_file(&script, &format!("{header}\n{a2}s/b/B/\n{a1}a\nx\n.\n"));
+
+    let bin = env!("CARGO_BIN_EXE_exhash");
+    let out = Command::new(bin).arg("-f").arg(&script).arg(&file).output().unwrap();
+    assert!(out.status.success(), "{}", String::from_utf8_lossy(&out.stderr));
+    assert_eq!(read_file(&file), "a\nx\nB\n");
+
+    // The script can come from stdin, but not alongside command arguments.
+    let mut child = Command::new(bin)
+        .args(["--dry-run", "--script", "-"])
+        .arg(&file)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .spawn()
+        .unwrap();
+    let cmd = format!("{}d\n", format_lnhash(2, "x"));
+    child.stdin.as_mut().unwrap().write_all(cmd.as_bytes()).unwrap();
+    assert!(child.wait_with_output().unwrap().status.success());
+
+    let out = Command::new(bin).arg("-f").arg(&script).arg(&file).arg("1|0000|d").output().unwrap();
+    assert!(!out.status.success());
+    assert_eq!(read_file(&file), "a\nx\nB\n");
+}
+
+#[test]
+fn exhash_json_reports_result_and_failing_command() {
+    let dir = mk_temp_dir("exhash_json");
+    let file = dir.join("f.txt");
+    write_file(&file, "a\nb\n");
+    let bin = env!("CARGO_BIN_EXE_exhash");
+
+    let cmd = format!("{}s/b/\"B\"/", format_lnhash(2, "b"));
+    let out = Command::new(bin).arg("--json").arg(&file).arg(&cmd).output().unwrap();
+    assert!(out.status.success());
+    let expected = format!(
+        "{{\"ok\":true,\"dry_run\":false,\"modified\":[2],\"deleted\":[],\"deleted_lines\":[],\
+         \"lines\":[{{\"lineno\":2,\"hash\":\"{}\",\"text\":\"\\\"B\\\"\"}}],\
+         \"commands\":[{{\"addr\":\"{}\",\"status\":\"ok\"}}]}}\n",
+        format_lnhash(2, "\"B\""),
+        format_lnhash(2, "b")
+    );
+    assert_eq!(String::from_utf8(out.stdout).unwrap(), expected);
+
+    let del = format!("{}d", format_lnhash(1, "a"));
+    let dry_run = ["--json", "--dry-run"];
+    let out = Command::new(bin).args(dry_run).arg(&file).arg(&del).output().unwrap();
+    let stdout = String::from_utf8(out.stdout).unwrap();
+    let addr = format_lnhash(1, "a");
+    let deleted =
+        format!("\"deleted_lines\":[{{\"lineno\":1,\"hash\":\"{addr}\",\"text\":\"a\"}}]");
+    assert!(stdout.contains(&deleted), "{stdout}");
+
+    let out = Command::new(bin)
+        .args(["--json", "--bottom-up"])
+        .arg(&file)
+        .arg(&del)
+        .arg("2|0000|d")
+        .output()
+        .unwrap();
+    assert_eq!(out.status.code(), Some(3));
+    let stdout = String::from_utf8(out.stdout).unwrap();
+    assert!(stdout.starts_with("{\"ok\":false,\"error\":{\"message\":\"stale lnhash"), "{stdout}");
+    assert!(stdout.contains("\"command\":1,\"text\":\"2|0000|d\"},"), "{stdout}");
+    assert!(stdout.contains("\"status\":\"skipped\"},{\"addr\":\"2|0000|\",\"status\":\"failed\""));
+    assert_eq!(read_file(&file), "a\n\"B\"\n");
+}
+
+#[test]
+fn exhash_diff_prints_unified_diff() {
+    let dir = mk_temp_dir("exhash_diff");
+    let file = dir.join("f.txt");
+    write_file(&file, "a\nb\nc\n");
+    let cmd = format!("{}s/b/B/", format_lnhash(2, "b"));
+
+    let bin = env!("CARGO_BIN_EXE_exhash");
+    let out = Command::new(bin)
+        .args(["--diff", "--dry-run"])
+        .arg(&file)
+        .arg(&cmd)
+        .output()
+        .unwrap();
+    assert!(out.status.success());
+    let name = file.display();
+    let expected = format!("--- {name}\n+++ {name}\n@@ -1,3 +1,3 @@\n a\n-b\n+B\n c\n");
+    assert_eq!(String::from_utf8(out.stdout).unwrap(), expected);
+    assert_eq!(read_file(&file), "a\nb\nc\n");
+
+    let out = Command::new(bin).arg("--diff").arg(&file).arg(&cmd).output().unwrap();
+    assert_eq!(String::from_utf8(out.stdout).unwrap(), expected);
+    assert_eq!(read_file(&file), "a\nB\nc\n");
+}
+
+#[test]
+fn exhash_quickfix_lists_changed_locations() {
+    let dir = mk_temp_dir("exhash_quickfix");
+    let file = dir.join("f.txt");
+    write_file(&file, "a\nb\nc\nd\n");
+    let del = format!("{}d", format_lnhash(2, "b"));
+    let sub = format!("{}s/d/D/", format_lnhash(3, "d"));
+
+    let bin = env!("CARGO_BIN_EXE_exhash");
+    let out = Command::new(bin).arg("--quickfix").arg(&file).args([&del, &sub]).output().unwrap();
+    assert!(out.status.success());
+    let name = file.display();
+    assert_eq!(
+        String::from_utf8(out.stdout).unwrap(),
+        format!("{name}:2: deleted line 2: b\n{name}:3: modified: D\n")
+    );
+    assert_eq!(read_file(&file), "a\nc\nD\n");
+
+    let out = Command::new(bin).args(["--quickfix", "--json"]).arg(&file).output().unwrap();
+    assert_eq!(out.status.code(), Some(2));
+}
+
+#[test]
+fn exhash_convert_turns_a_sed_script_into_a_verified_one() {
+    let dir = mk_temp_dir("exhash_convert");
+    let file = dir.join("f.txt");
+    write_file(&file, "one\n\ntwo\nthree\n");
+    let sed = dir.join("fix.sed");
+    write_file(&sed, "/^$/d\ns/t\\(w\\)o/T\\1O/\n$a\\\nend\n");
+
+    let bin = env!("CARGO_BIN_EXE_exhash");
+    let out = Command::new(bin).arg("convert").arg(&sed).arg(&file).output().unwrap();
+    assert!(out.status.success(), "{}", String::from_utf8_lossy(&out.stderr));
+    let script = String::from_utf8(out.stdout).unwrap();
+    let lines: Vec<&str> = script.lines().collect();
+    assert!(lines[0].starts_with("@file "), "{script}");
+    assert_eq!(lines[1..], [
+        format!("{}d", format_lnhash(2, "")),
+        format!("{}s/t(w)o/T${{1}}O/", format_lnhash(2, "two")),
+        format!("{}a\\end", format_lnhash(3, "three")),
+    ]);
+    let ex = dir.join("fix.ex");
+    write_file(&ex, &script);
+    let out = Command::new(bin).args(["-q", "-f"]).arg(&ex).arg(&file).output().unwrap();
+    assert!(out.status.success());
+    assert_eq!(read_file(&file), "one\nTwO\nthree\nend\n");
+    // The converted script is pinned to the file it was made for.
+    let out = Command::new(bin).args(["-q", "-f"]).arg(&ex).arg(&file).output().unwrap();
+    assert_eq!(out.status.code(), Some(3));
+
+    write_file(&sed, "1h\n");
+    let out = Command::new(bin).arg("convert").arg(&sed).arg(&file).output().unwrap();
+    assert_eq!(out.status.code(), Some(2));
+    assert!(String::from_utf8_lossy(&out.stderr).contains("command 'h' has no exhash equivalent"));
+}
+
+#[cfg(feature = "treesitter")]
+#[test]
+fn exhash_resolves_symbol_addresses() {
+    let dir = mk_temp_dir("exhash_symbols");
+    let file = dir.join("lib.rs");
+    write_file(&file, "/// Old.\nfn old() {\n    1\n}\n\nfn keep() {}\n");
+    let bin = env!("CARGO_BIN_EXE_exhash");
+    let run = |args: &[&str]| Command::new(bin).arg(&file).args(args).output().unwrap();
+
+    let out = run(&["@fn:keep s/keep/kept/", "@fn:old d"]);
+    assert!(out.status.success(), "{}", String::from_utf8_lossy(&out.stderr));
+    assert_eq!(read_file(&file), "\nfn kept() {}\n");
+    // A definition that is no longer there is stale, as an address would be.
+    let out = run(&["@fn:old d"]);
+    assert_eq!(out.status.code(), Some(3));
+    assert!(String::from_utf8_lossy(&out.stderr).contains("no fn named old found"));
+
+    let out = Command::new(bin)
+        .args(["--stdin", "--lang", "python", "-", "@class:A c\\pass"])
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .spawn()
+        .and_then(|mut child| {
+            child.stdin.take().unwrap().write_all(b"class A:\n    x = 1\n")?;
+            child.wait_with_output()
+        })
+        .unwrap();
+    assert!(out.status.success(), "{}", String::from_utf8_lossy(&out.stderr));
+    assert!(String::from_utf8_lossy(&out.stdout).contains("pass"));
+    let out = Command::new(bin).args(["--stdin", "-", "@fn:f d"]).output().unwrap();
+    assert_eq!(out.status.code(), Some(2));
+}
+
+#[test]
+fn exhash_backup_keeps_original_unless_dry_run() {
+    let dir = mk_temp_dir("exhash_backup");
+    let file = dir.join("f.txt");
+    let bak = dir.join("f.txt.bak");
+    write_file(&file, "a\nb\n");
+    let cmd = format!("{}d", format_lnhash(1, "a"));
+
+    let bin = env!("CARGO_BIN_EXE_exhash");
+    let out = Command::new(bin)
+        .args(["--dry-run", "--backup", ".bak"])
+        .arg(&file)
+        .arg(&cmd)
+        .output()
+        .unwrap();
+    assert!(out.status.success());
+    assert!(!bak.exists());
+
+    let out = Command::new(bin).args(["--backup", ".bak"]).arg(&file).arg(&cmd).output().unwrap();
+    assert!(out.status.success());
+    assert_eq!(read_file(&file), "b\n");
+    assert_eq!(read_file(&bak), "a\nb\n");
+}
+
+#[test]
+fn exhash_verify_only_lists_stale_addresses_without_writing() {
+    let dir = mk_temp_dir("exhash_verify_only");
+    let file = dir.join("f.txt");
+    write_file(&file, "a\nb\n");
+    let good = format!("{}d", format_lnhash(1, "a"));
+
+    let bin = env!("CARGO_BIN_EXE_exhash");
+    let out = Command::new(bin).arg("--verify-only").arg(&file).arg(&good).output().unwrap();
+    assert!(out.status.success());
+    assert!(out.stdout.is_empty());
+    assert_eq!(read_file(&file), "a\nb\n");
+
+    let stale = format!("{}d", format_lnhash(1, "x"));
+    let out = Command::new(bin)
+        .arg("--verify-only")
+        .arg(&file)
+        .arg(&stale)
+        .arg(&good)
+        .output()
+        .unwrap();
+    assert_eq!(out.status.code(), Some(3));
+    let stdout = String::from_utf8(out.stdout).unwrap();
+    assert!(stdout.starts_with("command 1 ("), "{stdout}");
+    assert!(stdout.contains("stale lnhash at line 1"), "{stdout}");
+    assert_eq!(stdout.lines().count(), 1);
+    assert_eq!(read_file(&file), "a\nb\n");
+}
+
+#[test]
+fn exhash_repl_applies_commands_and_writes_on_w() {
+    let dir = mk_temp_dir("exhash_repl");
+    let file = dir.join("f.txt");
+    write_file(&file, "a\nb\n");
+    let session = format!(
+        "{}a\nx\n.\n1|0000|d\n:q\n:w\n{}s/b/B/\n:q!\n",
+        format_lnhash(1, "a"),
+        format_lnhash(3, "b")
+    );
+
+    let bin = env!("CARGO_BIN_EXE_exhash");
+    let mut child = Command::new(bin)
+        .arg("--repl")
+        .arg(&file)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .unwrap();
+    child.stdin.as_mut().unwrap().write_all(session.as_bytes()).unwrap();
+    let out = child.wait_with_output().unwrap();
+    assert!(out.status.success());
+
+    let stdout = String::from_utf8(out.stdout).unwrap();
+    let expected = format!(
+        "{}  a\n{}  b\n{}  x\n{}  B\n",
+        format_lnhash(1, "a"),
+        format_lnhash(2, "b"),
+        format_lnhash(2, "x"),
+        format_lnhash(3, "B")
+    );
+    assert_eq!(stdout, expected);
+    let stderr = String::from_utf8(out.stderr).unwrap();
+    assert!(stderr.contains("stale lnhash at line 1"), "{stderr}");
+    assert!(stderr.contains("unsaved changes"), "{stderr}");
+    // Only the state at :w reached the file; the later substitution was discarded.
+    assert_eq!(read_file(&file), "a\nx\nb\n");
+
+    // A dry run could still write on :w, so the two are refused together.
+    let out = Command::new(bin).args(["--dry-run", "--repl"]).arg(&file).output().unwrap();
+    assert_eq!(out.status.code(), Some(2));
+    assert!(String::from_utf8_lossy(&out.stderr).contains("--dry-run"));
+    assert_eq!(read_file(&file), "a\nx\nb\n");
+}
+
+#[test]
+fn exhash_context_prints_marked_surrounding_lines() {
+    let dir = mk_temp_dir("exhash_context");
+    let file = dir.join("f.txt");
+    write_file(&file, "1\n2\n3\n4\n5\n6\n7\n");
+    let c2 = format!("{}s/2/two/", format_lnhash(2, "2"));
+    let c7 = format!("{}s/7/seven/", format_lnhash(7, "7"));
+
+    let bin = env!("CARGO_BIN_EXE_exhash");
+    let out = Command::new(bin)
+        .args(["--context", "1"])
+        .arg(&file)
+        .arg(&c2)
+        .arg(&c7)
+        .output()
+        .unwrap();
+    assert!(out.status.success());
+    let expected = format!(
+        "  {}  1\n> {}  two\n  {}  3\n--\n  {}  6\n> {}  seven\n",
+        format_lnhash(1, "1"),
+        format_lnhash(2, "two"),
+        format_lnhash(3, "3"),
+        format_lnhash(6, "6"),
+        format_lnhash(7, "seven")
+    );
+    assert_eq!(String::from_utf8(out.stdout).unwrap(), expected);
+}
+
+#[test]
+fn exhash_stdin_mode_takes_text_blocks_from_flags() {
+    let bin = env!("CARGO_BIN_EXE_exhash");
+    let cmd = format!("{}a", format_lnhash(1, "foo"));
+    let mut child = Command::new(bin)
+        .args(["--stdin", "--text-block", "x\ny"])
+        .arg("-")
+        .arg(&cmd)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .spawn()
+        .unwrap();
+    child.stdin.as_mut().unwrap().write_all(b"foo\n").unwrap();
+    let out = child.wait_with_output().unwrap();
+    assert!(out.status.success());
+    let expected = format!(
+        "{}  foo\n{}  x\n{}  y\n",
+        format_lnhash(1, "foo"),
+        format_lnhash(2, "x"),
+        format_lnhash(3, "y")
+    );
+    assert_eq!(String::from_utf8(out.stdout).unwrap(), expected);
+}
+
+#[test]
+fn exhash_stdin_mode_accepts_inline_text() {
+    let bin = env!("CARGO_BIN_EXE_exhash");
+    let cmd = format!("{}i\\x\\ny", format_lnhash(1, "foo"));
+    let mut child = Command::new(bin)
+        .args(["--stdin", "-", &cmd])
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .spawn()
+        .unwrap();
+    child.stdin.as_mut().unwrap().write_all(b"foo\n").unwrap();
+    let out = child.wait_with_output().unwrap();
+    assert!(out.status.success());
+    let expected = format!(
+        "{}  x\n{}  y\n{}  foo\n",
+        format_lnhash(1, "x"),
+        format_lnhash(2, "y"),
+        format_lnhash(3, "foo")
+    );
+    assert_eq!(String::from_utf8(out.stdout).unwrap(), expected);
+}
+
+#[test]
+fn exhash_exit_codes_distinguish_failure_classes() {
+    let dir = mk_temp_dir("exhash_exit_codes");
+    let file = dir.join("f.txt");
+    write_file(&file, "a\nb\n");
+    let bin = env!("CARGO_BIN_EXE_exhash");
+    let code = |cmd: &str| Command::new(bin).arg(&file).arg(cmd).output().unwrap().status.code();
+
+    assert_eq!(code(&format!("{}d", format_lnhash(1, "x"))), Some(3));
+    assert_eq!(code(&format!("{}d", format_lnhash(9, "a"))), Some(4));
+    assert_eq!(code("1|abcd|frobnicate"), Some(2));
+    let missing = Command::new(bin).arg(dir.join("nope.txt")).arg("1|abcd|d").output().unwrap();
+    assert_eq!(missing.status.code(), Some(1));
+    assert_eq!(read_file(&file), "a\nb\n");
+
+    // The failing command is named by number and text.
+    let good = format!("{}s/a/A/", format_lnhash(1, "a"));
+    let stale = format!("{}s/b/B/", format_lnhash(2, "x"));
+    let out = Command::new(bin).arg(&file).args([&good, &stale]).output().unwrap();
+    let stderr = String::from_utf8(out.stderr).unwrap();
+    assert!(stderr.starts_with(&format!("error: command 2 ({stale}): stale lnhash")), "{stderr}");
+}
+
+#[test]
+fn exhash_quiet_and_verbose_output_levels() {
+    let dir = mk_temp_dir("exhash_verbosity");
+    let file = dir.join("f.txt");
+    write_file(&file, "a\nb\n");
+    let bin = env!("CARGO_BIN_EXE_exhash");
+
+    let cmd = format!("{}s/a/A/", format_lnhash(1, "a"));
+    let quiet = ["--quiet", "--dry-run"];
+    let out = Command::new(bin).args(quiet).arg(&file).arg(&cmd).output().unwrap();
+    assert!(out.status.success());
+    assert!(out.stdout.is_empty() && out.stderr.is_empty());
+
+    let del = format!("{}d", format_lnhash(2, "b"));
+    let out = Command::new(bin).arg("-v").arg(&file).arg(&cmd).arg(&del).output().unwrap();
+    assert!(out.status.success());
+    let stderr = String::from_utf8(out.stderr).unwrap();
+    let expected = format!(
+        "command 1 ({}): 1 line(s) changed, 0 deleted, 2 line(s) now\n  1: A\n\
+         command 2 ({}): 0 line(s) changed, 1 deleted, 1 line(s) now\n",
+        format_lnhash(1, "a"),
+        format_lnhash(2, "b")
+    );
+    assert_eq!(stderr, expected);
+    let expected = format!("{}  A\n-{}  b\n", format_lnhash(1, "A"), format_lnhash(2, "b"));
+    assert_eq!(String::from_utf8(out.stdout).unwrap(), expected);
+}
+
+#[cfg(unix)]
+#[test]
+fn exhash_write_keeps_mode_and_leaves_no_temp_files() {
+    use std::os::unix::fs::PermissionsExt;
+
+    let dir = mk_temp_dir("write_atomic");
+    let file = dir.join("f.txt");
+    write_file(&file, "a\nb\n");
+    fs::set_permissions(&file, fs::Permissions::from_mode(0o640)).unwrap();
+
+    let cmd = format!("{}s/a/A/", format_lnhash(1, "a"));
+    let out = Command::new(env!("CARGO_BIN_EXE_exhash")).arg(&file).arg(&cmd).output().unwrap();
+    assert!(out.status.success());
+    assert_eq!(read_file(&file), "A\nb\n");
+    assert_eq!(fs::metadata(&file).unwrap().permissions().mode() & 0o7777, 0o640);
+    let names: Vec<_> = fs::read_dir(&dir).unwrap().map(|e| e.unwrap().file_name()).collect();
+    assert_eq!(names, ["f.txt"]);
+}
+
+#[cfg(feature = "encoding")]
+#[test]
+fn exhash_encoding_reads_and_writes_legacy_files() {
+    let dir = mk_temp_dir("encoding");
+    let file = dir.join("f.txt");
+    // "café\nold\n" in windows-1252.
+    fs::write(&file, b"caf\xe9\nold\n").unwrap();
+    let bin = env!("CARGO_BIN_EXE_exhash");
+
+    let cmd = format!("{}s/old/né/", format_lnhash(2, "old"));
+    let out = Command::new(bin).arg(&file).arg(&cmd).output().unwrap();
+    assert_eq!(out.status.code(), Some(1));
+    assert!(String::from_utf8(out.stderr).unwrap().contains("non-UTF8"));
+
+    let run = |label: &str, cmd: &str| {
+        Command::new(bin).args(["--encoding", label]).arg(&file).arg(cmd).output().unwrap()
+    };
+    for label in ["latin1", "auto"] {
+        fs::write(&file, b"caf\xe9\nold\n").unwrap();
+        let out = run(label, &cmd);
+        assert!(out.status.success(), "{label}");
+        let expected = format!("{}  né\n", format_lnhash(2, "né"));
+        assert_eq!(String::from_utf8(out.stdout).unwrap(), expected);
+        assert_eq!(fs::read(&file).unwrap(), b"caf\xe9\nn\xe9\n");
+    }
+
+    let cmd = format!("{}s/né/\u{3042}/", format_lnhash(2, "né"));
+    assert_eq!(run("latin1", &cmd).status.code(), Some(2));
+    assert_eq!(fs::read(&file).unwrap(), b"caf\xe9\nn\xe9\n");
+}
+
+#[test]
+fn exhash_keeps_crlf_line_endings() {
+    let dir = mk_temp_dir("crlf");
+    let file = dir.join("f.txt");
+    write_file(&file, "a\r\nb\r\nc\n");
+    let cmd = format!("{}c\\x\\ny", format_lnhash(2, "b"));
+    let out = Command::new(env!("CARGO_BIN_EXE_exhash")).arg(&file).arg(&cmd).output().unwrap();
+    assert!(out.status.success(), "{}", String::from_utf8_lossy(&out.stderr));
+    assert_eq!(read_file(&file), "a\r\nx\r\ny\r\nc\n");
+}
+
+#[test]
+fn exhash_fuzz_reanchors_drifted_address_with_a_note() {
+    let dir = mk_temp_dir("fuzz");
+    let file = dir.join("f.txt");
+    write_file(&file, "new\na\nb\n");
+    let bin = env!("CARGO_BIN_EXE_exhash");
+
+    // Taken before "new" was inserted above.
+    let cmd = format!("{}s/b/B/", format_lnhash(2, "b"));
+    let out = Command::new(bin).arg(&file).arg(&cmd).output().unwrap();
+    assert_eq!(out.status.code(), Some(3));
+
+    let out = Command::new(bin).args(["--fuzz", "2"]).arg(&file).arg(&cmd).output().unwrap();
+    assert!(out.status.success());
+    assert_eq!(read_file(&file), "new\na\nB\n");
+    let stderr = String::from_utf8(out.stderr).unwrap();
+    let addr = format_lnhash(2, "b");
+    assert_eq!(stderr, format!("note: command 1 ({addr}): {addr} is stale, used line 3 instead\n"));
+}
+
+#[test]
+fn exhash_suggest_prints_corrected_addresses() {
+    let dir = mk_temp_dir("suggest");
+    let file = dir.join("f.txt");
+    write_file(&file, "new\na\nb\n");
+    let bin = env!("CARGO_BIN_EXE_exhash");
+
+    let cmd = format!("{}s/b/B/", format_lnhash(2, "b"));
+    let out = Command::new(bin).arg("--suggest").arg(&file).arg(&cmd).output().unwrap();
+    assert_eq!(out.status.code(), Some(3));
+    assert_eq!(read_file(&file), "new\na\nb\n");
+    let stderr = String::from_utf8(out.stderr).unwrap();
+    let (stale, now, moved) = (format_lnhash(2, "b"), format_lnhash(2, "a"), format_lnhash(3, "b"));
+    let hints = format!(
+        "hint: {stale} is stale; line 2 is now:\n  {now}  a\nhint: {stale} matches nearby:\n  \
+         {moved}  b\n"
+    );
+    assert!(stderr.starts_with("error: command 1 ") && stderr.ends_with(&hints), "{stderr}");
+
+    let args = ["--suggest", "--json"];
+    let out = Command::new(bin).args(args).arg(&file).arg(&cmd).output().unwrap();
+    let stdout = String::from_utf8(out.stdout).unwrap();
+    let matched = format!("\"matches\":[{{\"lineno\":3,\"hash\":\"{moved}\",\"text\":\"b\"}}]");
+    assert!(stdout.contains(&matched), "{stdout}");
+}
+
+#[test]
+fn exhash_keep_going_applies_what_it_can_and_exits_5() {
+    let dir = mk_temp_dir("keep_going");
+    let file = dir.join("f.txt");
+    write_file(&file, "a\nb\n");
+    let bin = env!("CARGO_BIN_EXE_exhash");
+
+    let stale = format!("{}d", format_lnhash(1, "x"));
+    let good = format!("{}s/b/B/", format_lnhash(2, "b"));
+    let out =
+        Command::new(bin).arg("--keep-going").arg(&file).args([&stale, &good]).output().unwrap();
+    assert_eq!(out.status.code(), Some(5));
+    assert_eq!(read_file(&file), "a\nB\n");
+    let expected = format!("{}  B\n", format_lnhash(2, "B"));
+    assert_eq!(String::from_utf8(out.stdout).unwrap(), expected);
+    let stderr = String::from_utf8(out.stderr).unwrap();
+    assert!(stderr.starts_with(&format!("skipped: command 1 ({stale}): stale")));
+
+    let out = Command::new(bin)
+        .args(["--keep-going", "--json", "--dry-run"])
+        .arg(&file)
+        .arg(&stale)
+        .output()
+        .unwrap();
+    assert_eq!(out.status.code(), Some(5));
+    let stdout = String::from_utf8(out.stdout).unwrap();
+    assert!(stdout.starts_with("{\"ok\":false,"), "{stdout}");
+    assert!(stdout.contains("\"status\":\"skipped\""), "{stdout}");
+    assert!(stdout.contains("\"skipped\":[{\"message\":"), "{stdout}");
+    let skipped = format!("\"kind\":\"stale_hash\",\"command\":0,\"text\":\"{stale}\"}}]");
+    assert!(stdout.contains(&skipped), "{stdout}");
+}
+
+#[test]
+fn exhash_strict_substitute_fails_when_nothing_matches() {
+    let dir = mk_temp_dir("strict_substitute");
+    let file = dir.join("f.txt");
+    write_file(&file, "a\nb\n");
+    let bin = env!("CARGO_BIN_EXE_exhash");
+
+    let miss = format!("{}s/x/y/", format_lnhash(1, "a"));
+    let out = Command::new(bin).arg(&file).arg(&miss).output().unwrap();
+    assert_eq!(out.status.code(), Some(0));
+    let out = Command::new(bin).arg("--strict-substitute").arg(&file).arg(&miss).output().unwrap();
+    assert_eq!(out.status.code(), Some(2));
+    let stderr = String::from_utf8(out.stderr).unwrap();
+    assert!(stderr.contains("substitute pattern \"x\" matched nothing"), "{stderr}");
+    assert_eq!(read_file(&file), "a\nb\n");
+}
+
+#[test]
+fn exhash_print_all_shows_whole_file_after_writing() {
+    let dir = mk_temp_dir("print_all");
+    let file = dir.join("f.txt");
+    write_file(&file, "a\nb\nc\n");
+
+    let cmd = format!("{}d", format_lnhash(2, "b"));
+    let out = Command::new(env!("CARGO_BIN_EXE_exhash"))
+        .arg("--print-all")
+        .arg(&file)
+        .arg(&cmd)
+        .output()
+        .unwrap();
+    assert!(out.status.success());
+    assert_eq!(read_file(&file), "a\nc\n");
+    let expected = format!(
+        "{}  a\n{}  c\n-{}  b\n",
+        format_lnhash(1, "a"),
+        format_lnhash(2, "c"),
+        format_lnhash(2, "b")
+    );
+    assert_eq!(String::from_utf8(out.stdout).unwrap(), expected);
+}
+
+#[test]
+fn exhash_summary_counts_changes() {
+    let dir = mk_temp_dir("summary");
+    let file = dir.join("f.txt");
+    write_file(&file, "a a\nb\n");
+    let bin = env!("CARGO_BIN_EXE_exhash");
+
+    let subst = format!("{}s/a/x/g", format_lnhash(1, "a a"));
+    let del = format!("{}d", format_lnhash(2, "b"));
+    let args = ["--summary", "-v", "--dry-run"];
+    let out = Command::new(bin).args(args).arg(&file).args([&subst, &del]).output().unwrap();
+    assert!(out.status.success());
+    assert_eq!(
+        String::from_utf8(out.stdout).unwrap(),
+        format!(
+            "{}  x x\n-{}  b\n\
+             command 1 ({}): 0 added, 1 changed, 0 deleted, 2 substitution(s)\n\
+             command 2 ({}): 0 added, 0 changed, 1 deleted, 0 substitution(s)\n\
+             summary: 0 added, 1 changed, 1 deleted, 2 substitution(s)\n",
+            format_lnhash(1, "x x"),
+            format_lnhash(2, "b"),
+            format_lnhash(1, "a a"),
+            format_lnhash(2, "b")
+        )
+    );
+
+    let out = Command::new(bin).args(["--summary", "-q"]).arg(&file).arg(&del).output().unwrap();
+    let expected = "summary: 0 added, 0 changed, 1 deleted, 0 substitution(s)\n";
+    assert_eq!(String::from_utf8(out.stdout).unwrap(), expected);
+
+    let args = ["--summary", "--json", "--dry-run"];
+    let out = Command::new(bin).args(args).arg(&file).arg(&subst).output().unwrap();
+    let stdout = String::from_utf8(out.stdout).unwrap();
+    let expected = "\"summary\":{\"added\":0,\"changed\":1,\"deleted\":0,\"substitutions\":2}}";
+    assert!(stdout.trim_end().ends_with(expected), "{stdout}");
+}
+
+#[test]
+fn exhash_color_highlights_hashes_and_diff() {
+    let dir = mk_temp_dir("color");
+    let file = dir.join("f.txt");
+    write_file(&file, "a\n");
+    let bin = env!("CARGO_BIN_EXE_exhash");
+    let cmd = format!("{}s/a/b/", format_lnhash(1, "a"));
+
+    let out = Command::new(bin).args(["--color=always", "--dry-run"]).arg(&file).arg(&cmd).output();
+    let expected = format!("\x1b[33m{}\x1b[0m  b\n", format_lnhash(1, "b"));
+    assert_eq!(String::from_utf8(out.unwrap().stdout).unwrap(), expected);
+
+    let args = ["--color", "always", "--diff", "--dry-run"];
+    let out = Command::new(bin).args(args).arg(&file).arg(&cmd).output().unwrap();
+    let stdout = String::from_utf8(out.stdout).unwrap();
+    assert!(stdout.contains("\x1b[36m@@ -1 +1 @@\x1b[0m\n\x1b[31m-a\x1b[0m\n\x1b[32m+b\x1b[0m\n"));
+
+    // Not a terminal, so auto means no color.
+    let out = Command::new(bin).args(["--color=auto", "--dry-run"]).arg(&file).arg(&cmd).output();
+    assert!(!String::from_utf8(out.unwrap().stdout).unwrap().contains('\x1b'));
+    let out = Command::new(bin).args(["--color=sometimes"]).arg(&file).arg(&cmd).output();
+    assert_eq!(out.unwrap().status.code(), Some(2));
+}
+
+#[test]
+fn exhash_require_clean_and_git_add() {
+    let dir = mk_temp_dir("git");
+    let git = |args: &[&str]| {
+        let out = Command::new("git")
+            .arg("-C")
+            .arg(&dir)
+            .args(["-c", "user.name=t", "-c", "user.email=t@t"])
+            .args(args)
+            .output()
+            .unwrap();
+        assert!(out.status.success(), "git {args:?}: {}", String::from_utf8_lossy(&out.stderr));
+        String::from_utf8(out.stdout).unwrap()
+    };
+    git(&["init", "-q"]);
+    let file = dir.join("f.txt");
+    write_file(&file, "a\nb\n");
+    let blame = |range: &[&str]| {
+        let view = env!("CARGO_BIN_EXE_lnhashview");
+        Command::new(view).arg("--blame").arg(&file).args(range).output().unwrap()
+    };
+    assert_eq!(blame(&[]).status.code(), Some(1), "f.txt is not tracked yet");
+    let bin = env!("CARGO_BIN_EXE_exhash");
+    let edit = |args: &[&str], cmd: &str| {
+        Command::new(bin).args(args).arg(&file).arg(cmd).output().unwrap()
+    };
+
+    let cmd = format!("{}s/a/A/", format_lnhash(1, "a"));
+    let out = edit(&["--require-clean"], &cmd);
+    assert_eq!(out.status.code(), Some(2));
+    assert!(String::from_utf8(out.stderr).unwrap().contains("not tracked"));
+
+    git(&["add", "f.txt"]);
+    git(&["commit", "-q", "-m", "init"]);
+    let commit = git(&["rev-parse", "--short=8", "HEAD"]);
+    let expected = format!("{}  {} t  a\n", format_lnhash(1, "a"), commit.trim());
+    assert_eq!(String::from_utf8(blame(&["1"]).stdout).unwrap(), expected);
+    assert!(edit(&["--require-clean", "--git-add"], &cmd).status.success());
+    assert_eq!(git(&["status", "--porcelain"]), "M  f.txt\n");
+
+    // Now the file has uncommitted (staged) changes.
+    let cmd = format!("{}s/b/B/", format_lnhash(2, "b"));
+    assert_eq!(edit(&["--require-clean"], &cmd).status.code(), Some(2));
+    assert_eq!(read_file(&file), "A\nb\n");
+    assert!(edit(&["--require-clean", "--force"], &cmd).status.success());
+    assert_eq!(read_file(&file), "A\nB\n");
+}
+
+#[test]
+fn exhash_resource_limits() {
+    let dir = mk_temp_dir("limits");
+    let file = dir.join("f.txt");
+    write_file(&file, "a\nb\n");
+    let bin = env!("CARGO_BIN_EXE_exhash");
+    let run = |args: &[&str], cmds: &[&str]| {
+        let out = Command::new(bin).args(args).arg(&file).args(cmds).output().unwrap();
+        (out.status.code(), String::from_utf8(out.stderr).unwrap())
+    };
+    let del = format!("{}d", format_lnhash(1, "a"));
+    let app = format!("{}a", format_lnhash(2, "b"));
+    let block = ["--text-block", "xyz"];
+
+    let (code, err) = run(&["--max-file-size", "3", "--dry-run"], &[&del]);
+    assert_eq!(code, Some(2));
+    assert!(err.contains("over --max-file-size 3"), "{err}");
+    assert_eq!(run(&["--max-file-size", "1K", "--dry-run"], &[&del]).0, Some(0));
+
+    let (code, err) = run(&["--max-commands", "1", "--dry-run"], &[&del, &del]);
+    assert_eq!(code, Some(2));
+    assert!(err.contains("2 commands, over max-commands 1"), "{err}");
+
+    let args = [&block[..], &["--max-block-size", "3", "--dry-run"]].concat();
+    let (code, err) = run(&args, &[&app]);
+    assert_eq!(code, Some(2));
+    assert!(err.contains("text block is 4 bytes"), "{err}");
+    let args = [&block[..], &["--max-block-size", "4", "--dry-run"]].concat();
+    assert_eq!(run(&args, &[&app]).0, Some(0));
+    assert_eq!(read_file(&file), "a\nb\n");
+}
+
+#[test]
+fn exhash_expect_refuses_to_write_unexpected_result() {
+    let dir = mk_temp_dir("expect");
+    let file = dir.join("f.txt");
+    write_file(&file, "a\nb\n");
+    let bin = env!("CARGO_BIN_EXE_exhash");
+    let cmd = format!("{}s/a/A/", format_lnhash(1, "a"));
+
+    let wrong = format_lnhash(1, "AA");
+    let out = Command::new(bin).args(["--expect", &wrong]).arg(&file).arg(&cmd).output().unwrap();
+    assert_eq!(out.status.code(), Some(6));
+    assert!(String::from_utf8(out.stderr).unwrap().contains(&format!("expected {wrong}")));
+    assert_eq!(read_file(&file), "a\nb\n");
+
+    let fp = format!("@file {}", exhash::file_fingerprint("A\nb\n"));
+    let right = format_lnhash(1, "A");
+    let args = ["--expect", &right, "--expect", &fp];
+    let out = Command::new(bin).args(args).arg(&file).arg(&cmd).output().unwrap();
+    assert!(out.status.success());
+    assert_eq!(read_file(&file), "A\nb\n");
+}
+
+#[cfg(unix)]
+#[test]
+fn exhash_edit_applies_changes_made_in_the_editor() {
+    let dir = mk_temp_dir("exhash_edit");
+    let file = dir.join("f.txt");
+    write_file(&file, "a\nb\nc\nd\n");
+    let bin = env!("CARGO_BIN_EXE_exhash");
+    // The "editor" copies a prepared view over the one exhash hands it.
+    let edited = dir.join("edited.txt");
+    let view = format!(
+        "top\n{}  a\nB\n{}  c\n{}  new\n",
+        format_lnhash(1, "a"),
+        format_lnhash(3, "c"),
+        format_lnhash(1, "a")
+    );
+    write_file(&edited, &view);
+    let run = |editor: &str| {
+        Command::new(bin)
+            .env_remove("VISUAL")
+            .env("EDITOR", editor)
+            .args(["--edit", file.to_str().unwrap()])
+            .output()
+            .unwrap()
+    };
+
+    let out = run(&format!("cp {}", edited.display()));
+    assert!(out.status.success(), "{}", String::from_utf8_lossy(&out.stderr));
+    assert_eq!(read_file(&file), "top\na\nB\nc\nnew\n");
+
+    let out = run("true");
+    assert!(out.status.success());
+    assert!(String::from_utf8_lossy(&out.stderr).contains("no changes"));
+    let out = run("false");
+    assert_eq!(out.status.code(), Some(1));
+    assert_eq!(read_file(&file), "top\na\nB\nc\nnew\n");
+}
+
+#[test]
+fn exhash_multi_file_script_changes_all_files_or_none() {
+    let dir = mk_temp_dir("exhash_multi_file");
+    let (x, y) = (dir.join("x.txt"), dir.join("y.txt"));
+    write_file(&x, "a\nb\n");
+    write_file(&y, "c\nd\n");
+    let bin = env!("CARGO_BIN_EXE_exhash");
+    let script = dir.join("edits.ex");
+    let run = |second: &str| {
+        let text = format!(
+            "@path {}\n{}s/a/A/\n@path {}\n{second}\nnew\n.\n",
+            x.display(),
+            format_lnhash(1, "a"),
+            y.display()
+        );
+        write_file(&script, &text);
+        Command::new(bin).arg("-f").arg(&script).output().unwrap()
+    };
+
+    // The second file's address is stale, so neither file is written.
+    let out = run(&format!("{}a", format_lnhash(2, "x")));
+    assert_eq!(out.status.code(), Some(3));
+    assert!(String::from_utf8_lossy(&out.stderr).contains("y.txt"));
+    assert_eq!((read_file(&x), read_file(&y)), ("a\nb\n".into(), "c\nd\n".into()));
+
+    // With --json the failure is the structured error object, naming the file.
+    let out = Command::new(bin).arg("--json").arg("-f").arg(&script).output().unwrap();
+    assert_eq!(out.status.code(), Some(3));
+    let stdout = String::from_utf8(out.stdout).unwrap();
+    assert!(stdout.starts_with(&format!("{{\"file\":\"{}\",\"ok\":false,", y.display())));
+    assert!(stdout.contains(r#""kind":"stale_hash","command":0"#), "{stdout}");
+    assert!(stdout.contains(r#""status":"failed"}]"#), "{stdout}");
+
+    let out = run(&format!("{}a", format_lnhash(2, "d")));
+    assert!(out.status.success(), "{}", String::from_utf8_lossy(&out.stderr));
+    assert_eq!((read_file(&x), read_file(&y)), ("A\nb\n".into(), "c\nd\nnew\n".into()));
+    let stdout = String::from_utf8(out.stdout).unwrap();
+    assert!(stdout.contains(&format!("==> {} <==\n{}  A\n", x.display(), format_lnhash(1, "A"))));
+}
+
+#[test]
+fn exhash_patch_applies_a_unified_diff_to_every_file_or_none() {
+    let dir = mk_temp_dir("exhash_patch");
+    write_file(&dir.join("x.txt"), "a\nb\nc\n");
+    write_file(&dir.join("y.txt"), "d\ne\n");
+    let patch = dir.join("fix.diff");
+    write_file(
+        &patch,
+        "diff --git a/x.txt b/x.txt\n--- a/x.txt\n+++ b/x.txt\n@@ -1,3 +1,3 @@\n a\n-b\n+B\n c\n\
+         diff --git a/y.txt b/y.txt\n--- a/y.txt\n+++ b/y.txt\n@@ -2 +2,2 @@\n e\n+f\n",
+    );
+    let run = |args: &[&str]| {
+        Command::new(env!("CARGO_BIN_EXE_exhash")).current_dir(&dir).args(args).output().unwrap()
+    };
+    let files = || (read_file(&dir.join("x.txt")), read_file(&dir.join("y.txt")));
+
+    // y.txt no longer matches the patch's context, so neither file is written.
+    write_file(&dir.join("y.txt"), "d\nE\n");
+    let out = run(&["--patch", "fix.diff"]);
+    assert_eq!(out.status.code(), Some(3));
+    let stderr = String::from_utf8_lossy(&out.stderr);
+    let msg = "y.txt: hunk @@ -2 +2,2 @@ does not apply: line 2 is \"E\"";
+    assert!(stderr.contains(msg), "{stderr}");
+    assert_eq!(files(), ("a\nb\nc\n".into(), "d\nE\n".into()));
+
+    write_file(&dir.join("y.txt"), "d\ne\n");
+    let out = run(&["--patch", "fix.diff"]);
+    assert!(out.status.success(), "{}", String::from_utf8_lossy(&out.stderr));
+    assert_eq!(files(), ("a\nB\nc\n".into(), "d\ne\nf\n".into()));
+
+    // With a file, a one-file patch applies to it whatever name the patch gives.
+    write_file(&dir.join("z.txt"), "a\nb\nc\n");
+    let diff = read_file(&patch);
+    write_file(&patch, &diff[..diff.rfind("diff --git").unwrap()]);
+    assert!(run(&["--patch", "fix.diff", "z.txt"]).status.success());
+    assert_eq!(read_file(&dir.join("z.txt")), "a\nB\nc\n");
+    assert_eq!(run(&["--patch", "fix.diff", "z.txt", "1|0000|d"]).status.code(), Some(2));
+}
+
+#[test]
+fn exhash_convert_records_a_patch_as_a_replayable_script() {
+    let dir = mk_temp_dir("exhash_convert_patch");
+    write_file(&dir.join("x.txt"), "a\nb\nc\n");
+    write_file(&dir.join("y.txt"), "d\ne\n");
+    write_file(
+        &dir.join("fix.diff"),
+        "--- a/x.txt\n+++ b/x.txt\n@@ -1,3 +1,3 @@\n a\n-b\n+B\n c\n\
+         --- a/y.txt\n+++ b/y.txt\n@@ -2 +2,2 @@\n e\n+f\n",
+    );
+    let run = |args: &[&str]| {
+        Command::new(env!("CARGO_BIN_EXE_exhash")).current_dir(&dir).args(args).output().unwrap()
+    };
+
+    let out = run(&["convert", "--patch", "fix.diff"]);
+    assert!(out.status.success(), "{}", String::from_utf8_lossy(&out.stderr));
+    let script = String::from_utf8(out.stdout).unwrap();
+    let lines: Vec<&str> = script.lines().collect();
+    assert_eq!(lines.len(), 6, "{script}");
+    assert_eq!((lines[0], lines[3]), ("@path x.txt", "@path y.txt"));
+    assert!(lines[1].starts_with("@file ") && lines[4].starts_with("@file "), "{script}");
+    assert_eq!(lines[2], format!("{}c\\B", format_lnhash(2, "b")));
+    assert_eq!(lines[5], format!("{}a\\f", format_lnhash(2, "e")));
+    // Converting writes nothing; replaying the script makes the patch's change, once.
+    assert_eq!(read_file(&dir.join("x.txt")), "a\nb\nc\n");
+    write_file(&dir.join("fix.ex"), &script);
+    assert!(run(&["-q", "-f", "fix.ex"]).status.success());
+    assert_eq!(read_file(&dir.join("x.txt")), "a\nB\nc\n");
+    assert_eq!(read_file(&dir.join("y.txt")), "d\ne\nf\n");
+    assert_eq!(run(&["-q", "-f", "fix.ex"]).status.code(), Some(3));
+
+    // With a file, a one-file patch becomes that file's script alone.
+    write_file(&dir.join("z.txt"), "a\nb\nc\n");
+    let diff = read_file(&dir.join("fix.diff"));
+    write_file(&dir.join("fix.diff"), &diff[..diff.rfind("--- a/y.txt").unwrap()]);
+    let out = run(&["convert", "--patch", "fix.diff", "z.txt"]);
+    let script = String::from_utf8(out.stdout).unwrap();
+    assert!(script.starts_with("@file ") && !script.contains("@path"), "{script}");
+    assert_eq!(run(&["convert", "--patch", "fix.diff", "z.txt", "x.txt"]).status.code(), Some(2));
+}
+
+#[test]
+fn exhash_undo_reverts_saved_edits_in_turn() {
+    let dir = mk_temp_dir("exhash_undo");
+    let file = dir.join("f.txt");
+    write_file(&file, "a\nb\nc\n");
+    let bin = env!("CARGO_BIN_EXE_exhash");
+    let run = |args: &[&str]| Command::new(bin).args(args).arg(&file).output().unwrap();
+    let edit = |args: &[String]| {
+        let out = Command::new(bin).arg("--save-undo").arg(&file).args(args).output().unwrap();
+        assert!(out.status.success(), "{}", String::from_utf8_lossy(&out.stderr));
+    };
+
+    edit(&[format!("{}s/a/A/", format_lnhash(1, "a")), format!("{}d", format_lnhash(3, "c"))]);
+    edit(&[format!("{}c\\x\\ny", format_lnhash(2, "b"))]);
+    assert_eq!(read_file(&file), "A\nx\ny\n");
+
+    assert!(run(&["undo"]).status.success());
+    assert_eq!(read_file(&file), "A\nb\n");
+    assert!(run(&["undo"]).status.success());
+    assert_eq!(read_file(&file), "a\nb\nc\n");
+    assert_eq!(run(&["undo"]).status.code(), Some(1));
+
+    // An undo script is verified like any other: it refuses once the file has moved on.
+    edit(&[format!("{}s/a/A/", format_lnhash(1, "a"))]);
+    write_file(&file, "changed\nb\nc\n");
+    assert_eq!(run(&["undo"]).status.code(), Some(3));
+    assert_eq!(read_file(&file), "changed\nb\nc\n");
+
+    // A subcommand's name is always the subcommand, even beside a file of that name, which
+    // is edited as ./undo or after --.
+    for name in ["undo", "convert"] {
+        write_file(&dir.join(name), "a\n");
+        let cmd = format!("{}s/a/A/", format_lnhash(1, "a"));
+        let out = Command::new(bin).current_dir(&dir).args([name, &cmd]).output().unwrap();
+        assert!(!out.status.success());
+        assert_eq!(read_file(&dir.join(name)), "a\n");
+        for file in [format!("./{name}"), format!("-- {name}")] {
+            let args: Vec<&str> = file.split(' ').chain([cmd.as_str()]).collect();
+            write_file(&dir.join(name), "a\n");
+            let out = Command::new(bin).current_dir(&dir).args(&args).output().unwrap();
+            assert!(out.status.success(), "{}", String::from_utf8_lossy(&out.stderr));
+            assert_eq!(read_file(&dir.join(name)), "A\n");
+        }
+    }
+}
+
+#[test]
+fn exhash_keeps_or_strips_a_byte_order_mark() {
+    let dir = mk_temp_dir("exhash_bom");
+    let file = dir.join("f.txt");
+    write_file(&file, "\u{feff}a\nb\n");
+    let run = |bin: &str, flags: &[&str], args: &[String]| {
+        Command::new(bin).args(flags).arg(&file).args(args).output().unwrap()
+    };
+
+    // The mark is not part of line 1's address, as lnhashview prints it and exhash checks it.
+    let out = run(env!("CARGO_BIN_EXE_lnhashview"), &[], &["1".to_string()]);
+    let view = String::from_utf8(out.stdout).unwrap();
+    assert!(view.starts_with(&format_lnhash(1, "a")), "{view}");
+    let out = run(env!("CARGO_BIN_EXE_exhash"), &[], &[format!("{}s/a/A/", format_lnhash(1, "a"))]);
+    assert!(out.status.success(), "{}", String::from_utf8_lossy(&out.stderr));
+    assert_eq!(read_file(&file), "\u{feff}A\nb\n");
+
+    let delete_b = format!("{}d", format_lnhash(2, "b"));
+    let out = run(env!("CARGO_BIN_EXE_exhash"), &["--strip-bom"], &[delete_b]);
+    assert!(out.status.success(), "{}", String::from_utf8_lossy(&out.stderr));
+    assert_eq!(read_file(&file), "A\n");
+}
+
+#[test]
+fn exhash_and_lnhashview_read_project_config() {
+    let dir = mk_temp_dir("exhash_config");
+    let file = dir.join("f.txt");
+    write_file(&file, "a\nb\n");
+    write_file(&dir.join(".exhash.toml"), "hash-width = 6  # wider hashes\nindent-width = 2\n");
+    let run = |bin: &str, args: &[&str]| {
+        Command::new(bin)
+            .current_dir(&dir)
+            .env("XDG_CONFIG_HOME", dir.join("no-user-config"))
+            .env_remove("EXHASH_SEED")
+            .args(args)
+            .output()
+            .unwrap()
+    };
+
+    let out = run(env!("CARGO_BIN_EXE_lnhashview"), &["f.txt", "1"]);
+    let view = String::from_utf8(out.stdout).unwrap();
+    let addr = view.split("  ").next().unwrap().to_string();
+    assert_eq!(addr.len(), "1||".len() + 6, "{view}");
+
+    let out = run(env!("CARGO_BIN_EXE_exhash"), &["f.txt", &format!("{addr}>")]);
+    assert!(out.status.success(), "{}", String::from_utf8_lossy(&out.stderr));
+    assert_eq!(read_file(&file), "  a\nb\n");
+
+    let out = run(env!("CARGO_BIN_EXE_lnhashview"), &["--no-config", "f.txt", "2"]);
+    assert_eq!(String::from_utf8(out.stdout).unwrap(), format!("{}  b\n", format_lnhash(2, "b")));
+
+    // max-lines applies here, where stdout is a pipe, and --max-lines 0 lifts it.
+    write_file(&dir.join(".exhash.toml"), "max-lines = 1\n");
+    let out = run(env!("CARGO_BIN_EXE_lnhashview"), &["f.txt"]);
+    let notice = "… 1 more line (use start_line/end_line to view, from line 2)\n";
+    let first = format!("{}    a\n", format_lnhash(1, "  a"));
+    assert_eq!(String::from_utf8(out.stdout).unwrap(), first + notice);
+    let out = run(env!("CARGO_BIN_EXE_lnhashview"), &["--max-lines", "0", "f.txt"]);
+    assert_eq!(String::from_utf8(out.stdout).unwrap().lines().count(), 2);
+
+    write_file(&dir.join(".exhash.toml"), "hash-width = 5\n");
+    let out = run(env!("CARGO_BIN_EXE_exhash"), &["f.txt", "1|0000|d"]);
+    assert_eq!(out.status.code(), Some(2));
+    assert!(String::from_utf8_lossy(&out.stderr).contains(".exhash.toml: line 1:"));
+}
+
+#[cfg(feature = "server")]
+#[test]
+fn exhash_server_edits_files_over_json_rpc() {
+    use std::io::{BufRead, BufReader};
+
+    let dir = mk_temp_dir("server");
+    let file = dir.join("f.txt");
+    write_file(&file, "foo\nbar\n");
+    let mut child = Command::new(env!("CARGO_BIN_EXE_exhash-server"))
+        .arg("--no-config")
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .spawn()
+        .unwrap();
+    let mut stdin = child.stdin.take().unwrap();
+    let mut stdout = BufReader::new(child.stdout.take().unwrap());
+    let path = file.to_str().unwrap().replace('\\', "\\\\");
+    let request = |id: u32, method: &str, params: &str| {
+        format!(r#"{{"jsonrpc":"2.0","id":{id},"method":"{method}","params":{{{params}}}}}"#)
+    };
+    let mut call = |request: String| {
+        writeln!(stdin, "{request}").unwrap();
+        let mut line = String::new();
+        stdout.read_line(&mut line).unwrap();
+        line
+    };
+
+    let res = call(request(1, "view", &format!(r#""path":"{path}","start":2"#)));
+    let bar = format!(r#"{{"hash":"{}","lineno":2,"text":"bar"}}"#, format_lnhash(2, "bar"));
+    assert!(res.contains(&format!(r#""lines":[{bar}]"#)), "{res}");
+
+    let cmd = format!("{}s/foo/baz/", format_lnhash(1, "foo"));
+    let edit = format!(r#""path":"{path}","commands":["{cmd}"]"#);
+    let res = call(request(2, "editFile", &edit));
+    assert!(res.contains(r#""modified":[1]"#), "{res}");
+    assert_eq!(read_file(&file), "baz\nbar\n");
+
+    // The file's text is cached, and read again once it changes on disk.
+    let res = call(request(3, "editFile", &edit));
+    assert!(res.contains(r#""code":-32002"#) && res.contains(r#""kind":"stale_hash""#), "{res}");
+    assert!(res.contains(r#""command":0"#) && res.contains(r#""lineno":1"#), "{res}");
+    write_file(&file, "foo\nbar\nqux\n");
+    let res = call(request(4, "editFile", &edit));
+    assert!(res.contains(r#""text":"baz\nbar\nqux\n""#), "{res}");
+
+    let res = call(request(5, "verify", r#""text":"a\n","commands":["3|0000|d"]"#));
+    assert!(res.contains(r#""ok":false"#) && res.contains(r#""kind":"out_of_range""#), "{res}");
+    let res = call(request(6, "nope", ""));
+    assert!(res.contains(r#""code":-32601"#), "{res}");
+
+    // A notification gets no response; shutdown answers, then the server exits.
+    let close = format!(r#"{{"jsonrpc":"2.0","method":"close","params":{{"path":"{path}"}}}}"#);
+    let res = call(format!("{close}\n{}", request(7, "shutdown", "")));
+    assert_eq!(res, "{\"id\":7,\"jsonrpc\":\"2.0\",\"result\":null}\n");
+    assert!(child.wait().unwrap().success());
+}
+
+#[cfg(feature = "mcp")]
+#[test]
+fn exhash_mcp_serves_view_edit_and_verify_tools() {
+    let dir = mk_temp_dir("mcp");
+    let file = dir.join("f.txt");
+    write_file(&file, "foo\nbar\n");
+    let path = file.to_str().unwrap().replace('\\', "\\\\");
+    let request = |id: u32, method: &str, params: &str| {
+        format!(r#"{{"jsonrpc":"2.0","id":{id},"method":"{method}","params":{{{params}}}}}"#)
+    };
+    let call = |id: u32, tool: &str, args: &str| {
+        let params = format!(r#""name":"{tool}","arguments":{{"path":"{path}"{args}}}"#);
+        request(id, "tools/call", &params)
+    };
+    let foo = format_lnhash(1, "foo");
+    let requests = [
+        request(1, "initialize", r#""protocolVersion":"2024-11-05""#),
+        r#"{"jsonrpc":"2.0","method":"notifications/initialized"}"#.to_string(),
+        r#"{"jsonrpc":"2.0","id":2,"method":"tools/list"}"#.to_string(),
+        call(3, "lnhash_view", r#","start_line":2"#),
+        call(4, "exhash_verify", &format!(r#","commands":["{foo}d","9|0000|d"]"#)),
+        call(5, "exhash_edit", &format!(r#","commands":["{foo}s/foo/baz/"]"#)),
+        call(6, "exhash_edit", &format!(r#","commands":["{foo}d"]"#)),
+        call(7, "nope", ""),
+    ];
+    let mut child = Command::new(env!("CARGO_BIN_EXE_exhash-mcp"))
+        .arg("--no-config")
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .spawn()
+        .unwrap();
+    writeln!(child.stdin.take().unwrap(), "{}", requests.join("\n")).unwrap();
+    let out = child.wait_with_output().unwrap();
+    let out = String::from_utf8(out.stdout).unwrap();
+    let res: Vec<&str> = out.lines().collect();
+    assert_eq!(res.len(), 7, "{out}");
+
+    assert!(res[0].contains(r#""protocolVersion":"2024-11-05""#), "{}", res[0]);
+    assert!(res[0].contains(r#""capabilities":{"tools":{}}"#), "{}", res[0]);
+    for tool in ["lnhash_view", "exhash_edit", "exhash_verify"] {
+        assert!(res[1].contains(&format!(r#""name":"{tool}""#)), "{}", res[1]);
+    }
+    assert!(res[1].contains(r#""required":["path","commands"]"#), "{}", res[1]);
+    let bar = format_lnhash(2, "bar");
+    assert!(res[2].contains(&format!(r#""text":"{bar}  bar""#)), "{}", res[2]);
+    assert!(res[3].contains(r#""isError":true"#), "{}", res[3]);
+    assert!(res[3].contains("out_of_range error in command 2 (9|0000|d)"), "{}", res[3]);
+    assert!(!res[3].contains("command 1"), "{}", res[3]);
+    assert!(res[4].contains(&format!(r#"{}  baz""#, format_lnhash(1, "baz"))), "{}", res[4]);
+    assert_eq!(read_file(&file), "baz\nbar\n");
+    // A stale address fails the edit, and the hint gives the line's new address.
+    assert!(res[5].contains(r#""isError":true"#) && res[5].contains("stale_hash"), "{}", res[5]);
+    assert!(res[5].contains(&format!("line 1 is now: {}  baz", format_lnhash(1, "baz"))));
+    assert_eq!(read_file(&file), "baz\nbar\n");
+    assert!(res[6].contains(r#""code":-32602"#), "{}", res[6]);
+}
+
+#[cfg(feature = "httpd")]
+#[test]
+fn exhash_httpd_serves_allowed_files_over_http() {
+    use std::io::{BufRead, BufReader, Read};
+    use std::net::TcpStream;
+
+    let dir = mk_temp_dir("httpd");
+    let file = dir.join("f.txt");
+    write_file(&file, "foo\nbar\n");
+    let outside = mk_temp_dir("httpd-outside").join("g.txt");
+    write_file(&outside, "x\n");
+    let mut child = Command::new(env!("CARGO_BIN_EXE_exhash-httpd"))
+        .args(["--no-config", "--listen", "127.0.0.1:0", "--max-body", "200", "--allow"])
+        .arg(&dir)
+        .stderr(Stdio::piped())
+        .spawn()
+        .unwrap();
+    let mut banner = String::new();
+    BufReader::new(child.stderr.take().unwrap()).read_line(&mut banner).unwrap();
+    let addr = banner.trim().rsplit("http://").next().unwrap().to_string();
+    let post = |endpoint: &str, body: &str| {
+        let mut stream = TcpStream::connect(&addr).unwrap();
+        write!(
+            stream,
+            "POST {endpoint} HTTP/1.1\r\nHost: localhost\r\nContent-Type: application/json\r\n\
+             Content-Length: {}\r\nConnection: close\r\n\r\n{body}",
+            body.len()
+        )
+        .unwrap();
+        let mut res = String::new();
+        stream.read_to_string(&mut res).unwrap();
+        let (head, body) = res.split_once("\r\n\r\n").unwrap();
+        (head.split(' ').nth(1).unwrap().to_string(), body.to_string())
+    };
+    let path = file.to_str().unwrap().replace('\\', "\\\\");
+    let foo = format_lnhash(1, "foo");
+
+    let (status, body) = post("/view", &format!(r#"{{"path":"{path}","start":2}}"#));
+    assert_eq!(status, "200", "{body}");
+    let bar = format_lnhash(2, "bar");
+    assert!(body.contains(&format!(r#""hash":"{bar}","lineno":2,"text":"bar""#)), "{body}");
+    assert!(!body.contains(r#""text":"foo""#), "{body}");
+
+    let cmds = format!(r#""commands":["{foo}d","9|0000|d"]"#);
+    let (status, body) = post("/verify", &format!(r#"{{"path":"{path}",{cmds}}}"#));
+    assert_eq!(status, "200", "{body}");
+    assert!(body.contains(r#""ok":false"#) && body.contains("out_of_range"), "{body}");
+
+    let edit = format!(r#"{{"path":"{path}","commands":["{foo}s/foo/baz/"]}}"#);
+    let (status, body) = post("/edit", &edit);
+    assert_eq!(status, "200", "{body}");
+    assert!(body.contains(r#""modified":[1]"#), "{body}");
+    assert_eq!(read_file(&file), "baz\nbar\n");
+    // The same edit again has a stale address and conflicts with the file.
+    let (status, body) = post("/edit", &edit);
+    assert_eq!(status, "409", "{body}");
+    assert!(body.contains(r#""kind":"stale_hash""#) && body.contains(r#""lineno":1"#), "{body}");
+    assert_eq!(read_file(&file), "baz\nbar\n");
+
+    let outside = outside.to_str().unwrap().replace('\\', "\\\\");
+    assert_eq!(post("/view", &format!(r#"{{"path":"{outside}"}}"#)).0, "403");
+    let missing = dir.join("nope.txt");
+    let missing = missing.to_str().unwrap().replace('\\', "\\\\");
+    assert_eq!(post("/view", &format!(r#"{{"path":"{missing}"}}"#)).0, "404");
+    assert_eq!(post("/view", "[]").0, "400");
+    assert_eq!(post("/view", &format!(r#"{{"path":"{}"}}"#, "x".repeat(300))).0, "413");
+    child.kill().unwrap();
+    child.wait().unwrap();
+}
@@ -1,11 +1,72 @@
 import pytest
-from exhash import line_hash, lnhash, lnhashview, exhash, exhash_result
+from exhash import line_hash, line_hash_u64, lnhash, lnhashview, exhash, exhash_result, exhash_script, file_fingerprint
+from exhash import parse_lnhash, format_lnhash, lnhashes, exhash_many, verify_only
+from exhash import edit_file, aedit_file, EditSession, EditOptions, Command, Subcommand, LnHash, StaleHashError, ParseError, AddressRangeError
 
 def test_line_hash_returns_4_hex():
     h = line_hash("hello")
     assert len(h) == 4
     assert all(c in '0123456789abcdef' for c in h)
 
+def test_line_hash_wider_widths():
+    assert len(line_hash("}", 8)) == 8
+    assert line_hash("}", 8).endswith(line_hash("}"))
+    with pytest.raises(ValueError): line_hash("}", 5)
+
+def test_line_hash_u64_is_full_hash():
+    h = line_hash_u64("}")
+    assert 0 <= h < 2**64 and f"{h:016x}".endswith(line_hash("}", 8))
+    assert line_hash_u64("}", seed="p") != h
+    res = exhash("a\n}\n", [])
+    assert res["full_hashes"] == [line_hash_u64("a"), h]
+
+def test_exhash_accepts_wide_address():
+    res = exhash("}\n}\n", [f"{lnhash(2, '}', 8)}d"])
+    assert res["lines"] == ["}"]
+
+def test_compact_addresses():
+    addr = lnhash(12, "x", compact=True)
+    assert addr == f"c:{line_hash('x')}"
+    assert lnhashview("a\nb", compact=True)[1].startswith("2:")
+    res = exhash("a\nb\n", [lnhash(2, "b", compact=True) + "d"])
+    assert res["lines"] == ["a"]
+
+def test_seeded_addresses():
+    addr = lnhash(2, "b", seed="proj")
+    assert "|s" in addr and line_hash("b", seed="proj") != line_hash("b")
+    res = exhash("a\nb\n", [f"{addr}d"], seed="proj")
+    assert res["lines"] == ["a"]
+    assert res["hashes"] == [lnhash(1, "a", seed="proj")]
+    with pytest.raises(ValueError, match="seed"): exhash("a\nb\n", [f"{addr}d"])
+
+def test_nfc_hashing():
+    composed, decomposed = "caf\u00e9", "cafe\u0301"
+    assert line_hash(composed, nfc=True) == line_hash(decomposed, nfc=True)
+    addr = lnhash(1, composed, nfc=True)
+    assert addr.startswith("1|nfc:")
+    assert exhash(decomposed, [f"{addr}d"])["lines"] == []
+
+def test_ignore_case_addresses():
+    addr = lnhash(1, "SELECT 1", ignore_case=True)
+    assert addr.startswith("1|lc:")
+    assert line_hash("A", ignore_case=True) == line_hash("a", ignore_case=True)
+    assert exhash("select 1\n", [f"{addr}d"])["lines"] == []
+
+def test_chained_addresses():
+    text = "a:\n}\nb:\n}\n"
+    view = lnhashview(text, chained=True)
+    assert view[1].split("|")[1] != view[3].split("|")[1]
+    addr = lnhash(4, "}", chained=True, prev="b:")
+    assert view[3].startswith(addr)
+    assert exhash(text, [f"{addr}d"])["lines"] == ["a:", "}", "b:"]
+
+def test_versioned_addresses():
+    addr = lnhash(1, "a", versioned=True)
+    assert addr == f"1|v2:{line_hash('a')}|"
+    res = exhash("a\nb\n", [f"{addr}d"], versioned=True)
+    assert res["hashes"] == [lnhash(1, "b", versioned=True)]
+    with pytest.raises(ValueError, match="version"): exhash("a\n", ["1|v7:abcd|d"])
+
 def test_line_hash_deterministic():
     assert line_hash("foo") == line_hash("foo")
     assert line_hash("foo") != line_hash("bar")
@@ -71,7 +132,7 @@ def test_exhash_result_is_dict():
     addr = lnhash(1, "foo")
     res = exhash(text, [f"{addr}s/foo/baz/"])
     assert isinstance(res, dict)
-    assert set(res.keys()) == {"lines", "hashes", "modified", "deleted"}
+    assert set(res.keys()) == {"lines", "hashes", "full_hashes", "modified", "deleted"}
 
 
 def test_exhash_result_formats_modified():
@@ -123,6 +184,242 @@ def test_exhash_multiline_non_text_cmd_raises():
     addr = lnhash(1, "a")
     with pytest.raises(ValueError): exhash(text, [f"{addr}d\nextra"])
 
+def test_exhash_file_fingerprint_header():
+    text = "a\nb\n"
+    cmd = f"{lnhash(1, 'a')}d"
+    assert exhash(text, [f"@file {file_fingerprint(text)}", cmd])["lines"] == ["b"]
+    with pytest.raises(ValueError, match="fingerprint"): exhash("a\nc\n", [f"@file {file_fingerprint(text)}", cmd])
+
+def test_exhash_script_takes_a_whole_script():
+    text = "foo\nbar\n"
+    script = f"@file {file_fingerprint(text)}\n{lnhash(2, 'bar')}d\n{lnhash(1, 'foo')}a\nnew 1\nnew 2\n.\n"
+    res = exhash_script(text, script)
+    assert res["lines"] == ["foo", "new 1", "new 2"]
+    assert res["deleted"] == [2]
+    with pytest.raises(ValueError, match="fingerprint"): exhash_script("foo\nbaz\n", script)
+    with pytest.raises(ValueError): exhash_script(text, f"{lnhash(1, 'foo')}a\nunterminated\n")
+
+def test_edit_file_writes_in_place(tmp_path):
+    p = tmp_path / "f.txt"
+    p.write_bytes(b"foo\r\nbar\r\n")
+    cmd = f"{lnhash(1, 'foo')}s/foo/baz/"
+    assert edit_file(p, [cmd], dry_run=True)["lines"] == ["baz", "bar"]
+    assert p.read_bytes() == b"foo\r\nbar\r\n"
+    res = edit_file(str(p), [cmd], backup=".bak")
+    assert res["modified"] == [1]
+    assert p.read_bytes() == b"baz\r\nbar\r\n"
+    assert (tmp_path / "f.txt.bak").read_bytes() == b"foo\r\nbar\r\n"
+    with pytest.raises(ValueError, match="stale|mismatch"): edit_file(p, [cmd])
+    assert p.read_bytes() == b"baz\r\nbar\r\n"
+
+def test_aedit_file(tmp_path):
+    import asyncio
+    p = tmp_path / "f.txt"
+    p.write_text("foo\nbar\n")
+    res = asyncio.run(aedit_file(p, (f"{lnhash(1, 'foo')}s/foo/baz/",), backup=".bak"))
+    assert res["modified"] == [1] and p.read_text() == "baz\nbar\n"
+    assert (tmp_path / "f.txt.bak").read_text() == "foo\nbar\n"
+    with pytest.raises(StaleHashError): asyncio.run(aedit_file(p, [f"{lnhash(1, 'foo')}d"]))
+
+def test_edit_file_encoding(tmp_path):
+    p = tmp_path / "latin1.txt"
+    p.write_bytes("café\n".encode("latin-1"))
+    with pytest.raises(ValueError, match="UTF8"): edit_file(p, [f"{lnhash(1, 'café')}s/é/e/"])
+    edit_file(p, [f"{lnhash(1, 'café')}s/caf/th/"], encoding="latin1")
+    assert p.read_bytes() == "thé\n".encode("latin-1")
+
+def test_exhash_raises_specific_errors():
+    text = "foo\nbar\n"
+    with pytest.raises(StaleHashError) as e: exhash(text, [f"{lnhash(2, 'baz')}d"])
+    assert (e.value.lineno, e.value.expected, e.value.actual) == (2, line_hash("baz"), line_hash("bar"))
+    with pytest.raises(StaleHashError) as e: exhash(text, [f"@file {file_fingerprint('x')}"])
+    assert e.value.lineno is None and e.value.actual == file_fingerprint(text)
+    with pytest.raises(ParseError): exhash(text, [f"{lnhash(1, 'foo')}s/(/x/"])
+    with pytest.raises(AddressRangeError): exhash(text, [f"{lnhash(5, 'foo')}d"])
+    assert issubclass(StaleHashError, ValueError)
+
+def test_edit_session_applies_and_undoes():
+    s = EditSession("foo\r\nbar\r\n")
+    assert s.view() == lnhashview("foo\nbar\n")
+    assert s.view(2) == [f"{lnhash(2, 'bar')}  bar"]
+    res = s.apply([f"{lnhash(1, 'foo')}s/foo/baz/"])
+    assert res["modified"] == [1]
+    s.apply([f"{lnhash(2, 'bar')}a\nqux"])
+    assert s.text == "baz\r\nbar\r\nqux\r\n"
+    with pytest.raises(StaleHashError): s.apply([f"{lnhash(1, 'foo')}d"])
+    assert s.undo() and s.text == "baz\r\nbar\r\n"
+    assert s.undo() and s.text == "foo\r\nbar\r\n"
+    assert not s.undo()
+    assert EditSession("a\nb\n", compact=True).view(1, 1) == [f"{lnhash(1, 'a', compact=True)}  a"]
+
+def test_commands_built_from_objects():
+    text = "a/b\nfoo\nbar\n"
+    a1, a2, a3 = (LnHash(i + 1, l) for i, l in enumerate(text.splitlines()))
+    assert str(a1) == lnhash(1, "a/b") and LnHash.parse(str(a2)) == a2
+    assert (a2.lineno, f"{a2.hash:04x}", a2.width) == (2, line_hash("foo"), 4)
+    sub = Command.substitute(a1, "/", "\\", global_=True)
+    assert str(sub) == f"{a1}s/\\//\\\\/g"
+    assert str(Command.parse(str(sub))) == str(sub)
+    cmds = [Command(a3, Subcommand.append("x\\y\nz")), Command.change(a2, ["new"]), sub]
+    assert exhash(text, cmds)["lines"] == ["a\\b", "new", "bar", "x\\y", "z"]
+    assert str(Command.delete("2|0000|", end=a3)) == f"2|0000|,{a3}d"
+    assert str(Command(a1, Subcommand.global_("o", Subcommand.delete(), invert=True), end=a3)).endswith("v/o/d")
+    with pytest.raises(ParseError): Command.delete("nope")
+
+def test_address_utilities():
+    a = parse_lnhash("12|a3f2|")
+    assert (a.lineno, a.hash, a.width, a.formatted) == (12, 0xa3f2, 4, "12|a3f2|")
+    assert parse_lnhash("c:a3f2") == a
+    assert format_lnhash(12, 0xa3f2) == "12|a3f2|"
+    assert format_lnhash(12, 0xa3f2, width=6, compact=True) == "c:00a3f2"
+    with pytest.raises(ValueError): format_lnhash(1, 0x10000)
+    with pytest.raises(ParseError): parse_lnhash("12|zz|")
+    text = "foo\nbar\n"
+    assert [str(h) for h in lnhashes(text)] == [lnhash(1, "foo"), lnhash(2, "bar")]
+    assert [h.formatted for h in lnhashes(text, chained=True)] == [v.split("  ")[0] for v in lnhashview(text, chained=True)]
+
+def test_result_diff_and_patch(tmp_path):
+    res = exhash("foo\nbar\n", [f"{lnhash(1, 'foo')}s/foo/baz/"])
+    assert res.unified_diff() == "--- a\n+++ b\n@@ -1,2 +1,2 @@\n-foo\n+baz\n bar\n"
+    assert res.unified_diff("baz\nbar\n") == ""
+    assert res.unified_diff(context=0).endswith("@@ -1 +1 @@\n-foo\n+baz\n")
+    assert res.to_patch("f.txt").startswith("--- a/f.txt\n+++ b/f.txt\n")
+    html = res._repr_html_()
+    assert 'class="exhash-del"' in html and f'<td class="exhash-addr">{lnhash(1, "baz")}</td>' in html
+    assert res == dict(res)
+    p = tmp_path / "g.txt"
+    p.write_text("x\n")
+    res = edit_file(p, [Command.append(LnHash(1, "x"), "y")], dry_run=True)
+    assert res.to_patch() == f"--- a/{p}\n+++ b/{p}\n@@ -1 +1,2 @@\n x\n+y\n"
+
+def test_exhash_many_edits_in_parallel():
+    texts = [f"line {i}\nend\n" for i in range(50)]
+    items = [(t, [f"{lnhash(1, f'line {i}')}s/line/LINE/"]) for i, t in enumerate(texts)]
+    res = exhash_many(items)
+    assert [r["lines"][0] for r in res] == [f"LINE {i}" for i in range(50)]
+    assert res[3].unified_diff().count("+LINE 3") == 1
+    items[7] = ("other\n", items[7][1])
+    with pytest.raises(StaleHashError): exhash_many(items)
+    res = exhash_many(items, return_exceptions=True)
+    assert isinstance(res[7], StaleHashError) and res[7].lineno == 1
+    assert res[8]["lines"] == ["LINE 8", "end"]
+    assert exhash_many([]) == []
+
+def test_text_from_bytes_and_paths(tmp_path):
+    p = tmp_path / "f.txt"
+    p.write_bytes("café\nbar\n".encode("latin-1"))
+    cmd = f"{lnhash(1, 'café')}s/caf/th/"
+    assert lnhashview(p, encoding="latin1") == lnhashview("café\nbar\n")
+    assert exhash(p.read_bytes(), [cmd], encoding="latin1")["lines"] == ["thé", "bar"]
+    assert exhash("café\n".encode(), [cmd])["lines"] == ["thé"]
+    assert file_fingerprint(p, encoding="auto") == file_fingerprint("café\nbar\n")
+    assert EditSession(p, encoding="latin1").text == "café\nbar\n"
+    assert exhash_many([(p, [cmd])], encoding="latin1")[0]["lines"][0] == "thé"
+    with pytest.raises(ValueError, match="UTF8"): lnhashview(p)
+    with pytest.raises(ValueError, match="failed to read"): lnhashview(tmp_path / "missing.txt")
+
+def test_verify_only_reports_every_failure():
+    text = "a\nb\nc\nd\n"
+    cmds = [f"{lnhash(1, 'a')}s/a/A/", f"{lnhash(2, 'x')}d", f"{lnhash(9, 'a')}d", f"{lnhash(1, 'b')}d"]
+    report = verify_only(text, cmds, suggest=True)
+    assert not report["ok"]
+    stale, out_of_range, moved = report["errors"]
+    assert (stale["kind"], stale["command"], stale["lineno"]) == ("stale_hash", 1, 2)
+    assert (stale["expected"], stale["actual"]) == (line_hash("x"), line_hash("b"))
+    assert (out_of_range["kind"], out_of_range["command"], out_of_range["lineno"]) == ("out_of_range", 2, None)
+    assert moved["text"] == cmds[3]
+    hint = next(h for h in report["suggestions"] if h["command"] == 3)
+    assert hint["current"] == {"lineno": 1, "hash": lnhash(1, "A"), "text": "A"}
+    assert [m["lineno"] for m in hint["matches"]] == [2]
+    assert verify_only(text, cmds[:1]) == {"ok": True, "errors": [], "suggestions": []}
+    assert exhash(text, cmds[:1])["lines"][0] == "A"
+    with pytest.raises(ParseError): verify_only(text, ["1|zz|d"])
+
+def test_lnhashview_range_and_pattern():
+    text = "a\nfoo\nb\nc\nfoo2\nd\n"
+    view = lnhashview(text)
+    assert lnhashview(text, start=2, end=3) == view[1:3]
+    assert lnhashview(text, start=5, end=99) == view[4:]
+    assert lnhashview(text, pattern="^foo") == [view[1], view[4]]
+    assert lnhashview(text, pattern="foo", context=1, end=5) == view[:5]
+    rows = lnhashview(text, pattern="foo2", context=1, rows=True)
+    assert rows[1] == {"lineno": 5, "hash": lnhash(5, "foo2"), "text": "foo2", "context": False}
+    assert [(r["lineno"], r["context"]) for r in rows] == [(4, True), (5, False), (6, True)]
+    assert lnhashview(text, chained=True, start=3, end=3) == lnhashview(text, chained=True)[2:3]
+    with pytest.raises(ParseError): lnhashview(text, pattern="(")
+
+def test_result_introspection():
+    import json
+    res = exhash("foo\r\nbar\r\n", [f"{lnhash(1, 'foo')}s/foo/baz/"])
+    assert res.text == "baz\r\nbar\r\n"
+    assert res.rows() == [(1, lnhash(1, "baz"), "baz"), (2, lnhash(2, "bar"), "bar")]
+    d = json.loads(json.dumps(res.to_dict()))
+    assert d["text"] == res.text and d["modified"] == [1]
+    r = res._r
+    assert len(r) == 2 and list(r) == res.rows()
+    assert repr(r) == "EditResult(2 lines, modified=[1], deleted=[])"
+    # The result itself stays an ordinary dict of its fields.
+    assert len(res) == len(res.keys()) == 5 and {k: res[k] for k in res} == dict(res)
+
+def test_result_pickle_and_deepcopy(tmp_path):
+    import copy, pickle
+    p = tmp_path / "f.txt"
+    p.write_text("foo\nbar\n")
+    res = edit_file(p, [f"{lnhash(1, 'foo', width=6)}s/foo/baz/"], width=6)
+    for c in (copy.deepcopy(res), pickle.loads(pickle.dumps(res))):
+        assert c == res and type(c) is type(res) and c.text == res.text
+        assert c.rows() == res.rows() and c.to_patch() == res.to_patch() and c._repr_html_() == res._repr_html_()
+
+def test_edit_options(tmp_path, monkeypatch):
+    text = "def f():\nx\ny\n"
+    a2, a3 = lnhash(2, "x"), lnhash(3, "y")
+    opts = EditOptions(indent_width=2, join="never", width=6)
+    assert opts.indent_width == 2 and opts.width == 6 and opts.join == "never"
+    res = exhash(text, [f"{a2},{a3}j", f"{lnhash(2, 'xy')}>"], options=opts)
+    assert res["lines"] == ["def f():", "  xy"] and res["hashes"][1] == lnhash(2, "  xy", width=6)
+    assert exhash("a\nb\n", [f"{lnhash(1, 'b')}d"], options=EditOptions(fuzz=1))["lines"] == ["a"]
+    strict = EditOptions(strict_substitute=True)
+    assert strict.strict_substitute and exhash("a\n", [f"{lnhash(1, 'a')}s/x/y/"])["lines"] == ["a"]
+    with pytest.raises(ValueError, match="matched nothing"): exhash("a\n", [f"{lnhash(1, 'a')}s/x/y/"], options=strict)
+    with pytest.raises(ValueError, match="options"): exhash(text, [], width=6, options=opts)
+    with pytest.raises(ValueError): EditOptions(join="sometimes")
+    with pytest.raises(TypeError): EditOptions(indent=2)
+    with pytest.raises(TypeError): EditOptions(fuzz="1")
+    limits, small = EditOptions(max_commands=1, max_block_size=4), EditOptions(max_file_size=8)
+    with pytest.raises(ValueError, match="max-commands"): exhash(text, [f"{a2}d", f"{a3}d"], options=limits)
+    with pytest.raises(ValueError, match="max-block-size"): exhash("a\n", [f"{lnhash(1, 'a')}a\nlong"], options=limits)
+    with pytest.raises(ValueError, match="max_file_size"): exhash(text, [], options=small)
+    p = tmp_path / "f.txt"
+    p.write_text(text)
+    with pytest.raises(ValueError, match="max_file_size"): edit_file(p, [f"{a2}d"], options=small)
+    (tmp_path / ".exhash.toml").write_text("indent-width = 3\nmax-commands = 5\n")
+    monkeypatch.chdir(tmp_path)
+    monkeypatch.setenv("XDG_CONFIG_HOME", str(tmp_path / "none"))
+    monkeypatch.delenv("EXHASH_SEED", raising=False)
+    config = EditOptions.from_config()
+    assert (config.indent_width, config.max_commands, config.fuzz) == (3, 5, 0)
+    assert edit_file(p, [f"{a3}>"], options=config)["lines"][2] == "   y"
+
+def test_on_command_reports_each_step(tmp_path):
+    text = "foo\nbar\nbaz\n"
+    a1, a2 = lnhash(1, "foo"), lnhash(2, "bar")
+    steps = []
+    res = exhash(text, [f"@file {file_fingerprint(text)}", f"{a2}d", f"{a1}s/o/0/g"], on_command=steps.append)
+    assert res["lines"] == ["f00", "baz"]
+    assert [s["command"] for s in steps] == [0, 1] and steps[1]["text"] == f"{a1}s/o/0/g"
+    assert steps[0]["deleted"] == [2] and steps[0]["line_count"] == 2
+    assert steps[1]["changed"] == [(1, "f00")] and steps[1]["stats"]["substitutions"] == 2
+    steps.clear()
+    with pytest.raises(StaleHashError): exhash(text, [f"{a1}d", f"{a1}d"], on_command=steps.append)
+    assert steps == []
+    with pytest.raises(TypeError): exhash(text, [f"{a1}d"], on_command="log")
+    p = tmp_path / "f.txt"
+    p.write_text(text)
+    edit_file(p, [f"{a1}d"], on_command=steps.append)
+    s = EditSession(text)
+    s.apply([f"{a2}c\nBAR"], on_command=steps.append)
+    assert [st["text"] for st in steps] == [f"{a1}d", f"{a2}c\\BAR"]
+
 def test_exhash_accepts_tuple_cmds():
     text = "a\nb\n"
     a1, a2 = lnhash(1, "a"), lnhash(2, "b")
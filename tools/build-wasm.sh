@@ -0,0 +1,6 @@
+#!/bin/bash
+set -e
+# Builds the npm package (JavaScript bindings over WebAssembly) into pkg/. Needs wasm-pack and the
+# wasm32-unknown-unknown target. Pass a wasm-pack target: bundler (default), web or nodejs.
+target=${1:-bundler}
+wasm-pack build --release --target "$target" --out-dir pkg -- --features wasm